@@ -0,0 +1,78 @@
+//! Compares trying every configured SPN on a client's first token with selecting it by the ticket's target
+//!
+//! Needs a backend, keys for all SPNs in `BENCH_SPNS` (comma-separated, in `service/host` form) and a client ticket
+//! cache for `TEST_SPN`, which should come last in `BENCH_SPNS` to show the worst case of trying them in order. Run with
+//! `cargo bench --bench spn_selection`.
+use std::time::{Duration, Instant};
+
+use axum_negotiate_layer::{InitialToken, Spn};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+    server::ServerBuilder,
+};
+
+const ITERATIONS: u32 = 200;
+
+/// Whether the server credentials for `spn` accept `token`
+fn accepts(spn: &Spn, token: &[u8]) -> bool {
+    let Ok(credentials) = Credentials::inbound(Some(&spn.to_string()), Mechanism::Spnego) else {
+        return false;
+    };
+    ServerBuilder::new_from_credentials(credentials)
+        .initialize(token)
+        .is_ok()
+}
+
+fn sequential(spns: &[Spn], token: &[u8]) -> Option<usize> {
+    spns.iter().position(|spn| accepts(spn, token))
+}
+
+fn targeted(spns: &[Spn], token: &[u8]) -> Option<usize> {
+    let target = InitialToken::target_spn(token)?;
+    let index = spns.iter().position(|spn| {
+        spn.service().eq_ignore_ascii_case(target.service()) && spn.host().eq_ignore_ascii_case(target.host())
+    })?;
+    accepts(&spns[index], token).then_some(index)
+}
+
+/// A new first token of the client for `spn`
+fn client_token(spn: &str) -> Vec<u8> {
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    match ClientBuilder::new_from_credentials(credentials, Some(spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    }
+}
+
+fn measure(name: &str, spns: &[Spn], tokens: &[Vec<u8>], select: fn(&[Spn], &[u8]) -> Option<usize>) -> Duration {
+    let start = Instant::now();
+    for token in tokens {
+        assert!(select(spns, token).is_some(), "{name}: no SPN accepted the token");
+    }
+    let per_token = start.elapsed() / ITERATIONS;
+    println!("{name:>10}: {per_token:?} per token");
+    per_token
+}
+
+fn main() {
+    let (Ok(spns), Ok(test_spn)) = (std::env::var("BENCH_SPNS"), std::env::var("TEST_SPN")) else {
+        eprintln!("BENCH_SPNS and TEST_SPN must be set, see the comment at the top of benches/spn_selection.rs");
+        return;
+    };
+    let spns: Vec<Spn> = spns.split(',').map(|spn| spn.trim().parse().unwrap()).collect();
+    println!("{} SPNs, ticket for {test_spn}", spns.len());
+    // The replay detection of the backend rejects a token accepted before, so every selection gets a token of its own,
+    // created beforehand
+    let tokens = || (0..ITERATIONS).map(|_| client_token(&test_spn)).collect::<Vec<_>>();
+    let sequential = measure("sequential", &spns, &tokens(), sequential);
+    let targeted = measure("targeted", &spns, &tokens(), targeted);
+    println!(
+        "targeted selection takes {:.1}% of the time",
+        100.0 * targeted.as_secs_f64() / sequential.as_secs_f64()
+    );
+}
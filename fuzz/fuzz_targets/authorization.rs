@@ -0,0 +1,18 @@
+#![no_main]
+
+use axum_negotiate_layer::{InitialToken, NtlmPolicy, parse_negotiate_authorization};
+use http::HeaderValue;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|authorization: &[u8]| {
+    let Ok(value) = HeaderValue::from_bytes(authorization) else {
+        return;
+    };
+    let Ok(token) = parse_negotiate_authorization(&value, &["Negotiate"]) else {
+        return;
+    };
+    if let Ok(decoded) = token.decode() {
+        let _ = InitialToken::parse(&decoded);
+        let _ = NtlmPolicy::default().check(&decoded);
+    }
+});
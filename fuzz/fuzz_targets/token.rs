@@ -0,0 +1,12 @@
+#![no_main]
+
+use axum_negotiate_layer::{InitialToken, NtlmDetails, NtlmPolicy};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|token: &[u8]| {
+    if let Some(initial) = InitialToken::parse(token) {
+        let _ = initial.offers_kerberos();
+    }
+    let _ = NtlmPolicy::default().check(token);
+    let _ = NtlmDetails::from_token(token);
+});
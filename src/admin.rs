@@ -0,0 +1,90 @@
+use std::{fmt::Write, time::UNIX_EPOCH};
+
+use axum::{Router, extract::State, routing::get};
+use http::header::CONTENT_TYPE;
+
+use crate::{FailureReason, LayerStats, ROUNDS_BUCKETS};
+
+/// A [`Router`] exposing `stats` as JSON for operators
+///
+/// This is meant to be mounted on an operations network, outside of any [`NegotiateLayer`](crate::NegotiateLayer):
+/// - `GET /stats`: handshakes that succeeded, also by their rounds in [`ROUNDS_BUCKETS`], and failed by
+///   [`FailureReason`], the [identity changes](LayerStats::identity_changes), and the connections currently pending
+///   and authenticated
+/// - `GET /failures`: the recent failures, if enabled with [`LayerStats::with_recent_failures`]
+///
+/// ```rust
+/// use axum::{routing::get, Router};
+/// use axum_negotiate_layer::{admin_router, LayerStats, NegotiateLayer};
+///
+/// let stats = LayerStats::with_recent_failures(100);
+/// let app: Router = Router::new()
+///     .route("/", get(|| async { "hello" }))
+///     .layer(NegotiateLayer::new(Some("HTTP/example.com")).with_stats(&stats));
+/// let admin: Router = admin_router(stats);
+/// ```
+pub fn admin_router(stats: LayerStats) -> Router {
+    Router::new()
+        .route("/stats", get(stats_json))
+        .route("/failures", get(failures_json))
+        .with_state(stats)
+}
+
+async fn stats_json(State(stats): State<LayerStats>) -> ([(http::HeaderName, &'static str); 1], String) {
+    let mut failed = String::new();
+    for (i, reason) in FailureReason::ALL.into_iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let _ = write!(failed, "{separator}\"{reason}\":{}", stats.failed(reason));
+    }
+    let mut rounds = String::new();
+    for (rounds_in_bucket, bucket) in (1..).zip(ROUNDS_BUCKETS) {
+        let separator = if rounds_in_bucket == 1 { "" } else { "," };
+        let _ = write!(
+            rounds,
+            "{separator}\"{bucket}\":{}",
+            stats.succeeded_in_rounds(rounds_in_bucket)
+        );
+    }
+    let json = format!(
+        "{{\"handshakes\":{{\"succeeded\":{},\"rounds\":{{{rounds}}},\"failed\":{{{failed}}},\"identity_changes\":{}}},\"connections\":{{\"pending\":{},\"authenticated\":{}}}}}",
+        stats.succeeded(),
+        stats.identity_changes(),
+        stats.pending(),
+        stats.authenticated(),
+    );
+    ([(CONTENT_TYPE, "application/json")], json)
+}
+
+async fn failures_json(State(stats): State<LayerStats>) -> ([(http::HeaderName, &'static str); 1], String) {
+    let mut json = String::from("[");
+    for (i, failure) in stats.recent_failures().into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let time = failure.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let _ = write!(json, "{{\"reason\":\"{}\",\"client\":", failure.reason);
+        match &failure.client {
+            Some(client) => string(&mut json, client),
+            None => json.push_str("null"),
+        }
+        let _ = write!(json, ",\"time\":{time}}}");
+    }
+    json.push(']');
+    ([(CONTENT_TYPE, "application/json")], json)
+}
+
+/// Appends `s` as a JSON string
+fn string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
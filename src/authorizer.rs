@@ -0,0 +1,31 @@
+/// An authorization backend deciding what authenticated clients may do
+///
+/// Used with [`Authenticated::authorize`](crate::Authenticated::authorize), e.g. with an authorizer kept in the router's state:
+///
+/// ```rust
+/// use axum::{extract::State, http::StatusCode};
+/// use axum_negotiate_layer::{Authenticated, Authorizer};
+///
+/// struct Admins(Vec<String>);
+/// impl Authorizer for Admins {
+///     fn authorize(&self, client: &str, action: &str) -> bool {
+///         action == "read" || self.0.iter().any(|admin| admin == client)
+///     }
+/// }
+///
+/// async fn delete(State(admins): State<std::sync::Arc<Admins>>, auth: Authenticated) -> StatusCode {
+///     if !auth.authorize(admins.as_ref(), "delete") {
+///         return StatusCode::FORBIDDEN;
+///     }
+///     StatusCode::NO_CONTENT
+/// }
+/// ```
+pub trait Authorizer {
+    /// Whether `client` may perform `action`
+    fn authorize(&self, client: &str, action: &str) -> bool;
+}
+impl<F: Fn(&str, &str) -> bool> Authorizer for F {
+    fn authorize(&self, client: &str, action: &str) -> bool {
+        self(client, action)
+    }
+}
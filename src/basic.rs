@@ -0,0 +1,70 @@
+//! Basic authentication for clients that can't do SPNEGO, see [`NegotiateLayer::basic_for_user_agents`]
+//!
+//! [`NegotiateLayer::basic_for_user_agents`]: crate::NegotiateLayer::basic_for_user_agents
+use std::sync::Arc;
+
+use http::{
+    HeaderMap, HeaderValue,
+    header::{AUTHORIZATION, USER_AGENT},
+};
+
+use crate::{ParseError, parse_negotiate_authorization};
+
+type Verifier = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// The callback of [`NegotiateLayer::basic_auth`](crate::NegotiateLayer::basic_auth) and its challenge
+#[derive(Clone)]
+pub(crate) struct BasicAuth {
+    pub(crate) challenge: HeaderValue,
+    pub(crate) verify: Verifier,
+}
+impl BasicAuth {
+    /// # Panics
+    ///
+    /// If `realm` contains characters that aren't allowed in a header value
+    pub(crate) fn new(realm: &str, verify: impl Fn(&str, &str) -> bool + Send + Sync + 'static) -> Self {
+        let realm = realm.replace('\\', "\\\\").replace('"', "\\\"");
+        let challenge =
+            HeaderValue::from_str(&format!("Basic realm=\"{realm}\"")).expect("realm should be valid header material");
+        Self {
+            challenge,
+            verify: Arc::new(verify),
+        }
+    }
+}
+
+/// Whether the `User-Agent` of a request contains any of `agents`
+pub(crate) fn matches_user_agent(headers: &HeaderMap, agents: &[String]) -> bool {
+    let Some(user_agent) = headers.get(USER_AGENT).and_then(|agent| agent.to_str().ok()) else {
+        return false;
+    };
+    agents.iter().any(|agent| user_agent.contains(agent.as_str()))
+}
+
+/// Whether the `Authorization` header of a request uses the `Basic` scheme
+pub(crate) fn is_basic(headers: &HeaderMap) -> bool {
+    headers
+        .get(AUTHORIZATION)
+        .is_some_and(|value| parse_negotiate_authorization(value, &["Basic"]).is_ok())
+}
+
+/// Why no credentials could be taken from a request
+pub(crate) enum BasicError {
+    Missing,
+    Invalid(ParseError),
+}
+
+/// User name and password of a `Basic` `Authorization` header
+///
+/// Credentials decoding to more than `max` bytes are rejected as invalid.
+pub(crate) fn credentials(headers: &HeaderMap, max: usize) -> Result<(String, String), BasicError> {
+    let authorization = headers.get(AUTHORIZATION).ok_or(BasicError::Missing)?;
+    let token = parse_negotiate_authorization(authorization, &["Basic"]).map_err(BasicError::Invalid)?;
+    let decoded = token.decode_bounded(max).map_err(BasicError::Invalid)?;
+    // RFC 7617 leaves the charset to the server, only UTF-8 is accepted here
+    let decoded = String::from_utf8(decoded).map_err(|_| BasicError::Invalid(ParseError::NotVisibleAscii))?;
+    let (user, password) = decoded
+        .split_once(':')
+        .ok_or(BasicError::Invalid(ParseError::MissingToken))?;
+    Ok((user.to_owned(), password.to_owned()))
+}
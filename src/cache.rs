@@ -0,0 +1,126 @@
+//! Least-recently-used bound on the authenticated contexts, see [`NegotiateLayer::max_cached_contexts`]
+//!
+//! [`NegotiateLayer::max_cached_contexts`]: crate::NegotiateLayer::max_cached_contexts
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use kenobi::{cred::Inbound, server::PendingServerContext};
+
+use crate::{
+    Handshake, NegotiateConnection, SharedContext, lock_context,
+    sink::{Sink, event},
+    state::WeakConnection,
+};
+
+type WeakNegotiateConnection = WeakConnection<(PendingServerContext<Inbound>, Handshake), SharedContext>;
+
+/// Shared by all clones of a layer's middleware
+#[derive(Clone)]
+pub(crate) struct ContextCache(Arc<Mutex<Entries>>);
+
+struct Entries {
+    capacity: usize,
+    next_id: u64,
+    next_use: u64,
+    /// Connection and last use of every cached context
+    by_id: HashMap<u64, (WeakNegotiateConnection, u64)>,
+    /// Ids ordered by last use
+    by_use: BTreeMap<u64, u64>,
+}
+
+/// Keeps an authenticated context in the cache until dropped together with it
+pub(crate) struct CacheEntry {
+    cache: ContextCache,
+    id: u64,
+}
+
+/// A context pushed out of the cache, to be dropped with [`Evicted::evict`] once no connection is locked
+pub(crate) struct Evicted {
+    id: u64,
+    connection: WeakNegotiateConnection,
+}
+
+impl ContextCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(Entries {
+            capacity: capacity.max(1),
+            next_id: 0,
+            next_use: 0,
+            by_id: HashMap::new(),
+            by_use: BTreeMap::new(),
+        })))
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Adds the context about to be established on `connection`, returning those exceeding the capacity
+    pub(crate) fn insert(&self, connection: &NegotiateConnection) -> (CacheEntry, Vec<Evicted>) {
+        let mut entries = self.lock();
+        let id = entries.next_id;
+        entries.next_id += 1;
+        let last_use = entries.next_use();
+        entries.by_id.insert(id, (connection.downgrade(), last_use));
+        entries.by_use.insert(last_use, id);
+        let mut evicted = Vec::new();
+        while entries.by_id.len() > entries.capacity {
+            let (_, oldest) = entries.by_use.pop_first().expect("every entry has a use");
+            let (connection, _) = entries.by_id.remove(&oldest).expect("every use has an entry");
+            evicted.push(Evicted { id: oldest, connection });
+        }
+        let entry = CacheEntry {
+            cache: self.clone(),
+            id,
+        };
+        (entry, evicted)
+    }
+}
+impl Entries {
+    fn next_use(&mut self) -> u64 {
+        let last_use = self.next_use;
+        self.next_use += 1;
+        last_use
+    }
+}
+
+impl CacheEntry {
+    /// Marks the context as the most recently used
+    pub(crate) fn touch(&self) {
+        let mut entries = self.cache.lock();
+        let next_use = entries.next_use();
+        let Some((_, last_use)) = entries.by_id.get_mut(&self.id) else {
+            return;
+        };
+        let previous = std::mem::replace(last_use, next_use);
+        entries.by_use.remove(&previous);
+        entries.by_use.insert(next_use, self.id);
+    }
+}
+impl Drop for CacheEntry {
+    fn drop(&mut self) {
+        let mut entries = self.cache.lock();
+        if let Some((_, last_use)) = entries.by_id.remove(&self.id) {
+            entries.by_use.remove(&last_use);
+        }
+    }
+}
+
+impl Evicted {
+    /// Drops the context, unless its connection was closed or has authenticated again in the meantime
+    ///
+    /// Locks the connection, so it must not be called while another connection is locked, which could deadlock with
+    /// a concurrent eviction in the other direction.
+    pub(crate) fn evict(self, sink: &dyn Sink) {
+        let Some(connection) = self.connection.upgrade() else {
+            return;
+        };
+        event!(sink, Debug, "Evicting least recently used context");
+        connection.evict_if(|context| {
+            lock_context(context)
+                .cache_entry
+                .as_ref()
+                .is_some_and(|entry| entry.id == self.id)
+        });
+    }
+}
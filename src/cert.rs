@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+/// The certificate a client presented during the TLS handshake, see [`NegotiateInfo::with_client_cert`]
+///
+/// [`NegotiateInfo::with_client_cert`]: crate::NegotiateInfo::with_client_cert
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCertificate {
+    der: Arc<[u8]>,
+    verified: bool,
+}
+impl ClientCertificate {
+    /// A certificate whose chain the TLS library verified against the trusted roots of the server
+    #[must_use]
+    pub fn verified(der: &[u8]) -> Self {
+        Self {
+            der: der.into(),
+            verified: true,
+        }
+    }
+    /// A certificate that was presented but not verified, e.g. by a server accepting any client certificate
+    ///
+    /// It is never used to authenticate the connection.
+    #[must_use]
+    pub fn unverified(der: &[u8]) -> Self {
+        Self {
+            der: der.into(),
+            verified: false,
+        }
+    }
+    /// The DER encoding of the end-entity certificate
+    #[must_use]
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+    #[must_use]
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+}
+
+/// Which authentication a request with both a verified client certificate and a `Negotiate` token gets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CertPrecedence {
+    /// The token is used for the handshake, or the connection's established context if it has one. The certificate
+    /// only authenticates requests without a token.
+    #[default]
+    Token,
+    /// The certificate authenticates every request of its connection, tokens are ignored
+    Certificate,
+}
+
+/// Maps verified client certificates to client names, see [`NegotiateLayer::or_client_cert`]
+///
+/// [`NegotiateLayer::or_client_cert`]: crate::NegotiateLayer::or_client_cert
+#[derive(Clone)]
+pub struct CertIdentityMapper {
+    map: MapFn,
+    pub(crate) precedence: CertPrecedence,
+}
+type MapFn = Arc<dyn Fn(&ClientCertificate) -> Option<String> + Send + Sync>;
+impl CertIdentityMapper {
+    /// `map` returns the name of the client, e.g. from the subject of the certificate, or [`None`] to not authenticate
+    /// with it
+    ///
+    /// Parsing the certificate is left to `map`, which only ever sees verified certificates.
+    #[must_use]
+    pub fn new(map: impl Fn(&ClientCertificate) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self {
+            map: Arc::new(map),
+            precedence: CertPrecedence::default(),
+        }
+    }
+    #[must_use]
+    pub fn precedence(mut self, precedence: CertPrecedence) -> Self {
+        self.precedence = precedence;
+        self
+    }
+    /// The client name for `cert`, if it is verified and mapped to one
+    pub(crate) fn client(&self, cert: &ClientCertificate) -> Option<String> {
+        cert.verified.then(|| (self.map)(cert))?
+    }
+}
+impl std::fmt::Debug for CertIdentityMapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertIdentityMapper")
+            .field("precedence", &self.precedence)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    fmt::{Debug, Display},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::extract::ConnectInfo;
+use http::{HeaderMap, HeaderName, HeaderValue, header::WWW_AUTHENTICATE, request::Parts};
+
+use crate::Negotiated;
+
+/// How multiple `WWW-Authenticate` challenges are emitted
+///
+/// Clients differ in which of the two equivalent forms they understand.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChallengeStyle {
+    /// One `WWW-Authenticate` header per challenge
+    #[default]
+    SeparateHeaders,
+    /// A single `WWW-Authenticate` header with all challenges separated by commas
+    CommaJoined,
+}
+impl ChallengeStyle {
+    /// Appends `challenges` to `headers` in this style
+    pub fn append(self, headers: &mut HeaderMap, challenges: &[HeaderValue]) {
+        self.append_to(headers, &WWW_AUTHENTICATE, challenges);
+    }
+    /// Appends `challenges` as `name` headers in this style, see [`NegotiateLayer::challenge_header`]
+    ///
+    /// [`NegotiateLayer::challenge_header`]: crate::NegotiateLayer::challenge_header
+    pub(crate) fn append_to(self, headers: &mut HeaderMap, name: &HeaderName, challenges: &[HeaderValue]) {
+        match self {
+            Self::SeparateHeaders => {
+                for challenge in challenges {
+                    headers.append(name, challenge.clone());
+                }
+            }
+            Self::CommaJoined => {
+                if challenges.is_empty() {
+                    return;
+                }
+                let joined = challenges
+                    .iter()
+                    .map(HeaderValue::as_bytes)
+                    .collect::<Vec<_>>()
+                    .join(&b", "[..]);
+                let value = HeaderValue::from_bytes(&joined).expect("joined header values should be valid");
+                headers.append(name, value);
+            }
+        }
+    }
+}
+
+/// An authentication scheme challenged with, see
+/// [`NegotiateLayer::scheme_order`](crate::NegotiateLayer::scheme_order)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scheme {
+    Negotiate,
+    /// Raw NTLM, for clients that don't do SPNEGO
+    Ntlm,
+    /// Only challenged with [`NegotiateLayer::basic_auth`](crate::NegotiateLayer::basic_auth)
+    Basic,
+}
+
+/// Decides which unauthenticated requests are challenged with `Negotiate`, see
+/// [`NegotiateLayer::challenge_policy`](crate::NegotiateLayer::challenge_policy)
+///
+/// A request is challenged if its peer address is within one of the allowed networks, or the predicate given to
+/// [`ChallengePolicy::allow_if`] returns `true` for it. A policy without either challenges no one.
+#[derive(Clone, Default)]
+pub struct ChallengePolicy {
+    networks: Vec<Network>,
+    predicate: Option<Predicate>,
+}
+type Predicate = Arc<dyn Fn(&Parts) -> bool + Send + Sync>;
+impl ChallengePolicy {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Challenge peers within `cidr`, e.g. `10.0.0.0/8` or `fd00::/8`
+    ///
+    /// The peer address is taken from the [`ConnectInfo`] of the request, which must be a [`SocketAddr`] or a
+    /// [`Negotiated<SocketAddr>`](crate::Negotiated). Requests without one are never in any network.
+    pub fn allow_network(mut self, cidr: &str) -> Result<Self, CidrParseError> {
+        self.networks.push(cidr.parse()?);
+        Ok(self)
+    }
+    /// Challenge requests for which `predicate` returns `true`, e.g. those carrying a header set by a corporate proxy
+    #[must_use]
+    pub fn allow_if(mut self, predicate: impl Fn(&Parts) -> bool + Send + Sync + 'static) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+    pub(crate) fn challenges(&self, parts: &Parts) -> bool {
+        let peer = peer_address(parts);
+        peer.is_some_and(|peer| self.networks.iter().any(|network| network.contains(peer)))
+            || self.predicate.as_ref().is_some_and(|predicate| predicate(parts))
+    }
+}
+impl Debug for ChallengePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChallengePolicy")
+            .field("networks", &self.networks)
+            .field("predicate", &self.predicate.is_some())
+            .finish()
+    }
+}
+
+fn peer_address(parts: &Parts) -> Option<IpAddr> {
+    let peer = match parts.extensions.get::<ConnectInfo<Negotiated<SocketAddr>>>() {
+        Some(ConnectInfo(negotiated)) => negotiated.inner,
+        None => parts.extensions.get::<ConnectInfo<SocketAddr>>()?.0,
+    };
+    // Dual-stack listeners report IPv4 peers as IPv4-mapped IPv6 addresses
+    Some(peer.ip().to_canonical())
+}
+
+/// An IP network in CIDR notation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+impl Network {
+    fn contains(self, address: IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+impl FromStr for Network {
+    type Err = CidrParseError;
+    fn from_str(cidr: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = cidr.split_once('/').ok_or(CidrParseError::MissingPrefix)?;
+        let address: IpAddr = address.parse().map_err(|_| CidrParseError::InvalidAddress)?;
+        let prefix: u8 = prefix.parse().map_err(|_| CidrParseError::InvalidPrefix)?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(CidrParseError::InvalidPrefix);
+        }
+        Ok(Self { address, prefix })
+    }
+}
+
+/// Reason a network given to [`ChallengePolicy::allow_network`] couldn't be parsed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CidrParseError {
+    /// No `/` separates address and prefix length
+    MissingPrefix,
+    InvalidAddress,
+    /// The prefix length isn't a number or exceeds the length of the address
+    InvalidPrefix,
+}
+impl Display for CidrParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::MissingPrefix => "network has no prefix length",
+            Self::InvalidAddress => "network has an invalid address",
+            Self::InvalidPrefix => "network has an invalid prefix length",
+        })
+    }
+}
+impl std::error::Error for CidrParseError {}
@@ -0,0 +1,17 @@
+use std::time::SystemTime;
+
+/// Source of the current time for expiring authenticated connections, see [`NegotiateLayer::with_clock`]
+///
+/// [`NegotiateLayer::with_clock`]: crate::NegotiateLayer::with_clock
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// [`Clock`] reading the system time, the default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+use crate::sink::{LayerSink, Sink, default_sink, event};
+
+/// Coordinates a graceful shutdown with the [`NegotiateLayer`](crate::NegotiateLayer)
+///
+/// Once [`Drainer::drain`] was called, every layer this drainer was given to via
+/// [`NegotiateLayer::with_drainer`](crate::NegotiateLayer::with_drainer) refuses new handshakes with
+/// `503 Service Unavailable`, while already authenticated connections continue to be served.
+///
+/// Its events go to the [sinks](crate::NegotiateLayer::with_log_sink) of the layers built with it, or the default
+/// sink if none of them has one.
+#[derive(Clone, Debug, Default)]
+pub struct Drainer(Arc<DrainState>);
+
+#[derive(Debug, Default)]
+struct DrainState {
+    draining: AtomicBool,
+    active: AtomicUsize,
+    idle: Notify,
+    sinks: Mutex<Vec<LayerSink>>,
+}
+
+impl Drainer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Whether [`Drainer::drain`] has been called
+    #[must_use]
+    pub fn is_draining(&self) -> bool {
+        self.0.draining.load(Ordering::Acquire)
+    }
+    /// The number of currently open authenticated connections
+    #[must_use]
+    pub fn active_connections(&self) -> usize {
+        self.0.active.load(Ordering::Acquire)
+    }
+    /// Stops accepting new handshakes and waits for all authenticated connections to close
+    ///
+    /// Returns `false` if there were still open connections after `timeout`.
+    ///
+    /// A connection only counts as closed once the client or server closes it, so idle keep-alive connections are waited on as well.
+    /// Combine this with the graceful shutdown of `axum::serve` to close them.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.0.draining.store(true, Ordering::Release);
+        let active = self.active_connections();
+        let sinks = self.0.sinks.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        if sinks.is_empty() {
+            event!(
+                default_sink(),
+                Info,
+                "Draining authenticated connections",
+                active = active
+            );
+        }
+        for LayerSink(sink) in &sinks {
+            event!(&**sink, Info, "Draining authenticated connections", active = active);
+        }
+        tokio::time::timeout(timeout, async {
+            loop {
+                let idle = self.0.idle.notified();
+                if self.active_connections() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+    /// Sends the events of this drainer to `sink` as well
+    pub(crate) fn add_sink(&self, sink: &Arc<dyn Sink>) {
+        let mut sinks = self.0.sinks.lock().unwrap_or_else(PoisonError::into_inner);
+        if !sinks.iter().any(|LayerSink(known)| Arc::ptr_eq(known, sink)) {
+            sinks.push(LayerSink(sink.clone()));
+        }
+    }
+    pub(crate) fn guard(&self) -> DrainGuard {
+        self.0.active.fetch_add(1, Ordering::AcqRel);
+        DrainGuard(self.clone())
+    }
+}
+
+/// Counts an authenticated connection as active until dropped together with the connection state
+#[derive(Debug)]
+pub(crate) struct DrainGuard(Drainer);
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        if self.0.0.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.0.idle.notify_waiters();
+        }
+    }
+}
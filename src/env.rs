@@ -0,0 +1,84 @@
+//! Configuration of the [`NegotiateLayer`](crate::NegotiateLayer) from environment variables
+use std::{ffi::OsString, fmt::Display, path::Path};
+
+use crate::spn::{Spn, SpnParseError};
+
+/// Environment variable holding the SPN, see [`NegotiateLayer::from_env`](crate::NegotiateLayer::from_env)
+pub const SPN_VAR: &str = "NEGOTIATE_SPN";
+/// Environment variable naming the keytab, read by GSSAPI itself
+pub const KEYTAB_VAR: &str = "KRB5_KTNAME";
+/// Environment variable naming the credential cache, read by GSSAPI itself
+pub const CCACHE_VAR: &str = "KRB5CCNAME";
+
+/// Reason a [`NegotiateLayer`](crate::NegotiateLayer) couldn't be configured from the environment
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EnvError {
+    /// A required variable isn't set
+    Missing(&'static str),
+    /// A variable is set, but empty
+    Empty(&'static str),
+    /// A variable isn't valid unicode
+    NotUnicode(&'static str),
+    /// The SPN can't be parsed
+    InvalidSpn(SpnParseError),
+    /// The keytab file named by [`KEYTAB_VAR`] doesn't exist
+    KeytabNotFound(String),
+}
+impl Display for EnvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing(var) => write!(f, "{var} is not set"),
+            Self::Empty(var) => write!(f, "{var} is empty"),
+            Self::NotUnicode(var) => write!(f, "{var} is not valid unicode"),
+            Self::InvalidSpn(e) => write!(f, "{SPN_VAR} is invalid: {e}"),
+            Self::KeytabNotFound(path) => write!(f, "keytab {path} from {KEYTAB_VAR} does not exist"),
+        }
+    }
+}
+impl std::error::Error for EnvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidSpn(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Reads `var`, treating unset and empty the same way
+fn read(var: &'static str, lookup: &impl Fn(&str) -> Option<OsString>) -> Result<Option<String>, EnvError> {
+    let Some(value) = lookup(var) else {
+        return Ok(None);
+    };
+    let value = value.into_string().map_err(|_| EnvError::NotUnicode(var))?;
+    if value.trim().is_empty() {
+        return Err(EnvError::Empty(var));
+    }
+    Ok(Some(value))
+}
+
+/// Reads and validates the environment, returning the SPN
+///
+/// The Kerberos variables are only checked here, as GSSAPI picks them up from the environment on its own.
+pub(crate) fn spn_from_env(lookup: impl Fn(&str) -> Option<OsString>) -> Result<String, EnvError> {
+    let spn = read(SPN_VAR, &lookup)?.ok_or(EnvError::Missing(SPN_VAR))?;
+    Spn::parse(&spn).map_err(EnvError::InvalidSpn)?;
+    if let Some(keytab) = read(KEYTAB_VAR, &lookup)?
+        && let Some(path) = keytab_file(&keytab)
+        && !Path::new(path).exists()
+    {
+        return Err(EnvError::KeytabNotFound(path.to_owned()));
+    }
+    read(CCACHE_VAR, &lookup)?;
+    Ok(spn)
+}
+
+/// The file of the keytab named `keytab` in [`KEYTAB_VAR`]
+///
+/// Only file keytabs can be checked, other types like `MEMORY:` are left to the backend.
+pub(crate) fn keytab_file(keytab: &str) -> Option<&str> {
+    keytab
+        .strip_prefix("FILE:")
+        .or_else(|| keytab.strip_prefix("WRFILE:"))
+        .or_else(|| (!keytab.contains(':') || Path::new(keytab).is_absolute()).then_some(keytab))
+}
@@ -0,0 +1,15 @@
+/// Whether handshakes are bound to the TLS channel they run in, see [`NegotiateLayer::extended_protection`]
+///
+/// [`NegotiateLayer::extended_protection`]: crate::NegotiateLayer::extended_protection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EpaPolicy {
+    /// Ignore the channel bindings of connections, for clients whose bindings are known to be wrong, e.g. behind a
+    /// TLS-inspecting proxy
+    Off,
+    /// Bind handshakes on connections with channel bindings, and accept clients that send none
+    #[default]
+    WhenSupported,
+    /// Refuse handshakes on connections without channel bindings, and NTLM clients that send none
+    Required,
+}
@@ -0,0 +1,168 @@
+//! Handshakes driven by a handler instead of the middleware, see [`NegotiateHandshake`]
+use std::sync::atomic::Ordering;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderMap, StatusCode, Version, request::Parts},
+    response::{IntoResponse, Response},
+};
+use tower::Layer;
+
+use crate::{
+    Authenticated, NegotiateInfo, NegotiateLayer, NegotiateMiddleware, NegotiateProgress, NegotiateToken, ParseError,
+    Persistence, Round, Spn, SpnOverride, lock_context, persistence, request_host, session, unauthorized,
+};
+
+/// Extractor running the handshake in the handler, for endpoints that have to act between its rounds
+///
+/// Needs no [`NegotiateLayer`] on the route, only the connection's [`NegotiateInfo`] as connect info or request
+/// extension. The rounds step the same state of the connection as the layer does, so a connection authenticated here is
+/// authenticated on the routes behind the layer as well, and the other way around. The default configuration, i.e. the
+/// default server credentials, is used unless a layer is given with [`NegotiateHandshake::configured`].
+///
+/// ```rust
+/// use axum::response::Response;
+/// use axum_negotiate_layer::{HandshakeOutcome, NegotiateHandshake, NegotiateLayer};
+///
+/// async fn enroll(handshake: NegotiateHandshake) -> Response {
+///     let handshake = handshake.configured(&NegotiateLayer::new(Some("HTTP/enroll.example.com")));
+///     let Some(Ok(token)) = handshake.token() else {
+///         return handshake.challenge();
+///     };
+///     // e.g. check the device is known before stepping its token
+///     let mut outcome = handshake.step(&token);
+///     if let HandshakeOutcome::Authenticated(authenticated, _) = &mut outcome {
+///         println!("enrolling for {}", authenticated.client());
+///     }
+///     handshake.challenge_response(outcome)
+/// }
+/// ```
+pub struct NegotiateHandshake {
+    middleware: NegotiateMiddleware<()>,
+    info: NegotiateInfo,
+    headers: HeaderMap,
+    host: Option<String>,
+    spn_override: Option<Spn>,
+    persistence: Persistence,
+    version: Version,
+}
+impl NegotiateHandshake {
+    /// Uses the configuration of `layer`, e.g. its SPN and policies, for the following rounds
+    #[must_use]
+    pub fn configured(mut self, layer: &NegotiateLayer) -> Self {
+        self.middleware.config = layer.config.clone();
+        self
+    }
+    /// The client's token, if the request carries one
+    pub fn token(&self) -> Option<Result<NegotiateToken<'_>, ParseError>> {
+        self.middleware.config.token_headers.token(&self.headers)
+    }
+    /// Steps the handshake of the connection with the client's `token`
+    ///
+    /// Starts a new handshake unless one is pending on the connection, and completes with the connection's context if
+    /// it has been authenticated meanwhile.
+    pub fn step(&self, token: &NegotiateToken<'_>) -> HandshakeOutcome {
+        let middleware = &self.middleware;
+        let mut headers = self.headers.clone();
+        let round = middleware.handshake_round(
+            &self.info.auth,
+            &self.info.last_client,
+            self.info.channel.as_ref(),
+            self.spn_override.as_ref(),
+            self.info.sni.as_deref(),
+            self.host.as_deref(),
+            &mut headers,
+            token,
+            self.persistence,
+            self.version,
+        );
+        let (authenticated, final_token) = match round {
+            Some(Round::Respond(response, progress @ NegotiateProgress::HandshakeLeg(_))) => {
+                return HandshakeOutcome::Continue(middleware.config.finish_response(response, progress));
+            }
+            Some(Round::Respond(response, progress)) => {
+                return HandshakeOutcome::Failed(middleware.config.finish_response(response, progress));
+            }
+            Some(Round::Authenticated {
+                extension,
+                final_token,
+                evicted,
+                ..
+            }) => {
+                for evicted in evicted {
+                    evicted.evict(middleware.config.sink());
+                }
+                (extension, final_token)
+            }
+            None => {
+                let authenticated = self.info.auth.with_authenticated(|shared| {
+                    let identity = lock_context(shared).identity.clone();
+                    middleware.authenticated(shared, &identity, &mut headers)
+                });
+                let Some(authenticated) = authenticated else {
+                    // Evicted again in the meantime
+                    return HandshakeOutcome::Failed(self.challenge());
+                };
+                (authenticated, None)
+            }
+        };
+        self.info.served.store(1, Ordering::Relaxed);
+        self.info.challenged.store(0, Ordering::Relaxed);
+        HandshakeOutcome::Authenticated(authenticated, final_token)
+    }
+    /// The response to send for `outcome`
+    ///
+    /// Continues or fails the handshake with a `401 Unauthorized` as the layer would, or answers a completed one with
+    /// `200 OK` and the server's final token.
+    pub fn challenge_response(&self, outcome: HandshakeOutcome) -> Response {
+        match outcome {
+            HandshakeOutcome::Continue(response) | HandshakeOutcome::Failed(response) => response,
+            HandshakeOutcome::Authenticated(authenticated, final_token) => {
+                let config = &self.middleware.config;
+                let mut response = StatusCode::OK.into_response();
+                if let Some(token) = final_token {
+                    config
+                        .token_headers
+                        .append_challenge(config.challenge_style, response.headers_mut(), Some(&token));
+                }
+                response.extensions_mut().insert(authenticated);
+                response.extensions_mut().insert(session::Established);
+                config.finish_response(response, NegotiateProgress::Authenticated)
+            }
+        }
+    }
+    /// Challenges the client to start a handshake, e.g. if the request carries no token
+    pub fn challenge(&self) -> Response {
+        let config = &self.middleware.config;
+        let response = unauthorized(config, "No Authorization given");
+        config.finish_response(response, NegotiateProgress::ChallengeIssued)
+    }
+}
+
+/// Result of [`NegotiateHandshake::step`], to be answered with [`NegotiateHandshake::challenge_response`]
+#[derive(Debug)]
+pub enum HandshakeOutcome {
+    /// The handshake needs another round trip, with the response carrying the server's token
+    Continue(Response),
+    /// The connection is authenticated, with the server's final token if there is one
+    Authenticated(Authenticated, Option<Box<[u8]>>),
+    /// The token was rejected, with the response the layer would send
+    Failed(Response),
+}
+
+/// Rejects the request with `500 Internal Server Error` if the connection has no [`NegotiateInfo`]
+impl<S: Sync> FromRequestParts<S> for NegotiateHandshake {
+    type Rejection = Response;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let info = NegotiateInfo::from_request_parts(parts, state).await?;
+        Ok(Self {
+            middleware: NegotiateLayer::new(None).layer(()),
+            info,
+            headers: parts.headers.clone(),
+            host: request_host(parts).map(str::to_owned),
+            spn_override: parts.extensions.get::<SpnOverride>().map(|spn| spn.0.clone()),
+            persistence: persistence(parts.version, &parts.headers),
+            version: parts.version,
+        })
+    }
+}
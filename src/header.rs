@@ -0,0 +1,318 @@
+use std::{borrow::Cow, fmt::Display};
+
+use base64::{
+    Engine, alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    prelude::BASE64_STANDARD,
+};
+use http::{
+    HeaderMap, HeaderName, HeaderValue,
+    header::{AUTHORIZATION, Entry, WWW_AUTHENTICATE},
+};
+
+use crate::{ChallengeStyle, TokenKind};
+
+/// The credentials of an `Authorization` header, see [`parse_negotiate_authorization`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Token<'a> {
+    /// The authentication scheme as sent by the client
+    pub scheme: &'a str,
+    /// The base64 encoded token
+    pub token: &'a str,
+}
+impl Token<'_> {
+    /// Decodes the base64 token, see [`NegotiateToken::decode`]
+    pub fn decode(&self) -> Result<Vec<u8>, ParseError> {
+        self.decode_bounded(usize::MAX)
+    }
+    /// Decodes the base64 token, unless it would decode to more than `max` bytes, see
+    /// [`NegotiateToken::decode_bounded`]
+    pub fn decode_bounded(&self, max: usize) -> Result<Vec<u8>, ParseError> {
+        let token = NegotiateToken::encoded(self.token);
+        let decoded = token.decode_bounded(max).map_err(TokenParseError::into_parse_error)?;
+        Ok(decoded.into_owned())
+    }
+}
+
+/// A token of the `Negotiate` scheme, as parsed from or sent in an `Authorization` or `WWW-Authenticate` header
+///
+/// This is the parsing, decoding and encoding [`NegotiateLayer`](crate::NegotiateLayer) uses, for clients, proxies and
+/// tests that need to handle the same header values:
+///
+/// ```
+/// use axum_negotiate_layer::{NegotiateToken, TokenKind};
+/// use http::HeaderValue;
+///
+/// let header = NegotiateToken::new(*b"NTLMSSP\0\x01\0\0\0").to_header_value();
+/// assert_eq!(header, "Negotiate TlRMTVNTUAABAAAA");
+/// let token = NegotiateToken::parse_header(&header).unwrap();
+/// assert_eq!(token.kind(), TokenKind::Ntlm);
+/// assert_eq!(&*token.decode().unwrap(), b"NTLMSSP\0\x01\0\0\0");
+/// ```
+#[derive(Clone, Debug)]
+pub struct NegotiateToken<'a>(Repr<'a>);
+#[derive(Clone, Debug)]
+enum Repr<'a> {
+    /// The base64 encoded token of a header
+    Encoded(Cow<'a, str>),
+    Decoded(Cow<'a, [u8]>),
+}
+impl NegotiateToken<'_> {
+    /// A token to be sent, see [`NegotiateToken::to_header_value`]
+    #[must_use]
+    pub fn new(token: impl Into<Vec<u8>>) -> NegotiateToken<'static> {
+        NegotiateToken(Repr::Decoded(Cow::Owned(token.into())))
+    }
+    /// Parses a header value with the `Negotiate` or `NTLM` scheme
+    ///
+    /// As in [`parse_negotiate_authorization`], the scheme is compared case-insensitively and whitespace around it
+    /// and the token is ignored. The token is only decoded by [`NegotiateToken::decode`].
+    ///
+    /// # Errors
+    ///
+    /// If the header isn't visible ASCII, has another scheme or no token.
+    pub fn parse_header(header: &HeaderValue) -> Result<NegotiateToken<'_>, TokenParseError> {
+        let token = parse_negotiate_authorization(header, &["Negotiate", "NTLM"]).map_err(TokenParseError::Header)?;
+        Ok(NegotiateToken::encoded(token.token))
+    }
+    /// The decoded token
+    ///
+    /// Decodes standard base64 like the layer does. The padding may be left out, as some clients do.
+    ///
+    /// # Errors
+    ///
+    /// If the token isn't valid base64.
+    pub fn decode(&self) -> Result<Cow<'_, [u8]>, TokenParseError> {
+        self.decode_bounded(usize::MAX)
+    }
+    /// The decoded token, unless it would decode to more than `max` bytes, as with
+    /// [`NegotiateLayer::max_token_size`](crate::NegotiateLayer::max_token_size)
+    ///
+    /// The size is derived from the length of the token, so an oversized token is rejected before anything is
+    /// allocated for it.
+    ///
+    /// # Errors
+    ///
+    /// If the token isn't valid base64 or too large.
+    pub fn decode_bounded(&self, max: usize) -> Result<Cow<'_, [u8]>, TokenParseError> {
+        match &self.0 {
+            Repr::Encoded(token) if decoded_len(token) > max => Err(TokenParseError::TooLarge),
+            Repr::Encoded(token) => LENIENT_BASE64
+                .decode(token.as_bytes())
+                .map(Cow::Owned)
+                .map_err(|_| TokenParseError::InvalidBase64),
+            Repr::Decoded(token) if token.len() > max => Err(TokenParseError::TooLarge),
+            Repr::Decoded(token) => Ok(Cow::Borrowed(token)),
+        }
+    }
+    /// The kind of the token by its framing, [`TokenKind::Unknown`] if it can't be decoded
+    #[must_use]
+    pub fn kind(&self) -> TokenKind {
+        self.decode().map_or(TokenKind::Unknown, |token| TokenKind::of(&token))
+    }
+    /// The `Negotiate` header value carrying this token
+    #[must_use]
+    pub fn to_header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&format!("Negotiate {}", self.base64()))
+            .expect("Base64-string should be valid header material")
+    }
+    /// The token as base64, as received or encoded
+    pub(crate) fn base64(&self) -> Cow<'_, str> {
+        match &self.0 {
+            Repr::Encoded(token) => Cow::Borrowed(token),
+            Repr::Decoded(token) => Cow::Owned(BASE64_STANDARD.encode(token)),
+        }
+    }
+    /// The token without the borrow of the header it was parsed from
+    #[must_use]
+    pub fn into_owned(self) -> NegotiateToken<'static> {
+        NegotiateToken(match self.0 {
+            Repr::Encoded(token) => Repr::Encoded(Cow::Owned(token.into_owned())),
+            Repr::Decoded(token) => Repr::Decoded(Cow::Owned(token.into_owned())),
+        })
+    }
+}
+impl<'a> NegotiateToken<'a> {
+    /// The base64 encoded `token` of a header
+    pub(crate) fn encoded(token: &'a str) -> Self {
+        Self(Repr::Encoded(Cow::Borrowed(token)))
+    }
+}
+
+/// Standard base64, with or without padding
+const LENIENT_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// The number of bytes `token` decodes to, if it is valid base64
+fn decoded_len(token: &str) -> usize {
+    let padding = token.bytes().rev().take(2).take_while(|&byte| byte == b'=').count();
+    (token.len() / 4 * 3 + token.len() % 4 * 3 / 4).saturating_sub(padding)
+}
+
+/// Reason a [`NegotiateToken`] couldn't be parsed or decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TokenParseError {
+    /// The header isn't a valid `Negotiate` or `NTLM` authorization
+    Header(ParseError),
+    /// The token isn't valid base64
+    InvalidBase64,
+    /// The token decodes to more bytes than accepted, see [`NegotiateLayer::max_token_size`]
+    ///
+    /// [`NegotiateLayer::max_token_size`]: crate::NegotiateLayer::max_token_size
+    TooLarge,
+}
+impl TokenParseError {
+    /// The same reason for [`parse_negotiate_authorization`] and [`Token`], which predate this type
+    fn into_parse_error(self) -> ParseError {
+        match self {
+            Self::Header(e) => e,
+            Self::InvalidBase64 => ParseError::InvalidBase64,
+            Self::TooLarge => ParseError::TooLarge,
+        }
+    }
+}
+impl Display for TokenParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.into_parse_error(), f)
+    }
+}
+impl std::error::Error for TokenParseError {}
+
+/// Reason an `Authorization` header couldn't be parsed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The header contains characters other than visible ASCII
+    NotVisibleAscii,
+    /// The header doesn't contain a token after the scheme
+    MissingToken,
+    /// The scheme isn't one of the accepted ones
+    UnsupportedScheme,
+    /// The token isn't valid base64
+    InvalidBase64,
+    /// The token decodes to more bytes than accepted, see [`NegotiateLayer::max_token_size`]
+    ///
+    /// [`NegotiateLayer::max_token_size`]: crate::NegotiateLayer::max_token_size
+    TooLarge,
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotVisibleAscii => "header contains non-visible characters",
+            Self::MissingToken => "no token after the authentication scheme",
+            Self::UnsupportedScheme => "unsupported authentication scheme",
+            Self::InvalidBase64 => "token is not valid base64",
+            Self::TooLarge => "token exceeds the maximum size",
+        })
+    }
+}
+impl std::error::Error for ParseError {}
+
+/// Parses an `Authorization` header using one of `schemes`, e.g. `&["Negotiate"]`
+///
+/// Schemes are compared case-insensitively. The token is separated from the scheme by spaces or tabs and not decoded,
+/// see [`Token::decode`]. Whitespace around the scheme and token is ignored, as intermediaries may add some. This is the
+/// parsing used by [`NegotiateLayer`](crate::NegotiateLayer).
+pub fn parse_negotiate_authorization<'a>(header: &'a HeaderValue, schemes: &[&str]) -> Result<Token<'a>, ParseError> {
+    let header = header.to_str().map_err(|_| ParseError::NotVisibleAscii)?;
+    let Some((scheme, token)) = header.trim_matches([' ', '\t']).split_once([' ', '\t']) else {
+        return Err(ParseError::MissingToken);
+    };
+    if !schemes.iter().any(|accepted| scheme.eq_ignore_ascii_case(accepted)) {
+        return Err(ParseError::UnsupportedScheme);
+    }
+    let token = token.trim_matches([' ', '\t']);
+    if token.is_empty() {
+        return Err(ParseError::MissingToken);
+    }
+    Ok(Token { scheme, token })
+}
+
+/// Builds a `Negotiate` header value carrying `token`, as sent in `WWW-Authenticate`, see
+/// [`NegotiateToken::to_header_value`]
+#[must_use]
+pub fn negotiate_header(token: &[u8]) -> HeaderValue {
+    NegotiateToken(Repr::Decoded(Cow::Borrowed(token))).to_header_value()
+}
+
+/// The headers the tokens of a handshake travel in, see [`NegotiateLayer::token_header`]
+///
+/// [`NegotiateLayer::token_header`]: crate::NegotiateLayer::token_header
+#[derive(Clone, Debug)]
+pub(crate) struct TokenHeaders {
+    pub(crate) token: HeaderName,
+    pub(crate) challenge: HeaderName,
+    /// Whether tokens are prefixed with the `Negotiate` scheme, or sent as bare base64
+    pub(crate) prefixed: bool,
+    /// Whether tokens are also accepted with the `NTLM` scheme, see [`NegotiateLayer::scheme_order`]
+    ///
+    /// [`NegotiateLayer::scheme_order`]: crate::NegotiateLayer::scheme_order
+    pub(crate) ntlm: bool,
+}
+impl Default for TokenHeaders {
+    fn default() -> Self {
+        Self {
+            token: AUTHORIZATION,
+            challenge: WWW_AUTHENTICATE,
+            prefixed: true,
+            ntlm: false,
+        }
+    }
+}
+impl TokenHeaders {
+    /// The client's token, or [`None`] if it sent none
+    pub(crate) fn token<'a>(&self, headers: &'a HeaderMap) -> Option<Result<NegotiateToken<'a>, ParseError>> {
+        let value = headers.get(&self.token)?;
+        if self.prefixed {
+            let schemes: &[&str] = if self.ntlm {
+                &["Negotiate", "NTLM"]
+            } else {
+                &["Negotiate"]
+            };
+            return Some(
+                parse_negotiate_authorization(value, schemes).map(|token| NegotiateToken::encoded(token.token)),
+            );
+        }
+        Some(match value.to_str().map(|token| token.trim_matches([' ', '\t'])) {
+            Err(_) => Err(ParseError::NotVisibleAscii),
+            Ok("") => Err(ParseError::MissingToken),
+            Ok(token) => Ok(NegotiateToken::encoded(token)),
+        })
+    }
+    /// Appends the challenge carrying the server's `token` to `headers`
+    ///
+    /// Without a token, i.e. when starting a handshake, the challenge is `Negotiate` even if tokens aren't prefixed.
+    pub(crate) fn append_challenge(&self, style: ChallengeStyle, headers: &mut HeaderMap, token: Option<&[u8]>) {
+        let challenge = match token {
+            None => HeaderValue::from_static("Negotiate"),
+            Some(token) if self.prefixed => negotiate_header(token),
+            Some(token) => {
+                HeaderValue::from_str(&BASE64_STANDARD.encode(token)).expect("base64 should be valid header material")
+            }
+        };
+        style.append_to(headers, &self.challenge, &[challenge]);
+    }
+    /// Whether the client sent its token with the `NTLM` scheme, whose continuations have to use it as well
+    pub(crate) fn uses_ntlm_scheme(&self, headers: &HeaderMap) -> bool {
+        self.ntlm
+            && self.prefixed
+            && headers
+                .get(&self.token)
+                .is_some_and(|value| parse_negotiate_authorization(value, &["NTLM"]).is_ok())
+    }
+    /// Replaces the `Negotiate` scheme of the challenges carrying a token in `headers` with `NTLM`
+    pub(crate) fn use_ntlm_scheme(&self, headers: &mut HeaderMap) {
+        let Entry::Occupied(mut challenges) = headers.entry(&self.challenge) else {
+            return;
+        };
+        for value in challenges.iter_mut() {
+            if let Some(token) = value.to_str().ok().and_then(|value| value.strip_prefix("Negotiate ")) {
+                *value =
+                    HeaderValue::from_str(&format!("NTLM {token}")).expect("token should be valid header material");
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    SpnError,
+    sink::{Sink, default_sink},
+    spn::{self, AcceptorName},
+};
+
+/// Whether the server credentials can be acquired, see [`NegotiateLayer::credential_health`]
+///
+/// [`NegotiateLayer::credential_health`]: crate::NegotiateLayer::credential_health
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CredentialHealth {
+    /// Credentials for every configured SPN could be acquired
+    Ok,
+    /// Credentials for some of the SPNs selected by [`NegotiateLayer::spn_from_sni`] couldn't be acquired, so clients
+    /// of those server names can't authenticate
+    ///
+    /// [`NegotiateLayer::spn_from_sni`]: crate::NegotiateLayer::spn_from_sni
+    Degraded(Vec<CredentialFailure>),
+    /// No credentials could be acquired, so no client can authenticate
+    Failed(Vec<CredentialFailure>),
+}
+impl CredentialHealth {
+    /// Whether clients can authenticate at all, i.e. the instance should receive traffic
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        !matches!(self, Self::Failed(_))
+    }
+}
+
+/// Why the credentials for an SPN couldn't be acquired
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CredentialFailure {
+    /// The SPN as passed to the backend, or [`None`] for the default credentials
+    pub spn: Option<String>,
+    pub error: SpnError,
+}
+
+/// The last [`CredentialHealth`], shared by all clones of a layer
+#[derive(Clone, Default)]
+pub(crate) struct HealthCache(Arc<Mutex<Option<(Instant, CredentialHealth)>>>);
+impl HealthCache {
+    /// Returns the result cached less than `ttl` ago, or acquires the credentials for `names` again
+    ///
+    /// Acquiring may block on the keytab or the network, so it runs on the blocking thread pool. The lock is held until
+    /// it finishes, so probes racing for an expired result wait for a single acquisition and share its result.
+    pub(crate) async fn get(
+        &self,
+        ttl: Duration,
+        names: Vec<Option<AcceptorName>>,
+        sink: Option<Arc<dyn Sink>>,
+    ) -> CredentialHealth {
+        let mut cached = self.0.lock().await;
+        if let Some((checked, health)) = &*cached
+            && checked.elapsed() < ttl
+        {
+            return health.clone();
+        }
+        let probe = tokio::task::spawn_blocking(move || check(&names, sink.as_deref().unwrap_or(default_sink())));
+        let health = match probe.await {
+            Ok(health) => health,
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        };
+        *cached = Some((Instant::now(), health.clone()));
+        health
+    }
+}
+
+fn check(names: &[Option<AcceptorName>], sink: &dyn Sink) -> CredentialHealth {
+    let failures: Vec<_> = names
+        .iter()
+        .filter_map(|name| {
+            let error = spn::acquire_credentials(name.as_ref(), sink).err()?;
+            Some(CredentialFailure {
+                spn: name.as_ref().map(AcceptorName::backend_name),
+                error: SpnError::classify(error.to_string(), name.as_ref()),
+            })
+        })
+        .collect();
+    if failures.is_empty() {
+        CredentialHealth::Ok
+    } else if failures.len() < names.len() {
+        CredentialHealth::Degraded(failures)
+    } else {
+        CredentialHealth::Failed(failures)
+    }
+}
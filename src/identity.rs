@@ -0,0 +1,49 @@
+/// What to do when a handshake on a connection authenticates another client than the one before, see
+/// [`NegotiateLayer::identity_change_policy`]
+///
+/// [`NegotiateLayer::identity_change_policy`]: crate::NegotiateLayer::identity_change_policy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IdentityChangePolicy {
+    /// Respond with `403 Forbidden` and leave the connection unauthenticated, so only the previous client can
+    /// authenticate on it again
+    Reject,
+    /// Accept the new client
+    #[default]
+    Replace,
+    /// Respond with `403 Forbidden` and ask the client to close the connection
+    CloseConnection,
+}
+
+/// The principal `name` in the form clients of one connection are compared in
+///
+/// Realms are upper case by convention, but clients may send them in any case. Windows names (`DOMAIN\user`) are
+/// case-insensitive altogether.
+pub(crate) fn normalized_principal(name: &str) -> String {
+    if name.contains('\\') {
+        return name.to_lowercase();
+    }
+    match name.rsplit_once('@') {
+        Some((user, realm)) => format!("{user}@{}", realm.to_uppercase()),
+        None => name.to_owned(),
+    }
+}
+
+/// Whether `principal` names a computer account rather than a user, the default of
+/// [`NegotiateLayer::reject_machine_accounts`]
+///
+/// Matches the Windows forms `NAME$@REALM` and `DOMAIN\NAME$`, and the MIT forms `host/name@REALM` and
+/// `HOSTNAME$@REALM`.
+///
+/// [`NegotiateLayer::reject_machine_accounts`]: crate::NegotiateLayer::reject_machine_accounts
+#[must_use]
+pub fn is_machine_account(principal: &str) -> bool {
+    let name = match principal.rsplit_once('\\') {
+        Some((_, name)) => name,
+        None => principal.rsplit_once('@').map_or(principal, |(name, _)| name),
+    };
+    name.ends_with('$')
+        || name
+            .split_once('/')
+            .is_some_and(|(service, _)| service.eq_ignore_ascii_case("host"))
+}
@@ -0,0 +1,70 @@
+//! The principals of a keytab file, to tell why server credentials couldn't be acquired
+//!
+//! Only the names are read, the layout is that of MIT Kerberos and Heimdal version 2 keytabs.
+
+use crate::{AcceptorName, Spn};
+
+/// A principal with keys in a keytab
+#[derive(Debug)]
+pub(crate) struct Principal {
+    components: Vec<String>,
+    realm: String,
+}
+impl Principal {
+    /// Whether the backend acquires the credentials for `name` from the keys of this principal
+    ///
+    /// SPNs are compared like the SPNs of tickets, see [`Spn::accepts`], other principals by their name.
+    pub(crate) fn matches(&self, name: &AcceptorName) -> bool {
+        match (name, &self.components[..]) {
+            (AcceptorName::HostBased { service, host }, [entry_service, entry_host]) => {
+                Spn::new(service, host).accepts(&Spn::new(entry_service, entry_host))
+            }
+            (AcceptorName::Principal(principal), [entry_service, entry_host]) if principal.contains('/') => {
+                Spn::parse(principal)
+                    .is_ok_and(|spn| spn.accepts(&Spn::new(entry_service, entry_host).with_realm(&self.realm)))
+            }
+            (AcceptorName::Principal(principal), components) => {
+                let (name, realm) = principal.rsplit_once('@').unwrap_or((principal, &self.realm));
+                components.join("/") == name && realm == self.realm
+            }
+            (AcceptorName::HostBased { .. }, _) => false,
+        }
+    }
+}
+
+/// The principals in `keytab`, or [`None`] if it isn't a version 2 keytab
+pub(crate) fn principals(keytab: &[u8]) -> Option<Vec<Principal>> {
+    let mut rest = keytab.strip_prefix(&[0x05, 0x02])?;
+    let mut principals = Vec::new();
+    while let Some(size) = take(&mut rest, 4) {
+        let size = i32::from_be_bytes(*size.first_chunk()?);
+        // A negative size marks a hole left by a removed entry
+        let entry = take(&mut rest, usize::try_from(size.unsigned_abs()).ok()?)?;
+        if size > 0 {
+            principals.push(principal(entry)?);
+        } else if size == 0 {
+            break;
+        }
+    }
+    Some(principals)
+}
+
+/// The principal at the start of a keytab entry
+fn principal(mut entry: &[u8]) -> Option<Principal> {
+    let count = u16::from_be_bytes(*take(&mut entry, 2)?.first_chunk()?);
+    let realm = counted(&mut entry)?;
+    let components = (0..count).map(|_| counted(&mut entry)).collect::<Option<Vec<_>>>()?;
+    Some(Principal { components, realm })
+}
+
+/// Reads a string prefixed with its 16 bit length
+fn counted(data: &mut &[u8]) -> Option<String> {
+    let len = u16::from_be_bytes(*take(data, 2)?.first_chunk()?);
+    String::from_utf8(take(data, usize::from(len))?.to_vec()).ok()
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    let (taken, rest) = data.split_at_checked(len)?;
+    *data = rest;
+    Some(taken)
+}
@@ -0,0 +1,79 @@
+//! Checks of debug builds for layers placed in the wrong order around the [`NegotiateLayer`]
+//!
+//! Other layers can change a request before it reaches this one in ways that break authentication without an error of
+//! their own. The tell-tale signs of that are logged as warnings, each once per layer. Release builds skip the checks.
+//!
+//! [`NegotiateLayer`]: crate::NegotiateLayer
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::body::{Body, HttpBody};
+use http::{
+    header::{CONTENT_LENGTH, HOST},
+    request::Parts,
+    uri::Authority,
+};
+
+use crate::sink::{Sink, event};
+
+/// The signs already warned about, shared by the services of a layer
+#[derive(Debug, Default)]
+pub(crate) struct LayeringWarnings {
+    body_consumed: AtomicBool,
+    host_rewritten: AtomicBool,
+}
+impl LayeringWarnings {
+    /// Warns about the signs of misplaced layers on a request
+    ///
+    /// `host_spn` is whether the SPN is derived from the `Host`, see
+    /// [`NegotiateLayer::spn_from_host`](crate::NegotiateLayer::spn_from_host).
+    pub(crate) fn check(&self, parts: &Parts, body: &Body, sni: Option<&str>, host_spn: bool, sink: &dyn Sink) {
+        if body_consumed(parts, body) && !self.body_consumed.swap(true, Ordering::Relaxed) {
+            event!(
+                sink,
+                Warn,
+                "Request body was consumed before reaching the NegotiateLayer, it is likely placed inside a layer reading \
+                 the body of unauthenticated requests"
+            );
+        }
+        if !host_spn {
+            return;
+        }
+        let Some(host) = parts
+            .headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(hostname)
+        else {
+            return;
+        };
+        let expected = parts.uri.host().or(sni);
+        if let Some(expected) = expected.filter(|expected| !expected.eq_ignore_ascii_case(&host))
+            && !self.host_rewritten.swap(true, Ordering::Relaxed)
+        {
+            event!(
+                sink,
+                Warn,
+                "Host doesn't match the host the request was made to, a layer outside of the NegotiateLayer likely \
+                 rewrote it, so the SPN derived from it doesn't match the client's ticket",
+                host = host,
+                expected = expected
+            );
+        }
+    }
+}
+
+/// Whether the request announced a body that isn't there anymore
+fn body_consumed(parts: &Parts, body: &Body) -> bool {
+    let announced = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    announced.is_some_and(|announced| announced > 0) && body.size_hint().exact() == Some(0)
+}
+
+/// The name in a `Host` value, without its port
+fn hostname(host: &str) -> Option<String> {
+    let authority = host.parse::<Authority>().ok()?;
+    Some(authority.host().to_owned())
+}
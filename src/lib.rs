@@ -7,7 +7,8 @@
 //! - A [`Authenticated`] request extension object to get information about authenticated clients (so far only the user identity)
 //! - An extension to the standard [`axum::serve::Listener`] (with feature `http1`) to add negotiation info to every connection.
 //!   As SPNEGO is a non-http standard authentication method authenticating by connection, the negotiation info has to be included in every
-//!   connection given to axum, either via this struct or by manually providing it as a `ConnectInfo` extension when driving the routing loop yourself.
+//!   connection given to axum, either via this struct or by manually providing it as a `ConnectInfo` or plain [`NegotiateInfo`]
+//!   extension when driving the routing loop yourself.
 //!
 //! # Usage
 //! The middleware and layer require the Kerberos SPN for the Router in question.
@@ -31,7 +32,7 @@
 //!
 //! The most convenient use case shown above will use the layer object to verify all routes above it are authenticated.
 //! The [`Router::into_make_service_with_connect_info`](axum::Router::into_make_service_with_connect_info) call is mandatory for this layer to work
-//! on the used Router, otherwise the layer will panic.
+//! on the used Router, otherwise the layer will panic (see [`MisusePolicy`]).
 //!
 //! ## Axum handler usage example
 //!
@@ -53,116 +54,511 @@
 //!
 //! When getting the [`Authenticated`] object from the request extension or extracting it directly, the authentication can be guaranteed for this route, as this object can
 //! only be set by a middleware of this crate.
+//!
+//! ## Pre-emptive authentication
+//!
+//! Clients don't have to wait for the `401 Unauthorized` challenge. A token sent with the very first request of a connection
+//! is processed right away, so a client holding a service ticket authenticates without an additional round trip if the
+//! mechanism completes in a single round, as Kerberos does.
+//!
+//! ## Layer order
+//!
+//! The layer has to see requests as the client sent them. Layers consuming the request body belong inside of it, so
+//! the body isn't read before the request is authenticated, and layers rewriting the `Host` break
+//! [`NegotiateLayer::spn_from_host`] when placed outside of it. Debug builds warn once per layer when a request shows
+//! signs of either, i.e. a body announced by `Content-Length` that is already gone, or a `Host` differing from the
+//! host of the request URI or the TLS server name.
 use axum::{
+    body::Bytes,
     extract::{ConnectInfo, FromRequestParts, Request, connect_info::Connected},
     http::{
-        HeaderMap, HeaderValue, StatusCode,
-        header::{AUTHORIZATION, CONNECTION, WWW_AUTHENTICATE},
+        HeaderMap, HeaderName, HeaderValue, StatusCode, Uri, Version,
+        header::{AUTHORIZATION, CACHE_CONTROL, CONNECTION, HOST, PRAGMA, UPGRADE, VARY},
         request::Parts,
     },
     response::{IntoResponse, Response},
 };
-use base64::{Engine, prelude::BASE64_STANDARD};
+use basic::{BasicAuth, BasicError};
+use cache::ContextCache;
 use futures_util::future::BoxFuture;
+use header::TokenHeaders;
+#[cfg(feature = "health")]
+use health::HealthCache;
 use kenobi::{
     channel_bindings::Channel,
-    cred::{Credentials, Inbound},
-    mech::Mechanism,
+    cred::Inbound,
     server::{PendingServerContext, ServerBuilder, ServerContext},
 };
-use sspi::handle_sspi;
+use redirect::FailureRedirect;
+use sink::{Debugged, LayerSink, Sink, default_sink, event};
+use sspi::{continue_response, handle_sspi};
+use state::{Connection, State};
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     convert::Infallible,
     fmt::Debug,
-    ops::{Deref, DerefMut},
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex, MutexGuard, Once, PoisonError, Weak,
+        atomic::{AtomicU32, Ordering},
+    },
     task::Poll,
+    time::{Duration, Instant, SystemTime},
 };
 use tower::{Layer, Service};
 
+#[cfg(all(feature = "dev-insecure", feature = "forbid-insecure"))]
+compile_error!("the `dev-insecure` feature must not be enabled together with `forbid-insecure`");
+
+#[cfg(feature = "admin")]
+mod admin;
+mod authorizer;
+mod basic;
+mod cache;
+mod cert;
+mod challenge;
+mod clock;
+#[cfg(feature = "drain")]
+mod drain;
+mod env;
+mod epa;
+mod handshake;
+mod header;
+#[cfg(feature = "health")]
+mod health;
+mod identity;
+mod keytab;
+#[cfg(debug_assertions)]
+mod layering;
 #[cfg(feature = "http1")]
 mod listener;
+mod ntlm;
+mod redirect;
+mod replay;
+mod session;
+pub mod sink;
+mod spn;
+#[cfg(feature = "spn-file")]
+mod spn_file;
+mod spnego;
 mod sspi;
+#[cfg(negotiate_loom)]
+#[doc(hidden)]
+pub mod state;
+#[cfg(not(negotiate_loom))]
+mod state;
+mod stats;
+mod steer;
+#[cfg(feature = "http1")]
+mod store;
+#[cfg(feature = "test-util")]
+mod test_util;
+#[cfg(feature = "tracing")]
+pub mod trace;
+mod trailers;
+#[cfg(feature = "admin")]
+pub use admin::admin_router;
+pub use authorizer::Authorizer;
+pub use cert::{CertIdentityMapper, CertPrecedence, ClientCertificate};
+pub use challenge::{ChallengePolicy, ChallengeStyle, CidrParseError, Scheme};
+pub use clock::{Clock, SystemClock};
+#[cfg(feature = "drain")]
+pub use drain::Drainer;
+pub use env::{CCACHE_VAR, EnvError, KEYTAB_VAR, SPN_VAR};
+pub use epa::EpaPolicy;
+pub use handshake::{HandshakeOutcome, NegotiateHandshake};
+pub use header::{NegotiateToken, ParseError, Token, TokenParseError, negotiate_header, parse_negotiate_authorization};
+#[cfg(feature = "health")]
+pub use health::{CredentialFailure, CredentialHealth};
+pub use identity::{IdentityChangePolicy, is_machine_account};
 #[cfg(feature = "http1")]
-pub use listener::{HasNegotiateInfo, Negotiator, WithNegotiateInfo};
+pub use listener::{HasNegotiateInfo, HasNegotiateStore, Negotiator, WithNegotiateInfo};
+pub use ntlm::{NtlmDetails, NtlmPolicy, NtlmPolicyViolation};
+pub use redirect::RETURN_TO_PARAMETER;
+pub use replay::{InMemoryReplayCache, NoReplayCache, ReplayCache};
+pub use session::{SessionIdentity, session_client};
+pub use spn::{
+    AcceptorName, CanonicalizeOptions, Spn, SpnError, SpnOverride, SpnParseError, validate_spn, validate_spn_with_sink,
+};
+pub use spnego::{InitialToken, Mech, TokenKind};
+pub use stats::{FailureReason, LayerStats, ROUNDS_BUCKETS, RecentFailure};
+pub use steer::NegotiateSteer;
+#[cfg(feature = "http1")]
+pub use store::{ConnectionStore, NewPerConnection, RecyclingStore};
+#[cfg(feature = "test-util")]
+pub use test_util::ClientIdentity;
+pub use trailers::{CLIENT_TRAILER, ERROR_CLIENT_HEADER, MECHANISM_TRAILER};
 
-#[derive(Default)]
-enum NegotiateState {
-    #[default]
-    Unauthorized,
-    Pending(PendingServerContext<Inbound>),
-    Authenticated(ServerContext<Inbound>),
+/// An established context, owned by the connection state and only referenced weakly by [`Authenticated`]
+type SharedContext = Arc<Mutex<AuthenticatedContext>>;
+type NegotiateState = State<(PendingServerContext<Inbound>, Handshake), SharedContext>;
+type NegotiateConnection = Connection<(PendingServerContext<Inbound>, Handshake), SharedContext>;
+/// What is known about a handshake in progress
+struct Handshake {
+    started: Instant,
+    /// What the client offered in the first token, if it could be parsed
+    offered: Option<InitialToken>,
+    /// Counts the connection as pending in [`LayerStats`] once a round continued the handshake
+    pending_gauge: Option<stats::Gauge>,
+    /// The client tokens stepped so far, see [`NegotiateProgress::HandshakeLeg`]
+    legs: u8,
+    /// Hash of the last client token, to answer a client retrying it without stepping the context again
+    last_token: Option<u64>,
+    /// The [`NegotiateLayer::correlation_header`] of the current round's request
+    correlation_id: Option<String>,
 }
-impl NegotiateState {
-    fn is_authenticated(&self) -> bool {
-        matches!(self, Self::Authenticated(_))
-    }
-}
-impl Debug for NegotiateState {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Authenticated(_) => f.write_str("Authenticated"),
-            Self::Pending(_) => f.write_str("Pending"),
-            Self::Unauthorized => f.write_str("Unauthenticated"),
+impl Handshake {
+    fn new(correlation_id: Option<String>) -> Self {
+        Self {
+            started: Instant::now(),
+            offered: None,
+            pending_gauge: None,
+            legs: 0,
+            last_token: None,
+            correlation_id,
         }
     }
 }
+/// What a handshake established about the client, fixed once it is finished
+///
+/// Shared by the context and every [`Authenticated`] made from it, so reading it doesn't lock the context.
+struct EstablishedIdentity {
+    client: String,
+    mechanism: Option<Mech>,
+    ntlm: Option<NtlmDetails>,
+    /// Client tokens stepped by the handshake, `0` without one
+    rounds: u8,
+}
+struct AuthenticatedContext {
+    _context: ServerContext<Inbound>,
+    identity: Arc<EstablishedIdentity>,
+    client_token: Vec<u8>,
+    #[cfg(feature = "drain")]
+    _drain_guard: Option<drain::DrainGuard>,
+    _stats_gauge: Option<stats::Gauge>,
+    cache_entry: Option<cache::CacheEntry>,
+    /// When the connection has to authenticate again
+    expires: Option<SystemTime>,
+}
+// Only held briefly. Locks are taken in the order connection state, context, context cache: the context is locked
+// inside of `Connection::with_authenticated` and `Connection::evict_if`, but a context never locks its connection, and
+// the cache never locks a connection or context while its own lock is held. A poisoned context is still consistent.
+fn lock_context(context: &SharedContext) -> MutexGuard<'_, AuthenticatedContext> {
+    context.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
 /// [`Extension`](axum::Extension) or Extractor type that gets set after successful Authentication
+///
+/// The middleware also adds it to the extensions of the response, for layers outside of it.
+///
+/// It doesn't keep the connection's context alive. Once the connection is closed, or its context evicted (see
+/// [`NegotiateLayer::max_cached_contexts`]) or expired (see [`NegotiateLayer::max_session_age`]), the context is
+/// released and [`Authenticated::is_released`] returns `true`. What the handshake established, i.e. the client names,
+/// [`mechanism`](Authenticated::mechanism) and [`ntlm_details`](Authenticated::ntlm_details), stays available
+/// without locking the context, while the accessors
+/// that query the context return [`None`]. A clone never sees a context the connection established later.
+///
+/// Handlers of long-lived responses, e.g. Server-Sent Events, may thus keep it for the whole response to tell who it
+/// is streamed to. Nothing ends such a response on behalf of the layer, it just can't rely on the context anymore.
 // This struct can only be created by the middleware in this crate or cloned from an
-// existing one. Extracting it directly panics when the Layer has not been applied yet.
-#[derive(Debug, Clone)]
-pub struct Authenticated(Arc<Mutex<NegotiateState>>);
+// existing one. Extracting it directly panics when the Layer has not been applied yet, see MisusePolicy.
+#[derive(Clone)]
+pub struct Authenticated {
+    context: Weak<Mutex<AuthenticatedContext>>,
+    /// Keeps the context alive in stateless mode, where no connection owns it
+    _owned: Option<SharedContext>,
+    identity: Arc<EstablishedIdentity>,
+    forwarded_client: Option<String>,
+}
+impl Debug for Authenticated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authenticated")
+            .field("transport_client", &self.identity.client)
+            .field("forwarded_client", &self.forwarded_client)
+            .field("released", &self.is_released())
+            .finish_non_exhaustive()
+    }
+}
 impl Authenticated {
-    fn call<T>(&self, f: impl Fn(&mut ServerContext<Inbound>) -> T) -> T {
-        let mut guard = self.0.lock().unwrap();
-        match guard.deref_mut() {
-            NegotiateState::Authenticated(x) => f(x),
-            _ => unreachable!("Authenticated only exists after successful authentication"),
-        }
+    /// Runs `f` on the context, unless it was released
+    fn call<T>(&self, f: impl FnOnce(&mut AuthenticatedContext) -> T) -> Option<T> {
+        let context = self.context.upgrade()?;
+        Some(f(&mut lock_context(&context)))
     }
+    /// Whether the connection's context was released, see [`Authenticated`]
+    #[must_use]
+    pub fn is_released(&self) -> bool {
+        self.context.strong_count() == 0
+    }
+    /// The client identity this request is made on behalf of
+    ///
+    /// This is the authenticated connection's principal, unless a trusted forwarded user header was configured via
+    /// [`NegotiateLayer::trust_forwarded_user`] and sent by a trusted proxy, in which case that header's value is returned.
     pub fn client(&mut self) -> String {
-        self.call(|x| x.client_name().to_string())
+        match &self.forwarded_client {
+            Some(forwarded) => forwarded.clone(),
+            None => self.transport_client(),
+        }
+    }
+    /// Asks `authorizer` whether the [`client`](Authenticated::client) may perform `action`
+    pub fn authorize<A: Authorizer + ?Sized>(&self, authorizer: &A, action: &str) -> bool {
+        let client = self.forwarded_client.as_ref().unwrap_or(&self.identity.client);
+        authorizer.authorize(client, action)
+    }
+    /// The principal that authenticated the underlying connection
+    ///
+    /// When running behind a trusted proxy, this is the proxy's service account rather than the end user.
+    ///
+    /// The name is taken from the ticket, so it is canonical as far as the KDC canonicalized it when issuing the ticket,
+    /// e.g. resolved an enterprise name (`alice@example.com`) to the principal it belongs to (`alice@EXAMPLE.COM`).
+    pub fn transport_client(&mut self) -> String {
+        self.identity.client.clone()
+    }
+    /// Domain and workstation the client sent, if the connection was authenticated via NTLM
+    ///
+    /// These are informational only, the client is free to put anything there.
+    /// Always [`None`] for Kerberos.
+    #[must_use]
+    pub fn ntlm_details(&self) -> Option<NtlmDetails> {
+        self.identity.ntlm.clone()
+    }
+    /// The mechanism the connection was authenticated with
+    ///
+    /// [`None`] if it couldn't be determined from the client's tokens.
+    #[must_use]
+    pub fn mechanism(&self) -> Option<Mech> {
+        self.identity.mechanism.clone()
+    }
+    /// The number of tokens the client sent to complete the handshake, e.g. `1` for Kerberos and `3` for NTLM in
+    /// SPNEGO
+    ///
+    /// Counted per handshake, so rounds of a handshake that failed on the connection before don't add up. Retries of a
+    /// token aren't counted. `0` for requests that were authenticated without a handshake, e.g. with `Basic` or a
+    /// client certificate.
+    #[must_use]
+    pub fn handshake_rounds(&self) -> u8 {
+        self.identity.rounds
+    }
+    /// The decoded token the client sent in the final round of the handshake
+    ///
+    /// This can be forwarded to a backend that verifies the client itself. Note that Kerberos tokens are only accepted within
+    /// the allowed clock skew of their creation (usually 5 minutes), and only once by acceptors with a replay cache. The backend
+    /// must also be the service the ticket was issued for, i.e. share this service's SPN and keys. If the client has to be
+    /// impersonated towards arbitrary services, use constrained delegation instead.
+    #[must_use]
+    pub fn original_token(&self) -> Option<Vec<u8>> {
+        self.call(|x| x.client_token.clone())
+    }
+}
+impl Authenticated {
+    /// Fallible version of the [`FromRequestParts`] implementation
+    ///
+    /// Prefers the extension set by the middleware, and otherwise falls back to the connection's [`NegotiateInfo`].
+    pub fn try_from_parts(parts: &Parts) -> Result<Self, AuthenticatedRejection> {
+        if let Some(authenticated) = parts.extensions.get::<Authenticated>() {
+            return Ok(authenticated.clone());
+        }
+        let auth = try_negotiate_info(parts)
+            .ok_or(AuthenticatedRejection::MissingNegotiateInfo)?
+            .auth;
+        auth.with_authenticated(|shared| Authenticated {
+            context: Arc::downgrade(shared),
+            _owned: None,
+            identity: lock_context(shared).identity.clone(),
+            forwarded_client: None,
+        })
+        .ok_or(AuthenticatedRejection::NotAuthenticated)
     }
 }
+/// Panics on a misconfigured router, unless [`MisusePolicy::InternalServerError`] was set
 impl<S: Sync> FromRequestParts<S> for Authenticated {
-    type Rejection = Infallible;
+    type Rejection = Response;
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        let auth = get_state_from_extension(parts).0;
-        let Ok(au) = auth.lock() else {
-            #[cfg(feature = "tracing")]
-            tracing::error!("Concurrency error, multiple threads accessing the same NegotiateInfo");
-            panic!()
-        };
-        if au.is_authenticated() {
-            Ok(Authenticated(auth.clone()))
-        } else {
-            #[cfg(feature = "tracing")]
-            tracing::error!(r#"NegotiateInfo not authorized. Probably extracted "Authenticated" outside of layer"#);
-            panic!("NegotiateInfo was not authorized. you may have extracted `Authenticated` outside of the layer")
+        Self::try_from_parts(parts).map_err(|rejection| {
+            misuse(
+                MisusePolicy::of(parts),
+                &rejection.to_string(),
+                LayerSink::of(&parts.extensions),
+            )
+        })
+    }
+}
+
+/// Extractor for the identity a request is made by, which may be anonymous
+///
+/// For authenticated requests, this is the identity of [`Authenticated::client`]. Otherwise it is the identity set with an
+/// [`AnonymousIdentity`] extension, and [`Identity::is_authenticated`] is `false`. If there is none, the request is
+/// rejected with `401 Unauthorized`.
+///
+/// Authorization decisions must always check [`Identity::is_authenticated`], as anonymous identities
+/// can't be told apart from real ones by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    name: String,
+    authenticated: bool,
+}
+impl Identity {
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    #[must_use]
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+}
+impl<S: Sync> FromRequestParts<S> for Identity {
+    type Rejection = Response;
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        if let Ok(mut authenticated) = Authenticated::try_from_parts(parts) {
+            return Ok(Identity {
+                name: authenticated.client(),
+                authenticated: true,
+            });
+        }
+        match parts.extensions.get::<AnonymousIdentity>() {
+            Some(AnonymousIdentity(name)) => Ok(Identity {
+                name: name.to_string(),
+                authenticated: false,
+            }),
+            None => {
+                let challenge = www_authenticate_map(
+                    ChallengeStyle::default(),
+                    &TokenHeaders::default(),
+                    &[Scheme::Negotiate],
+                    None,
+                );
+                Err((StatusCode::UNAUTHORIZED, challenge, "No Authorization given").into_response())
+            }
         }
     }
 }
 
-fn get_state_from_extension(parts: &Parts) -> (Arc<Mutex<NegotiateState>>, Option<ChannelBindings>) {
-    match parts.extensions.get::<ConnectInfo<NegotiateInfo>>().cloned() {
-        Some(ConnectInfo(NegotiateInfo { auth, channel })) => (auth, channel),
-        None => {
-            #[cfg(feature = "tracing")]
-            tracing::error!("Panicking due to no ConnectInfo given");
-            panic!(
-                "No NegotiateInfo ConnectInfo was given. you may have forgotten to use into_make_service_with_connect_info"
-            )
+/// Request extension providing the name of unauthenticated requests' [`Identity`]
+///
+/// Set via [`Extension`](axum::Extension) on routes outside of the [`NegotiateLayer`], e.g. public ones, as the layer
+/// rejects every unauthenticated request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnonymousIdentity(pub Arc<str>);
+impl AnonymousIdentity {
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self(name.into())
+    }
+}
+
+/// How a misconfigured router is dealt with, i.e. extracting [`Authenticated`] outside of the [`NegotiateLayer`] or
+/// without [`NegotiateInfo`]
+///
+/// Set by the [`NegotiateLayer`] if configured with [`NegotiateLayer::misuse_policy`], and may be set via
+/// [`Extension`](axum::Extension) for routes outside of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MisusePolicy {
+    /// Fail fast by panicking
+    #[default]
+    Panic,
+    /// Log the error and respond with `500 Internal Server Error`
+    ///
+    /// Useful when building with `panic = "abort"`, where a single misconfigured route would take the process down.
+    InternalServerError,
+}
+impl MisusePolicy {
+    fn of(parts: &Parts) -> Self {
+        parts.extensions.get::<Self>().copied().unwrap_or_default()
+    }
+}
+
+/// Panics or builds the response for a misconfigured router according to `policy`
+fn misuse(policy: MisusePolicy, message: &str, sink: &dyn Sink) -> Response {
+    match policy {
+        MisusePolicy::Panic => {
+            event!(
+                sink,
+                Error,
+                "Panicking due to misconfigured NegotiateLayer",
+                reason = message
+            );
+            panic!("{message}")
+        }
+        MisusePolicy::InternalServerError => {
+            event!(sink, Error, "Misconfigured NegotiateLayer", reason = message);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
 }
+
+/// Reason [`Authenticated`] couldn't be extracted from a request
+///
+/// All of these indicate a misconfigured router rather than a client error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AuthenticatedRejection {
+    /// No [`NegotiateInfo`] was attached to the connection
+    MissingNegotiateInfo,
+    /// The connection hasn't been authenticated, the [`NegotiateLayer`] is probably not applied to this route
+    NotAuthenticated,
+}
+impl std::fmt::Display for AuthenticatedRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::MissingNegotiateInfo => {
+                "No NegotiateInfo ConnectInfo was given. you may have forgotten to use into_make_service_with_connect_info"
+            }
+            Self::NotAuthenticated => {
+                "NegotiateInfo was not authorized. you may have extracted `Authenticated` outside of the layer"
+            }
+        })
+    }
+}
+impl std::error::Error for AuthenticatedRejection {}
+
+/// Whether the connection of a request has already been authenticated, or [`None`] if it has no [`NegotiateInfo`]
+///
+/// This only reads the connection's state and never starts or advances a handshake, so outer layers may use it for
+/// routing decisions. It never waits for a handshake round running concurrently on the same connection.
+#[must_use]
+pub fn is_connection_authenticated(parts: &Parts) -> Option<bool> {
+    try_negotiate_info(parts).map(|info| info.is_authenticated())
+}
+
+/// Looks up the connection's [`NegotiateInfo`], preferring the one set as [`ConnectInfo`]
+fn try_negotiate_info(parts: &Parts) -> Option<NegotiateInfo> {
+    if let Some(ConnectInfo(info)) = parts.extensions.get::<ConnectInfo<NegotiateInfo>>() {
+        return Some(info.clone());
+    }
+    parts.extensions.get::<NegotiateInfo>().cloned()
+}
 /// Type that must be set via [`Router::into_make_service_with_connect_info`](axum::Router::into_make_service_with_connect_info).
 ///
 /// Without this, the [`NegotiateLayer`] will not work
+///
+/// When driving the middleware manually, e.g. in tests with [`tower::ServiceExt::oneshot`] or from integrations that
+/// can't produce [`ConnectInfo`], it may instead be inserted as a plain request extension. The same `NegotiateInfo`
+/// must be given to every request of a connection:
+///
+/// ```rust
+/// # use axum::body::Body;
+/// # use axum_negotiate_layer::NegotiateInfo;
+/// # use http::Request;
+/// let info = NegotiateInfo::new();
+/// let mut req = Request::new(Body::empty());
+/// req.extensions_mut().insert(info.clone());
+/// ```
 #[derive(Clone, Debug, Default)]
 pub struct NegotiateInfo {
-    auth: Arc<Mutex<NegotiateState>>,
+    auth: NegotiateConnection,
     channel: Option<ChannelBindings>,
+    sni: Option<Arc<str>>,
+    client_cert: Option<ClientCertificate>,
+    /// Requests passed on since the connection last authenticated, see [`NegotiateLayer::max_requests_per_connection`]
+    served: Arc<AtomicU32>,
+    /// Challenges issued since the connection last authenticated, see [`NegotiateLayer::failure_redirect`]
+    challenged: Arc<AtomicU32>,
+    /// The normalized principal of the client last authenticated, see [`NegotiateLayer::identity_change_policy`]
+    last_client: Arc<Mutex<Option<String>>>,
+    /// The sink of the layer handling the connection, for the events of [`NegotiateInfo::close`]
+    sink: Arc<Mutex<Option<LayerSink>>>,
 }
 impl Connected<NegotiateInfo> for NegotiateInfo {
     fn connect_info(value: NegotiateInfo) -> Self {
@@ -181,11 +577,211 @@ impl NegotiateInfo {
             Ok(bindings) => ChannelBindings(bindings.map(|ar| ar.into())),
         };
         Ok(NegotiateInfo {
-            auth: self.auth,
             channel: Some(channel),
+            ..self
+        })
+    }
+    /// Record the server name the client requested during the TLS handshake
+    ///
+    /// Used to select the SPN with [`NegotiateLayer::spn_from_sni`].
+    #[must_use]
+    pub fn with_sni(self, server_name: &str) -> NegotiateInfo {
+        NegotiateInfo {
+            sni: Some(server_name.to_ascii_lowercase().into()),
+            ..self
+        }
+    }
+    /// Record the certificate the client presented during the TLS handshake
+    ///
+    /// Used to authenticate the connection with [`NegotiateLayer::or_client_cert`], if the certificate is
+    /// [verified](ClientCertificate::verified).
+    #[must_use]
+    pub fn with_client_cert(self, cert: ClientCertificate) -> NegotiateInfo {
+        NegotiateInfo {
+            client_cert: Some(cert),
+            ..self
+        }
+    }
+    /// Whether the handshake on this connection has completed
+    #[must_use]
+    pub fn is_authenticated(&self) -> bool {
+        self.auth.is_authenticated()
+    }
+    /// Whether this is the last clone of a closed connection's state, so it may be reused by another connection
+    #[cfg(feature = "http1")]
+    pub(crate) fn is_reusable(&self) -> bool {
+        self.auth.is_unique()
+            && Arc::strong_count(&self.served) == 1
+            && Arc::strong_count(&self.challenged) == 1
+            && self.auth.inspect(|state| matches!(state, State::Unauthorized))
+    }
+    /// This state, emptied for use by another connection, see [`NegotiateInfo::is_reusable`]
+    #[cfg(feature = "http1")]
+    pub(crate) fn reused(self) -> NegotiateInfo {
+        self.served.store(0, Ordering::Relaxed);
+        self.challenged.store(0, Ordering::Relaxed);
+        *self.sink.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        *self.last_client.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        NegotiateInfo {
+            channel: None,
+            sni: None,
+            client_cert: None,
+            ..self
+        }
+    }
+    /// Releases the state of the connection once it is closed
+    ///
+    /// Drops a pending or established context right away, instead of when the last clone of this `NegotiateInfo` is
+    /// dropped, and releases it for all [`Authenticated`] of the connection. Done by [`HasNegotiateInfo`] when a
+    /// connection closes, so this is only needed when driving the IO loop yourself. A request arriving afterwards
+    /// starts a new handshake.
+    pub fn close(&self) {
+        let state = self.auth.reset();
+        let sink = self.sink.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        event!(
+            LayerSink::or_default(sink.as_ref()),
+            Debug,
+            "Connection closed",
+            state = Debugged(&state)
+        );
+        // Dropped only now, so the connection isn't locked while the backend releases the context
+        drop(state);
+    }
+    /// The progress of the handshake on this connection
+    #[must_use]
+    pub fn status(&self) -> HandshakeStatus {
+        self.auth.inspect(|state| match state {
+            State::Unauthorized => HandshakeStatus::Unauthenticated,
+            State::Pending(_) => HandshakeStatus::Pending,
+            State::Authenticated(_) => HandshakeStatus::Authenticated,
         })
     }
 }
+/// Connect info combining an application's own connect info `C` with the [`NegotiateInfo`]
+///
+/// For applications that already rely on a connect info type, which would otherwise have to be given up for
+/// [`NegotiateInfo`]. With [`HasNegotiateInfo`], `C` is created from the remote address, as for
+/// [`SocketAddr`](std::net::SocketAddr). The layer has to be told about `C` with [`NegotiateLayer::with_connect_info`]:
+///
+/// ```rust
+/// use std::net::SocketAddr;
+///
+/// use axum::{Router, extract::ConnectInfo, routing::get};
+/// use axum_negotiate_layer::{Negotiated, NegotiateLayer};
+///
+/// let router = Router::new()
+///     .route(
+///         "/",
+///         get(|ConnectInfo(info): ConnectInfo<Negotiated<SocketAddr>>| async move { info.inner.to_string() }),
+///     )
+///     .layer(NegotiateLayer::new(Some("HTTP/example.com")).with_connect_info::<SocketAddr>())
+///     .into_make_service_with_connect_info::<Negotiated<SocketAddr>>();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Negotiated<C> {
+    pub inner: C,
+    pub negotiate: NegotiateInfo,
+}
+impl<C: Clone + Send + Sync + 'static> Connected<Negotiated<C>> for Negotiated<C> {
+    fn connect_info(value: Negotiated<C>) -> Self {
+        value
+    }
+}
+impl<C: Send + Sync + 'static> Negotiated<C> {
+    fn lookup(parts: &Parts) -> Option<NegotiateInfo> {
+        let ConnectInfo(negotiated) = parts.extensions.get::<ConnectInfo<Self>>()?;
+        Some(negotiated.negotiate.clone())
+    }
+}
+
+/// Extracts the connection's [`NegotiateInfo`] regardless of its handshake state
+///
+/// Rejects the request with `500 Internal Server Error` if there is none, as that indicates a misconfigured router.
+impl<S: Sync> FromRequestParts<S> for NegotiateInfo {
+    type Rejection = Response;
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        try_negotiate_info(parts).ok_or_else(|| {
+            let rejection = AuthenticatedRejection::MissingNegotiateInfo;
+            event!(
+                LayerSink::of(&parts.extensions),
+                Error,
+                "Misconfigured NegotiateLayer",
+                reason = rejection
+            );
+            (StatusCode::INTERNAL_SERVER_ERROR, rejection.to_string()).into_response()
+        })
+    }
+}
+
+/// Progress of the handshake on a connection, see [`NegotiateInfo::status`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HandshakeStatus {
+    /// No handshake has been started, or the last one failed
+    Unauthenticated,
+    /// The server is waiting for the client's next token
+    Pending,
+    /// The handshake has completed, see [`Authenticated`]
+    Authenticated,
+}
+
+/// What the [`NegotiateLayer`] did with a request, for middleware outside of it, e.g. to log or score requests
+///
+/// Set in the extensions of every request the layer passes on, which is always [`NegotiateProgress::Authenticated`],
+/// and in the extensions of every response the layer answers itself, with one of the other variants. Middleware
+/// wrapping the layer thus finds it in the response extensions for both, while middleware and handlers inside of it
+/// find it in the request extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NegotiateProgress {
+    /// The layer answered without attempting a handshake, e.g. for a connection without [`NegotiateInfo`], while
+    /// draining or without a host for [`NegotiateLayer::spn_from_host`]
+    Skipped,
+    /// The layer answered without authenticating the connection, usually with a challenge. Also used when a handshake
+    /// failed, with the challenge to start over or a `403 Forbidden` if
+    /// [`NegotiateLayer::forbid_failed_handshakes`] is set.
+    ChallengeIssued,
+    /// The layer answered with the server's token of a handshake needing another round trip, after the given number
+    /// of client tokens
+    HandshakeLeg(u8),
+    /// The layer redirected a browser that didn't authenticate, see [`NegotiateLayer::failure_redirect`]
+    Redirected,
+    /// The layer couldn't attempt the handshake for a fault of the server, e.g. server credentials that couldn't be
+    /// acquired, see [`NegotiateLayer::credentials_failure_response`]
+    ServerError,
+    /// The request was authenticated and passed on
+    Authenticated,
+}
+
+/// Why the [`NegotiateLayer`] answered a request with an error, for middleware outside of it, e.g. to log failures
+///
+/// Set in the extensions of every error response the layer generates for a failure counted by
+/// [`LayerStats::failed`], alongside its [`NegotiateProgress`]. A challenge to a request without credentials isn't a
+/// failure and carries none. It holds no token material, so it is safe to log.
+///
+/// ```
+/// use axum::{Router, middleware::map_response, response::Response};
+/// use axum_negotiate_layer::{NegotiateFailure, NegotiateLayer};
+///
+/// let router: Router = Router::new()
+///     .layer(NegotiateLayer::new(Some("HTTP/example.com")))
+///     .layer(map_response(|response: Response| async move {
+///         if let Some(failure) = response.extensions().get::<NegotiateFailure>() {
+///             eprintln!("authentication failed in leg {}: {}", failure.leg, failure.reason);
+///         }
+///         response
+///     }));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct NegotiateFailure {
+    pub reason: FailureReason,
+    /// The leg of the handshake that failed, counting the client's tokens from 1
+    ///
+    /// 0 if the request failed outside of a handshake step, e.g. for a malformed header, while draining or with
+    /// [`NegotiateLayer::basic_auth`].
+    pub leg: u8,
+}
 
 #[derive(Debug, Clone)]
 pub struct ChannelBindings(Option<Arc<[u8]>>);
@@ -196,28 +792,1052 @@ impl Channel for ChannelBindings {
     }
 }
 
+#[derive(Clone)]
+struct NegotiateConfig {
+    spn: Option<AcceptorName>,
+    /// Replaces `spn`, see [`NegotiateLayer::spn_from_file`]
+    #[cfg(feature = "spn-file")]
+    spn_file: Option<spn_file::SpnFile>,
+    #[cfg(feature = "spn-file")]
+    spn_file_poll_interval: Duration,
+    #[cfg(debug_assertions)]
+    layering: Arc<layering::LayeringWarnings>,
+    suppress_final_token: bool,
+    forwarded_user: Option<ForwardedUser>,
+    identity_header: Option<HeaderName>,
+    ntlm_policy: Option<NtlmPolicy>,
+    #[cfg(feature = "drain")]
+    drainer: Option<Drainer>,
+    forbid_failed_handshakes: bool,
+    replay_cache: Option<Arc<dyn ReplayCache>>,
+    replay_window: Duration,
+    correlation_header: Option<HeaderName>,
+    log_raw_tokens: bool,
+    sink: Option<Arc<dyn Sink>>,
+    stats: Option<LayerStats>,
+    clock: Arc<dyn Clock>,
+    max_session_age: Option<Duration>,
+    max_requests_per_connection: Option<u32>,
+    context_cache: Option<ContextCache>,
+    sni_spns: Option<SniSpns>,
+    host_spn_service: Option<String>,
+    misuse_policy: Option<MisusePolicy>,
+    connect_info: Option<fn(&Parts) -> Option<NegotiateInfo>>,
+    challenge_style: ChallengeStyle,
+    /// Schemes of the challenge starting a handshake, in order
+    scheme_order: Vec<Scheme>,
+    token_headers: TokenHeaders,
+    failure_redirect: Option<FailureRedirect>,
+    challenge_policy: Option<ChallengePolicy>,
+    client_cert: Option<CertIdentityMapper>,
+    identity_change_policy: IdentityChangePolicy,
+    reject_machine_accounts: bool,
+    is_machine_account: PrincipalPredicate,
+    session_identity: bool,
+    extended_protection: EpaPolicy,
+    basic_auth: Option<BasicAuth>,
+    basic_user_agents: Vec<String>,
+    prevent_caching: bool,
+    on_handshake_complete: Option<HandshakeCallback>,
+    on_handshake_reset: Option<ResetCallback>,
+    max_token_size: usize,
+    invalid_token_response: Option<ResponseFactory>,
+    non_persistent_response: Option<ResponseFactory>,
+    non_persistent_transport: bool,
+    credentials_failure_response: Option<ResponseFactory>,
+    #[cfg(feature = "health")]
+    credential_health: HealthCache,
+    #[cfg(feature = "health")]
+    credential_health_ttl: Duration,
+    continue_body: Bytes,
+    map_continue_response: Option<ResponseMapper>,
+    #[cfg(feature = "dev-insecure")]
+    dev_identity: Option<String>,
+    require_kerberos: bool,
+    stateless: bool,
+    /// See [`NegotiateLayer::no_cache`]
+    no_cache: bool,
+    auth_trailers: bool,
+    annotate_errors: bool,
+    /// Guards the configuration warnings, so applying the layer to many routes logs them once
+    warned: Arc<Once>,
+}
+
+type HandshakeCallback = Arc<dyn Fn(Duration) + Send + Sync>;
+type ResetCallback = Arc<dyn Fn(u8) + Send + Sync>;
+type PrincipalPredicate = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+type ResponseFactory = Arc<dyn Fn() -> Response + Send + Sync>;
+type ResponseMapper = Arc<dyn Fn(Response) -> Response + Send + Sync>;
+
+impl Default for NegotiateConfig {
+    fn default() -> Self {
+        Self {
+            spn: None,
+            #[cfg(feature = "spn-file")]
+            spn_file: None,
+            #[cfg(feature = "spn-file")]
+            spn_file_poll_interval: spn_file::DEFAULT_POLL_INTERVAL,
+            #[cfg(debug_assertions)]
+            layering: Arc::default(),
+            suppress_final_token: false,
+            forwarded_user: None,
+            identity_header: None,
+            ntlm_policy: None,
+            #[cfg(feature = "drain")]
+            drainer: None,
+            forbid_failed_handshakes: false,
+            replay_cache: None,
+            replay_window: DEFAULT_REPLAY_WINDOW,
+            correlation_header: None,
+            log_raw_tokens: false,
+            sink: None,
+            stats: None,
+            clock: Arc::new(SystemClock),
+            max_session_age: None,
+            max_requests_per_connection: None,
+            context_cache: None,
+            sni_spns: None,
+            host_spn_service: None,
+            misuse_policy: None,
+            connect_info: None,
+            challenge_style: ChallengeStyle::default(),
+            scheme_order: vec![Scheme::Negotiate],
+            token_headers: TokenHeaders::default(),
+            failure_redirect: None,
+            challenge_policy: None,
+            client_cert: None,
+            identity_change_policy: IdentityChangePolicy::default(),
+            reject_machine_accounts: false,
+            is_machine_account: Arc::new(is_machine_account),
+            session_identity: false,
+            extended_protection: EpaPolicy::default(),
+            basic_auth: None,
+            basic_user_agents: Vec::new(),
+            prevent_caching: true,
+            on_handshake_complete: None,
+            on_handshake_reset: None,
+            max_token_size: DEFAULT_MAX_TOKEN_SIZE,
+            invalid_token_response: None,
+            non_persistent_response: None,
+            non_persistent_transport: false,
+            credentials_failure_response: None,
+            #[cfg(feature = "health")]
+            credential_health: HealthCache::default(),
+            #[cfg(feature = "health")]
+            credential_health_ttl: DEFAULT_CREDENTIAL_HEALTH_TTL,
+            continue_body: Bytes::from_static(b"continue"),
+            map_continue_response: None,
+            #[cfg(feature = "dev-insecure")]
+            dev_identity: None,
+            require_kerberos: false,
+            stateless: false,
+            no_cache: false,
+            auth_trailers: false,
+            annotate_errors: false,
+            warned: Arc::new(Once::new()),
+        }
+    }
+}
+impl NegotiateConfig {
+    /// Warns about settings that must not be used in production, once the layer is complete
+    fn warn_configuration(&self) {
+        if self.no_cache {
+            event!(
+                self.sink(),
+                Warn,
+                "DEBUG: discarding the authentication of connections before every request, never use this in production"
+            );
+        }
+        #[cfg(feature = "dev-insecure")]
+        if let Some(principal) = &self.dev_identity {
+            event!(
+                self.sink(),
+                Warn,
+                "INSECURE: authenticating every request without credentials, never use this in production",
+                principal = principal
+            );
+        }
+    }
+    /// The value of the [`NegotiateLayer::correlation_header`] in `headers`, if it is set and valid
+    fn correlation_id<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        let header = self.correlation_header.as_ref()?;
+        headers.get(header).and_then(|id| id.to_str().ok())
+    }
+    fn sink(&self) -> &dyn Sink {
+        self.sink.as_deref().unwrap_or(default_sink())
+    }
+    /// The name given to the layer, as last read from its file with [`NegotiateLayer::spn_from_file`]
+    fn acceptor_name(&self) -> Option<Cow<'_, AcceptorName>> {
+        #[cfg(feature = "spn-file")]
+        if let Some(file) = &self.spn_file {
+            return Some(Cow::Owned(file.current()));
+        }
+        self.spn.as_ref().map(Cow::Borrowed)
+    }
+    fn record_failure(&self, reason: FailureReason, client: Option<&str>) {
+        if let Some(stats) = &self.stats {
+            stats.record_failure(reason, client);
+        }
+    }
+    /// The response to a token that isn't valid base64
+    fn invalid_token(&self) -> Response {
+        match &self.invalid_token_response {
+            Some(response) => response(),
+            None => StatusCode::BAD_REQUEST.into_response(),
+        }
+    }
+    /// The response to a handshake needing another round trip on a connection that isn't kept alive
+    fn non_persistent(&self, persistence: Persistence) -> Response {
+        if let Some(response) = &self.non_persistent_response {
+            return response();
+        }
+        match persistence {
+            Persistence::Http10 => (
+                StatusCode::UPGRADE_REQUIRED,
+                [(UPGRADE, "HTTP/1.1"), (CONNECTION, "Upgrade")],
+                "authentication needs another round trip on the same connection, which requires HTTP/1.1 or \
+                 Connection: keep-alive",
+            )
+                .into_response(),
+            Persistence::Transport => forbidden(
+                "authentication needs another round trip, which this server doesn't support, use Kerberos instead",
+            ),
+            Persistence::KeepAlive | Persistence::Close => forbidden(
+                "authentication needs another round trip, which requires HTTP/1.1 or HTTP/1.0 with Connection: keep-alive",
+            ),
+        }
+    }
+    /// The response when the server credentials can't be acquired to start a handshake
+    fn credentials_failure(&self) -> Response {
+        match &self.credentials_failure_response {
+            Some(response) => response(),
+            None => (StatusCode::INTERNAL_SERVER_ERROR, "internal server error").into_response(),
+        }
+    }
+    /// Finishes a response generated by the middleware itself
+    fn respond<E: 'static>(
+        &self,
+        response: Response,
+        progress: NegotiateProgress,
+    ) -> BoxFuture<'static, Result<Response, E>> {
+        let response = self.finish_response(response, progress);
+        Box::pin(async { Ok(response) })
+    }
+    /// See [`NegotiateConfig::respond`]
+    fn finish_response(&self, mut response: Response, progress: NegotiateProgress) -> Response {
+        response.extensions_mut().insert(progress);
+        if self.prevent_caching {
+            let headers = response.headers_mut();
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            headers.insert(PRAGMA, HeaderValue::from_static("no-cache"));
+            let vary = match &self.token_headers.token {
+                token if *token == AUTHORIZATION => HeaderValue::from_static("Authorization"),
+                token => HeaderValue::from_name(token.clone()),
+            };
+            headers.insert(VARY, vary);
+        }
+        response
+    }
+}
+
+/// The default maximum clock skew of Kerberos, after which authenticators are rejected anyway
+const DEFAULT_REPLAY_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// The `MaxTokenSize` of Windows, which Kerberos tickets with large PACs of users in many groups approach
+const DEFAULT_MAX_TOKEN_SIZE: usize = 48 * 1024;
+/// Short enough for probes to notice a removed keytab soon, long enough not to read it on every probe
+#[cfg(feature = "health")]
+const DEFAULT_CREDENTIAL_HEALTH_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+struct SniSpns {
+    spns: HashMap<String, Spn>,
+    fallback: Spn,
+}
+impl SniSpns {
+    /// Selects the SPN by the server name, or else by the ticket in the client's first `token`
+    ///
+    /// Looking into the ticket picks the one SPN the backend can accept it with, instead of trying each.
+    fn select(
+        &self,
+        sni: Option<&str>,
+        token: &NegotiateToken,
+        max_token_size: usize,
+        sink: &dyn Sink,
+    ) -> AcceptorName {
+        if let Some(spn) = sni.and_then(|sni| self.spns.get(sni)) {
+            event!(sink, Trace, "Selected SPN by SNI", sni = Debugged(sni), spn = spn);
+            return spn.clone().into();
+        }
+        let target = token
+            .decode_bounded(max_token_size)
+            .ok()
+            .and_then(|token| InitialToken::target_spn(&token));
+        let by_ticket = target.as_ref().and_then(|target| {
+            self.spns
+                .values()
+                .chain([&self.fallback])
+                .find(|spn| spn.accepts(target))
+        });
+        if let Some(spn) = by_ticket {
+            event!(sink, Trace, "Selected SPN by ticket", sni = Debugged(sni), spn = spn);
+            return spn.clone().into();
+        }
+        event!(
+            sink,
+            Trace,
+            "Selected fallback SPN",
+            sni = Debugged(sni),
+            spn = &self.fallback
+        );
+        self.fallback.clone().into()
+    }
+}
+
+#[derive(Clone)]
+struct ForwardedUser {
+    header: HeaderName,
+    proxies: Vec<String>,
+}
+
 /// [`Layer`] which will enforce authentication
 ///
 /// The SPN must be correctly installed in the local realm
 ///
-/// Also a [`ConnectInfo`] extension must have been set on the router.
+/// Also a [`NegotiateInfo`] must have been set on the router as [`ConnectInfo`] or request extension.
 #[derive(Clone)]
 pub struct NegotiateLayer {
-    spn: Option<String>,
+    config: NegotiateConfig,
 }
 impl NegotiateLayer {
+    /// `spn` may be given as principal (`HTTP/host`) or host-based service name (`HTTP@host`), see [`AcceptorName`]
     #[must_use]
     pub fn new(spn: Option<&str>) -> Self {
         Self {
-            spn: spn.map(ToOwned::to_owned),
+            config: NegotiateConfig {
+                spn: spn.map(AcceptorName::from),
+                ..Default::default()
+            },
+        }
+    }
+    /// Creates a layer configured from the environment
+    ///
+    /// The SPN is read from [`SPN_VAR`] (`NEGOTIATE_SPN`), which must be set and non-empty. The keytab and credential
+    /// cache are taken by GSSAPI from [`KEYTAB_VAR`] (`KRB5_KTNAME`) and [`CCACHE_VAR`] (`KRB5CCNAME`), overriding the
+    /// defaults of `krb5.conf`. These are optional, but are checked here when set so misconfigurations surface at
+    /// startup instead of on the first request. Further options may be set with the other builder methods.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let spn = env::spn_from_env(|var| std::env::var_os(var))?;
+        Ok(Self::new(Some(&spn)))
+    }
+    /// Set the name to acquire the server credentials for, see [`AcceptorName`]
+    ///
+    /// [`None`] uses the default credentials of the backend.
+    #[must_use]
+    pub fn acceptor_name(mut self, name: Option<AcceptorName>) -> Self {
+        self.config.spn = name;
+        #[cfg(feature = "spn-file")]
+        {
+            self.config.spn_file = None;
+        }
+        self
+    }
+    /// Read the name to acquire the server credentials for from the file at `path`, and reload it when it changes
+    ///
+    /// The file contains the name on its first non-empty line, in either form (see [`AcceptorName`]). Once the layer
+    /// is applied, a background thread checks it for changes every [`NegotiateLayer::spn_file_poll_interval`], and
+    /// ends once the layer and its services are dropped. An SPN can thus be rotated by writing or replacing the file,
+    /// without a restart. If the changed file can't be read or is empty, e.g. while being written, the previous name
+    /// is kept and a warning is logged.
+    ///
+    /// Credentials are acquired on the first request of a handshake, so only handshakes starting after the reload use
+    /// the new name. Pending handshakes continue with the credentials they started with, and authenticated
+    /// connections stay authenticated. Replaces the name given in [`NegotiateLayer::new`], while the other ways of
+    /// selecting the SPN take precedence over it.
+    ///
+    /// Only available with the `spn-file` feature.
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be read or is empty. If the thread can't be started, an error is logged and the name read
+    /// first is kept.
+    #[cfg(feature = "spn-file")]
+    pub fn spn_from_file(mut self, path: impl Into<std::path::PathBuf>) -> std::io::Result<Self> {
+        self.config.spn_file = Some(spn_file::SpnFile::read(path.into())?);
+        Ok(self)
+    }
+    /// How often the file of [`NegotiateLayer::spn_from_file`] is checked for changes, every second by default
+    ///
+    /// Only available with the `spn-file` feature.
+    #[cfg(feature = "spn-file")]
+    #[must_use]
+    pub fn spn_file_poll_interval(mut self, interval: Duration) -> Self {
+        self.config.spn_file_poll_interval = interval;
+        self
+    }
+    /// Accept tickets for any principal with keys in the keytab, instead of a single SPN
+    ///
+    /// The server credentials are acquired without a name, so the backend matches whichever SPN the client requested
+    /// a ticket for against the keytab. This is the same as passing [`None`] to [`NegotiateLayer::new`], and also
+    /// discards a selection made with [`NegotiateLayer::spn_from_sni`] or [`NegotiateLayer::spn_from_host`].
+    ///
+    /// # Security
+    /// Every key in the keytab is accepted, including ones of unrelated services like `host/` keys in the system
+    /// keytab. Use a dedicated keytab (see [`KEYTAB_VAR`]) containing only the intended SPNs.
+    #[must_use]
+    pub fn accept_any_keytab_principal(mut self) -> Self {
+        self.config.spn = None;
+        #[cfg(feature = "spn-file")]
+        {
+            self.config.spn_file = None;
+        }
+        self.config.sni_spns = None;
+        self.config.host_spn_service = None;
+        self
+    }
+    /// Canonicalize the acceptor name set so far, see [`AcceptorName::canonicalize`]
+    ///
+    /// Must be called after the name was set, it doesn't affect names set afterwards.
+    #[must_use]
+    pub fn canonicalize_spn(mut self, options: &CanonicalizeOptions) -> Self {
+        if let Some(name) = &self.config.spn {
+            let canonical = name.canonicalize(options);
+            if &canonical != name {
+                event!(
+                    self.config.sink(),
+                    Info,
+                    "Canonicalized SPN",
+                    before = name,
+                    after = canonical
+                );
+            }
+            self.config.spn = Some(canonical);
+        }
+        self
+    }
+    /// Select the SPN by the server name the client requested during the TLS handshake
+    ///
+    /// The server name must have been recorded on the connection with [`NegotiateInfo::with_sni`]. Connections without
+    /// one, or with a name not contained in `spns`, use the SPN among `spns` and `fallback` that their Kerberos ticket
+    /// was issued for, see [`InitialToken::target_spn`], and `fallback` otherwise. This takes precedence over the name
+    /// given in [`NegotiateLayer::new`], while a [`SpnOverride`] takes precedence over this. Server names are matched
+    /// case-insensitively.
+    #[must_use]
+    pub fn spn_from_sni(mut self, spns: HashMap<String, Spn>, fallback: Spn) -> Self {
+        let spns = spns
+            .into_iter()
+            .map(|(name, spn)| (name.to_ascii_lowercase(), spn))
+            .collect();
+        self.config.sni_spns = Some(SniSpns { spns, fallback });
+        self
+    }
+    /// Derive the SPN from the host the request was made to, as `service/host`
+    ///
+    /// The host is taken from the `Host` header, or the URI of HTTP/2 requests, and canonicalized like with
+    /// [`Spn::for_host`]. This takes precedence over the name given in [`NegotiateLayer::new`], while
+    /// [`NegotiateLayer::spn_from_sni`] takes precedence over this. Requests without a host on connections that aren't
+    /// authenticated are refused with `400 Bad Request` unless they carry a [`SpnOverride`], as no SPN can be derived
+    /// for them.
+    ///
+    /// # Security
+    /// The host is chosen by the client, so every SPN of `service` with keys in the keytab is accepted. Use a
+    /// dedicated keytab (see [`KEYTAB_VAR`]) containing only the intended SPNs.
+    #[must_use]
+    pub fn spn_from_host(mut self, service: &str) -> Self {
+        self.config.host_spn_service = Some(service.to_owned());
+        self
+    }
+    /// Omit the final mutual authentication token from the successful response
+    ///
+    /// By default, the last token produced by the handshake is sent back in a `WWW-Authenticate` header
+    /// alongside the inner service's response if it is successful (2xx), as specified by RFC 4559. This is required
+    /// for correct mutual authentication.
+    /// Some clients misinterpret that header on a successful response as a new challenge, in which case it can be suppressed here.
+    #[must_use]
+    pub fn suppress_final_token(mut self, suppress: bool) -> Self {
+        self.config.suppress_final_token = suppress;
+        self
+    }
+    /// Trust `header` to carry the end user's identity on connections authenticated by one of `proxies`
+    ///
+    /// This is meant for deployments where a reverse proxy authenticates itself to this service and then forwards
+    /// requests of many different users over the same connection. For such requests, [`Authenticated::client`] returns
+    /// the value of `header` while [`Authenticated::transport_client`] still returns the proxy's principal. The same
+    /// applies to requests authenticated without a handshake, e.g. by the proxy's client certificate.
+    ///
+    /// # Security
+    /// Whoever may set `header` can impersonate any user. Only list principals here that are guaranteed to overwrite or
+    /// strip that header from their own clients' requests. On connections authenticated as any other principal, the header is
+    /// removed from the request before it reaches the inner service.
+    #[must_use]
+    pub fn trust_forwarded_user(mut self, header: HeaderName, proxies: &[&str]) -> Self {
+        self.config.forwarded_user = Some(ForwardedUser {
+            header,
+            proxies: proxies.iter().map(|&p| p.to_owned()).collect(),
+        });
+        self
+    }
+    /// Set `header` to the authenticated client on every request passed on, for services behind this one
+    ///
+    /// The value is [`Authenticated::client`], so the forwarded user of a trusted proxy, see
+    /// [`NegotiateLayer::trust_forwarded_user`]. Any value of `header` sent by the client is overwritten, or removed if the
+    /// client's name isn't a valid header value, so it can't be spoofed.
+    #[must_use]
+    pub fn forward_identity_header(mut self, header: HeaderName) -> Self {
+        self.config.identity_header = Some(header);
+        self
+    }
+    /// Enforce an [`NtlmPolicy`] on clients that authenticate via NTLM
+    ///
+    /// Without a policy, any NTLM authentication the backend accepts is accepted.
+    #[must_use]
+    pub fn ntlm_policy(mut self, policy: NtlmPolicy) -> Self {
+        self.config.ntlm_policy = Some(policy);
+        self
+    }
+    /// Don't keep the authentication on the connection, so every request has to carry its own token
+    ///
+    /// This is useful behind proxies that reuse their upstream connections for requests of different clients.
+    /// The established context is only available through the request's [`Authenticated`] extension.
+    /// Clients must complete a handshake for every request, which works best with Kerberos completing in a single round.
+    /// Handshakes needing several rounds are still kept on the connection until they complete.
+    #[must_use]
+    pub fn stateless(mut self, stateless: bool) -> Self {
+        self.config.stateless = stateless;
+        self
+    }
+    /// Send the authenticated client and mechanism as [`CLIENT_TRAILER`] and [`MECHANISM_TRAILER`] response trailers
+    ///
+    /// Only responses to requests with `TE: trailers` receive them. Any `Content-Length` of those responses is removed,
+    /// as HTTP/1.1 only supports trailers on chunked responses.
+    #[must_use]
+    pub fn auth_trailers(mut self, enabled: bool) -> Self {
+        self.config.auth_trailers = enabled;
+        self
+    }
+    /// Set [`ERROR_CLIENT_HEADER`] to the [`client`](Authenticated::client) on `4xx` and `5xx` responses of the inner
+    /// service, for support staff reading them
+    ///
+    /// Every response of the inner service carries the [`Authenticated`] extension anyway, which middleware wrapping
+    /// the layer can read instead. The header reaches the client, which only learns its own name from it, but also
+    /// every proxy and log on the way. Names that aren't valid header values are left out.
+    #[must_use]
+    pub fn annotate_errors_with_identity(mut self, annotate: bool) -> Self {
+        self.config.annotate_errors = annotate;
+        self
+    }
+    /// Reject clients that don't offer Kerberos in the first token of their handshake, e.g. NTLM-only clients
+    ///
+    /// The mechanisms are read from the SPNEGO `negTokenInit` before the token is passed to the backend.
+    /// Tokens that can't be parsed are left for the backend to judge.
+    #[must_use]
+    pub fn require_kerberos(mut self, require: bool) -> Self {
+        self.config.require_kerberos = require;
+        self
+    }
+    /// Respond with `403 Forbidden` instead of `401 Unauthorized` when the client's token was rejected
+    ///
+    /// Requests without any credentials are always answered with `401 Unauthorized` and a challenge. With this option,
+    /// a handshake that was attempted but failed is answered with `403 Forbidden` and no new challenge instead.
+    #[must_use]
+    pub fn forbid_failed_handshakes(mut self, forbid: bool) -> Self {
+        self.config.forbid_failed_handshakes = forbid;
+        self
+    }
+    /// Reject tokens that authenticate in a single round if they were already seen by `cache`
+    ///
+    /// Tokens are remembered for the replay window set with [`NegotiateLayer::replay_window`].
+    /// Replays are answered with `401 Unauthorized`.
+    #[must_use]
+    pub fn replay_cache(mut self, cache: impl ReplayCache + 'static) -> Self {
+        self.config.replay_cache = Some(Arc::new(cache));
+        self
+    }
+    /// How long single-leg tokens are remembered by the [`ReplayCache`], 5 minutes by default
+    ///
+    /// This should be at least the maximum clock skew accepted by the local Kerberos configuration.
+    #[must_use]
+    pub fn replay_window(mut self, window: Duration) -> Self {
+        self.config.replay_window = window;
+        self
+    }
+    /// Attach the value of `header` (e.g. `X-Request-Id`) as `correlation_id` to the events of every handshake round,
+    /// and with the `tracing` feature to a `negotiate` span around it, which also covers the inner service
+    #[must_use]
+    pub fn correlation_header(mut self, header: HeaderName) -> Self {
+        self.config.correlation_header = Some(header);
+        self
+    }
+    /// Log the base64 token of every handshake round at `trace` level, disabled by default
+    ///
+    /// **Security warning:** tokens are credentials. Anyone able to read these logs may replay them within their
+    /// validity window, or attack NTLM responses offline. Only enable this temporarily for debugging a specific client.
+    /// This is the only event carrying token material.
+    #[must_use]
+    pub fn log_raw_tokens(mut self, log: bool) -> Self {
+        self.config.log_raw_tokens = log;
+        self
+    }
+    /// Send the diagnostics of this layer to `sink` instead of the default, see the [`sink`] module
+    ///
+    /// This includes the events of the extractors on requests the layer passed on, of [`NegotiateInfo::close`] on its
+    /// connections and of its `Drainer`. Extractors outside of a layer and [`validate_spn`] use the
+    /// default sink, [`validate_spn_with_sink`] takes a sink of its own.
+    #[must_use]
+    pub fn with_log_sink(mut self, sink: Arc<dyn Sink>) -> Self {
+        self.config.sink = Some(sink);
+        self
+    }
+    /// Read the current time from `clock` when expiring connections, see [`NegotiateLayer::max_session_age`]
+    #[must_use]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.config.clock = clock;
+        self
+    }
+    /// Require connections to authenticate again once `age` has passed since their handshake
+    ///
+    /// The next request after that is challenged as if the connection had never authenticated. Not enforced with
+    /// [`NegotiateLayer::stateless`], which authenticates every request anyway.
+    ///
+    /// The age is only checked when a request starts, so long-lived responses like Server-Sent Events streams aren't
+    /// cut off. Their [`Authenticated`] keeps the client names, but once another request of the connection found the
+    /// session expired, its context is released, see [`Authenticated::is_released`].
+    #[must_use]
+    pub fn max_session_age(mut self, age: Duration) -> Self {
+        self.config.max_session_age = Some(age);
+        self
+    }
+    /// Require connections to authenticate again after passing on `max` requests
+    ///
+    /// Bounds the use of a hijacked keep-alive connection. The request after the last one is answered with `401
+    /// Unauthorized` and a new challenge, on HTTP/1.x with `Connection: close`, so the client reconnects and
+    /// authenticates again. HTTP/2 clients authenticate again on the same connection. The requests of the handshake
+    /// count as one, the one that completed it. Not enforced with [`NegotiateLayer::stateless`], which authenticates
+    /// every request anyway.
+    #[must_use]
+    pub fn max_requests_per_connection(mut self, max: u32) -> Self {
+        self.config.max_requests_per_connection = Some(max);
+        self
+    }
+    /// Mark all responses generated by this layer as uncacheable, enabled by default
+    ///
+    /// Challenges and continue responses carry per-connection tokens, so a caching proxy replaying them to other clients
+    /// breaks their authentication. They receive `Cache-Control: no-store`, `Pragma: no-cache` and `Vary` with the
+    /// [token header](NegotiateLayer::token_header), i.e. `Vary: Authorization` by default.
+    /// Successful responses carrying a final mutual authentication token receive `Cache-Control: no-store`.
+    #[must_use]
+    pub fn prevent_caching(mut self, prevent: bool) -> Self {
+        self.config.prevent_caching = prevent;
+        self
+    }
+    /// Call `callback` with the time from the first handshake round to authentication for every successful handshake
+    ///
+    /// This includes the client's round trips and its requests to the KDC, e.g. for recording a
+    /// `negotiate_handshake_duration_seconds` histogram. It is called before the request is passed on.
+    #[must_use]
+    pub fn on_handshake_complete(mut self, callback: impl Fn(Duration) + Send + Sync + 'static) -> Self {
+        self.config.on_handshake_complete = Some(Arc::new(callback));
+        self
+    }
+    /// Call `callback` with the number of rounds, including the failing one, whenever a pending handshake fails
+    ///
+    /// Handshakes failing in their first round are left out, so this tells clients that start handshakes and fail
+    /// midway, e.g. flapping between mechanisms, apart from clients that never start one properly. Only failures of the
+    /// handshake itself count, i.e. [`FailureReason::WrongContinuation`] and [`FailureReason::Rejected`], while clients
+    /// rejected by a policy, e.g. the [`NtlmPolicy`], are not reported. Each such reset is also logged as a warning.
+    #[must_use]
+    pub fn on_handshake_reset(mut self, callback: impl Fn(u8) + Send + Sync + 'static) -> Self {
+        self.config.on_handshake_reset = Some(Arc::new(callback));
+        self
+    }
+    /// Reject tokens decoding to more than `bytes`, 48 KiB by default
+    ///
+    /// The size is checked against the length of the base64 token before decoding it, so no request can make the
+    /// middleware allocate more than `bytes` for its token. Oversized `Negotiate` tokens fail the handshake with
+    /// `431 Request Header Fields Too Large`, oversized `Basic` credentials are rejected as invalid.
+    #[must_use]
+    pub fn max_token_size(mut self, bytes: usize) -> Self {
+        self.config.max_token_size = bytes;
+        self
+    }
+    /// Respond to tokens that aren't valid base64 with the response of `response` instead of an empty `400 Bad Request`
+    ///
+    /// Clients mostly send such tokens when a proxy mangled the `Authorization` header, which an application may want
+    /// to explain in the response body. The caching headers of [`NegotiateLayer::prevent_caching`] are added to it.
+    #[must_use]
+    pub fn invalid_token_response(mut self, response: impl Fn() -> Response + Send + Sync + 'static) -> Self {
+        self.config.invalid_token_response = Some(Arc::new(response));
+        self
+    }
+    /// Respond with the response of `response` when a handshake can't continue, as the client closes the connection
+    ///
+    /// Handshakes take multiple round trips on the same connection, unless the client authenticates with Kerberos
+    /// right away. A connection is taken to be closed after the response for HTTP/1.0 requests without
+    /// `Connection: keep-alive`, for any request with `Connection: close`, and for every request with
+    /// [`NegotiateLayer::non_persistent_transport`]. Its client would send the next token on a new connection,
+    /// starting the handshake over again in an endless loop. Such a handshake fails instead, as soon as the client's
+    /// first token shows that it can't authenticate with Kerberos, e.g. a raw NTLM message or a SPNEGO token without
+    /// Kerberos among its mechanisms, and otherwise once the backend asks for another round trip.
+    ///
+    /// HTTP/1.0 requests fail with `426 Upgrade Required` asking for HTTP/1.1, others with `403 Forbidden`, both with
+    /// a body explaining why. `response` replaces both. The caching headers of [`NegotiateLayer::prevent_caching`]
+    /// are added to it.
+    #[must_use]
+    pub fn non_persistent_response(mut self, response: impl Fn() -> Response + Send + Sync + 'static) -> Self {
+        self.config.non_persistent_response = Some(Arc::new(response));
+        self
+    }
+    /// Treat every connection as closed after the response, so only handshakes completing in a single round trip
+    /// succeed, see [`NegotiateLayer::non_persistent_response`]
+    ///
+    /// For servers behind a proxy that doesn't keep the client's requests on one upstream connection, e.g. one pooling
+    /// its upstream connections, as the layer can't detect that from the requests.
+    #[must_use]
+    pub fn non_persistent_transport(mut self, non_persistent: bool) -> Self {
+        self.config.non_persistent_transport = non_persistent;
+        self
+    }
+    /// Respond with the response of `response` when the server credentials can't be acquired to start a handshake
+    ///
+    /// Such a handshake fails with `500 Internal Server Error` and no details by default, while the cause is logged.
+    /// `response` may e.g. add a reference for support to the body. The caching headers of
+    /// [`NegotiateLayer::prevent_caching`] are added to it.
+    #[must_use]
+    pub fn credentials_failure_response(mut self, response: impl Fn() -> Response + Send + Sync + 'static) -> Self {
+        self.config.credentials_failure_response = Some(Arc::new(response));
+        self
+    }
+    /// How long [`NegotiateLayer::credential_health`] reuses its last result, 30 seconds by default
+    #[cfg(feature = "health")]
+    #[must_use]
+    pub fn credential_health_ttl(mut self, ttl: Duration) -> Self {
+        self.config.credential_health_ttl = ttl;
+        self
+    }
+    /// Whether the server credentials can be acquired, e.g. for a readiness probe
+    ///
+    /// Acquires the credentials for every configured SPN the same way handshakes do, including all of
+    /// [`NegotiateLayer::spn_from_sni`]. With [`NegotiateLayer::spn_from_host`], the credentials for the name given in
+    /// [`NegotiateLayer::new`] are acquired instead, as the hosts aren't known in advance. The result is reused for
+    /// [`NegotiateLayer::credential_health_ttl`] by all clones of this layer, so probes stay cheap, and concurrent
+    /// probes wait for a single acquisition. Acquiring runs on tokio's blocking thread pool, as it may block on the
+    /// keytab or the network.
+    ///
+    /// Like [`validate_spn`], this only tells whether local keys are available, not whether the KDC knows the SPN.
+    #[cfg(feature = "health")]
+    pub async fn credential_health(&self) -> CredentialHealth {
+        #[cfg(feature = "dev-insecure")]
+        if self.config.dev_identity.is_some() {
+            return CredentialHealth::Ok;
+        }
+        let names: Vec<_> = match &self.config.sni_spns {
+            Some(sni) => sni
+                .spns
+                .values()
+                .chain([&sni.fallback])
+                .map(|spn| Some(AcceptorName::from(spn.clone())))
+                .collect(),
+            None => vec![self.config.acceptor_name().map(Cow::into_owned)],
+        };
+        let (ttl, sink) = (self.config.credential_health_ttl, self.config.sink.clone());
+        self.config.credential_health.get(ttl, names, sink).await
+    }
+    /// Discard the connection's authentication before every request, for diagnosing handshake issues only
+    ///
+    /// Every request then has to complete a fresh handshake, which rules out state kept on the connection as the cause
+    /// of intermittent failures. Unlike [`NegotiateLayer::stateless`], this isn't meant for production: clients that
+    /// only send a token when challenged get a `401` before every request. Only an authenticated connection is reset,
+    /// so handshakes needing several rounds still complete. Off by default. A warning is logged when the layer is
+    /// applied with it enabled, and every reset is logged at debug level.
+    #[must_use]
+    pub fn no_cache(mut self, no_cache: bool) -> Self {
+        self.config.no_cache = no_cache;
+        self.config.warned = Arc::new(Once::new());
+        self
+    }
+    /// Authenticate every request as `principal` without checking any credentials, for local development
+    ///
+    /// Requests are passed on with an [`Authenticated`] for `principal`, without a context like for
+    /// [`NegotiateLayer::basic_auth`]. [`NegotiateLayer::on_handshake_complete`] and [`NegotiateLayer::with_stats`] see
+    /// a successful handshake on every request. The backend isn't used at all, so no keytab or KDC is needed.
+    ///
+    /// Only available with the `dev-insecure` feature, which can't be enabled together with `forbid-insecure`. A
+    /// warning is logged when the layer is applied.
+    #[cfg(feature = "dev-insecure")]
+    #[must_use]
+    pub fn dev_identity(mut self, principal: &str) -> Self {
+        self.config.dev_identity = Some(principal.to_owned());
+        self.config.warned = Arc::new(Once::new());
+        self
+    }
+    /// The body of the `401 Unauthorized` responses continuing a handshake, `continue` by default
+    ///
+    /// Some proxies replace short error bodies with error pages, mangling the response. An empty body is sent with
+    /// `Content-Length: 0`.
+    #[must_use]
+    pub fn continue_body(mut self, body: impl Into<Bytes>) -> Self {
+        self.config.continue_body = body.into();
+        self
+    }
+    /// Pass the `401 Unauthorized` responses continuing a handshake through `map`, e.g. to add headers
+    ///
+    /// The `WWW-Authenticate` header carrying the server's token is restored verbatim afterwards, as the client can't
+    /// continue the handshake without it.
+    #[must_use]
+    pub fn map_continue_response(mut self, map: impl Fn(Response) -> Response + Send + Sync + 'static) -> Self {
+        self.config.map_continue_response = Some(Arc::new(map));
+        self
+    }
+    /// How `WWW-Authenticate` challenges are emitted on all responses of this layer
+    #[must_use]
+    pub fn challenge_style(mut self, style: ChallengeStyle) -> Self {
+        self.config.challenge_style = style;
+        self
+    }
+    /// Which schemes to challenge unauthenticated requests with, in this order
+    ///
+    /// Some clients pick the first scheme they support, so the order steers e.g. a client capable of both towards
+    /// `NTLM` or `Negotiate`. Schemes not listed aren't challenged with, duplicates after the first are ignored.
+    /// Defaults to only [`Scheme::Negotiate`]. The order applies to all challenges starting a handshake, while a
+    /// handshake that is in progress continues with the scheme the client chose.
+    ///
+    /// With [`Scheme::Ntlm`], tokens are also accepted with the `NTLM` scheme and handled like those of `Negotiate`,
+    /// but answered with `NTLM` challenges. With [`Scheme::Basic`], `Basic` credentials of any client are verified with
+    /// the callback of [`NegotiateLayer::basic_auth`], without which it has no effect.
+    #[must_use]
+    pub fn scheme_order(mut self, schemes: &[Scheme]) -> Self {
+        let mut order = Vec::new();
+        for scheme in schemes {
+            if !order.contains(scheme) {
+                order.push(*scheme);
+            }
         }
+        self.config.token_headers.ntlm = order.contains(&Scheme::Ntlm);
+        self.config.scheme_order = order;
+        self
+    }
+    /// Redirect browsers that didn't authenticate to `uri`, e.g. a login page, once `after_attempts` challenges have
+    /// been issued on their connection
+    ///
+    /// Browsers outside the domain would otherwise end up on the body of the `401`. Only requests preferring
+    /// `text/html` in their `Accept` header are redirected, so API clients keep getting challenged. The redirect is a
+    /// `302` to `uri` with the path and query of the original request in the [`RETURN_TO_PARAMETER`] query parameter.
+    ///
+    /// Challenges count towards `after_attempts` whether the request carried no token or its handshake failed, and the
+    /// count starts over once the connection authenticated. With `0`, such browsers are never challenged, so they
+    /// can't attempt to negotiate at all. Only `401 Unauthorized` challenges are counted and redirected, while other
+    /// failures, e.g. a `403 Forbidden` with [`NegotiateLayer::forbid_failed_handshakes`] or server errors, are
+    /// answered as usual. A redirect replacing a failed handshake carries its [`NegotiateFailure`].
+    #[must_use]
+    pub fn failure_redirect(mut self, uri: Uri, after_attempts: u8) -> Self {
+        self.config.failure_redirect = Some(FailureRedirect { uri, after_attempts });
+        self
+    }
+    /// Only challenge unauthenticated requests matching `policy` with `Negotiate`, e.g. those from the intranet
+    ///
+    /// Challenges make some clients on the internet prompt for credentials, and reveal that Kerberos is used. Requests
+    /// not matching `policy` are answered with a `401 Unauthorized` without the challenge, or redirected with
+    /// [`NegotiateLayer::failure_redirect`]. Requests carrying a token are processed regardless of `policy`, only the
+    /// challenge after a failed handshake is left out.
+    #[must_use]
+    pub fn challenge_policy(mut self, policy: ChallengePolicy) -> Self {
+        self.config.challenge_policy = Some(policy);
+        self
+    }
+    /// What to do when a handshake authenticates another client than the one last authenticated on its connection
+    ///
+    /// Connections authenticate again once their session expired, see [`NegotiateLayer::max_session_age`], and with
+    /// every request with [`NegotiateLayer::stateless`]. On shared machines, another user may complete that handshake.
+    /// Clients are compared by their principal with the realm in upper case, or case-insensitively for Windows names.
+    /// Clients authenticated by their [`SessionIdentity`] count as well. Every change is logged as a warning and counted
+    /// by [`LayerStats::identity_changes`], and by default the new client is accepted. Changes rejected by `policy` are
+    /// also counted as [`FailureReason::IdentityChanged`].
+    #[must_use]
+    pub fn identity_change_policy(mut self, policy: IdentityChangePolicy) -> Self {
+        self.config.identity_change_policy = policy;
+        self
+    }
+    /// Reject computer accounts with `403 Forbidden` once their handshake completed, instead of passing their requests
+    /// on
+    ///
+    /// The client's principal is normalized like for [`NegotiateLayer::identity_change_policy`] and matched with
+    /// [`is_machine_account`], or the predicate of [`NegotiateLayer::machine_account_pattern`]. Rejections are counted
+    /// as [`FailureReason::MachineAccount`].
+    #[must_use]
+    pub fn reject_machine_accounts(mut self, reject: bool) -> Self {
+        self.config.reject_machine_accounts = reject;
+        self
+    }
+    /// Recognize computer accounts by `is_machine_account` instead of [`is_machine_account`], for environments with
+    /// unusual naming, see [`NegotiateLayer::reject_machine_accounts`]
+    #[must_use]
+    pub fn machine_account_pattern(
+        mut self,
+        is_machine_account: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.config.is_machine_account = Arc::new(is_machine_account);
+        self
+    }
+    /// Authenticate connections with a verified TLS client certificate as well, as the client `mapper` maps it to
+    ///
+    /// The certificate must have been recorded on the connection with [`NegotiateInfo::with_client_cert`], and is only
+    /// used if it is [verified](ClientCertificate::verified). Requests authenticated by it report
+    /// [`Mech::ClientCertificate`] as [`Authenticated::mechanism`]. Whether a request also carrying a `Negotiate` token
+    /// is authenticated by the token or the certificate is decided by [`CertIdentityMapper::precedence`]. Connections
+    /// without a certificate, or one `mapper` doesn't map, negotiate as usual.
+    #[must_use]
+    pub fn or_client_cert(mut self, mapper: CertIdentityMapper) -> Self {
+        self.config.client_cert = Some(mapper);
+        self
+    }
+    /// Authenticate requests carrying a [`SessionIdentity`] as its client, instead of negotiating
+    ///
+    /// This lets a session middleware in front of the layer keep clients signed in with a cookie after their first
+    /// handshake, see [`SessionIdentity`] for the pattern. Such requests report [`Mech::Session`] as
+    /// [`Authenticated::mechanism`]. Requests carrying a `Negotiate` token, and those of connections that are
+    /// authenticated already, are handled as usual.
+    #[must_use]
+    pub fn or_session(mut self, accept: bool) -> Self {
+        self.config.session_identity = accept;
+        self
+    }
+    /// Whether handshakes are bound to the TLS channel of their connection (Extended Protection for Authentication),
+    /// [`EpaPolicy::WhenSupported`] by default
+    ///
+    /// The channel bindings must have been recorded on the connection with [`NegotiateInfo::with_channel`], e.g. from
+    /// kenobi's `Channel` implementations for TLS streams, which compute `tls-server-end-point` over the server's leaf
+    /// certificate like IIS does. The backend checks the client's bindings against them, on Windows by passing them to
+    /// `AcceptSecurityContext` as `SEC_CHANNEL_BINDINGS`.
+    ///
+    /// With [`EpaPolicy::Required`], handshakes on connections without bindings fail with `401 Unauthorized`, as do NTLM
+    /// clients that send none, see [`NtlmPolicy::require_channel_bindings`]. Kerberos clients send their bindings in
+    /// the encrypted authenticator, so a Kerberos client sending none is only refused if the backend is configured to
+    /// require them.
+    #[must_use]
+    pub fn extended_protection(mut self, policy: EpaPolicy) -> Self {
+        self.config.extended_protection = policy;
+        self
+    }
+    /// Read the client's tokens from `header` instead of `Authorization`
+    ///
+    /// For gateways that reserve `Authorization` for their own credentials and forward the client's token in another
+    /// header. See [`NegotiateLayer::prefixed`] for headers carrying the bare token. `Basic` credentials, see
+    /// [`NegotiateLayer::basic_auth`], are still read from `Authorization`.
+    #[must_use]
+    pub fn token_header(mut self, header: HeaderName) -> Self {
+        self.config.token_headers.token = header;
+        self
+    }
+    /// Send the server's challenges and tokens in `header` instead of `WWW-Authenticate`
+    ///
+    /// The counterpart of [`NegotiateLayer::token_header`], used for every challenge of the handshake, including the
+    /// final token, see [`NegotiateLayer::suppress_final_token`]. `Basic` challenges stay in `WWW-Authenticate`.
+    #[must_use]
+    pub fn challenge_header(mut self, header: HeaderName) -> Self {
+        self.config.token_headers.challenge = header;
+        self
+    }
+    /// Whether tokens in both directions are prefixed with the `Negotiate` scheme, `true` by default
+    ///
+    /// Without the prefix, the headers of [`NegotiateLayer::token_header`] and [`NegotiateLayer::challenge_header`]
+    /// carry the bare base64 token. The challenge starting a handshake carries no token, so it is `Negotiate` either way.
+    #[must_use]
+    pub fn prefixed(mut self, prefixed: bool) -> Self {
+        self.config.token_headers.prefixed = prefixed;
+        self
+    }
+    /// Verify `Basic` credentials of the clients selected by [`NegotiateLayer::basic_for_user_agents`] with `verify`
+    ///
+    /// `verify` is called with the user name and password of every request of such a client and returns whether they
+    /// are valid. The user name becomes the [`client`](Authenticated::client) of the request. As there is no security
+    /// context, [`Authenticated::is_released`] is always `true` for these requests and the other accessors return
+    /// [`None`]. The password is sent in the clear with every request, so this should only be used over TLS.
+    ///
+    /// # Panics
+    ///
+    /// If `realm` contains characters that aren't allowed in a header value
+    #[must_use]
+    pub fn basic_auth(mut self, realm: &str, verify: impl Fn(&str, &str) -> bool + Send + Sync + 'static) -> Self {
+        self.config.basic_auth = Some(BasicAuth::new(realm, verify));
+        self
+    }
+    /// Challenge clients whose `User-Agent` contains any of `user_agents` with only `Basic`
+    ///
+    /// Meant for automated clients like monitoring agents that can't do SPNEGO. Their requests are authenticated with
+    /// the callback of [`NegotiateLayer::basic_auth`] on every request, instead of the connection's handshake, and a
+    /// failed verification is answered like a failed handshake (see [`NegotiateLayer::forbid_failed_handshakes`]). All
+    /// other clients are challenged with `Negotiate` as usual. Has no effect without [`NegotiateLayer::basic_auth`].
+    ///
+    /// The `User-Agent` is chosen by the client, so any client can opt into `Basic` this way.
+    #[must_use]
+    pub fn basic_for_user_agents(mut self, user_agents: Vec<String>) -> Self {
+        self.config.basic_user_agents = user_agents;
+        self
+    }
+    /// Find the [`NegotiateInfo`] in a [`Negotiated<C>`] connect info
+    ///
+    /// The layer then adds the [`NegotiateInfo`] to the request extensions, so that it and [`Authenticated`] can be
+    /// extracted as usual by the routes below it.
+    #[must_use]
+    pub fn with_connect_info<C: Send + Sync + 'static>(mut self) -> Self {
+        self.config.connect_info = Some(Negotiated::<C>::lookup);
+        self
+    }
+    /// How this layer and the [`Authenticated`] extractor of the routes below deal with a misconfigured router
+    ///
+    /// Defaults to [`MisusePolicy::Panic`]. Routes outside of this layer use the [`MisusePolicy`] extension instead.
+    #[must_use]
+    pub fn misuse_policy(mut self, policy: MisusePolicy) -> Self {
+        self.config.misuse_policy = Some(policy);
+        self
+    }
+    /// Count handshakes and connections of this layer in `stats`
+    ///
+    /// The same stats may be given to several layers to aggregate them.
+    #[must_use]
+    pub fn with_stats(mut self, stats: &LayerStats) -> Self {
+        self.config.stats = Some(stats.clone());
+        self
+    }
+    /// Keep the authenticated contexts of at most `max` connections, evicting the least recently used beyond that
+    ///
+    /// A connection whose context was evicted is unauthenticated again: its next request without an `Authorization`
+    /// header is challenged, so the client has to go through a new handshake. Clients that only authenticate once
+    /// per connection (like most browsers) may see a `401` on a request they expected to succeed, so this is meant for
+    /// memory-constrained deployments that prefer re-authentication over unbounded state.
+    ///
+    /// The limit applies to all services created from this layer, and contexts of closed connections don't count
+    /// towards it. It has no effect with [`NegotiateLayer::stateless`], which doesn't keep contexts. At least one
+    /// context is kept.
+    #[must_use]
+    pub fn max_cached_contexts(mut self, max: usize) -> Self {
+        self.config.context_cache = Some(ContextCache::new(max));
+        self
+    }
+    /// Track authenticated connections with `drainer` and refuse new handshakes once it is draining
+    #[cfg(feature = "drain")]
+    #[must_use]
+    pub fn with_drainer(mut self, drainer: &Drainer) -> Self {
+        self.config.drainer = Some(drainer.clone());
+        self
     }
 }
 impl<S> Layer<S> for NegotiateLayer {
     type Service = NegotiateMiddleware<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        NegotiateMiddleware::new(inner, self.spn.as_deref())
+        self.config.warned.call_once(|| self.config.warn_configuration());
+        #[cfg(feature = "drain")]
+        if let (Some(drainer), Some(sink)) = (&self.config.drainer, &self.config.sink) {
+            drainer.add_sink(sink);
+        }
+        #[cfg(feature = "spn-file")]
+        if let Some(file) = &self.config.spn_file {
+            file.watch(self.config.spn_file_poll_interval, self.config.sink.clone());
+        }
+        NegotiateMiddleware {
+            inner,
+            config: self.config.clone(),
+        }
     }
 }
 #[derive(Clone)]
@@ -225,17 +1845,832 @@ impl<S> Layer<S> for NegotiateLayer {
 ///
 /// A layer may be made from this via [`NegotiateLayer::new`]
 ///
-/// This middleware will not work without the [`NegotiateInfo`] [`ConnectInfo`] object.
-/// If there is no such connection information set, this middleware will panic.
+/// This middleware will not work without the [`NegotiateInfo`], given as [`ConnectInfo`] or request extension.
+/// If there is no such connection information set, this middleware will panic, unless configured otherwise with
+/// [`NegotiateLayer::misuse_policy`].
 pub struct NegotiateMiddleware<S> {
     inner: S,
-    spn: Option<String>,
+    config: NegotiateConfig,
 }
 impl<S> NegotiateMiddleware<S> {
     #[must_use]
     pub fn new(service: S, spn: Option<&str>) -> NegotiateMiddleware<S> {
-        let spn = spn.map(ToOwned::to_owned);
-        NegotiateMiddleware { inner: service, spn }
+        NegotiateLayer::new(spn).layer(service)
+    }
+    /// See [`NegotiateLayer::suppress_final_token`]
+    #[must_use]
+    pub fn suppress_final_token(mut self, suppress: bool) -> Self {
+        self.config.suppress_final_token = suppress;
+        self
+    }
+    /// Builds the [`Authenticated`] extension for a request on an authenticated connection
+    ///
+    /// Strips the forwarded user header if the connection isn't one of the trusted proxies.
+    fn authenticated(
+        &self,
+        shared: &SharedContext,
+        identity: &Arc<EstablishedIdentity>,
+        headers: &mut HeaderMap,
+    ) -> Authenticated {
+        Authenticated {
+            context: Arc::downgrade(shared),
+            _owned: self.config.stateless.then(|| shared.clone()),
+            identity: identity.clone(),
+            forwarded_client: self.forwarded_client(&identity.client, headers),
+        }
+    }
+    /// The user forwarded by `transport_client`, see [`NegotiateLayer::trust_forwarded_user`]
+    ///
+    /// Strips the forwarded user header if `transport_client` isn't one of the trusted proxies.
+    fn forwarded_client(&self, transport_client: &str, headers: &mut HeaderMap) -> Option<String> {
+        let forwarded = self.config.forwarded_user.as_ref()?;
+        if !forwarded.proxies.iter().any(|proxy| proxy == transport_client) {
+            if headers.remove(&forwarded.header).is_some() {
+                event!(
+                    self.config.sink(),
+                    Warn,
+                    "Removed forwarded user header from untrusted client",
+                    client = transport_client,
+                );
+            }
+            return None;
+        }
+        let value = headers.get(&forwarded.header)?.to_str().ok()?;
+        event!(
+            self.config.sink(),
+            Debug,
+            "Using forwarded user",
+            proxy = transport_client,
+            client = value
+        );
+        Some(value.to_owned())
+    }
+    /// The trailers to append to the response, if enabled and accepted by the client
+    fn trailers(&self, headers: &HeaderMap, authenticated: &Authenticated) -> Option<HeaderMap> {
+        if !self.config.auth_trailers || !trailers::accepts_trailers(headers) {
+            return None;
+        }
+        let identity = &authenticated.identity;
+        let client = authenticated.forwarded_client.as_ref().unwrap_or(&identity.client);
+        Some(trailers::auth_trailers(client, identity.mechanism.as_ref()))
+    }
+    /// Marks a request as authenticated by `authenticated` before it is passed on
+    ///
+    /// Sets [`NegotiateLayer::forward_identity_header`], replacing whatever the client sent.
+    fn forward(&self, parts: &mut Parts, authenticated: &Authenticated) {
+        if let Some(header) = &self.config.identity_header {
+            let client = authenticated
+                .forwarded_client
+                .as_ref()
+                .unwrap_or(&authenticated.identity.client);
+            match HeaderValue::from_str(client) {
+                Ok(value) => {
+                    parts.headers.insert(header, value);
+                }
+                Err(_) => {
+                    event!(
+                        self.config.sink(),
+                        Warn,
+                        "Client name is not a valid header value, not forwarding it",
+                        client = client
+                    );
+                    parts.headers.remove(header);
+                }
+            }
+        }
+        parts.extensions.insert(authenticated.clone());
+        parts.extensions.insert(NegotiateProgress::Authenticated);
+    }
+    /// The client of the request's [`SessionIdentity`] with [`NegotiateLayer::or_session`], unless it carries a token
+    fn session_client(&self, parts: &Parts) -> Option<String> {
+        if !self.config.session_identity || matches!(self.config.token_headers.token(&parts.headers), Some(Ok(_))) {
+            return None;
+        }
+        let SessionIdentity(client) = parts.extensions.get::<SessionIdentity>()?;
+        event!(self.config.sink(), Debug, "Authenticated by session", client = client);
+        Some(client.clone())
+    }
+    /// The client authenticated by the connection's certificate with [`NegotiateLayer::or_client_cert`], if `precedence`
+    /// is the configured one
+    fn cert_client(
+        &self,
+        cert: Option<&ClientCertificate>,
+        headers: &HeaderMap,
+        precedence: CertPrecedence,
+    ) -> Option<String> {
+        let mapper = self
+            .config
+            .client_cert
+            .as_ref()
+            .filter(|mapper| mapper.precedence == precedence)?;
+        let cert = cert?;
+        if precedence == CertPrecedence::Token && matches!(self.config.token_headers.token(headers), Some(Ok(_))) {
+            return None;
+        }
+        if !cert.is_verified() {
+            event!(self.config.sink(), Debug, "Ignoring unverified client certificate");
+            return None;
+        }
+        let Some(client) = mapper.client(cert) else {
+            event!(self.config.sink(), Debug, "Client certificate isn't mapped to a client");
+            return None;
+        };
+        event!(
+            self.config.sink(),
+            Debug,
+            "Client certificate authentication succeeded",
+            client = client
+        );
+        if let Some(stats) = &self.config.stats {
+            stats.record_success();
+        }
+        Some(client)
+    }
+    /// Compares the client of a completed handshake with the one last authenticated on the connection
+    ///
+    /// Returns the response rejecting the handshake according to [`NegotiateLayer::identity_change_policy`], or else
+    /// records the client as the last one.
+    fn check_identity_change(
+        &self,
+        last_client: &Mutex<Option<String>>,
+        client: &str,
+        leg: u8,
+        version: Version,
+    ) -> Option<Response> {
+        let client = identity::normalized_principal(client);
+        let mut last = last_client.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(old) = last.as_ref().filter(|old| **old != client) {
+            let policy = self.config.identity_change_policy;
+            event!(
+                self.config.sink(),
+                Warn,
+                "Another client authenticated on the connection",
+                old = old,
+                new = client,
+                policy = Debugged(policy)
+            );
+            if let Some(stats) = &self.config.stats {
+                stats.record_identity_change();
+            }
+            if policy != IdentityChangePolicy::Replace {
+                self.config
+                    .record_failure(FailureReason::IdentityChanged, Some(&client));
+                let mut response = forbidden("another client authenticated on the connection");
+                // HTTP/2 has no `Connection` header, its clients authenticate again on the same connection
+                if policy == IdentityChangePolicy::CloseConnection && version < Version::HTTP_2 {
+                    response
+                        .headers_mut()
+                        .insert(CONNECTION, HeaderValue::from_static("close"));
+                }
+                return Some(failed(response, FailureReason::IdentityChanged, leg));
+            }
+        }
+        *last = Some(client);
+        None
+    }
+    /// The response rejecting `client` with [`NegotiateLayer::reject_machine_accounts`], if it is a computer account
+    fn check_machine_account(&self, client: &str, leg: u8) -> Option<Response> {
+        let normalized = identity::normalized_principal(client);
+        if !self.config.reject_machine_accounts || !(self.config.is_machine_account)(&normalized) {
+            return None;
+        }
+        event!(self.config.sink(), Warn, "Rejecting machine account", client = client);
+        self.config.record_failure(FailureReason::MachineAccount, Some(client));
+        let response = forbidden("machine accounts are not allowed");
+        Some(failed(response, FailureReason::MachineAccount, leg))
+    }
+    /// Checks a completed handshake against the configured policies and establishes the context
+    #[allow(clippy::too_many_arguments)]
+    fn finish(
+        &self,
+        auth: &NegotiateConnection,
+        last_client: &Mutex<Option<String>>,
+        headers: &mut HeaderMap,
+        mut context: ServerContext<Inbound>,
+        last_token: Option<Box<[u8]>>,
+        client_token: Vec<u8>,
+        handshake: Handshake,
+        first_leg: bool,
+        version: Version,
+    ) -> (NegotiateState, Round) {
+        if first_leg
+            && let Some(cache) = &self.config.replay_cache
+            && !cache.check_and_insert(&client_token, Instant::now() + self.config.replay_window)
+        {
+            event!(self.config.sink(), Warn, "Rejecting replayed token");
+            let client = context.client_name().to_string();
+            self.config.record_failure(FailureReason::Replay, Some(&client));
+            let response = failed(
+                unauthorized(&self.config, "replayed token"),
+                FailureReason::Replay,
+                handshake.legs,
+            );
+            return (
+                State::Unauthorized,
+                Round::Respond(response, NegotiateProgress::ChallengeIssued),
+            );
+        }
+        let client = context.client_name().to_string();
+        if let Some(response) = self.check_machine_account(&client, handshake.legs) {
+            return (
+                State::Unauthorized,
+                Round::Respond(response, NegotiateProgress::ChallengeIssued),
+            );
+        }
+        if let Some(response) = self.check_identity_change(last_client, &client, handshake.legs, version) {
+            return (
+                State::Unauthorized,
+                Round::Respond(response, NegotiateProgress::ChallengeIssued),
+            );
+        }
+        let handshake_duration = handshake.started.elapsed();
+        event!(
+            self.config.sink(),
+            Debug,
+            "Handshake complete",
+            duration = Debugged(handshake_duration),
+            rounds = handshake.legs
+        );
+        if let Some(callback) = &self.config.on_handshake_complete {
+            callback(handshake_duration);
+        }
+        if let Some(stats) = &self.config.stats {
+            stats.record_success();
+            stats.record_rounds(handshake.legs);
+        }
+        let final_token = last_token.filter(|_| !self.config.suppress_final_token);
+        let ntlm = NtlmDetails::from_token(&client_token);
+        let mechanism = if ntlm.is_some() {
+            Some(Mech::Ntlm)
+        } else {
+            handshake
+                .offered
+                .as_ref()
+                .filter(|offered| offered.offers_kerberos())
+                .map(|_| Mech::Kerberos)
+        };
+        let (cache_entry, evicted) = match &self.config.context_cache {
+            Some(cache) if !self.config.stateless => {
+                let (entry, evicted) = cache.insert(auth);
+                (Some(entry), evicted)
+            }
+            _ => (None, Vec::new()),
+        };
+        let expires = self.config.max_session_age.map(|age| self.config.clock.now() + age);
+        let identity = Arc::new(EstablishedIdentity {
+            client: context.client_name().to_string(),
+            mechanism,
+            ntlm,
+            rounds: handshake.legs,
+        });
+        let shared = Arc::new(Mutex::new(AuthenticatedContext {
+            _context: context,
+            identity: identity.clone(),
+            client_token,
+            #[cfg(feature = "drain")]
+            _drain_guard: self.config.drainer.as_ref().map(Drainer::guard),
+            _stats_gauge: self.config.stats.as_ref().map(LayerStats::authenticated_guard),
+            cache_entry,
+            expires,
+        }));
+        let extension = self.authenticated(&shared, &identity, headers);
+        let trailers = self.trailers(headers, &extension);
+        let round = Round::Authenticated {
+            extension,
+            trailers,
+            final_token,
+            evicted,
+        };
+        // In stateless mode, the extension owns the context while the connection stays unauthorized
+        if self.config.stateless {
+            (State::Unauthorized, round)
+        } else {
+            (State::Authenticated(shared), round)
+        }
+    }
+    /// Steps the handshake of `auth` with the client's `token`, shared by the middleware and [`NegotiateHandshake`]
+    ///
+    /// Returns [`None`] if the connection has been authenticated in the meantime.
+    #[allow(clippy::too_many_arguments)]
+    fn handshake_round(
+        &self,
+        auth: &NegotiateConnection,
+        last_client: &Mutex<Option<String>>,
+        channel: Option<&ChannelBindings>,
+        spn_override: Option<&Spn>,
+        sni: Option<&str>,
+        host: Option<&str>,
+        headers: &mut HeaderMap,
+        token: &NegotiateToken,
+        persistence: Persistence,
+        version: Version,
+    ) -> Option<Round> {
+        let persistence = if self.config.non_persistent_transport {
+            Persistence::Transport
+        } else {
+            persistence
+        };
+        let token_hash = token_hash(token);
+        let ntlm_scheme = self.config.token_headers.uses_ntlm_scheme(headers);
+        let correlation_id = self.config.correlation_id(headers).map(str::to_owned);
+        let mut round = auth.round(|pending| {
+            let first_leg = pending.is_none();
+            let mut handshake = Handshake::new(correlation_id);
+            let step_result = match pending {
+                // Stepping the same token twice fails, so the retry gets the response the client missed instead
+                Some((context, pending)) if pending.last_token == Some(token_hash) => {
+                    event!(
+                        self.config.sink(),
+                        Debug,
+                        "Repeating the response to a retried handshake token"
+                    );
+                    let response = continue_response(&self.config, context.next_token());
+                    let progress = NegotiateProgress::HandshakeLeg(pending.legs);
+                    return (State::Pending((context, pending)), Round::Respond(response, progress));
+                }
+                Some((context, mut pending)) => {
+                    pending.correlation_id = handshake.correlation_id.take();
+                    handshake = pending;
+                    handle_sspi(context, token, &self.config, &mut handshake, persistence)
+                }
+                None => {
+                    let channel = match self.config.extended_protection {
+                        EpaPolicy::Off => None,
+                        EpaPolicy::WhenSupported => channel,
+                        EpaPolicy::Required => {
+                            let Some(channel) = channel.filter(|channel| channel.0.is_some()) else {
+                                event!(
+                                    self.config.sink(),
+                                    Warn,
+                                    "Refusing handshake on a connection without channel bindings"
+                                );
+                                self.config.record_failure(FailureReason::Policy, None);
+                                let response = failed(
+                                    unauthorized(&self.config, "extended protection required"),
+                                    FailureReason::Policy,
+                                    1,
+                                );
+                                return (
+                                    State::Unauthorized,
+                                    Round::Respond(response, NegotiateProgress::ChallengeIssued),
+                                );
+                            };
+                            Some(channel)
+                        }
+                    };
+                    if persistence != Persistence::KeepAlive
+                        && token
+                            .decode_bounded(self.config.max_token_size)
+                            .ok()
+                            .and_then(|token| InitialToken::parse(&token))
+                            .is_some_and(|offered| !offered.offers_kerberos())
+                    {
+                        event!(
+                            self.config.sink(),
+                            Warn,
+                            "Refusing a handshake without Kerberos on a connection that is closed after the response",
+                            persistence = Debugged(persistence)
+                        );
+                        self.config.record_failure(FailureReason::Policy, None);
+                        let response = failed(self.config.non_persistent(persistence), FailureReason::Policy, 1);
+                        return (
+                            State::Unauthorized,
+                            Round::Respond(response, NegotiateProgress::ChallengeIssued),
+                        );
+                    }
+                    let override_spn = spn_override.map(|spn| {
+                        event!(
+                            self.config.sink(),
+                            Trace,
+                            "Selected SPN by request extension",
+                            spn = spn
+                        );
+                        AcceptorName::from(spn.clone())
+                    });
+                    let sni_spn = self
+                        .config
+                        .sni_spns
+                        .as_ref()
+                        .filter(|_| override_spn.is_none())
+                        .map(|spns| spns.select(sni, token, self.config.max_token_size, self.config.sink()));
+                    let host_spn = self
+                        .config
+                        .host_spn_service
+                        .as_ref()
+                        .zip(host)
+                        .map(|(service, host)| AcceptorName::from(Spn::for_host(service, host)));
+                    let configured = self.config.acceptor_name();
+                    let name = override_spn
+                        .as_ref()
+                        .or(sni_spn.as_ref())
+                        .or(host_spn.as_ref())
+                        .or(configured.as_deref());
+                    let cred = match spn::acquire_credentials(name, self.config.sink()) {
+                        Ok(cred) => cred,
+                        Err(e) => {
+                            event!(
+                                self.config.sink(),
+                                Error,
+                                "Failed to create credentials handle",
+                                spn = Debugged(name.map(AcceptorName::backend_name)),
+                                error = Debugged(e)
+                            );
+                            self.config.record_failure(FailureReason::ServerCredentials, None);
+                            let response =
+                                failed(self.config.credentials_failure(), FailureReason::ServerCredentials, 1);
+                            return (
+                                State::Unauthorized,
+                                Round::Respond(response, NegotiateProgress::ServerError),
+                            );
+                        }
+                    };
+                    let builder = ServerBuilder::new_from_credentials(cred).with_mutual_auth();
+                    let builder_with_bindings = if let Some(channel) = channel {
+                        if channel.0.is_some() {
+                            event!(self.config.sink(), Trace, "Adding channel bindings");
+                        } else {
+                            event!(self.config.sink(), Warn, "channel bindings provided but were empty");
+                        }
+                        builder.bind_to_channel(channel).expect("infallible")
+                    } else {
+                        builder
+                    };
+                    handle_sspi(builder_with_bindings, token, &self.config, &mut handshake, persistence)
+                }
+            };
+            handshake.legs = handshake.legs.saturating_add(1);
+            handshake.last_token = Some(token_hash);
+            let legs = handshake.legs;
+            let (state, round) = match step_result {
+                StepResult::Finished {
+                    context,
+                    last_token,
+                    client_token,
+                } => self.finish(
+                    auth,
+                    last_client,
+                    headers,
+                    context,
+                    last_token,
+                    client_token,
+                    handshake,
+                    first_leg,
+                    version,
+                ),
+                StepResult::ContinueWith(server_context, response) => {
+                    if handshake.pending_gauge.is_none() {
+                        handshake.pending_gauge = self.config.stats.as_ref().map(LayerStats::pending_guard);
+                    }
+                    let progress = NegotiateProgress::HandshakeLeg(handshake.legs);
+                    (
+                        State::Pending((server_context, handshake)),
+                        Round::Respond(response, progress),
+                    )
+                }
+                StepResult::Error(response) => (
+                    State::Unauthorized,
+                    Round::Respond(response, NegotiateProgress::ChallengeIssued),
+                ),
+            };
+            if !first_leg
+                && matches!(state, State::Unauthorized)
+                && let Round::Respond(response, _) = &round
+                && resets_handshake(response)
+            {
+                event!(self.config.sink(), Warn, "Pending handshake failed", rounds = legs);
+                if let Some(callback) = &self.config.on_handshake_reset {
+                    callback(legs);
+                }
+            }
+            (state, round)
+        });
+        if ntlm_scheme && let Some(Round::Respond(response, NegotiateProgress::HandshakeLeg(_))) = &mut round {
+            self.config.token_headers.use_ntlm_scheme(response.headers_mut());
+        }
+        round
+    }
+}
+/// Waits for the inner response, adds `authenticated` to its extensions and appends `trailers` to it
+///
+/// With `annotate_errors`, error responses get the client as [`ERROR_CLIENT_HEADER`].
+fn respond_with_trailers<F, E>(
+    future: F,
+    authenticated: Authenticated,
+    trailers: Option<HeaderMap>,
+    annotate_errors: bool,
+) -> BoxFuture<'static, Result<Response, E>>
+where
+    F: Future<Output = Result<Response, E>> + Send + 'static,
+{
+    Box::pin(async move {
+        let mut response = future.await?;
+        let status = response.status();
+        if annotate_errors && (status.is_client_error() || status.is_server_error()) {
+            let client = authenticated
+                .forwarded_client
+                .as_ref()
+                .unwrap_or(&authenticated.identity.client);
+            if let Ok(client) = HeaderValue::from_str(client) {
+                response.headers_mut().insert(ERROR_CLIENT_HEADER, client);
+            }
+        }
+        // For layers outside of this one, e.g. to log the client, see trace::on_response
+        response.extensions_mut().insert(authenticated);
+        Ok(match trailers {
+            Some(trailers) => trailers::with_trailers(response, trailers),
+            None => response,
+        })
+    })
+}
+impl<S> NegotiateMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    /// Answers a request that didn't authenticate its connection with `challenge`, or redirects it, see
+    /// [`NegotiateLayer::failure_redirect`]
+    fn challenge(
+        &self,
+        parts: &Parts,
+        challenged: &AtomicU32,
+        challenge: Response,
+    ) -> BoxFuture<'static, Result<Response, S::Error>> {
+        // Failures answered otherwise, e.g. with `403 Forbidden`, aren't challenges
+        if challenge.status() != StatusCode::UNAUTHORIZED {
+            return self.config.respond(challenge, NegotiateProgress::ChallengeIssued);
+        }
+        if let Some(redirect) = &self.config.failure_redirect
+            && challenged.load(Ordering::Relaxed) >= u32::from(redirect.after_attempts)
+            && redirect::prefers_html(&parts.headers)
+        {
+            event!(
+                self.config.sink(),
+                Debug,
+                "Redirecting browser that didn't authenticate",
+                path = parts.uri.path()
+            );
+            let mut response = redirect.response(&parts.uri);
+            if let Some(failure) = challenge.extensions().get::<NegotiateFailure>() {
+                response.extensions_mut().insert(*failure);
+            }
+            return self.config.respond(response, NegotiateProgress::Redirected);
+        }
+        challenged.fetch_add(1, Ordering::Relaxed);
+        let mut challenge = challenge;
+        if let Some(policy) = &self.config.challenge_policy
+            && !policy.challenges(parts)
+        {
+            event!(
+                self.config.sink(),
+                Trace,
+                "Leaving out the challenge for a request outside the challenge policy"
+            );
+            challenge.headers_mut().remove(&self.config.token_headers.challenge);
+        }
+        self.config.respond(challenge, NegotiateProgress::ChallengeIssued)
+    }
+    /// Authenticates a request of a client selected by [`NegotiateLayer::basic_for_user_agents`]
+    fn basic(
+        &mut self,
+        basic: &BasicAuth,
+        parts: Parts,
+        body: axum::body::Body,
+    ) -> BoxFuture<'static, Result<Response, S::Error>> {
+        let challenge = |status, message: &str, reason: Option<FailureReason>| {
+            let mut response = (status, message.to_owned()).into_response();
+            if let Some(reason) = reason {
+                response = failed(response, reason, 0);
+            }
+            if status == StatusCode::UNAUTHORIZED {
+                let challenge = basic.challenge.clone();
+                self.config.challenge_style.append(response.headers_mut(), &[challenge]);
+            }
+            self.config.respond(response, NegotiateProgress::ChallengeIssued)
+        };
+        let (user, password) = match basic::credentials(&parts.headers, self.config.max_token_size) {
+            Ok(credentials) => credentials,
+            Err(BasicError::Missing) => return challenge(StatusCode::UNAUTHORIZED, "No Authorization given", None),
+            Err(BasicError::Invalid(e)) => {
+                event!(
+                    self.config.sink(),
+                    Debug,
+                    "Invalid Basic Authorization header",
+                    error = e
+                );
+                self.config.record_failure(FailureReason::InvalidHeader, None);
+                return challenge(
+                    StatusCode::UNAUTHORIZED,
+                    "Invalid Authorization Header",
+                    Some(FailureReason::InvalidHeader),
+                );
+            }
+        };
+        if !(basic.verify)(&user, &password) {
+            event!(self.config.sink(), Debug, "Basic authentication failed", client = user);
+            self.config.record_failure(FailureReason::Rejected, Some(&user));
+            let status = if self.config.forbid_failed_handshakes {
+                StatusCode::FORBIDDEN
+            } else {
+                StatusCode::UNAUTHORIZED
+            };
+            return challenge(status, "authentication failed", Some(FailureReason::Rejected));
+        }
+        event!(
+            self.config.sink(),
+            Debug,
+            "Basic authentication succeeded",
+            client = user
+        );
+        if let Some(stats) = &self.config.stats {
+            stats.record_success();
+        }
+        self.forward_as(user, None, parts, body)
+    }
+    /// Authenticates every request as the principal of [`NegotiateLayer::dev_identity`]
+    #[cfg(feature = "dev-insecure")]
+    fn dev(
+        &mut self,
+        client: String,
+        last_client: &Mutex<Option<String>>,
+        parts: Parts,
+        body: axum::body::Body,
+    ) -> BoxFuture<'static, Result<Response, S::Error>> {
+        if let Some(response) = self.check_machine_account(&client, 0) {
+            return self.config.respond(response, NegotiateProgress::ChallengeIssued);
+        }
+        if let Some(response) = self.check_identity_change(last_client, &client, 0, parts.version) {
+            return self.config.respond(response, NegotiateProgress::ChallengeIssued);
+        }
+        if let Some(callback) = &self.config.on_handshake_complete {
+            callback(Duration::ZERO);
+        }
+        if let Some(stats) = &self.config.stats {
+            stats.record_success();
+        }
+        self.forward_as(client, None, parts, body)
+    }
+    /// Passes the request on as made by `client`, without a context on the connection
+    fn forward_as(
+        &mut self,
+        client: String,
+        mechanism: Option<Mech>,
+        mut parts: Parts,
+        body: axum::body::Body,
+    ) -> BoxFuture<'static, Result<Response, S::Error>> {
+        // Requests authenticated by their session are already part of one
+        let establishes_session = mechanism != Some(Mech::Session);
+        let forwarded_client = self.forwarded_client(&client, &mut parts.headers);
+        let authenticated = Authenticated {
+            context: Weak::new(),
+            _owned: None,
+            identity: Arc::new(EstablishedIdentity {
+                client,
+                mechanism,
+                ntlm: None,
+                rounds: 0,
+            }),
+            forwarded_client,
+        };
+        let trailers = self.trailers(&parts.headers, &authenticated);
+        self.forward(&mut parts, &authenticated);
+        let request = Request::from_parts(parts, body);
+        let future = respond_with_trailers(
+            self.inner.call(request),
+            authenticated,
+            trailers,
+            self.config.annotate_errors,
+        );
+        Box::pin(async move {
+            let mut response = future.await?;
+            if establishes_session {
+                response.extensions_mut().insert(session::Established);
+            }
+            Ok(response)
+        })
+    }
+    /// Runs a handshake round for a request the connection, its session or its certificate didn't authenticate
+    #[allow(clippy::too_many_arguments)]
+    fn negotiate(
+        &mut self,
+        mut parts: Parts,
+        body: axum::body::Body,
+        auth: NegotiateConnection,
+        channel: Option<ChannelBindings>,
+        sni: Option<Arc<str>>,
+        served: Arc<AtomicU32>,
+        challenged: Arc<AtomicU32>,
+        last_client: Arc<Mutex<Option<String>>>,
+    ) -> BoxFuture<'static, Result<Response, S::Error>> {
+        let host = request_host(&parts).map(str::to_owned);
+        let spn_override = parts.extensions.get::<SpnOverride>().map(|spn| spn.0.clone());
+        if self.config.host_spn_service.is_some() && host.is_none() && spn_override.is_none() {
+            event!(
+                self.config.sink(),
+                Debug,
+                "Refusing handshake without a host to derive the SPN from"
+            );
+            self.config.record_failure(FailureReason::InvalidHeader, None);
+            let response = (
+                StatusCode::BAD_REQUEST,
+                "Host header required for Negotiate authentication",
+            )
+                .into_response();
+            let response = failed(response, FailureReason::InvalidHeader, 0);
+            return self.config.respond(response, NegotiateProgress::Skipped);
+        }
+        if auth.inspect(|state| matches!(state, State::Pending(_)))
+            && awaits_pending_round(&parts.headers, &self.config)
+        {
+            event!(
+                self.config.sink(),
+                Debug,
+                "Challenging request during a pending handshake"
+            );
+            let response = unauthorized(&self.config, "authentication pending");
+            return self.config.respond(response, NegotiateProgress::ChallengeIssued);
+        }
+        #[cfg(feature = "drain")]
+        if self.config.drainer.as_ref().is_some_and(Drainer::is_draining) {
+            event!(self.config.sink(), Debug, "Refusing handshake while draining");
+            self.config.record_failure(FailureReason::Draining, None);
+            auth.round(|_| (State::Unauthorized, ()));
+            let response = (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response();
+            let response = failed(response, FailureReason::Draining, 0);
+            return self.config.respond(response, NegotiateProgress::Skipped);
+        }
+        // Owned, as the headers are modified when the handshake completes
+        let token = match extract_token(&parts.headers, &self.config) {
+            Ok(token) => token.into_owned(),
+            Err(response) => {
+                return self.challenge(&parts, &challenged, response);
+            }
+        };
+        if self.config.log_raw_tokens {
+            event!(self.config.sink(), Trace, "Raw Negotiate token", token = token.base64());
+        }
+        let persistence = persistence(parts.version, &parts.headers);
+        let outcome = self.handshake_round(
+            &auth,
+            &last_client,
+            channel.as_ref(),
+            spn_override.as_ref(),
+            sni.as_deref(),
+            host.as_deref(),
+            &mut parts.headers,
+            &token,
+            persistence,
+            parts.version,
+        );
+        let Some(outcome) = outcome else {
+            // Another request on this connection completed the handshake in the meantime
+            return self.call(Request::from_parts(parts, body));
+        };
+        let (authenticated, trailers, final_token) = match outcome {
+            Round::Respond(response, NegotiateProgress::ChallengeIssued) => {
+                return self.challenge(&parts, &challenged, response);
+            }
+            Round::Respond(response, progress) => return self.config.respond(response, progress),
+            Round::Authenticated {
+                extension,
+                trailers,
+                final_token,
+                evicted,
+            } => {
+                for evicted in evicted {
+                    evicted.evict(self.config.sink());
+                }
+                (extension, trailers, final_token)
+            }
+        };
+        // Counting starts over with every handshake
+        served.store(1, Ordering::Relaxed);
+        challenged.store(0, Ordering::Relaxed);
+        self.forward(&mut parts, &authenticated);
+        let request = Request::from_parts(parts, body);
+        let next_future = respond_with_trailers(
+            self.inner.call(request),
+            authenticated,
+            trailers,
+            self.config.annotate_errors,
+        );
+        let challenge_style = self.config.challenge_style;
+        let token_headers = self.config.token_headers.clone();
+        let prevent_caching = self.config.prevent_caching;
+        Box::pin(async move {
+            let mut response = next_future.await?;
+            response.extensions_mut().insert(session::Established);
+            // RFC 4559 sends the final token with the successful response. On e.g. a 401 of the inner service, clients
+            // would take it for the start of a new handshake.
+            if let Some(token) = final_token.filter(|_| response.status().is_success()) {
+                token_headers.append_challenge(challenge_style, response.headers_mut(), Some(&token));
+                if prevent_caching {
+                    response
+                        .headers_mut()
+                        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+                }
+            }
+            Ok(response)
+        })
     }
 }
 impl<S> Service<Request> for NegotiateMiddleware<S>
@@ -251,115 +2686,275 @@ where
     }
     fn call(&mut self, req: Request) -> Self::Future {
         let (mut parts, body) = req.into_parts();
-        let (auth, channel) = get_state_from_extension(&parts);
-        let mut lock = auth.lock().unwrap();
-        if lock.deref().is_authenticated() {
-            let request = Request::from_parts(parts, body);
-            return Box::pin(self.inner.call(request));
+        if let Some(policy) = self.config.misuse_policy {
+            parts.extensions.insert(policy);
         }
-        let token = match extract_token(&parts.headers) {
-            Ok(token) => token,
-            Err(response) => {
-                return Box::pin(async { Ok(response) });
-            }
+        let info = try_negotiate_info(&parts).or_else(|| {
+            let info = self.config.connect_info.and_then(|lookup| lookup(&parts))?;
+            parts.extensions.insert(info.clone());
+            Some(info)
+        });
+        let Some(NegotiateInfo {
+            auth,
+            channel,
+            sni,
+            client_cert,
+            served,
+            challenged,
+            last_client,
+            sink: info_sink,
+        }) = info
+        else {
+            let rejection = AuthenticatedRejection::MissingNegotiateInfo;
+            let mut response = misuse(MisusePolicy::of(&parts), &rejection.to_string(), self.config.sink());
+            response.extensions_mut().insert(NegotiateProgress::Skipped);
+            return Box::pin(async { Ok(response) });
         };
-        let step_result = match std::mem::take(&mut *lock) {
-            NegotiateState::Authenticated(_) => unreachable!(),
-            NegotiateState::Pending(context) => handle_sspi(context, token),
-            NegotiateState::Unauthorized => {
-                #[cfg(feature = "tracing")]
-                tracing::debug!(spn = self.spn.as_deref(), "Getting local SPNEGO credentials");
-                let cred = match Credentials::inbound(self.spn.as_deref(), Mechanism::Spnego) {
-                    Ok(cred) => cred,
-                    Err(_e) => {
-                        #[cfg(feature = "tracing")]
-                        tracing::error!(error = ?_e, "Failed to create credentials handle");
-                        let response = failed_to_create_context().into_response();
-                        return Box::pin(async move { Ok(response) });
-                    }
-                };
-                let builder = ServerBuilder::new_from_credentials(cred).with_mutual_auth();
-                let builder_with_bindings = if let Some(channel) = channel {
-                    #[cfg(feature = "tracing")]
-                    if channel.0.is_some() {
-                        tracing::trace!("Adding channel bindings");
-                    } else {
-                        tracing::warn!("channel bindings provided but were empty");
-                    }
-                    builder.bind_to_channel(&channel).expect("infallible")
-                } else {
-                    builder
-                };
-                handle_sspi(builder_with_bindings, token)
-            }
-        };
-        match step_result {
-            StepResult::Finished(f, maybe_token) => {
-                parts.extensions.insert(Authenticated(auth.clone()));
-                let request = Request::from_parts(parts, body);
-                let next_future = self.inner.call(request);
-                *lock = NegotiateState::Authenticated(f);
-                Box::pin(async move {
-                    let mut response = next_future.await?;
-                    if let Some(token) = maybe_token {
-                        response
-                            .headers_mut()
-                            .append(WWW_AUTHENTICATE, to_negotiate_header(&token));
-                    }
-                    Ok(response)
-                })
-            }
-            StepResult::ContinueWith(server_context, response) => {
-                *lock = NegotiateState::Pending(server_context);
-                Box::pin(async move { Ok(response) })
+        if let Some(sink) = &self.config.sink {
+            let sink = LayerSink(sink.clone());
+            parts.extensions.insert(sink.clone());
+            *info_sink.lock().unwrap_or_else(PoisonError::into_inner) = Some(sink);
+        }
+        #[cfg(debug_assertions)]
+        self.config.layering.check(
+            &parts,
+            &body,
+            sni.as_deref(),
+            self.config.host_spn_service.is_some(),
+            self.config.sink(),
+        );
+        #[cfg(feature = "dev-insecure")]
+        if let Some(client) = self.config.dev_identity.clone() {
+            return self.dev(client, &last_client, parts, body);
+        }
+        if let Some(basic) = &self.config.basic_auth
+            && (basic::matches_user_agent(&parts.headers, &self.config.basic_user_agents)
+                || self.config.scheme_order.contains(&Scheme::Basic) && basic::is_basic(&parts.headers))
+        {
+            let basic = basic.clone();
+            return self.basic(&basic, parts, body);
+        }
+        if let Some(client) = self.cert_client(client_cert.as_ref(), &parts.headers, CertPrecedence::Certificate) {
+            return self.forward_as(client, Some(Mech::ClientCertificate), parts, body);
+        }
+        if self.config.no_cache && auth.evict_if(|_| true) {
+            event!(
+                self.config.sink(),
+                Debug,
+                "Discarding the connection's authentication, see NegotiateLayer::no_cache"
+            );
+        }
+        let now = self.config.clock.now();
+        if auth.evict_if(|shared| lock_context(shared).expires.is_some_and(|expires| now >= expires)) {
+            event!(self.config.sink(), Debug, "Session expired, authenticating again");
+        }
+        if let Some(max) = self.config.max_requests_per_connection
+            && served.load(Ordering::Relaxed) >= max
+            && auth.evict_if(|_| true)
+        {
+            event!(self.config.sink(), Debug, "Request limit reached, authenticating again");
+            let mut response = unauthorized(&self.config, "request limit reached");
+            if parts.version < Version::HTTP_2 {
+                response
+                    .headers_mut()
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
             }
-            StepResult::Error(response) => {
-                *lock = NegotiateState::Unauthorized;
-                Box::pin(async { Ok(response) })
+            return self.config.respond(response, NegotiateProgress::ChallengeIssued);
+        }
+        let already_authenticated = auth.with_authenticated(|shared| {
+            let identity = {
+                let authenticated = lock_context(shared);
+                if let Some(entry) = &authenticated.cache_entry {
+                    entry.touch();
+                }
+                authenticated.identity.clone()
+            };
+            let extension = self.authenticated(shared, &identity, &mut parts.headers);
+            let trailers = self.trailers(&parts.headers, &extension);
+            (extension, trailers)
+        });
+        // The connection is unlocked again before calling the inner service, which may access it
+        if let Some((authenticated, trailers)) = already_authenticated {
+            served.fetch_add(1, Ordering::Relaxed);
+            self.forward(&mut parts, &authenticated);
+            let request = Request::from_parts(parts, body);
+            return respond_with_trailers(
+                self.inner.call(request),
+                authenticated,
+                trailers,
+                self.config.annotate_errors,
+            );
+        }
+        if let Some(client) = self.cert_client(client_cert.as_ref(), &parts.headers, CertPrecedence::Token) {
+            return self.forward_as(client, Some(Mech::ClientCertificate), parts, body);
+        }
+        if let Some(client) = self.session_client(&parts) {
+            if let Some(response) = self.check_identity_change(&last_client, &client, 0, parts.version) {
+                return self.config.respond(response, NegotiateProgress::ChallengeIssued);
             }
+            return self.forward_as(client, Some(Mech::Session), parts, body);
         }
+        #[cfg(feature = "tracing")]
+        if self.config.correlation_header.is_some() {
+            // The span covers the inner service as well, correlating the events of the handler
+            let correlation_id = self.config.correlation_id(&parts.headers);
+            let span = tracing::info_span!("negotiate", correlation_id);
+            let future =
+                span.in_scope(|| self.negotiate(parts, body, auth, channel, sni, served, challenged, last_client));
+            return Box::pin(tracing::Instrument::instrument(future, span));
+        }
+        self.negotiate(parts, body, auth, channel, sni, served, challenged, last_client)
     }
 }
 
-fn to_negotiate_header(token_bytes: &[u8]) -> HeaderValue {
-    let encoded = BASE64_STANDARD.encode(token_bytes.as_ref());
-    HeaderValue::from_str(&format!("Negotiate {encoded}")).expect("Base64-string should be valid header material")
+/// Outcome of a handshake round, acted upon once the connection is unlocked again
+enum Round {
+    Respond(Response, NegotiateProgress),
+    Authenticated {
+        extension: Authenticated,
+        trailers: Option<HeaderMap>,
+        final_token: Option<Box<[u8]>>,
+        /// Contexts to drop once this connection is unlocked
+        evicted: Vec<cache::Evicted>,
+    },
 }
 
 enum StepResult {
-    Finished(ServerContext<Inbound>, Option<Box<[u8]>>),
+    Finished {
+        context: ServerContext<Inbound>,
+        last_token: Option<Box<[u8]>>,
+        client_token: Vec<u8>,
+    },
     ContinueWith(PendingServerContext<Inbound>, Response),
     Error(Response),
 }
 
 #[allow(clippy::result_large_err)]
-fn extract_token(headers: &HeaderMap) -> Result<&str, Response> {
-    let Some(authorization) = headers.get(AUTHORIZATION) else {
-        return Err(unauthorized("No Authorization given"));
+fn extract_token<'a>(headers: &'a HeaderMap, config: &NegotiateConfig) -> Result<NegotiateToken<'a>, Response> {
+    let Some(token) = config.token_headers.token(headers) else {
+        return Err(unauthorized(config, "No Authorization given"));
     };
-    let s = authorization
-        .to_str()
-        .map_err(|_| unauthorized("Invalid Authorization Header"))?;
-    let Some((prefix, base64)) = s.split_once(' ') else {
-        return Err(unauthorized("Invalid Authorization Header"));
-    };
-    if !prefix.eq_ignore_ascii_case("Negotiate") {
-        return Err(unauthorized("Invalid Authorization Header"));
+    match token {
+        Ok(token) => Ok(token),
+        Err(e) => {
+            event!(config.sink(), Debug, "Invalid Authorization header", error = e);
+            config.record_failure(FailureReason::InvalidHeader, None);
+            let response = unauthorized(config, "Invalid Authorization Header");
+            Err(failed(response, FailureReason::InvalidHeader, 0))
+        }
     }
-    Ok(base64.trim_start())
 }
 
-fn www_authenticate_map() -> HeaderMap {
+/// Short hash of a client token, see [`Handshake::last_token`]
+fn token_hash(token: &NegotiateToken) -> u64 {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    let mut hasher = DefaultHasher::new();
+    token.base64().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The host a request was made to, from the `Host` header or the URI of HTTP/2 requests
+fn request_host(parts: &Parts) -> Option<&str> {
+    parts
+        .headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| parts.uri.host())
+        .filter(|host| !host.is_empty())
+}
+
+/// Whether a request on a connection with a pending handshake carries no token for its next round
+///
+/// Any token is left to the handshake, which continues with it, repeats its response to a retried one, or fails the
+/// pending handshake if it starts a new one or is malformed.
+fn awaits_pending_round(headers: &HeaderMap, config: &NegotiateConfig) -> bool {
+    config.token_headers.token(headers).is_none()
+}
+
+/// Whether the client keeps the connection open after the response, so a handshake can continue on it
+///
+/// Requests with `Connection: close` close it, as do HTTP/1.0 requests without `Connection: keep-alive`.
+fn persistence(version: Version, headers: &HeaderMap) -> Persistence {
+    let options = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim);
+    let (mut close, mut keep_alive) = (false, false);
+    for option in options {
+        close |= option.eq_ignore_ascii_case("close");
+        keep_alive |= option.eq_ignore_ascii_case("keep-alive");
+    }
+    match version {
+        _ if close => Persistence::Close,
+        Version::HTTP_09 | Version::HTTP_10 if !keep_alive => Persistence::Http10,
+        // Connection options don't exist in HTTP/2 and later
+        _ => Persistence::KeepAlive,
+    }
+}
+
+/// Whether a handshake can continue on the connection of a request, see [`NegotiateLayer::non_persistent_response`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Persistence {
+    KeepAlive,
+    /// An HTTP/1.0 request without `Connection: keep-alive`, whose connection HTTP/1.1 would keep open
+    Http10,
+    /// A request with `Connection: close`
+    Close,
+    /// Any request with [`NegotiateLayer::non_persistent_transport`]
+    Transport,
+}
+
+/// The challenges starting a handshake with each of `schemes` in order, leaving out `Basic` without `basic`
+fn www_authenticate_map(
+    style: ChallengeStyle,
+    token_headers: &TokenHeaders,
+    schemes: &[Scheme],
+    basic: Option<&BasicAuth>,
+) -> HeaderMap {
+    let challenges = schemes
+        .iter()
+        .filter_map(|scheme| match scheme {
+            Scheme::Negotiate => Some(HeaderValue::from_static("Negotiate")),
+            Scheme::Ntlm => Some(HeaderValue::from_static("NTLM")),
+            Scheme::Basic => basic.map(|basic| basic.challenge.clone()),
+        })
+        .collect::<Vec<_>>();
     let mut map = HeaderMap::new();
-    map.insert(WWW_AUTHENTICATE, HeaderValue::from_static("Negotiate"));
+    style.append_to(&mut map, &token_headers.challenge, &challenges);
     map.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
     map
 }
 
-fn unauthorized(message: &str) -> Response {
-    (StatusCode::UNAUTHORIZED, www_authenticate_map(), message.to_owned()).into_response()
+fn unauthorized(config: &NegotiateConfig, message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        www_authenticate_map(
+            config.challenge_style,
+            &config.token_headers,
+            &config.scheme_order,
+            config.basic_auth.as_ref(),
+        ),
+        message.to_owned(),
+    )
+        .into_response()
 }
 
-fn failed_to_create_context() -> Response {
-    (StatusCode::INTERNAL_SERVER_ERROR, "internal server error").into_response()
+fn forbidden(message: &str) -> Response {
+    (StatusCode::FORBIDDEN, message.to_owned()).into_response()
+}
+
+/// Marks `response` as answering a failure, see [`NegotiateFailure`]
+fn failed(mut response: Response, reason: FailureReason, leg: u8) -> Response {
+    response.extensions_mut().insert(NegotiateFailure { reason, leg });
+    response
+}
+/// Whether `response` failed the handshake itself, rather than a policy rejecting the client midway
+fn resets_handshake(response: &Response) -> bool {
+    let reason = response
+        .extensions()
+        .get::<NegotiateFailure>()
+        .map(|failure| failure.reason);
+    matches!(reason, Some(FailureReason::WrongContinuation | FailureReason::Rejected))
 }
@@ -9,13 +9,17 @@ use std::{
 };
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
-use crate::NegotiateInfo;
+use crate::{ConnectionStore, NegotiateInfo, Negotiated, NewPerConnection};
 
 /// [`axum::serve::Listener`] extension for a convenient way to create a [`HasNegotiateInfo`]
 pub trait WithNegotiateInfo: Sized + Listener {
     fn with_negotiate_info(self) -> HasNegotiateInfo<Self> {
         HasNegotiateInfo(self)
     }
+    /// Like [`WithNegotiateInfo::with_negotiate_info`], taking the state of each connection from `store`
+    fn with_negotiate_store<S: ConnectionStore>(self, store: S) -> HasNegotiateStore<Self, S> {
+        HasNegotiateStore::new(self, store)
+    }
 }
 impl<T: Listener> WithNegotiateInfo for T {}
 /// [`axum::serve::Listener`] wrapper that provides connection-bound negotiation info.
@@ -31,17 +35,75 @@ where
     fn accept(&mut self) -> impl std::future::Future<Output = (Self::Io, Self::Addr)> + Send {
         self.0
             .accept()
-            .map(|(io, addr)| (Negotiator(io, NegotiateInfo::new()), addr))
+            .map(|(io, addr)| (Negotiator::new(io, NewPerConnection), addr))
     }
     fn local_addr(&self) -> tokio::io::Result<Self::Addr> {
         self.0.local_addr()
     }
 }
+/// [`axum::serve::Listener`] wrapper like [`HasNegotiateInfo`], taking the state of each connection from the
+/// [`ConnectionStore`] `S`
+pub struct HasNegotiateStore<L, S>
+where
+    L: Listener,
+    S: ConnectionStore,
+{
+    listener: L,
+    store: S,
+}
+impl<L, S> HasNegotiateStore<L, S>
+where
+    L: Listener,
+    S: ConnectionStore,
+{
+    #[must_use]
+    pub fn new(listener: L, store: S) -> Self {
+        Self { listener, store }
+    }
+}
+impl<L, S> Listener for HasNegotiateStore<L, S>
+where
+    L: Listener,
+    S: ConnectionStore,
+{
+    type Addr = L::Addr;
+    type Io = Negotiator<L::Io, S>;
+    fn accept(&mut self) -> impl std::future::Future<Output = (Self::Io, Self::Addr)> + Send {
+        let store = self.store.clone();
+        self.listener
+            .accept()
+            .map(move |(io, addr)| (Negotiator::new(io, store), addr))
+    }
+    fn local_addr(&self) -> tokio::io::Result<Self::Addr> {
+        self.listener.local_addr()
+    }
+}
 /// Io Wrapper that carries a specific connection's negotiation information
-pub struct Negotiator<T>(T, NegotiateInfo);
-impl<L> AsyncRead for Negotiator<L>
+///
+/// Dropping it, i.e. closing the connection, releases the connection's state, see [`NegotiateInfo::close`], and
+/// hands it back to its [`ConnectionStore`].
+pub struct Negotiator<T, S: ConnectionStore = NewPerConnection>(T, Option<NegotiateInfo>, S);
+impl<T, S: ConnectionStore> Negotiator<T, S> {
+    fn new(io: T, store: S) -> Self {
+        let info = store.acquire();
+        Self(io, Some(info), store)
+    }
+    fn info(&self) -> &NegotiateInfo {
+        self.1.as_ref().expect("only taken when dropped")
+    }
+}
+impl<T, S: ConnectionStore> Drop for Negotiator<T, S> {
+    fn drop(&mut self) {
+        if let Some(info) = self.1.take() {
+            info.close();
+            self.2.release(info);
+        }
+    }
+}
+impl<L, S> AsyncRead for Negotiator<L, S>
 where
     L: AsyncRead + Unpin,
+    S: ConnectionStore,
 {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -51,9 +113,10 @@ where
         pin!(&mut self.0).poll_read(cx, buf)
     }
 }
-impl<L> AsyncWrite for Negotiator<L>
+impl<L, S> AsyncWrite for Negotiator<L, S>
 where
     L: AsyncWrite + Unpin,
+    S: ConnectionStore,
 {
     fn poll_write(
         mut self: Pin<&mut Self>,
@@ -82,6 +145,42 @@ where
     L: Listener,
 {
     fn connect_info(target: IncomingStream<'_, HasNegotiateInfo<L>>) -> Self {
-        target.io().1.clone()
+        target.io().info().clone()
+    }
+}
+impl<L, C> Connected<IncomingStream<'_, HasNegotiateInfo<L>>> for Negotiated<C>
+where
+    L: Listener,
+    L::Addr: Clone,
+    C: Connected<L::Addr>,
+{
+    fn connect_info(target: IncomingStream<'_, HasNegotiateInfo<L>>) -> Self {
+        Negotiated {
+            inner: C::connect_info(target.remote_addr().clone()),
+            negotiate: target.io().info().clone(),
+        }
+    }
+}
+impl<L, S> Connected<IncomingStream<'_, HasNegotiateStore<L, S>>> for NegotiateInfo
+where
+    L: Listener,
+    S: ConnectionStore,
+{
+    fn connect_info(target: IncomingStream<'_, HasNegotiateStore<L, S>>) -> Self {
+        target.io().info().clone()
+    }
+}
+impl<L, S, C> Connected<IncomingStream<'_, HasNegotiateStore<L, S>>> for Negotiated<C>
+where
+    L: Listener,
+    L::Addr: Clone,
+    S: ConnectionStore,
+    C: Connected<L::Addr>,
+{
+    fn connect_info(target: IncomingStream<'_, HasNegotiateStore<L, S>>) -> Self {
+        Negotiated {
+            inner: C::connect_info(target.remote_addr().clone()),
+            negotiate: target.io().info().clone(),
+        }
     }
 }
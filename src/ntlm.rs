@@ -0,0 +1,218 @@
+use std::fmt::Display;
+
+const SIGNATURE: &[u8] = b"NTLMSSP\0";
+const AUTHENTICATE_MESSAGE: u32 = 3;
+const AUTHENTICATE_HEADER_LENGTH: usize = 64;
+const NT_V1_RESPONSE_LENGTH: usize = 24;
+/// NTProofStr followed by the fixed part of the NTLMv2_CLIENT_CHALLENGE structure
+const NT_V2_AV_PAIRS_OFFSET: usize = 44;
+const NTLMSSP_NEGOTIATE_UNICODE: u32 = 0x0000_0001;
+const NTLMSSP_NEGOTIATE_ANONYMOUS: u32 = 0x0000_0800;
+const NTLMSSP_NEGOTIATE_EXTENDED_SESSIONSECURITY: u32 = 0x0008_0000;
+const MSV_AV_EOL: u16 = 0;
+const MSV_AV_CHANNEL_BINDINGS: u16 = 10;
+
+/// Requirements on NTLM authentications
+///
+/// NTLM is only used when client and server fall back to it instead of Kerberos. The policy is checked against the
+/// client's NTLM `AUTHENTICATE` message before it is handed to the backend, tokens without one are not affected.
+///
+/// The [`Default`] policy is the strictest one that works without TLS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NtlmPolicy {
+    /// Reject NTLMv1 (and LM) responses
+    pub require_v2: bool,
+    /// Reject clients that did not negotiate `NTLMSSP_NEGOTIATE_EXTENDED_SESSIONSECURITY`
+    pub require_ess: bool,
+    /// Reject NTLMv2 responses that don't echo the server's target information
+    pub require_target_info: bool,
+    /// Accept anonymous authentications, which carry neither a user name nor a response
+    pub allow_anonymous: bool,
+    /// Reject NTLMv2 responses without the hash of the TLS channel bindings (`MsvAvChannelBindings`), which clients
+    /// only send over TLS. Also enforced by [`EpaPolicy::Required`](crate::EpaPolicy::Required).
+    pub require_channel_bindings: bool,
+}
+impl Default for NtlmPolicy {
+    fn default() -> Self {
+        Self {
+            require_v2: true,
+            require_ess: true,
+            require_target_info: true,
+            allow_anonymous: false,
+            require_channel_bindings: false,
+        }
+    }
+}
+impl NtlmPolicy {
+    /// Checks a decoded client token against this policy
+    ///
+    /// The token may either be a raw NTLM message or one wrapped in SPNEGO. Tokens that contain no NTLM `AUTHENTICATE`
+    /// message are always accepted.
+    pub fn check(&self, token: &[u8]) -> Result<(), NtlmPolicyViolation> {
+        let Some(message) = find_authenticate_message(token) else {
+            return Ok(());
+        };
+        let message = AuthenticateMessage::parse(message).ok_or(NtlmPolicyViolation::Malformed)?;
+        if message.is_anonymous() {
+            return if self.allow_anonymous {
+                Ok(())
+            } else {
+                Err(NtlmPolicyViolation::Anonymous)
+            };
+        }
+        if self.require_v2 && !message.is_v2() {
+            return Err(NtlmPolicyViolation::NtlmV1);
+        }
+        if self.require_ess && message.flags & NTLMSSP_NEGOTIATE_EXTENDED_SESSIONSECURITY == 0 {
+            return Err(NtlmPolicyViolation::NoExtendedSessionSecurity);
+        }
+        if self.require_target_info && !message.has_target_info() {
+            return Err(NtlmPolicyViolation::NoTargetInfo);
+        }
+        if self.require_channel_bindings && !message.has_channel_bindings() {
+            return Err(NtlmPolicyViolation::NoChannelBindings);
+        }
+        Ok(())
+    }
+}
+
+/// Reason an NTLM authentication was rejected by an [`NtlmPolicy`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NtlmPolicyViolation {
+    Anonymous,
+    NtlmV1,
+    NoExtendedSessionSecurity,
+    NoTargetInfo,
+    NoChannelBindings,
+    Malformed,
+}
+impl Display for NtlmPolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Anonymous => "anonymous NTLM authentication is not allowed",
+            Self::NtlmV1 => "NTLMv1 is not allowed",
+            Self::NoExtendedSessionSecurity => "NTLM extended session security is required",
+            Self::NoTargetInfo => "NTLM target information is required",
+            Self::NoChannelBindings => "NTLM channel bindings are required",
+            Self::Malformed => "malformed NTLM message",
+        })
+    }
+}
+impl std::error::Error for NtlmPolicyViolation {}
+
+/// Client information taken from an NTLM `AUTHENTICATE` message
+///
+/// See [`Authenticated::ntlm_details`](crate::Authenticated::ntlm_details)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NtlmDetails {
+    /// The domain the client authenticated in, as sent by the client
+    pub domain: String,
+    /// The name of the client's machine, as sent by the client
+    pub workstation: String,
+}
+impl NtlmDetails {
+    /// Extracts the details from a decoded client token containing an NTLM `AUTHENTICATE` message
+    ///
+    /// The token may either be a raw NTLM message or one wrapped in SPNEGO.
+    #[must_use]
+    pub fn from_token(token: &[u8]) -> Option<Self> {
+        let message = AuthenticateMessage::parse(find_authenticate_message(token)?)?;
+        let unicode = message.flags & NTLMSSP_NEGOTIATE_UNICODE != 0;
+        Some(Self {
+            domain: decode_string(message.domain, unicode),
+            workstation: decode_string(message.workstation, unicode),
+        })
+    }
+}
+
+/// Locates an NTLM `AUTHENTICATE` message, which may be nested within a SPNEGO `NegTokenResp`
+fn find_authenticate_message(token: &[u8]) -> Option<&[u8]> {
+    let start = token.windows(SIGNATURE.len()).position(|w| w == SIGNATURE)?;
+    let message = &token[start..];
+    (read_u32(message, SIGNATURE.len())? == AUTHENTICATE_MESSAGE).then_some(message)
+}
+
+struct AuthenticateMessage<'a> {
+    nt_response: &'a [u8],
+    domain: &'a [u8],
+    user_name: &'a [u8],
+    workstation: &'a [u8],
+    flags: u32,
+}
+impl<'a> AuthenticateMessage<'a> {
+    fn parse(message: &'a [u8]) -> Option<Self> {
+        if message.len() < AUTHENTICATE_HEADER_LENGTH {
+            return None;
+        }
+        Some(Self {
+            nt_response: read_field(message, 20)?,
+            domain: read_field(message, 28)?,
+            user_name: read_field(message, 36)?,
+            workstation: read_field(message, 44)?,
+            flags: read_u32(message, 60)?,
+        })
+    }
+    fn is_anonymous(&self) -> bool {
+        self.flags & NTLMSSP_NEGOTIATE_ANONYMOUS != 0 || (self.user_name.is_empty() && self.nt_response.is_empty())
+    }
+    fn is_v2(&self) -> bool {
+        self.nt_response.len() > NT_V1_RESPONSE_LENGTH
+    }
+    fn has_target_info(&self) -> bool {
+        self.nt_response
+            .get(NT_V2_AV_PAIRS_OFFSET..NT_V2_AV_PAIRS_OFFSET + 2)
+            .is_some_and(|id| u16::from_le_bytes([id[0], id[1]]) != MSV_AV_EOL)
+    }
+    /// Whether the AV pairs of an NTLMv2 response carry channel bindings, which clients without them send as zeros
+    fn has_channel_bindings(&self) -> bool {
+        let mut pairs = self.nt_response.get(NT_V2_AV_PAIRS_OFFSET..).unwrap_or_default();
+        while let [id_low, id_high, length_low, length_high, rest @ ..] = pairs {
+            let id = u16::from_le_bytes([*id_low, *id_high]);
+            let length = usize::from(u16::from_le_bytes([*length_low, *length_high]));
+            let Some(value) = rest.get(..length) else {
+                return false;
+            };
+            match id {
+                MSV_AV_EOL => return false,
+                MSV_AV_CHANNEL_BINDINGS => return value.iter().any(|&byte| byte != 0),
+                _ => pairs = &rest[length..],
+            }
+        }
+        false
+    }
+}
+
+/// Reads a `(length, max length, offset)` payload reference at `at`
+fn read_field(message: &[u8], at: usize) -> Option<&[u8]> {
+    let length = u16::from_le_bytes(message.get(at..at + 2)?.try_into().ok()?) as usize;
+    let offset = read_u32(message, at + 4)? as usize;
+    message.get(offset..offset.checked_add(length)?)
+}
+
+fn read_u32(message: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(message.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Decodes a string field, which is UTF-16LE if Unicode was negotiated and in the OEM code page otherwise
+// The OEM code page is unknown to the server, so anything outside of ASCII is replaced.
+fn decode_string(field: &[u8], unicode: bool) -> String {
+    if unicode {
+        let units: Vec<u16> = field
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        field
+            .iter()
+            .map(|&b| {
+                if b.is_ascii() {
+                    char::from(b)
+                } else {
+                    char::REPLACEMENT_CHARACTER
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,66 @@
+use axum_core::response::{IntoResponse, Response};
+use http::{
+    HeaderMap, HeaderValue, StatusCode, Uri,
+    header::{ACCEPT, LOCATION},
+};
+
+/// The query parameter carrying the path of the original request, see [`NegotiateLayer::failure_redirect`]
+///
+/// [`NegotiateLayer::failure_redirect`]: crate::NegotiateLayer::failure_redirect
+pub const RETURN_TO_PARAMETER: &str = "return_to";
+
+/// See [`NegotiateLayer::failure_redirect`](crate::NegotiateLayer::failure_redirect)
+#[derive(Clone, Debug)]
+pub(crate) struct FailureRedirect {
+    pub(crate) uri: Uri,
+    pub(crate) after_attempts: u8,
+}
+impl FailureRedirect {
+    /// The redirect for a request to `original`
+    pub(crate) fn response(&self, original: &Uri) -> Response {
+        let path = original.path_and_query().map_or("/", |path| path.as_str());
+        let separator = if self.uri.query().is_some() { '&' } else { '?' };
+        let location = format!("{}{separator}{RETURN_TO_PARAMETER}={}", self.uri, encode(path));
+        let location = HeaderValue::from_str(&location).expect("percent-encoded URIs are valid header values");
+        (StatusCode::FOUND, [(LOCATION, location)]).into_response()
+    }
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Whether the client prefers HTML, as browsers navigating to a page do
+///
+/// That is the case if `text/html` or `application/xhtml+xml` is accepted at least as much as any other specific media
+/// type. Wildcards are ignored, as API clients commonly send `*/*`.
+pub(crate) fn prefers_html(headers: &HeaderMap) -> bool {
+    let (mut html, mut other) = (0.0_f32, 0.0_f32);
+    let ranges = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for range in ranges {
+        let mut parameters = range.split(';').map(str::trim);
+        let media = parameters.next().unwrap_or_default();
+        let quality = parameters
+            .find_map(|parameter| parameter.strip_prefix("q="))
+            .map_or(1.0, |q| q.parse().unwrap_or(0.0));
+        if media.eq_ignore_ascii_case("text/html") || media.eq_ignore_ascii_case("application/xhtml+xml") {
+            html = html.max(quality);
+        } else if !media.ends_with("/*") {
+            other = other.max(quality);
+        }
+    }
+    html > 0.0 && html >= other
+}
@@ -0,0 +1,110 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Remembers single-leg tokens to detect replays
+///
+/// A Kerberos `AP-REQ` that authenticates in a single round can be captured and replayed by anyone within its validity window,
+/// unless the acceptor keeps track of the authenticators it has already seen. The system Kerberos replay cache does this,
+/// but is often unavailable or ineffective (e.g. on ephemeral container filesystems, or with multiple replicas).
+///
+/// See [`NegotiateLayer::replay_cache`](crate::NegotiateLayer::replay_cache)
+pub trait ReplayCache: Send + Sync {
+    /// Records `token` as seen until `expiry`
+    ///
+    /// Returns `false` if the token had already been seen and has not expired yet, i.e. if it is a replay.
+    fn check_and_insert(&self, token: &[u8], expiry: Instant) -> bool;
+}
+
+/// A [`ReplayCache`] that never detects a replay
+///
+/// Only use this if replay protection is handled elsewhere, e.g. by a working system replay cache.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoReplayCache;
+impl ReplayCache for NoReplayCache {
+    fn check_and_insert(&self, _token: &[u8], _expiry: Instant) -> bool {
+        true
+    }
+}
+
+const SHARDS: usize = 16;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An in-process [`ReplayCache`]
+///
+/// Tokens are only stored as hashes. Expired entries are evicted as new tokens come in, so memory use is bounded
+/// by the number of single-leg authentications within the replay window.
+///
+/// This doesn't protect against replays to other processes, e.g. other replicas behind a load balancer.
+#[derive(Debug)]
+pub struct InMemoryReplayCache {
+    hasher: RandomState,
+    shards: [Mutex<Shard>; SHARDS],
+}
+#[derive(Debug)]
+struct Shard {
+    seen: HashMap<u64, Instant>,
+    next_sweep: Instant,
+}
+impl Default for InMemoryReplayCache {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            hasher: RandomState::new(),
+            shards: std::array::from_fn(|_| {
+                Mutex::new(Shard {
+                    seen: HashMap::new(),
+                    next_sweep: now,
+                })
+            }),
+        }
+    }
+}
+impl InMemoryReplayCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Number of remembered tokens, including expired ones that haven't been evicted yet
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().seen.len()).sum()
+    }
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Evicts all expired entries
+    pub fn evict_expired(&self) {
+        let now = Instant::now();
+        for shard in &self.shards {
+            shard.lock().unwrap().sweep(now);
+        }
+    }
+}
+impl Shard {
+    fn sweep(&mut self, now: Instant) {
+        self.seen.retain(|_, expiry| *expiry > now);
+        self.next_sweep = now + SWEEP_INTERVAL;
+    }
+}
+impl ReplayCache for InMemoryReplayCache {
+    fn check_and_insert(&self, token: &[u8], expiry: Instant) -> bool {
+        let hash = self.hasher.hash_one(token);
+        let now = Instant::now();
+        let mut shard = self.shards[hash as usize % SHARDS].lock().unwrap();
+        if shard.next_sweep <= now {
+            shard.sweep(now);
+        }
+        match shard.seen.insert(hash, expiry) {
+            Some(previous) if previous > now => {
+                shard.seen.insert(hash, previous.max(expiry));
+                false
+            }
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use axum::response::Response;
+
+use crate::Authenticated;
+
+/// Request extension authenticating a request as the client of its cookie session, inserted by a middleware in front
+/// of [`NegotiateLayer`](crate::NegotiateLayer), see [`NegotiateLayer::or_session`](crate::NegotiateLayer::or_session)
+///
+/// The layer doesn't depend on a session library. A middleware between it and the session layer connects the two:
+/// it inserts this extension for requests whose session has a client, and stores the client in the session once
+/// [`session_client`] returns one. Later requests then skip the handshake. With the `Session` of `tower-sessions`:
+///
+/// ```ignore
+/// use axum::{Router, extract::Request, middleware::{self, Next}, response::Response, routing::get};
+/// use axum_negotiate_layer::{NegotiateLayer, SessionIdentity, session_client};
+/// use tower_sessions::{MemoryStore, Session, SessionManagerLayer};
+///
+/// const CLIENT_KEY: &str = "negotiate.client";
+///
+/// async fn bridge(session: Session, mut request: Request, next: Next) -> Response {
+///     if let Ok(Some(client)) = session.get::<String>(CLIENT_KEY).await {
+///         request.extensions_mut().insert(SessionIdentity(client));
+///     }
+///     let response = next.run(request).await;
+///     if let Some(client) = session_client(&response) {
+///         // A new session ID once authenticated, against session fixation
+///         let _ = session.cycle_id().await;
+///         let _ = session.insert(CLIENT_KEY, client).await;
+///     }
+///     response
+/// }
+///
+/// let router: Router = Router::new()
+///     .route("/", get(|| async { "Hello" }))
+///     .layer(NegotiateLayer::new(Some("HTTP/example.com")).or_session(true))
+///     .layer(middleware::from_fn(bridge))
+///     .layer(SessionManagerLayer::new(MemoryStore::default()));
+/// ```
+///
+/// # Security
+/// Extensions can only be inserted by code in the server process, not by clients, so the client is as trustworthy as
+/// the session store and its cookie. Ending the session is up to the application, the layer keeps accepting it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionIdentity(pub String);
+
+/// The client to establish a session for, if `response` answers the request that authenticated the client by other
+/// means than a [`SessionIdentity`]
+///
+/// That is the request completing a handshake, or one authenticated by itself, e.g. with `Basic`. Later requests of an
+/// authenticated connection, and those authenticated by their session, return [`None`], so the session is only
+/// written once.
+#[must_use]
+pub fn session_client(response: &Response) -> Option<String> {
+    response.extensions().get::<Established>()?;
+    let mut authenticated = response.extensions().get::<Authenticated>()?.clone();
+    Some(authenticated.client())
+}
+
+/// Response extension marking the request that authenticated the client, see [`session_client`]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Established;
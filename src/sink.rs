@@ -0,0 +1,176 @@
+//! Destination of the crate's diagnostics
+//!
+//! Every diagnostic of the middleware is emitted as an [`Event`] to the [`Sink`] installed with
+//! [`NegotiateLayer::with_log_sink`](crate::NegotiateLayer::with_log_sink). Without one, events go to `tracing` (feature
+//! `tracing`), or else to `log` (feature `log`), or else nowhere.
+use std::{
+    fmt::{Debug, Display},
+    sync::Arc,
+};
+
+/// Severity of an [`Event`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// A diagnostic message with structured fields
+///
+/// Events never contain token bytes, unless [`NegotiateLayer::log_raw_tokens`](crate::NegotiateLayer::log_raw_tokens)
+/// was enabled.
+#[derive(Clone, Copy)]
+pub struct Event<'a> {
+    pub level: Level,
+    pub message: &'a str,
+    pub fields: &'a [(&'static str, &'a dyn Display)],
+}
+impl Debug for Event<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Event")
+            .field("level", &self.level)
+            .field("message", &self.message)
+            .field("fields", &format_args!("{}", Fields(self.fields)))
+            .finish()
+    }
+}
+/// Formats as the message followed by the fields as `key=value`
+impl Display for Event<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)?;
+        if !self.fields.is_empty() {
+            write!(f, " {}", Fields(self.fields))?;
+        }
+        Ok(())
+    }
+}
+
+struct Fields<'a>(&'a [(&'static str, &'a dyn Display)]);
+impl Display for Fields<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{key}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Receiver of the middleware's [`Event`]s
+pub trait Sink: Send + Sync {
+    fn event(&self, event: &Event<'_>);
+}
+
+/// [`Sink`] emitting `tracing` events with the target `axum_negotiate_layer`
+///
+/// tracing needs the names of an event's fields when compiling its callsite, so the fields of an [`Event`] are
+/// recorded together as a single `fields` field, formatted as `key=value` like the [`Display`] of the event. The
+/// target is the same for every event, so directives like `axum_negotiate_layer=debug` select all of them.
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingSink;
+#[cfg(feature = "tracing")]
+impl Sink for TracingSink {
+    fn event(&self, event: &Event<'_>) {
+        let fields = (!event.fields.is_empty()).then(|| tracing::field::display(Fields(event.fields)));
+        macro_rules! emit {
+            ($level:expr) => {
+                tracing::event!(target: "axum_negotiate_layer", $level, fields, "{}", event.message)
+            };
+        }
+        match event.level {
+            Level::Trace => emit!(tracing::Level::TRACE),
+            Level::Debug => emit!(tracing::Level::DEBUG),
+            Level::Info => emit!(tracing::Level::INFO),
+            Level::Warn => emit!(tracing::Level::WARN),
+            Level::Error => emit!(tracing::Level::ERROR),
+        }
+    }
+}
+
+/// [`Sink`] emitting `log` records with the target `axum_negotiate_layer`
+///
+/// The fields are appended to the message as `key=value`.
+#[cfg(feature = "log")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogSink;
+#[cfg(feature = "log")]
+impl Sink for LogSink {
+    fn event(&self, event: &Event<'_>) {
+        let level = match event.level {
+            Level::Trace => log::Level::Trace,
+            Level::Debug => log::Level::Debug,
+            Level::Info => log::Level::Info,
+            Level::Warn => log::Level::Warn,
+            Level::Error => log::Level::Error,
+        };
+        log::log!(target: "axum_negotiate_layer", level, "{event}");
+    }
+}
+
+#[cfg(not(any(feature = "tracing", feature = "log")))]
+struct Discard;
+#[cfg(not(any(feature = "tracing", feature = "log")))]
+impl Sink for Discard {
+    fn event(&self, _: &Event<'_>) {}
+}
+
+/// The sink used where none was installed
+pub(crate) fn default_sink() -> &'static dyn Sink {
+    #[cfg(feature = "tracing")]
+    return &TracingSink;
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    return &LogSink;
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    return &Discard;
+}
+
+/// The [`Sink`] installed on a layer, kept where events are emitted outside of its `call`
+///
+/// Added to the extensions of the requests the layer passes on, for the extractors.
+#[derive(Clone)]
+pub(crate) struct LayerSink(pub(crate) Arc<dyn Sink>);
+impl LayerSink {
+    pub(crate) fn or_default(sink: Option<&Self>) -> &dyn Sink {
+        sink.map_or(default_sink(), |sink| &*sink.0)
+    }
+    /// The sink of the layer that passed on a request with these extensions
+    pub(crate) fn of(extensions: &http::Extensions) -> &dyn Sink {
+        Self::or_default(extensions.get())
+    }
+}
+impl Debug for LayerSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LayerSink")
+    }
+}
+
+/// Displays a field value with its [`Debug`] implementation
+pub(crate) struct Debugged<T>(pub T);
+impl<T: Debug> Display for Debugged<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+/// Emits an event to a [`Sink`], e.g. `event!(sink, Warn, "Rejecting client", client = name)`
+///
+/// Field values must implement [`Display`], wrap them in [`Debugged`] otherwise.
+macro_rules! event {
+    ($sink:expr, $level:ident, $message:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        $crate::sink::Sink::event(
+            $sink,
+            &$crate::sink::Event {
+                level: $crate::sink::Level::$level,
+                message: &$message,
+                fields: &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),*],
+            },
+        )
+    };
+}
+pub(crate) use event;
@@ -0,0 +1,350 @@
+use kenobi::{
+    cred::{Credentials, CredentialsError, Inbound},
+    mech::Mechanism,
+};
+use std::{fmt::Display, io::ErrorKind, str::FromStr};
+
+use crate::{
+    env::{KEYTAB_VAR, keytab_file},
+    keytab,
+    sink::{Debugged, Sink, default_sink, event},
+};
+
+/// A Kerberos service principal name, consisting of a service class, a host and optionally a realm
+///
+/// Can be parsed from both the principal form `HTTP/host.example.com@EXAMPLE.COM` (the realm is optional)
+/// and the GSSAPI host-based service form `HTTP@host.example.com`. [`Display`] uses the principal form.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Spn {
+    service: String,
+    host: String,
+    realm: Option<String>,
+}
+impl Spn {
+    #[must_use]
+    pub fn new(service: &str, host: &str) -> Self {
+        Self {
+            service: service.to_owned(),
+            host: host.to_owned(),
+            realm: None,
+        }
+    }
+    /// Builds a canonical SPN for `service` on the given host name, see [`Spn::canonicalize`]
+    ///
+    /// A port of 80 or 443 and a trailing dot are removed from `host`, and it is converted to lowercase.
+    #[must_use]
+    pub fn for_host(service: &str, host: &str) -> Self {
+        let options = CanonicalizeOptions {
+            strip_default_ports: true,
+            default_realm: None,
+        };
+        Self::new(service, host).canonicalize(&options)
+    }
+    #[must_use]
+    pub fn with_realm(mut self, realm: &str) -> Self {
+        self.realm = Some(realm.to_owned());
+        self
+    }
+    pub fn parse(s: &str) -> Result<Self, SpnParseError> {
+        let (service, host, realm) = if let Some((service, rest)) = s.split_once('/') {
+            match rest.rsplit_once('@') {
+                Some((host, realm)) => (service, host, Some(realm)),
+                None => (service, rest, None),
+            }
+        } else if let Some((service, host)) = s.split_once('@') {
+            (service, host, None)
+        } else {
+            return Err(SpnParseError::MissingHost);
+        };
+        if host.contains(['/', '@']) {
+            return Err(SpnParseError::TooManyComponents);
+        }
+        if service.is_empty() || host.is_empty() || realm.is_some_and(str::is_empty) {
+            return Err(SpnParseError::EmptyComponent);
+        }
+        Ok(Self {
+            service: service.to_owned(),
+            host: host.to_owned(),
+            realm: realm.map(ToOwned::to_owned),
+        })
+    }
+    #[must_use]
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+    #[must_use]
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+    #[must_use]
+    pub fn realm(&self) -> Option<&str> {
+        self.realm.as_deref()
+    }
+    /// Whether a ticket for `target` is meant for this SPN, comparing realms only if both have one
+    pub(crate) fn accepts(&self, target: &Spn) -> bool {
+        self.service.eq_ignore_ascii_case(&target.service)
+            && self.host.eq_ignore_ascii_case(&target.host)
+            && self
+                .realm
+                .as_ref()
+                .zip(target.realm.as_ref())
+                .is_none_or(|(a, b)| a == b)
+    }
+    /// Normalizes the host part the way the KDC expects it
+    ///
+    /// The host is converted to lowercase and trailing dots of fully qualified DNS names are removed.
+    /// See [`CanonicalizeOptions`] for further steps.
+    #[must_use]
+    pub fn canonicalize(&self, options: &CanonicalizeOptions) -> Self {
+        let mut host = self.host.trim_end_matches('.').to_ascii_lowercase();
+        if options.strip_default_ports
+            && let Some((name, port)) = host.rsplit_once(':')
+            && (port == "80" || port == "443")
+        {
+            host = name.trim_end_matches('.').to_owned();
+        }
+        Self {
+            service: self.service.clone(),
+            host,
+            realm: self.realm.clone().or_else(|| options.default_realm.clone()),
+        }
+    }
+    /// This SPN as a host-based service name, dropping the realm
+    #[must_use]
+    pub fn host_based(&self) -> AcceptorName {
+        AcceptorName::HostBased {
+            service: self.service.clone(),
+            host: self.host.clone(),
+        }
+    }
+    /// This SPN as a full principal name
+    #[must_use]
+    pub fn principal(&self) -> AcceptorName {
+        AcceptorName::Principal(self.to_string())
+    }
+}
+impl Display for Spn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.service, self.host)?;
+        if let Some(realm) = &self.realm {
+            write!(f, "@{realm}")?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for Spn {
+    type Err = SpnParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Request extension selecting the SPN of a handshake, inserted by a middleware in front of
+/// [`NegotiateLayer`](crate::NegotiateLayer), e.g. by the tenant of the request path
+///
+/// It takes precedence over every other way of selecting the SPN, but only on the first request of a handshake. The
+/// later rounds continue with the credentials the handshake started with, whatever SPN they carry.
+///
+/// # Security
+/// Extensions can only be inserted by code in the server process, not by clients, so the SPN is as trustworthy as the
+/// middleware choosing it. Deriving it from the request means the client chooses among the SPNs that middleware
+/// allows, as with [`NegotiateLayer::spn_from_host`](crate::NegotiateLayer::spn_from_host).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpnOverride(pub Spn);
+
+/// Optional steps of [`Spn::canonicalize`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CanonicalizeOptions {
+    /// Remove a port of 80 or 443 from the host, which is never part of an HTTP SPN
+    pub strip_default_ports: bool,
+    /// Realm to add if the SPN has none
+    pub default_realm: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SpnParseError {
+    /// Neither a `/` nor an `@` separates service and host
+    MissingHost,
+    /// The host is followed by further components
+    TooManyComponents,
+    EmptyComponent,
+}
+impl Display for SpnParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::MissingHost => "SPN has no host component",
+            Self::TooManyComponents => "SPN has too many components",
+            Self::EmptyComponent => "SPN has an empty component",
+        })
+    }
+}
+impl std::error::Error for SpnParseError {}
+
+/// The name the server acquires its credentials for
+///
+/// GSSAPI expects a host-based service name (`HTTP@host`), while SSPI expects a principal name (`HTTP/host`).
+/// Passing the wrong form usually results in errors about missing keys. Either form is converted to the one
+/// expected by the backend of the current platform.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AcceptorName {
+    /// A GSSAPI host-based service name, `service@host`
+    HostBased { service: String, host: String },
+    /// A principal name such as `HTTP/host@REALM`
+    Principal(String),
+}
+impl AcceptorName {
+    /// The name in host-based service form, if it can be expressed in it
+    ///
+    /// Principals with a realm lose their realm, principals with more or less than two components can't be converted.
+    #[must_use]
+    pub fn host_based_form(&self) -> Option<String> {
+        match self {
+            Self::HostBased { service, host } => Some(format!("{service}@{host}")),
+            Self::Principal(principal) if principal.contains('/') => {
+                let spn = Spn::parse(principal).ok()?;
+                Some(format!("{}@{}", spn.service, spn.host))
+            }
+            Self::Principal(_) => None,
+        }
+    }
+    /// The name in principal form
+    #[must_use]
+    pub fn principal_form(&self) -> String {
+        match self {
+            Self::HostBased { service, host } => format!("{service}/{host}"),
+            Self::Principal(principal) => principal.clone(),
+        }
+    }
+    /// Applies [`Spn::canonicalize`] to the name, keeping its form
+    ///
+    /// Host-based names never receive a default realm. Principals that are no SPN are returned unchanged.
+    #[must_use]
+    pub fn canonicalize(&self, options: &CanonicalizeOptions) -> Self {
+        match self {
+            Self::HostBased { service, host } => Spn::new(service, host).canonicalize(options).host_based(),
+            Self::Principal(principal) => match Spn::parse(principal) {
+                Ok(spn) if principal.contains('/') => spn.canonicalize(options).principal(),
+                _ => self.clone(),
+            },
+        }
+    }
+    /// The name in the form expected by the backend of the current platform
+    pub(crate) fn backend_name(&self) -> String {
+        #[cfg(unix)]
+        return self.host_based_form().unwrap_or_else(|| self.principal_form());
+        #[cfg(windows)]
+        self.principal_form()
+    }
+}
+impl From<Spn> for AcceptorName {
+    fn from(spn: Spn) -> Self {
+        spn.principal()
+    }
+}
+/// Interprets names without a `/` but with an `@` as host-based, and everything else as a principal
+impl From<&str> for AcceptorName {
+    fn from(name: &str) -> Self {
+        match Spn::parse(name) {
+            Ok(spn) if !name.contains('/') => spn.host_based(),
+            _ => Self::Principal(name.to_owned()),
+        }
+    }
+}
+impl Display for AcceptorName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HostBased { service, host } => write!(f, "{service}@{host}"),
+            Self::Principal(principal) => f.write_str(principal),
+        }
+    }
+}
+
+/// Acquires the server credentials for `name` the same way the middleware does
+pub(crate) fn acquire_credentials(
+    name: Option<&AcceptorName>,
+    sink: &dyn Sink,
+) -> Result<Credentials<Inbound>, CredentialsError> {
+    let spn = name.map(AcceptorName::backend_name);
+    event!(sink, Debug, "Getting local SPNEGO credentials", spn = Debugged(&spn));
+    Credentials::inbound(spn.as_deref(), Mechanism::Spnego)
+}
+
+/// Checks whether server credentials can be acquired for `spn`, without starting a server
+///
+/// This goes through the same credential acquisition as the [`NegotiateLayer`](crate::NegotiateLayer), so it can be used
+/// as a pre-flight check in deployment scripts. `spn` may be given in either form, see [`AcceptorName`].
+///
+/// Note that this can't tell whether the KDC knows the SPN, only whether local keys for it are available.
+pub fn validate_spn(spn: &str) -> Result<(), SpnError> {
+    validate_spn_with_sink(spn, default_sink())
+}
+
+/// [`validate_spn`], sending its events to `sink`, e.g. the one given to
+/// [`NegotiateLayer::with_log_sink`](crate::NegotiateLayer::with_log_sink)
+pub fn validate_spn_with_sink(spn: &str, sink: &dyn Sink) -> Result<(), SpnError> {
+    match acquire_credentials(Some(&AcceptorName::from(spn)), sink) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(SpnError::classify(e.to_string(), Some(&AcceptorName::from(spn)))),
+    }
+}
+
+/// Reason server credentials couldn't be acquired, see [`validate_spn`]
+///
+/// The backends only report a generic message, so the reason is found by inspecting the keytab named by
+/// [`KEYTAB_VAR`](crate::KEYTAB_VAR). Without a file keytab there, e.g. on Windows, every failure is reported
+/// as [`SpnError::Other`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SpnError {
+    /// The keytab has no keys for the SPN
+    NotRegistered(String),
+    /// There is no keytab
+    KeytabMissing(String),
+    /// The keytab can't be read by this process
+    PermissionDenied(String),
+    Other(String),
+}
+impl SpnError {
+    /// Classifies the failure to acquire credentials for `name`, with `message` reported by the backend
+    pub(crate) fn classify(message: String, name: Option<&AcceptorName>) -> Self {
+        let Some(keytab) = std::env::var(KEYTAB_VAR).ok().filter(|_| cfg!(unix)) else {
+            return Self::Other(message);
+        };
+        let Some(path) = keytab_file(&keytab) else {
+            return Self::Other(message);
+        };
+        let principals = match std::fs::read(path) {
+            Ok(contents) => keytab::principals(&contents),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Self::KeytabMissing(message),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => return Self::PermissionDenied(message),
+            Err(_) => return Self::Other(message),
+        };
+        match (principals, name) {
+            (Some(principals), Some(name)) if !principals.iter().any(|p| p.matches(name)) => {
+                Self::NotRegistered(message)
+            }
+            (Some(principals), None) if principals.is_empty() => Self::NotRegistered(message),
+            _ => Self::Other(message),
+        }
+    }
+    /// The message reported by the backend
+    #[must_use]
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotRegistered(m) | Self::KeytabMissing(m) | Self::PermissionDenied(m) | Self::Other(m) => m,
+        }
+    }
+}
+impl Display for SpnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::NotRegistered(_) => "no keys for SPN",
+            Self::KeytabMissing(_) => "keytab missing",
+            Self::PermissionDenied(_) => "keytab not readable",
+            Self::Other(_) => "failed to acquire credentials",
+        };
+        write!(f, "{kind}: {}", self.message())
+    }
+}
+impl std::error::Error for SpnError {}
@@ -0,0 +1,117 @@
+//! The SPN read from a file and reloaded on changes, see [`NegotiateLayer::spn_from_file`]
+//!
+//! [`NegotiateLayer::spn_from_file`]: crate::NegotiateLayer::spn_from_file
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Once, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    AcceptorName,
+    sink::{Debugged, Sink, default_sink, event},
+};
+
+/// How often the watcher checks the file for changes by default
+pub(crate) const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The name last read from the file, shared with its watcher
+#[derive(Clone, Debug)]
+pub(crate) struct SpnFile {
+    name: Arc<RwLock<AcceptorName>>,
+    path: Arc<Path>,
+    /// Modification time and size of the file when the name was read
+    read_stamp: Option<(SystemTime, u64)>,
+    watching: Arc<Once>,
+}
+impl SpnFile {
+    /// Reads the name from `path`, without watching it yet
+    pub(crate) fn read(path: PathBuf) -> io::Result<Self> {
+        let read_stamp = stamp(&path);
+        let name = read(&path)?;
+        Ok(Self {
+            name: Arc::new(RwLock::new(name)),
+            path: path.into(),
+            read_stamp,
+            watching: Arc::new(Once::new()),
+        })
+    }
+    /// Starts a thread checking the file for changes every `interval`, unless a clone of this handle did already
+    ///
+    /// The thread ends once every clone of this handle has been dropped.
+    pub(crate) fn watch(&self, interval: Duration, sink: Option<Arc<dyn Sink>>) {
+        self.watching.call_once(|| {
+            let log = sink.as_deref().unwrap_or(default_sink());
+            event!(log, Info, "Read SPN from file", spn = self.current());
+            let (path, watched, mut last) = (self.path.clone(), Arc::downgrade(&self.name), self.read_stamp);
+            let thread_sink = sink.clone();
+            let spawned = std::thread::Builder::new()
+                .name("negotiate-spn-file".to_owned())
+                .spawn(move || {
+                    loop {
+                        std::thread::sleep(interval);
+                        let Some(name) = watched.upgrade() else {
+                            return;
+                        };
+                        let current = stamp(&path);
+                        if current == last {
+                            continue;
+                        }
+                        last = current;
+                        reload(&path, &name, thread_sink.as_deref().unwrap_or(default_sink()));
+                    }
+                });
+            if let Err(e) = spawned {
+                event!(
+                    log,
+                    Error,
+                    "Failed to start watching the SPN file, keeping the current SPN",
+                    path = Debugged(&self.path),
+                    error = e
+                );
+            }
+        });
+    }
+    /// The name as last read from the file
+    pub(crate) fn current(&self) -> AcceptorName {
+        self.name.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+/// Replaces `name` with the one in the file at `path`, keeping it if the file can't be read
+fn reload(path: &Path, name: &RwLock<AcceptorName>, sink: &dyn Sink) {
+    match read(path) {
+        Ok(new) => {
+            let mut name = name.write().unwrap_or_else(PoisonError::into_inner);
+            if *name != new {
+                event!(sink, Info, "Reloaded SPN from file", before = *name, after = new);
+                *name = new;
+            }
+        }
+        Err(e) => event!(
+            sink,
+            Warn,
+            "Failed to reload SPN from file, keeping the current one",
+            path = Debugged(path),
+            error = e
+        ),
+    }
+}
+
+/// The name on the first non-empty line of the file at `path`
+fn read(path: &Path) -> io::Result<AcceptorName> {
+    let contents = std::fs::read_to_string(path)?;
+    let name = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "SPN file is empty"))?;
+    Ok(AcceptorName::from(name))
+}
+
+/// Modification time and size of the file at `path`, which change when it is written or replaced
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,308 @@
+use std::fmt::{Display, Write};
+
+use crate::Spn;
+
+/// OID 1.3.6.1.5.5.2
+const SPNEGO: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+/// OID 1.2.840.113554.1.2.2
+const KERBEROS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02];
+/// OID 1.2.840.48018.1.2.2, the Kerberos OID as mistakenly sent by old Windows versions
+const MS_KERBEROS: &[u8] = &[0x2a, 0x86, 0x48, 0x82, 0xf7, 0x12, 0x01, 0x02, 0x02];
+/// OID 1.3.6.1.5.2.5
+const IAKERB: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x02, 0x05];
+/// OID 1.3.6.1.4.1.311.2.2.10
+const NTLM: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a];
+/// OID 1.3.6.1.4.1.311.2.2.30
+const NEGOEX: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x1e];
+
+const NTLMSSP_SIGNATURE: &[u8] = b"NTLMSSP\0";
+
+/// Mechanism lists longer than this are truncated
+const MAX_MECHS: usize = 16;
+
+/// A security mechanism offered by a client
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Mech {
+    Kerberos,
+    /// Kerberos under the wrong OID `1.2.840.48018.1.2.2` used by Windows
+    MsKerberos,
+    /// Kerberos tunneled through the server (IAKERB)
+    IaKerb,
+    Ntlm,
+    NegoEx,
+    /// Any other mechanism, in dotted OID notation
+    Other(String),
+    /// Not a SPNEGO mechanism, but a verified TLS client certificate, see
+    /// [`NegotiateLayer::or_client_cert`](crate::NegotiateLayer::or_client_cert)
+    ClientCertificate,
+    /// Not a SPNEGO mechanism, but a cookie session established by an earlier authentication, see
+    /// [`NegotiateLayer::or_session`](crate::NegotiateLayer::or_session)
+    Session,
+}
+impl Mech {
+    fn from_oid(oid: &[u8]) -> Self {
+        match oid {
+            KERBEROS => Self::Kerberos,
+            MS_KERBEROS => Self::MsKerberos,
+            IAKERB => Self::IaKerb,
+            NTLM => Self::Ntlm,
+            NEGOEX => Self::NegoEx,
+            other => Self::Other(dotted(other)),
+        }
+    }
+    /// Whether this is any form of Kerberos
+    #[must_use]
+    pub fn is_kerberos(&self) -> bool {
+        matches!(self, Self::Kerberos | Self::MsKerberos | Self::IaKerb)
+    }
+}
+impl Display for Mech {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Kerberos => f.write_str("Kerberos"),
+            Self::MsKerberos => f.write_str("Kerberos (MS)"),
+            Self::IaKerb => f.write_str("IAKERB"),
+            Self::Ntlm => f.write_str("NTLM"),
+            Self::NegoEx => f.write_str("NegoEx"),
+            Self::Other(oid) => f.write_str(oid),
+            Self::ClientCertificate => f.write_str("client certificate"),
+            Self::Session => f.write_str("session"),
+        }
+    }
+}
+
+/// What a client offered in the first token of a handshake
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InitialToken {
+    /// A SPNEGO `negTokenInit` with the client's mechanisms in order of preference
+    ///
+    /// At most 16 mechanisms are reported.
+    Spnego(Vec<Mech>),
+    /// A raw Kerberos `AP-REQ` without SPNEGO framing
+    Kerberos,
+    /// A raw `NTLMSSP` message without SPNEGO framing
+    Ntlm,
+}
+impl InitialToken {
+    /// Inspects the decoded first token of a handshake
+    ///
+    /// Returns `None` for anything that isn't recognizably one of the variants. This doesn't verify the token beyond
+    /// the framing needed to find the mechanism list.
+    #[must_use]
+    pub fn parse(token: &[u8]) -> Option<Self> {
+        if token.starts_with(NTLMSSP_SIGNATURE) {
+            return Some(Self::Ntlm);
+        }
+        // InitialContextToken ::= [APPLICATION 0] IMPLICIT SEQUENCE { thisMech MechType, innerContextToken ANY }
+        let mut application = Der::new(Der::new(token).expect(0x60)?);
+        let this_mech = application.expect(0x06)?;
+        if this_mech == KERBEROS || this_mech == MS_KERBEROS {
+            return Some(Self::Kerberos);
+        }
+        if this_mech != SPNEGO {
+            return None;
+        }
+        // NegotiationToken ::= CHOICE { negTokenInit [0] NegTokenInit, ... }
+        // NegTokenInit ::= SEQUENCE { mechTypes [0] MechTypeList, ... }
+        let neg_token_init = Der::new(application.expect(0xa0)?).expect(0x30)?;
+        let mech_type_list = Der::new(Der::new(neg_token_init).expect(0xa0)?).expect(0x30)?;
+        let mut mech_types = Der::new(mech_type_list);
+        let mut mechs = Vec::new();
+        while !mech_types.is_empty() && mechs.len() < MAX_MECHS {
+            mechs.push(Mech::from_oid(mech_types.expect(0x06)?));
+        }
+        Some(Self::Spnego(mechs))
+    }
+    /// The SPN of the service ticket in the decoded first token of a Kerberos handshake
+    ///
+    /// The token may be a raw `AP-REQ` or carry one as the optimistic mechanism token of a SPNEGO `negTokenInit`. The
+    /// ticket names its service in the clear, so this needs no keys and verifies nothing. Returns `None` for other
+    /// tokens, e.g. of NTLM or of clients only offering Kerberos without sending a ticket yet.
+    #[must_use]
+    pub fn target_spn(token: &[u8]) -> Option<Spn> {
+        let mut application = Der::new(Der::new(token).expect(0x60)?);
+        let this_mech = application.expect(0x06)?;
+        let kerberos = if this_mech == SPNEGO {
+            // NegTokenInit ::= SEQUENCE { mechTypes [0], reqFlags [1] OPTIONAL, mechToken [2] OCTET STRING, ... }
+            let mut neg_token_init = Der::new(Der::new(application.expect(0xa0)?).expect(0x30)?);
+            neg_token_init.expect(0xa0)?;
+            neg_token_init.skip(0xa1);
+            let mech_token = Der::new(neg_token_init.expect(0xa2)?).expect(0x04)?;
+            let mut inner = Der::new(Der::new(mech_token).expect(0x60)?);
+            let mech = inner.expect(0x06)?;
+            (mech == KERBEROS || mech == MS_KERBEROS).then_some(inner)?
+        } else if this_mech == KERBEROS || this_mech == MS_KERBEROS {
+            application
+        } else {
+            return None;
+        };
+        // The TOK_ID of an AP-REQ
+        let ap_req = kerberos.0.strip_prefix(&[0x01, 0x00])?;
+        // AP-REQ ::= [APPLICATION 14] SEQUENCE { pvno [0], msg-type [1], ap-options [2], ticket [3] Ticket, ... }
+        let mut ap_req = Der::new(Der::new(Der::new(ap_req).expect(0x6e)?).expect(0x30)?);
+        for tag in [0xa0, 0xa1, 0xa2] {
+            ap_req.expect(tag)?;
+        }
+        // Ticket ::= [APPLICATION 1] SEQUENCE { tkt-vno [0], realm [1] Realm, sname [2] PrincipalName, ... }
+        let mut ticket = Der::new(Der::new(Der::new(ap_req.expect(0xa3)?).expect(0x61)?).expect(0x30)?);
+        ticket.expect(0xa0)?;
+        let realm = kerberos_string(Der::new(ticket.expect(0xa1)?).expect(0x1b)?)?;
+        // PrincipalName ::= SEQUENCE { name-type [0] Int32, name-string [1] SEQUENCE OF KerberosString }
+        let mut sname = Der::new(Der::new(ticket.expect(0xa2)?).expect(0x30)?);
+        sname.expect(0xa0)?;
+        let mut components = Der::new(Der::new(sname.expect(0xa1)?).expect(0x30)?);
+        let service = kerberos_string(components.expect(0x1b)?)?;
+        let host = kerberos_string(components.expect(0x1b)?)?;
+        if !components.is_empty() || service.is_empty() || host.is_empty() || realm.is_empty() {
+            return None;
+        }
+        Some(Spn::new(service, host).with_realm(realm))
+    }
+    /// Whether `token` is the first token of a handshake rather than one continuing it
+    pub(crate) fn starts_handshake(token: &[u8]) -> bool {
+        if let Some(message) = token.strip_prefix(NTLMSSP_SIGNATURE) {
+            // NEGOTIATE_MESSAGE, as opposed to the AUTHENTICATE_MESSAGE of the second leg
+            return message.starts_with(&1u32.to_le_bytes());
+        }
+        Self::parse(token).is_some()
+    }
+    /// Whether the client is able to authenticate with Kerberos
+    #[must_use]
+    pub fn offers_kerberos(&self) -> bool {
+        match self {
+            Self::Spnego(mechs) => mechs.iter().any(Mech::is_kerberos),
+            Self::Kerberos => true,
+            Self::Ntlm => false,
+        }
+    }
+}
+
+/// The kind of a decoded token, see [`NegotiateToken::kind`](crate::NegotiateToken::kind)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TokenKind {
+    /// A SPNEGO `negTokenInit`, starting a handshake
+    SpnegoInit,
+    /// A SPNEGO `negTokenResp`, continuing or completing a handshake
+    SpnegoResp,
+    /// A Kerberos token in its GSS-API framing, without SPNEGO
+    RawKerberos,
+    /// A raw `NTLMSSP` message without SPNEGO framing
+    Ntlm,
+    /// Anything else
+    Unknown,
+}
+impl TokenKind {
+    /// Classifies `token` by its framing, without verifying anything beyond it
+    pub(crate) fn of(token: &[u8]) -> Self {
+        if token.starts_with(NTLMSSP_SIGNATURE) {
+            return Self::Ntlm;
+        }
+        // NegotiationToken ::= CHOICE { negTokenInit [0] NegTokenInit, negTokenResp [1] NegTokenResp }
+        if Der::new(token)
+            .expect(0xa1)
+            .is_some_and(|resp| Der::new(resp).expect(0x30).is_some())
+        {
+            return Self::SpnegoResp;
+        }
+        let Some(mut application) = Der::new(token).expect(0x60).map(Der::new) else {
+            return Self::Unknown;
+        };
+        match application.expect(0x06) {
+            Some(SPNEGO) => Self::SpnegoInit,
+            Some(KERBEROS | MS_KERBEROS) => Self::RawKerberos,
+            _ => Self::Unknown,
+        }
+    }
+}
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::SpnegoInit => "SPNEGO negTokenInit",
+            Self::SpnegoResp => "SPNEGO negTokenResp",
+            Self::RawKerberos => "Kerberos",
+            Self::Ntlm => "NTLM",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// A reader over consecutive DER elements
+///
+/// Only single-byte tags and definite lengths are supported, which covers everything in SPNEGO.
+struct Der<'a>(&'a [u8]);
+impl<'a> Der<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self(bytes)
+    }
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Reads the next element if it has the given tag, returning its contents
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        let (&actual, rest) = self.0.split_first()?;
+        if actual != tag {
+            return None;
+        }
+        let (&first, mut rest) = rest.split_first()?;
+        let length = if first & 0x80 == 0 {
+            usize::from(first)
+        } else {
+            let octets = usize::from(first & 0x7f);
+            if octets == 0 || octets > 4 || octets > rest.len() {
+                return None;
+            }
+            let (length, after) = rest.split_at(octets);
+            rest = after;
+            length.iter().fold(0, |acc, &byte| (acc << 8) | usize::from(byte))
+        };
+        if length > rest.len() {
+            return None;
+        }
+        let (contents, after) = rest.split_at(length);
+        self.0 = after;
+        Some(contents)
+    }
+    /// Skips the next element if it has the given tag, for optional fields
+    fn skip(&mut self, tag: u8) {
+        if self.0.first() == Some(&tag) {
+            self.expect(tag);
+        }
+    }
+}
+
+/// The contents of a `KerberosString`, which are restricted to ASCII in practice
+fn kerberos_string(contents: &[u8]) -> Option<&str> {
+    std::str::from_utf8(contents).ok()
+}
+
+/// Formats the contents of a DER OID in dotted notation
+fn dotted(oid: &[u8]) -> String {
+    let mut out = String::new();
+    let mut arc: u64 = 0;
+    let mut first = true;
+    for &byte in oid {
+        let Some(shifted) = arc.checked_mul(128) else {
+            out.push('…');
+            return out;
+        };
+        arc = shifted | u64::from(byte & 0x7f);
+        if byte & 0x80 != 0 {
+            continue;
+        }
+        if first {
+            let (top, second) = match arc {
+                0..40 => (0, arc),
+                40..80 => (1, arc - 40),
+                _ => (2, arc - 80),
+            };
+            let _ = write!(out, "{top}.{second}");
+            first = false;
+        } else {
+            let _ = write!(out, ".{arc}");
+        }
+        arc = 0;
+    }
+    out
+}
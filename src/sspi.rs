@@ -1,57 +1,260 @@
-use crate::{StepResult, to_negotiate_header, unauthorized};
-use axum_core::response::IntoResponse;
-use base64::{Engine, prelude::BASE64_STANDARD};
-use http::{
-    HeaderMap, HeaderValue, StatusCode,
-    header::{CONNECTION, WWW_AUTHENTICATE},
+//! Handshake rounds over kenobi, the only backend
+//!
+//! kenobi wraps GSSAPI on Unix and SSPI on Windows, so this is the single path for both. [`Step`] covers the first
+//! round, which starts a context from the server credentials, and the later ones continuing a pending context.
+use crate::{
+    EpaPolicy, FailureReason, Handshake, InitialToken, NegotiateConfig, NegotiateToken, NtlmPolicy, Persistence,
+    StepResult, TokenParseError, failed, forbidden,
+    sink::{Debugged, event},
+    unauthorized,
 };
+use axum_core::response::{IntoResponse, Response};
+use http::{HeaderMap, HeaderValue, StatusCode, header::CONNECTION};
 use kenobi::{
     cred::Inbound,
     server::{AcceptError, PendingServerContext, ServerBuilder, StepOut},
 };
 
 pub trait Step {
+    /// Whether this steps the first token of a handshake
+    const INITIAL: bool;
     fn step(self, token: &[u8]) -> Result<StepOut<Inbound>, AcceptError>;
 }
 impl Step for PendingServerContext<Inbound> {
+    const INITIAL: bool = false;
     fn step(self, token: &[u8]) -> Result<StepOut<Inbound>, AcceptError> {
         self.step(token)
     }
 }
 impl Step for ServerBuilder<Inbound> {
+    const INITIAL: bool = true;
     fn step(self, token: &[u8]) -> Result<StepOut<Inbound>, AcceptError> {
         self.initialize(token)
     }
 }
 
-pub fn handle_sspi(context: impl Step, token: &str) -> StepResult {
-    #[cfg(feature = "tracing")]
-    tracing::trace!(token_length = token.len());
-    let Ok(header_bytes) = BASE64_STANDARD.decode(token) else {
-        return StepResult::Error(StatusCode::BAD_REQUEST.into_response());
+/// The configured [`NtlmPolicy`], requiring channel bindings as well with [`EpaPolicy::Required`]
+fn ntlm_policy(config: &NegotiateConfig) -> Option<NtlmPolicy> {
+    if config.extended_protection != EpaPolicy::Required {
+        return config.ntlm_policy;
+    }
+    let policy = config.ntlm_policy.unwrap_or(NtlmPolicy {
+        require_v2: false,
+        require_ess: false,
+        require_target_info: false,
+        allow_anonymous: false,
+        require_channel_bindings: false,
+    });
+    Some(NtlmPolicy {
+        require_channel_bindings: true,
+        ..policy
+    })
+}
+
+/// Steps `context` with the client's `token`
+///
+/// A handshake needing another round trip fails right away unless the connection is kept alive, as the client's
+/// next token would arrive on a new connection without the pending context.
+pub fn handle_sspi<C: Step>(
+    context: C,
+    token: &NegotiateToken,
+    config: &NegotiateConfig,
+    handshake: &mut Handshake,
+    persistence: Persistence,
+) -> StepResult {
+    let sink = config.sink();
+    let correlation_id = Debugged(handshake.correlation_id.clone());
+    let leg = handshake.legs.saturating_add(1);
+    event!(
+        sink,
+        Trace,
+        "Handshake round",
+        token_length = token.base64().len(),
+        correlation_id = correlation_id
+    );
+    let header_bytes = match token.decode_bounded(config.max_token_size) {
+        Ok(header_bytes) => header_bytes,
+        Err(TokenParseError::TooLarge) => {
+            event!(
+                sink,
+                Warn,
+                "Token exceeds the maximum size",
+                max_token_size = config.max_token_size,
+                correlation_id = correlation_id
+            );
+            config.record_failure(FailureReason::InvalidHeader, None);
+            let response = (
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                "token exceeds the maximum size",
+            );
+            return StepResult::Error(failed(response.into_response(), FailureReason::InvalidHeader, leg));
+        }
+        Err(_) => {
+            event!(
+                sink,
+                Debug,
+                "Token is not valid base64",
+                correlation_id = correlation_id
+            );
+            config.record_failure(FailureReason::InvalidHeader, None);
+            return StepResult::Error(failed(config.invalid_token(), FailureReason::InvalidHeader, leg));
+        }
     };
+    if C::INITIAL {
+        handshake.offered = InitialToken::parse(&header_bytes);
+        event!(
+            sink,
+            Debug,
+            "Client offered",
+            offered = Debugged(&handshake.offered),
+            correlation_id = correlation_id
+        );
+        if config.require_kerberos
+            && handshake
+                .offered
+                .as_ref()
+                .is_some_and(|offered| !offered.offers_kerberos())
+        {
+            event!(
+                sink,
+                Warn,
+                "Rejecting client that doesn't offer Kerberos",
+                correlation_id = correlation_id
+            );
+            config.record_failure(FailureReason::Policy, None);
+            let response = unauthorized(config, "Kerberos required");
+            return StepResult::Error(failed(response, FailureReason::Policy, leg));
+        }
+    }
+    if !C::INITIAL && InitialToken::starts_handshake(&header_bytes) {
+        return wrong_continuation(
+            config,
+            "client started a new handshake",
+            leg,
+            handshake.correlation_id.as_deref(),
+        );
+    }
+    if let Some(policy) = ntlm_policy(config)
+        && let Err(violation) = policy.check(&header_bytes)
+    {
+        event!(
+            sink,
+            Warn,
+            "Rejecting NTLM authentication",
+            violation = violation,
+            correlation_id = correlation_id
+        );
+        config.record_failure(FailureReason::Policy, None);
+        let response = unauthorized(config, &violation.to_string());
+        return StepResult::Error(failed(response, FailureReason::Policy, leg));
+    }
     match context.step(&header_bytes) {
+        Ok(StepOut::Pending(_)) if persistence != Persistence::KeepAlive => {
+            event!(
+                sink,
+                Warn,
+                "Handshake needs another round trip on a connection that is closed after the response",
+                correlation_id = correlation_id
+            );
+            config.record_failure(FailureReason::Policy, None);
+            StepResult::Error(failed(config.non_persistent(persistence), FailureReason::Policy, leg))
+        }
         Ok(StepOut::Pending(context)) => {
-            let response_bytes = context.next_token();
-            #[cfg(feature = "tracing")]
-            tracing::debug!("SPNEGO Continue, sending {} bytes", response_bytes.len());
-            let mut header_map = HeaderMap::new();
-            header_map.insert(WWW_AUTHENTICATE, to_negotiate_header(response_bytes));
-            header_map.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
-            let response = (StatusCode::UNAUTHORIZED, header_map, "continue").into_response();
+            event!(
+                sink,
+                Debug,
+                "SPNEGO Continue",
+                token_length = context.next_token().len(),
+                correlation_id = correlation_id
+            );
+            let response = continue_response(config, context.next_token());
             StepResult::ContinueWith(context, response)
         }
-        #[allow(unused_mut)]
         Ok(StepOut::Finished(mut context)) => {
             let maybe_token = context.last_token().map(|x| x.to_vec().into_boxed_slice());
-            #[cfg(feature = "tracing")]
-            tracing::info!("SPNEGO Finished: authenticated {}", context.client_name());
-            StepResult::Finished(context, maybe_token)
+            event!(
+                sink,
+                Info,
+                "SPNEGO Finished",
+                client = context.client_name(),
+                correlation_id = correlation_id
+            );
+            StepResult::Finished {
+                context,
+                last_token: maybe_token,
+                client_token: header_bytes.into_owned(),
+            }
         }
-        Err(_e) => {
-            #[cfg(feature = "tracing")]
-            tracing::error!("Authentication failed: {_e:?}");
-            StepResult::Error(unauthorized("authorization failed"))
+        Err(e) if !C::INITIAL && is_wrong_continuation(e) => {
+            wrong_continuation(config, Debugged(e), leg, handshake.correlation_id.as_deref())
+        }
+        Err(e) => {
+            event!(
+                sink,
+                Error,
+                "Authentication failed",
+                error = Debugged(e),
+                correlation_id = correlation_id
+            );
+            config.record_failure(FailureReason::Rejected, None);
+            let response = if config.forbid_failed_handshakes {
+                forbidden("authorization failed")
+            } else {
+                unauthorized(config, "authorization failed")
+            };
+            StepResult::Error(failed(response, FailureReason::Rejected, leg))
+        }
+    }
+}
+
+/// The response carrying the server's `token` of a handshake needing another round trip
+pub fn continue_response(config: &NegotiateConfig, token: &[u8]) -> Response {
+    let mut header_map = HeaderMap::new();
+    config
+        .token_headers
+        .append_challenge(config.challenge_style, &mut header_map, Some(token));
+    let challenge = header_map.clone();
+    header_map.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+    let mut response = (StatusCode::UNAUTHORIZED, header_map, config.continue_body.clone()).into_response();
+    if let Some(map) = &config.map_continue_response {
+        response = map(response);
+        // The handshake breaks without the token, whatever the mapping did to it
+        let headers = response.headers_mut();
+        headers.remove(&config.token_headers.challenge);
+        for (name, value) in &challenge {
+            headers.append(name, value.clone());
         }
     }
+    response
+}
+
+/// Rejects a token that doesn't continue the pending handshake, instead of treating it as failed authentication
+fn wrong_continuation(
+    config: &NegotiateConfig,
+    cause: impl std::fmt::Display,
+    leg: u8,
+    correlation_id: Option<&str>,
+) -> StepResult {
+    event!(
+        config.sink(),
+        Warn,
+        "Rejecting token that doesn't continue the pending handshake",
+        cause = cause,
+        correlation_id = Debugged(correlation_id)
+    );
+    config.record_failure(FailureReason::WrongContinuation, None);
+    let message = "authentication failed: token doesn't continue the pending handshake";
+    let response = if config.forbid_failed_handshakes {
+        forbidden(message)
+    } else {
+        unauthorized(config, message)
+    };
+    StepResult::Error(failed(response, FailureReason::WrongContinuation, leg))
+}
+
+/// Whether the backend rejected a later round's token as not belonging to the pending context
+// GSSAPI reports tokens of another exchange as GSS_S_DEFECTIVE_TOKEN, or GSS_S_NO_CONTEXT once it gave up on the
+// context, and SSPI as SEC_E_INVALID_TOKEN or SEC_E_INVALID_HANDLE. A failing signature is left to the other failures.
+fn is_wrong_continuation(error: AcceptError) -> bool {
+    matches!(error, AcceptError::DefectiveToken | AcceptError::InvalidContext)
 }
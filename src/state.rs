@@ -0,0 +1,188 @@
+//! The handshake state of a connection
+//!
+//! Built with `--cfg negotiate_loom`, the synchronization is done with [loom](https://docs.rs/loom) primitives, so the
+//! transitions can be model-checked by `tests/loom.rs`.
+use std::{fmt::Debug, sync::PoisonError};
+
+#[cfg(negotiate_loom)]
+use loom::sync::{
+    Arc, Mutex, MutexGuard,
+    atomic::{AtomicBool, Ordering},
+};
+#[cfg(not(negotiate_loom))]
+use std::sync::{
+    Arc, Mutex, MutexGuard,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// State of a connection with a handshake context `P` while pending and an established context `A`
+#[derive(Default)]
+pub enum State<P, A> {
+    #[default]
+    Unauthorized,
+    Pending(P),
+    Authenticated(A),
+}
+impl<P, A> Debug for State<P, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Authenticated(_) => f.write_str("Authenticated"),
+            Self::Pending(_) => f.write_str("Pending"),
+            Self::Unauthorized => f.write_str("Unauthenticated"),
+        }
+    }
+}
+
+/// The shared state of one connection
+///
+/// A handshake round never replaces an established context. It is only dropped by [`Connection::evict_if`] and
+/// [`Connection::reset`], after which the connection starts over as [`State::Unauthorized`].
+pub struct Connection<P, A>(Arc<Shared<P, A>>);
+struct Shared<P, A> {
+    state: Mutex<State<P, A>>,
+    /// Mirrors whether `state` is [`State::Authenticated`], so reading it never waits for a round
+    authenticated: AtomicBool,
+}
+impl<P, A> Connection<P, A> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Arc::new(Shared {
+            state: Mutex::new(State::Unauthorized),
+            authenticated: AtomicBool::new(false),
+        }))
+    }
+    // A round that panicked left the state `Unauthorized` behind, which is safe to continue with
+    fn lock(&self) -> MutexGuard<'_, State<P, A>> {
+        self.0.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Updates the mirror of the `state` about to be unlocked
+    fn mirror(&self, state: &State<P, A>) {
+        let authenticated = matches!(state, State::Authenticated(_));
+        self.0.authenticated.store(authenticated, Ordering::Release);
+    }
+    /// Whether the connection is authenticated, without waiting for a round in progress
+    #[must_use]
+    pub fn is_authenticated(&self) -> bool {
+        self.0.authenticated.load(Ordering::Acquire)
+    }
+    /// Runs `f` on the current state without changing it
+    pub fn inspect<T>(&self, f: impl FnOnce(&State<P, A>) -> T) -> T {
+        f(&self.lock())
+    }
+    /// Runs `f` on the established context, if the connection is authenticated
+    pub fn with_authenticated<T>(&self, f: impl FnOnce(&mut A) -> T) -> Option<T> {
+        match &mut *self.lock() {
+            State::Authenticated(context) => Some(f(context)),
+            _ => None,
+        }
+    }
+    /// Resets the connection to [`State::Unauthorized`] if it is authenticated with a context matching `f`
+    ///
+    /// Returns whether it was reset.
+    pub fn evict_if(&self, f: impl FnOnce(&A) -> bool) -> bool {
+        let mut state = self.lock();
+        if let State::Authenticated(context) = &*state
+            && f(context)
+        {
+            *state = State::Unauthorized;
+            self.mirror(&state);
+            return true;
+        }
+        false
+    }
+    /// Resets the connection to [`State::Unauthorized`], returning the previous state
+    pub fn reset(&self) -> State<P, A> {
+        let mut state = self.lock();
+        let previous = std::mem::take(&mut *state);
+        self.mirror(&state);
+        previous
+    }
+    /// Whether no other handle keeps the state alive, ignoring [`WeakConnection`]s
+    #[cfg(feature = "http1")]
+    #[must_use]
+    pub fn is_unique(&self) -> bool {
+        #[cfg(not(negotiate_loom))]
+        return Arc::strong_count(&self.0) == 1;
+        #[cfg(negotiate_loom)]
+        return false;
+    }
+    /// A handle that doesn't keep the state alive
+    #[must_use]
+    pub fn downgrade(&self) -> WeakConnection<P, A> {
+        #[cfg(not(negotiate_loom))]
+        return WeakConnection(Arc::downgrade(&self.0));
+        // loom has no weak references, model tests don't rely on the state being dropped
+        #[cfg(negotiate_loom)]
+        return WeakConnection(self.0.clone());
+    }
+    /// Runs one round of a handshake
+    ///
+    /// `f` receives the pending handshake, if any, and returns the next state. The connection stays locked meanwhile,
+    /// so rounds on the same connection never interleave. Returns `None` without calling `f` if the connection
+    /// has been authenticated in the meantime.
+    pub fn round<T>(&self, f: impl FnOnce(Option<P>) -> (State<P, A>, T)) -> Option<T> {
+        let mut state = self.lock();
+        let pending = match std::mem::take(&mut *state) {
+            State::Unauthorized => None,
+            State::Pending(pending) => Some(pending),
+            authenticated @ State::Authenticated(_) => {
+                *state = authenticated;
+                return None;
+            }
+        };
+        let (next, output) = f(pending);
+        *state = next;
+        self.mirror(&state);
+        Some(output)
+    }
+}
+/// A [`Connection`] that may have been dropped, see [`Connection::downgrade`]
+pub struct WeakConnection<P, A>(
+    #[cfg(not(negotiate_loom))] std::sync::Weak<Shared<P, A>>,
+    #[cfg(negotiate_loom)] Arc<Shared<P, A>>,
+);
+impl<P, A> WeakConnection<P, A> {
+    #[must_use]
+    pub fn upgrade(&self) -> Option<Connection<P, A>> {
+        #[cfg(not(negotiate_loom))]
+        return self.0.upgrade().map(Connection);
+        #[cfg(negotiate_loom)]
+        return Some(Connection(self.0.clone()));
+    }
+}
+
+impl<P, A> Default for Connection<P, A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P, A> Clone for Connection<P, A> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+impl<P, A> Debug for Connection<P, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never block here, this may be called while a round holds the lock
+        match self.0.state.try_lock() {
+            Ok(state) => f.debug_tuple("Connection").field(&*state).finish(),
+            Err(_) => f.debug_tuple("Connection").field(&format_args!("<locked>")).finish(),
+        }
+    }
+}
+
+#[cfg(all(test, not(negotiate_loom)))]
+mod tests {
+    use super::{Connection, State};
+
+    #[test]
+    fn authenticated_read_during_round() {
+        let connection = Connection::<(), ()>::new();
+        // Waiting for the round to release the state would deadlock here
+        connection.round(|_| {
+            assert!(!connection.is_authenticated());
+            (State::Authenticated(()), ())
+        });
+        assert!(connection.is_authenticated());
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::SystemTime,
+};
+
+/// Counters and gauges of one or more [`NegotiateLayer`](crate::NegotiateLayer)s
+///
+/// Given to layers via [`NegotiateLayer::with_stats`](crate::NegotiateLayer::with_stats). All updates are atomic, apart
+/// from the list of recent failures, which is only kept if enabled with [`LayerStats::with_recent_failures`].
+#[derive(Clone, Debug, Default)]
+pub struct LayerStats(Arc<StatsState>);
+
+#[derive(Debug, Default)]
+struct StatsState {
+    succeeded: AtomicU64,
+    /// Successful handshakes by [`rounds_bucket`]
+    rounds: [AtomicU64; ROUNDS_BUCKETS.len()],
+    failed: [AtomicU64; FailureReason::ALL.len()],
+    identity_changes: AtomicU64,
+    pending: AtomicUsize,
+    authenticated: AtomicUsize,
+    recent: Option<Mutex<Recent>>,
+}
+
+#[derive(Debug)]
+struct Recent {
+    capacity: usize,
+    failures: VecDeque<RecentFailure>,
+}
+
+/// Why a handshake failed, see [`LayerStats::failed`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FailureReason {
+    /// The `Authorization` header or the token in it couldn't be parsed
+    InvalidHeader,
+    /// The backend rejected the client's token
+    Rejected,
+    /// The context was rejected by a configured policy, e.g. the NTLM policy
+    Policy,
+    /// The token was replayed
+    Replay,
+    /// The server credentials couldn't be acquired
+    ServerCredentials,
+    /// The handshake was refused while draining
+    Draining,
+    /// A later round's token didn't continue the pending handshake of its connection, e.g. a foreign token injected
+    /// mid-exchange
+    WrongContinuation,
+    /// A handshake authenticated another client than the one before on the same connection, see
+    /// [`NegotiateLayer::identity_change_policy`](crate::NegotiateLayer::identity_change_policy)
+    ///
+    /// Only counted if the policy rejected the new client, while [`LayerStats::identity_changes`] counts every change.
+    IdentityChanged,
+    /// A computer account authenticated, see
+    /// [`NegotiateLayer::reject_machine_accounts`](crate::NegotiateLayer::reject_machine_accounts)
+    MachineAccount,
+}
+impl FailureReason {
+    /// Every reason, in the order used by [`LayerStats::failed`]
+    pub const ALL: [Self; 9] = [
+        Self::InvalidHeader,
+        Self::Rejected,
+        Self::Policy,
+        Self::Replay,
+        Self::ServerCredentials,
+        Self::Draining,
+        Self::WrongContinuation,
+        Self::IdentityChanged,
+        Self::MachineAccount,
+    ];
+    /// A `snake_case` name for use as a label or key
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidHeader => "invalid_header",
+            Self::Rejected => "rejected",
+            Self::Policy => "policy",
+            Self::Replay => "replay",
+            Self::ServerCredentials => "server_credentials",
+            Self::Draining => "draining",
+            Self::WrongContinuation => "wrong_continuation",
+            Self::IdentityChanged => "identity_changed",
+            Self::MachineAccount => "machine_account",
+        }
+    }
+}
+impl Display for FailureReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The labels of [`LayerStats::succeeded_in_rounds`], the last counting handshakes of that many rounds or more
+pub const ROUNDS_BUCKETS: [&str; 4] = ["1", "2", "3", "4+"];
+
+/// The index into [`ROUNDS_BUCKETS`] of a handshake completed in `rounds` rounds
+fn rounds_bucket(rounds: u8) -> usize {
+    usize::from(rounds.clamp(1, 4) - 1)
+}
+
+/// A failed handshake, see [`LayerStats::recent_failures`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecentFailure {
+    pub reason: FailureReason,
+    /// The client's principal, if the backend had already authenticated it when a policy rejected it
+    pub client: Option<String>,
+    pub time: SystemTime,
+}
+
+impl LayerStats {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Also keep the last `capacity` failed handshakes, see [`LayerStats::recent_failures`]
+    #[must_use]
+    pub fn with_recent_failures(capacity: usize) -> Self {
+        Self(Arc::new(StatsState {
+            recent: Some(Mutex::new(Recent {
+                capacity,
+                failures: VecDeque::with_capacity(capacity),
+            })),
+            ..Default::default()
+        }))
+    }
+    /// The number of successful handshakes
+    #[must_use]
+    pub fn succeeded(&self) -> u64 {
+        self.0.succeeded.load(Ordering::Relaxed)
+    }
+    /// The number of handshakes that succeeded after `rounds` client tokens, see
+    /// [`Authenticated::handshake_rounds`](crate::Authenticated::handshake_rounds)
+    ///
+    /// Handshakes of 4 or more rounds share a bucket, so any `rounds` above 4 returns the same as 4, labeled by
+    /// [`ROUNDS_BUCKETS`]. One round is healthy Kerberos, more usually mean NTLM. Only counts handshakes, unlike
+    /// [`LayerStats::succeeded`].
+    #[must_use]
+    pub fn succeeded_in_rounds(&self, rounds: u8) -> u64 {
+        if rounds == 0 {
+            return 0;
+        }
+        self.0.rounds[rounds_bucket(rounds)].load(Ordering::Relaxed)
+    }
+    /// The number of handshakes that failed for `reason`
+    #[must_use]
+    pub fn failed(&self, reason: FailureReason) -> u64 {
+        self.0.failed[reason as usize].load(Ordering::Relaxed)
+    }
+    /// The number of times another client authenticated on a connection than the one before, whether the policy
+    /// accepted it or not, see [`NegotiateLayer::identity_change_policy`](crate::NegotiateLayer::identity_change_policy)
+    #[must_use]
+    pub fn identity_changes(&self) -> u64 {
+        self.0.identity_changes.load(Ordering::Relaxed)
+    }
+    /// The number of connections currently in the middle of a handshake
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.0.pending.load(Ordering::Relaxed)
+    }
+    /// The number of currently open authenticated connections
+    #[must_use]
+    pub fn authenticated(&self) -> usize {
+        self.0.authenticated.load(Ordering::Relaxed)
+    }
+    /// The most recent failed handshakes, oldest first
+    ///
+    /// Always empty unless created with [`LayerStats::with_recent_failures`].
+    #[must_use]
+    pub fn recent_failures(&self) -> Vec<RecentFailure> {
+        match &self.0.recent {
+            Some(recent) => recent
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .failures
+                .iter()
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+    pub(crate) fn record_success(&self) {
+        self.0.succeeded.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn record_rounds(&self, rounds: u8) {
+        self.0.rounds[rounds_bucket(rounds)].fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn record_failure(&self, reason: FailureReason, client: Option<&str>) {
+        self.0.failed[reason as usize].fetch_add(1, Ordering::Relaxed);
+        let Some(recent) = &self.0.recent else {
+            return;
+        };
+        let mut recent = recent.lock().unwrap_or_else(PoisonError::into_inner);
+        if recent.capacity == 0 {
+            return;
+        }
+        if recent.failures.len() == recent.capacity {
+            recent.failures.pop_front();
+        }
+        recent.failures.push_back(RecentFailure {
+            reason,
+            client: client.map(ToOwned::to_owned),
+            time: SystemTime::now(),
+        });
+    }
+    pub(crate) fn record_identity_change(&self) {
+        self.0.identity_changes.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn pending_guard(&self) -> Gauge {
+        Gauge::new(self, |state| &state.pending)
+    }
+    pub(crate) fn authenticated_guard(&self) -> Gauge {
+        Gauge::new(self, |state| &state.authenticated)
+    }
+}
+
+/// Counts towards a gauge of [`LayerStats`] until dropped together with the connection state
+#[derive(Debug)]
+pub(crate) struct Gauge(LayerStats, fn(&StatsState) -> &AtomicUsize);
+impl Gauge {
+    fn new(stats: &LayerStats, gauge: fn(&StatsState) -> &AtomicUsize) -> Self {
+        gauge(&stats.0).fetch_add(1, Ordering::Relaxed);
+        Self(stats.clone(), gauge)
+    }
+}
+impl Drop for Gauge {
+    fn drop(&mut self) {
+        (self.1)(&self.0.0).fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    sync::Arc,
+    task::{Context, Poll, ready},
+};
+
+use axum::extract::Request;
+use http::request::Parts;
+use tower::Service;
+
+use crate::Authenticated;
+
+type Selector = Arc<dyn Fn(&Authenticated, &Parts) -> usize + Send + Sync>;
+
+/// Forwards each authenticated request to one of several services, chosen by its client
+///
+/// Placed inside the [`NegotiateLayer`](crate::NegotiateLayer), so the selector only sees requests the layer passes
+/// on, with the [`Authenticated`] it added. Handshakes and challenges are answered before steering applies. Requests
+/// without an [`Authenticated`], i.e. if it isn't placed inside the layer, go to the first service.
+///
+/// ```
+/// use axum::{Router, routing::get};
+/// use axum_negotiate_layer::{NegotiateLayer, NegotiateSteer};
+///
+/// let instrumented = Router::new().route("/", get(|| async { "Hello, admin" }));
+/// let normal = Router::new().route("/", get(|| async { "Hello" }));
+/// let steer = NegotiateSteer::new(
+///     |authenticated, _parts| {
+///         let admin = authenticated.clone().client() == "admin@EXAMPLE.COM";
+///         if admin { 0 } else { 1 }
+///     },
+///     vec![instrumented, normal],
+/// );
+/// let router: Router = Router::new()
+///     .fallback_service(steer)
+///     .layer(NegotiateLayer::new(Some("HTTP/example.com")));
+/// ```
+///
+/// Like [`tower::steer`](https://docs.rs/tower/latest/tower/steer/index.html), it is only ready once all services
+/// are, as the service a request goes to is only known once it is called.
+pub struct NegotiateSteer<S> {
+    services: Vec<S>,
+    selector: Selector,
+    /// Services that have to become ready before the next request
+    not_ready: VecDeque<usize>,
+}
+impl<S> NegotiateSteer<S> {
+    /// Steers requests to the service at the index `selector` returns for them
+    ///
+    /// # Panics
+    ///
+    /// If `services` is empty. Calling it panics if `selector` returns an index out of range.
+    pub fn new(selector: impl Fn(&Authenticated, &Parts) -> usize + Send + Sync + 'static, services: Vec<S>) -> Self {
+        assert!(!services.is_empty(), "NegotiateSteer needs at least one service");
+        Self {
+            not_ready: (0..services.len()).collect(),
+            services,
+            selector: Arc::new(selector),
+        }
+    }
+}
+/// Clones have to become ready themselves
+impl<S: Clone> Clone for NegotiateSteer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            services: self.services.clone(),
+            selector: self.selector.clone(),
+            not_ready: (0..self.services.len()).collect(),
+        }
+    }
+}
+impl<S> Debug for NegotiateSteer<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NegotiateSteer")
+            .field("services", &self.services.len())
+            .finish_non_exhaustive()
+    }
+}
+impl<S: Service<Request>> Service<Request> for NegotiateSteer<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        while let Some(&index) = self.not_ready.front() {
+            ready!(self.services[index].poll_ready(cx))?;
+            self.not_ready.pop_front();
+        }
+        Poll::Ready(Ok(()))
+    }
+    fn call(&mut self, request: Request) -> Self::Future {
+        assert!(self.not_ready.is_empty(), "NegotiateSteer called before it was ready");
+        let (parts, body) = request.into_parts();
+        let index = parts
+            .extensions
+            .get::<Authenticated>()
+            .map_or(0, |authenticated| (self.selector)(authenticated, &parts));
+        assert!(
+            index < self.services.len(),
+            "selector returned {index}, but there are only {} services",
+            self.services.len()
+        );
+        self.not_ready.push_back(index);
+        self.services[index].call(Request::from_parts(parts, body))
+    }
+}
@@ -0,0 +1,90 @@
+//! Where [`HasNegotiateStore`] gets the state of each connection from
+//!
+//! [`HasNegotiateStore`]: crate::HasNegotiateStore
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::NegotiateInfo;
+
+/// Provides the [`NegotiateInfo`] of connections accepted by a [`HasNegotiateStore`]
+///
+/// A connection gets its state from [`ConnectionStore::acquire`] when it is accepted and hands it back to
+/// [`ConnectionStore::release`] once it is closed, after [`NegotiateInfo::close`] dropped its context.
+///
+/// [`HasNegotiateStore`]: crate::HasNegotiateStore
+pub trait ConnectionStore: Clone + Send + Sync + Unpin + 'static {
+    /// The state for a newly accepted connection
+    fn acquire(&self) -> NegotiateInfo;
+    /// Takes back the state of a closed connection
+    fn release(&self, info: NegotiateInfo);
+}
+
+/// The [`ConnectionStore`] of [`HasNegotiateInfo`](crate::HasNegotiateInfo), allocating the state of every connection
+/// anew
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NewPerConnection;
+impl ConnectionStore for NewPerConnection {
+    fn acquire(&self) -> NegotiateInfo {
+        NegotiateInfo::new()
+    }
+    fn release(&self, _: NegotiateInfo) {}
+}
+
+/// [`ConnectionStore`] that reuses the state of closed connections to save allocations under connection churn
+///
+/// Keeps up to `capacity` released states, reused in the order they were released. A state is only reused once nothing
+/// else refers to it anymore, so a request or [`Authenticated`](crate::Authenticated) outliving its connection never
+/// sees a later connection's state. A state still referred to when its turn comes is dropped, and a new one allocated
+/// in its place.
+#[derive(Clone, Debug)]
+pub struct RecyclingStore {
+    free: Arc<Mutex<VecDeque<NegotiateInfo>>>,
+    capacity: usize,
+    reuses: Arc<AtomicU64>,
+}
+impl RecyclingStore {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            free: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            reuses: Arc::default(),
+        }
+    }
+    /// The number of released states kept for reuse
+    #[must_use]
+    pub fn available(&self) -> usize {
+        self.free.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+    /// The number of connections that got a reused state so far
+    #[must_use]
+    pub fn reuses(&self) -> u64 {
+        self.reuses.load(Ordering::Relaxed)
+    }
+}
+impl ConnectionStore for RecyclingStore {
+    // The connection's service holds a clone of the state until right after the connection released it, so only the
+    // oldest state is checked. One still in use by then, e.g. by a request outliving its connection, is dropped
+    // instead of being checked again.
+    fn acquire(&self) -> NegotiateInfo {
+        let oldest = self.free.lock().unwrap_or_else(PoisonError::into_inner).pop_front();
+        match oldest.filter(NegotiateInfo::is_reusable) {
+            Some(info) => {
+                self.reuses.fetch_add(1, Ordering::Relaxed);
+                info.reused()
+            }
+            None => NegotiateInfo::new(),
+        }
+    }
+    fn release(&self, info: NegotiateInfo) {
+        let mut free = self.free.lock().unwrap_or_else(PoisonError::into_inner);
+        if free.len() < self.capacity {
+            free.push_back(info);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Fabricated [`Authenticated`] handles for testing handlers, with the `test-util` feature
+use std::sync::{Arc, Weak};
+
+use crate::{Authenticated, EstablishedIdentity, Mech, NtlmDetails};
+
+/// What an [`Authenticated`] made by [`Authenticated::for_tests`] reports
+///
+/// Everything except the client is unset by default, as if the backend couldn't report it.
+#[derive(Clone, Debug)]
+pub struct ClientIdentity {
+    client: String,
+    forwarded_client: Option<String>,
+    mechanism: Option<Mech>,
+    ntlm: Option<NtlmDetails>,
+    rounds: u8,
+}
+impl ClientIdentity {
+    /// An identity authenticated as the principal `client`
+    #[must_use]
+    pub fn new(client: &str) -> Self {
+        Self {
+            client: client.to_owned(),
+            forwarded_client: None,
+            mechanism: None,
+            ntlm: None,
+            rounds: 0,
+        }
+    }
+    /// The user forwarded by a trusted proxy, making `client` the proxy, see [`Authenticated::client`]
+    #[must_use]
+    pub fn forwarded_client(mut self, client: &str) -> Self {
+        self.forwarded_client = Some(client.to_owned());
+        self
+    }
+    /// See [`Authenticated::mechanism`]
+    #[must_use]
+    pub fn mechanism(mut self, mechanism: Mech) -> Self {
+        self.mechanism = Some(mechanism);
+        self
+    }
+    /// See [`Authenticated::ntlm_details`]
+    #[must_use]
+    pub fn ntlm_details(mut self, details: NtlmDetails) -> Self {
+        self.ntlm = Some(details);
+        self
+    }
+    /// See [`Authenticated::handshake_rounds`]
+    #[must_use]
+    pub fn handshake_rounds(mut self, rounds: u8) -> Self {
+        self.rounds = rounds;
+        self
+    }
+}
+
+impl Authenticated {
+    /// Creates a handle reporting `identity`, for calling handlers directly in tests
+    ///
+    /// Only available with the `test-util` feature. There is no context behind it, so [`Authenticated::is_released`]
+    /// is `true` and the accessors querying the context return [`None`].
+    #[must_use]
+    pub fn for_tests(identity: ClientIdentity) -> Self {
+        Self {
+            context: Weak::new(),
+            _owned: None,
+            identity: Arc::new(EstablishedIdentity {
+                client: identity.client,
+                mechanism: identity.mechanism,
+                ntlm: identity.ntlm,
+                rounds: identity.rounds,
+            }),
+            forwarded_client: identity.forwarded_client,
+        }
+    }
+}
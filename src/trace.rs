@@ -0,0 +1,76 @@
+//! Spans with the authenticated client for `tower_http::trace::TraceLayer`
+//!
+//! [`make_span`] and [`on_response`] are meant for `TraceLayer::make_span_with` and `TraceLayer::on_response`. The span
+//! has the fields of tower-http's `DefaultMakeSpan` (`method`, `uri`, `version`) at the same level, and:
+//! - `enduser.id`: the [`client`](Authenticated::client) of an authenticated request
+//! - `auth.mechanism`: its [`mechanism`](Authenticated::mechanism), if known
+//! - `auth.outcome`: `authenticated`, `challenged` for a `401` or `rejected` for a `403`
+//!
+//! # Layer order
+//!
+//! Both arrangements of the two layers work, but see different requests:
+//! - With the `TraceLayer` inside of the [`NegotiateLayer`](crate::NegotiateLayer), i.e. added to the router before
+//!   it, the request already carries [`Authenticated`], which [`make_span`] records right away. Responses of the
+//!   handshake itself never reach the `TraceLayer`, so only a `403` of the application is recorded as `rejected`.
+//! - With the `TraceLayer` outside, the span is created before authentication. The middleware adds [`Authenticated`]
+//!   to the response, from which [`on_response`] records the fields instead. Events of the handler aren't in a span
+//!   with the client then, but challenges and rejections of the middleware are recorded.
+//!
+//! ```ignore
+//! use axum::{Router, routing::get};
+//! use axum_negotiate_layer::{NegotiateLayer, trace};
+//! use tower_http::trace::TraceLayer;
+//!
+//! let router: Router = Router::new()
+//!     .route("/", get(|| async { "Hello" }))
+//!     .layer(TraceLayer::new_for_http().make_span_with(trace::make_span).on_response(trace::on_response))
+//!     .layer(NegotiateLayer::new(Some("HTTP/example.com")));
+//! ```
+use std::time::Duration;
+
+use http::{Request, Response, StatusCode};
+use tracing::{Span, field::Empty};
+
+use crate::Authenticated;
+
+/// Creates the span of a request, recording the client if it is already authenticated
+pub fn make_span<B>(request: &Request<B>) -> Span {
+    let span = tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        enduser.id = Empty,
+        auth.mechanism = Empty,
+        auth.outcome = Empty,
+    );
+    if let Some(authenticated) = request.extensions().get::<Authenticated>() {
+        record_authenticated(&span, authenticated);
+    }
+    span
+}
+
+/// Records the outcome of authentication in `span` and emits an event like tower-http's `DefaultOnResponse`
+pub fn on_response<B>(response: &Response<B>, latency: Duration, span: &Span) {
+    if let Some(authenticated) = response.extensions().get::<Authenticated>() {
+        record_authenticated(span, authenticated);
+    } else if response.status() == StatusCode::UNAUTHORIZED {
+        span.record("auth.outcome", "challenged");
+    } else if response.status() == StatusCode::FORBIDDEN {
+        span.record("auth.outcome", "rejected");
+    }
+    tracing::debug!(
+        parent: span,
+        latency = ?latency,
+        status = response.status().as_u16(),
+        "finished processing request"
+    );
+}
+
+fn record_authenticated(span: &Span, authenticated: &Authenticated) {
+    span.record("enduser.id", authenticated.clone().client().as_str());
+    if let Some(mechanism) = authenticated.mechanism() {
+        span.record("auth.mechanism", tracing::field::debug(mechanism));
+    }
+    span.record("auth.outcome", "authenticated");
+}
@@ -0,0 +1,104 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{
+        HeaderMap, HeaderName, HeaderValue,
+        header::{CONTENT_LENGTH, TE, TRAILER},
+    },
+    response::Response,
+};
+use http_body::{Frame, SizeHint};
+
+use crate::Mech;
+
+/// Trailer carrying the authenticated client
+pub const CLIENT_TRAILER: HeaderName = HeaderName::from_static("negotiate-client");
+/// Trailer carrying the mechanism the client authenticated with, if known
+pub const MECHANISM_TRAILER: HeaderName = HeaderName::from_static("negotiate-mechanism");
+/// Header carrying the authenticated client on error responses, see
+/// [`NegotiateLayer::annotate_errors_with_identity`](crate::NegotiateLayer::annotate_errors_with_identity)
+pub const ERROR_CLIENT_HEADER: HeaderName = HeaderName::from_static("negotiate-error-client");
+
+/// Whether the request announced it accepts trailers with `TE: trailers`
+pub(crate) fn accepts_trailers(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| coding.trim().eq_ignore_ascii_case("trailers"))
+}
+
+/// The authentication metadata to send as trailers
+///
+/// Values that can't be represented in a header are left out.
+pub(crate) fn auth_trailers(client: &str, mechanism: Option<&Mech>) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    if let Ok(client) = HeaderValue::from_str(client) {
+        trailers.insert(CLIENT_TRAILER, client);
+    }
+    if let Some(mechanism) = mechanism.and_then(|mech| HeaderValue::from_str(&mech.to_string()).ok()) {
+        trailers.insert(MECHANISM_TRAILER, mechanism);
+    }
+    trailers
+}
+
+/// Appends `trailers` to the body of `response` and announces them in its `Trailer` header
+pub(crate) fn with_trailers(response: Response, trailers: HeaderMap) -> Response {
+    if trailers.is_empty() {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    for name in trailers.keys() {
+        parts.headers.append(TRAILER, HeaderValue::from_name(name.clone()));
+    }
+    let body = TrailerBody {
+        inner: body,
+        trailers: Some(trailers),
+    };
+    Response::from_parts(parts, Body::new(body))
+}
+
+/// Body sending additional trailers after the end of the inner body
+///
+/// Trailers of the inner body are merged with them.
+struct TrailerBody {
+    inner: Body,
+    trailers: Option<HeaderMap>,
+}
+impl HttpBody for TrailerBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        if this.trailers.is_none() {
+            return Pin::new(&mut this.inner).poll_frame(cx);
+        }
+        match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => match frame.into_trailers() {
+                Ok(mut inner_trailers) => {
+                    inner_trailers.extend(this.trailers.take().unwrap_or_default());
+                    Poll::Ready(Some(Ok(Frame::trailers(inner_trailers))))
+                }
+                Err(frame) => Poll::Ready(Some(Ok(frame))),
+            },
+            Some(Err(e)) => Poll::Ready(Some(Err(e))),
+            None => Poll::Ready(this.trailers.take().map(|trailers| Ok(Frame::trailers(trailers)))),
+        }
+    }
+    fn is_end_stream(&self) -> bool {
+        self.trailers.is_none() && self.inner.is_end_stream()
+    }
+    fn size_hint(&self) -> SizeHint {
+        // Without an upper bound, HTTP/1.1 responses are chunked, which is required to send trailers
+        let mut hint = SizeHint::new();
+        hint.set_lower(self.inner.size_hint().lower());
+        hint
+    }
+}
@@ -0,0 +1,9 @@
+use axum_negotiate_layer::Authorizer;
+
+#[test]
+fn closures_are_authorizers() {
+    let readers_only = |client: &str, action: &str| action == "read" || client == "admin@EXAMPLE.COM";
+    assert!(readers_only.authorize("user@EXAMPLE.COM", "read"));
+    assert!(!readers_only.authorize("user@EXAMPLE.COM", "delete"));
+    assert!(readers_only.authorize("admin@EXAMPLE.COM", "delete"));
+}
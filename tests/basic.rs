@@ -0,0 +1,129 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{Authenticated, FailureReason, LayerStats, NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, USER_AGENT, WWW_AUTHENTICATE},
+};
+use tower::ServiceExt;
+
+const MONITORING: &str = "check_http/v2.3.3 (monitoring-plugins 2.3.3)";
+
+fn router(layer: NegotiateLayer) -> Router {
+    Router::new()
+        .route("/", get(|mut auth: Authenticated| async move { auth.client() }))
+        .layer(layer)
+}
+
+fn layer() -> NegotiateLayer {
+    NegotiateLayer::new(None)
+        .basic_auth("monitoring", |user, password| user == "nagios" && password == "secret")
+        .basic_for_user_agents(vec!["check_http".to_owned()])
+}
+
+fn request(user_agent: &str, authorization: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder().uri("/").header(USER_AGENT, user_agent);
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    request
+}
+
+fn basic(credentials: &str) -> String {
+    format!("Basic {}", BASE64_STANDARD.encode(credentials))
+}
+
+fn challenges(response: &Response) -> Vec<&str> {
+    response
+        .headers()
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn challenge_by_user_agent() {
+    let response = router(layer()).oneshot(request(MONITORING, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(challenges(&response), ["Basic realm=\"monitoring\""]);
+    let response = router(layer()).oneshot(request("Mozilla/5.0", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(challenges(&response), ["Negotiate"]);
+}
+
+#[tokio::test]
+async fn verifies_credentials() {
+    let stats = LayerStats::new();
+    let router = router(layer().with_stats(&stats));
+    let response = router
+        .clone()
+        .oneshot(request(MONITORING, Some(&basic("nagios:secret"))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"nagios");
+    let response = router
+        .clone()
+        .oneshot(request(MONITORING, Some(&basic("nagios:wrong"))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(challenges(&response), ["Basic realm=\"monitoring\""]);
+    let response = router
+        .oneshot(request(MONITORING, Some("Basic not-base64")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(stats.succeeded(), 1);
+    assert_eq!(stats.failed(FailureReason::Rejected), 1);
+    assert_eq!(stats.failed(FailureReason::InvalidHeader), 1);
+}
+
+#[tokio::test]
+async fn other_clients_cant_use_basic() {
+    let response = router(layer())
+        .oneshot(request("Mozilla/5.0", Some(&basic("nagios:secret"))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(challenges(&response), ["Negotiate"]);
+}
+
+#[tokio::test]
+async fn without_verifier() {
+    let layer = NegotiateLayer::new(None).basic_for_user_agents(vec!["check_http".to_owned()]);
+    let response = router(layer).oneshot(request(MONITORING, None)).await.unwrap();
+    assert_eq!(challenges(&response), ["Negotiate"]);
+}
+
+#[tokio::test]
+async fn forbidden_when_configured() {
+    let response = router(layer().forbid_failed_handshakes(true))
+        .oneshot(request(MONITORING, Some(&basic("nagios:wrong"))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(challenges(&response).is_empty());
+}
+
+#[tokio::test]
+async fn rejects_oversized_credentials() {
+    let stats = LayerStats::new();
+    let router = router(layer().max_token_size(16).with_stats(&stats));
+    let response = router
+        .clone()
+        .oneshot(request(MONITORING, Some(&basic("nagios:secret"))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = router
+        .oneshot(request(MONITORING, Some(&basic(&format!("nagios:{}", "x".repeat(64))))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(stats.failed(FailureReason::InvalidHeader), 1);
+}
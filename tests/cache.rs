@@ -0,0 +1,61 @@
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{Authenticated, HandshakeStatus, NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+fn token(spn: &str) -> Vec<u8> {
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    match ClientBuilder::new_from_credentials(credentials, Some(spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    }
+}
+
+fn request(info: &NegotiateInfo, token: Option<&[u8]>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    request
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn evicts_least_recently_used() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let router = Router::new()
+        .route("/", get(|_: Authenticated| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)).max_cached_contexts(2));
+    let connections = [NegotiateInfo::new(), NegotiateInfo::new(), NegotiateInfo::new()];
+    for (i, info) in connections.iter().enumerate() {
+        let response = router.clone().oneshot(request(info, Some(&token(&spn)))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        if i == 0 {
+            // Makes the second connection the least recently used one
+            let response = router.clone().oneshot(request(info, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+    let status = connections.each_ref().map(NegotiateInfo::status);
+    assert_eq!(
+        status,
+        [
+            HandshakeStatus::Authenticated,
+            HandshakeStatus::Unauthenticated,
+            HandshakeStatus::Authenticated
+        ]
+    );
+    let response = router.oneshot(request(&connections[1], None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,46 @@
+use axum_negotiate_layer::ChallengeStyle;
+use http::{HeaderMap, HeaderValue, header::WWW_AUTHENTICATE};
+
+fn challenges() -> [HeaderValue; 3] {
+    [
+        HeaderValue::from_static("Negotiate abc="),
+        HeaderValue::from_static("NTLM"),
+        HeaderValue::from_static(r#"Basic realm="example""#),
+    ]
+}
+
+fn emitted(style: ChallengeStyle) -> Vec<String> {
+    let mut headers = HeaderMap::new();
+    style.append(&mut headers, &challenges());
+    headers
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .map(|value| value.to_str().unwrap().to_owned())
+        .collect()
+}
+
+#[test]
+fn separate_headers() {
+    assert_eq!(
+        emitted(ChallengeStyle::SeparateHeaders),
+        ["Negotiate abc=", "NTLM", r#"Basic realm="example""#]
+    );
+}
+
+#[test]
+fn comma_joined() {
+    assert_eq!(
+        emitted(ChallengeStyle::CommaJoined),
+        [r#"Negotiate abc=, NTLM, Basic realm="example""#]
+    );
+}
+
+#[test]
+fn single_challenge_is_identical() {
+    let single = [HeaderValue::from_static("Negotiate")];
+    let mut separate = HeaderMap::new();
+    ChallengeStyle::SeparateHeaders.append(&mut separate, &single);
+    let mut joined = HeaderMap::new();
+    ChallengeStyle::CommaJoined.append(&mut joined, &single);
+    assert_eq!(separate, joined);
+}
@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{ChallengePolicy, CidrParseError, NegotiateLayer, NegotiateProgress, Negotiated};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+fn intranet() -> ChallengePolicy {
+    ChallengePolicy::new()
+        .allow_network("10.0.0.0/8")
+        .unwrap()
+        .allow_network("fd00::/8")
+        .unwrap()
+}
+
+fn router(spn: Option<&str>, policy: ChallengePolicy) -> Router {
+    let layer = NegotiateLayer::new(spn).challenge_policy(policy);
+    Router::new()
+        .route("/", get(|| async {}))
+        .layer(layer.with_connect_info::<SocketAddr>())
+}
+
+async fn send(router: &Router, peer: &str, token: Option<&str>) -> Response {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, token);
+    }
+    let mut request = request.header("X-Internal", "1").body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(Negotiated {
+        inner: peer.parse::<SocketAddr>().unwrap(),
+        negotiate: Default::default(),
+    }));
+    router.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn challenges_peers_in_range() {
+    let router = router(None, intranet());
+    for peer in ["10.1.2.3:1234", "[fd12::1]:1234", "[::ffff:10.0.0.1]:1234"] {
+        let response = send(&router, peer, None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{peer}");
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Negotiate", "{peer}");
+    }
+}
+
+#[tokio::test]
+async fn leaves_out_challenge_for_peers_out_of_range() {
+    let router = router(None, intranet());
+    for peer in ["192.0.2.1:1234", "11.0.0.1:1234", "[2001:db8::1]:1234"] {
+        let response = send(&router, peer, None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{peer}");
+        assert!(response.headers().get(WWW_AUTHENTICATE).is_none(), "{peer}");
+        assert_eq!(
+            response.extensions().get::<NegotiateProgress>(),
+            Some(&NegotiateProgress::ChallengeIssued)
+        );
+    }
+}
+
+#[tokio::test]
+async fn challenges_matching_requests() {
+    let router = router(
+        None,
+        ChallengePolicy::new().allow_if(|parts| parts.headers.contains_key("X-Internal")),
+    );
+    let response = send(&router, "192.0.2.1:1234", None).await;
+    assert_eq!(response.headers()[WWW_AUTHENTICATE], "Negotiate");
+    // A policy without networks or predicate challenges no one
+    let response = send(&self::router(None, ChallengePolicy::new()), "10.0.0.1:1234", None).await;
+    assert!(response.headers().get(WWW_AUTHENTICATE).is_none());
+}
+
+#[tokio::test]
+async fn misformatted_token_out_of_range() {
+    let response = send(&router(None, intranet()), "192.0.2.1:1234", Some("Bearer token")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get(WWW_AUTHENTICATE).is_none());
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn processes_proactive_tokens_out_of_range() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let token = format!("Negotiate {}", BASE64_STANDARD.encode(token));
+    let response = send(&router(Some(&spn), intranet()), "192.0.2.1:1234", Some(&token)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn parses_cidrs() {
+    for cidr in ["10.0.0.0/8", "192.0.2.1/32", "0.0.0.0/0", "fd00::/8", "::1/128"] {
+        assert!(ChallengePolicy::new().allow_network(cidr).is_ok(), "{cidr}");
+    }
+    for (cidr, error) in [
+        ("10.0.0.0", CidrParseError::MissingPrefix),
+        ("10.0.0/8", CidrParseError::InvalidAddress),
+        ("10.0.0.0/33", CidrParseError::InvalidPrefix),
+        ("fd00::/129", CidrParseError::InvalidPrefix),
+        ("10.0.0.0/x", CidrParseError::InvalidPrefix),
+    ] {
+        assert_eq!(ChallengePolicy::new().allow_network(cidr).unwrap_err(), error, "{cidr}");
+    }
+}
@@ -0,0 +1,123 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    Authenticated, CertIdentityMapper, CertPrecedence, ClientCertificate, Mech, NegotiateInfo, NegotiateLayer,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+/// Stands in for a certificate, whose subject is the DER encoding itself
+const MACHINE: &[u8] = b"CN=backup.example.com";
+
+fn mapper() -> CertIdentityMapper {
+    CertIdentityMapper::new(|cert| {
+        let subject = std::str::from_utf8(cert.der()).ok()?;
+        subject.strip_prefix("CN=").map(|name| format!("host/{name}"))
+    })
+}
+
+fn router(layer: NegotiateLayer) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(|mut auth: Authenticated| async move { format!("{} {:?}", auth.client(), auth.mechanism()) }),
+        )
+        .layer(layer)
+}
+
+async fn send(router: &Router, cert: Option<ClientCertificate>, authorization: Option<&str>) -> Response {
+    let mut info = NegotiateInfo::new();
+    if let Some(cert) = cert {
+        info = info.with_client_cert(cert);
+    }
+    let mut request = Request::builder().uri("/");
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info));
+    router.clone().oneshot(request).await.unwrap()
+}
+
+async fn body(response: Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn authenticates_verified_certificate() {
+    let router = router(NegotiateLayer::new(None).or_client_cert(mapper()));
+    let response = send(&router, Some(ClientCertificate::verified(MACHINE)), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        body(response).await,
+        format!("host/backup.example.com {:?}", Some(Mech::ClientCertificate))
+    );
+}
+
+#[tokio::test]
+async fn never_uses_unverified_certificate() {
+    let router = router(NegotiateLayer::new(None).or_client_cert(mapper()));
+    let response = send(&router, Some(ClientCertificate::unverified(MACHINE)), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[WWW_AUTHENTICATE], "Negotiate");
+    let router =
+        self::router(NegotiateLayer::new(None).or_client_cert(mapper().precedence(CertPrecedence::Certificate)));
+    let response = send(&router, Some(ClientCertificate::unverified(MACHINE)), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn negotiates_without_mapped_certificate() {
+    let router = router(NegotiateLayer::new(None).or_client_cert(mapper()));
+    let response = send(&router, Some(ClientCertificate::verified(b"O=Example")), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(&router, None, None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    // Without the mapper, certificates aren't looked at
+    let response = send(
+        &self::router(NegotiateLayer::new(None)),
+        Some(ClientCertificate::verified(MACHINE)),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn certificate_precedence_ignores_token() {
+    let layer = NegotiateLayer::new(None).or_client_cert(mapper().precedence(CertPrecedence::Certificate));
+    let token = format!("Negotiate {}", BASE64_STANDARD.encode(vectors::KERBEROS_AP_REQ));
+    let response = send(&router(layer), Some(ClientCertificate::verified(MACHINE)), Some(&token)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body(response).await.starts_with("host/backup.example.com "));
+}
+
+#[tokio::test]
+async fn other_schemes_are_no_token() {
+    let router = router(NegotiateLayer::new(None).or_client_cert(mapper()));
+    let response = send(
+        &router,
+        Some(ClientCertificate::verified(MACHINE)),
+        Some("Bearer token"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn token_precedence_uses_token() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let layer = NegotiateLayer::new(Some(&spn)).or_client_cert(mapper());
+    // The vector's ticket isn't for this server, so it is rejected rather than falling back to the certificate
+    let token = format!("Negotiate {}", BASE64_STANDARD.encode(vectors::KERBEROS_AP_REQ));
+    let response = send(&router(layer), Some(ClientCertificate::verified(MACHINE)), Some(&token)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
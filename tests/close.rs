@@ -0,0 +1,136 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{Router, routing::get, serve::Listener};
+use axum_negotiate_layer::{Authenticated, LayerStats, NegotiateInfo, NegotiateLayer, WithNegotiateInfo};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+
+#[allow(dead_code)]
+mod keytab;
+
+/// Listener accepting a single in-memory connection
+struct OneConnection(Option<DuplexStream>);
+impl Listener for OneConnection {
+    type Io = DuplexStream;
+    type Addr = ();
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.0.take() {
+            Some(io) => (io, ()),
+            None => std::future::pending().await,
+        }
+    }
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(())
+    }
+}
+
+/// Serves `router` on a new connection, returning the client half
+fn connect(router: Router) -> DuplexStream {
+    let (client, server) = duplex(64 * 1024);
+    let listener = OneConnection(Some(server)).with_negotiate_info();
+    let service = router.into_make_service_with_connect_info::<NegotiateInfo>();
+    tokio::spawn(async move { axum::serve(listener, service).await });
+    client
+}
+
+/// Sends a request with `token` and returns the status code of the response
+async fn exchange(client: &mut DuplexStream, token: &[u8]) -> u16 {
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Negotiate {}\r\n\r\n",
+        BASE64_STANDARD.encode(token)
+    );
+    client.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    let head_end = loop {
+        let read = client.read(&mut buf).await.unwrap();
+        assert_ne!(read, 0, "connection closed before the response");
+        response.extend_from_slice(&buf[..read]);
+        if let Some(end) = response.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+    let head = String::from_utf8(response[..head_end].to_vec()).unwrap();
+    let content_length: usize = head
+        .lines()
+        .find_map(|line| {
+            line.to_ascii_lowercase()
+                .strip_prefix("content-length:")?
+                .trim()
+                .parse()
+                .ok()
+        })
+        .unwrap_or(0);
+    while response.len() < head_end + content_length {
+        let read = client.read(&mut buf).await.unwrap();
+        response.extend_from_slice(&buf[..read]);
+    }
+    head.split(' ').nth(1).unwrap().parse().unwrap()
+}
+
+/// Waits for `condition`, as the server notices the closed connection asynchronously
+async fn eventually(condition: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("condition not met after closing the connection");
+}
+
+#[tokio::test]
+async fn releases_pending_context() {
+    keytab::install();
+    let stats = LayerStats::new();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(None).with_stats(&stats));
+    let mut client = connect(router);
+    assert_eq!(exchange(&mut client, keytab::NEG_TOKEN_INIT_WITHOUT_TOKEN).await, 401);
+    assert_eq!(stats.pending(), 1);
+    drop(client);
+    eventually(|| stats.pending() == 0).await;
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn releases_authenticated_context() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let stats = LayerStats::new();
+    let stray = Arc::new(Mutex::new(None));
+    let handler_stray = stray.clone();
+    let router = Router::new()
+        .route(
+            "/",
+            get(move |auth: Authenticated| async move {
+                *handler_stray.lock().unwrap() = Some(auth);
+            }),
+        )
+        .layer(NegotiateLayer::new(Some(&spn)).with_stats(&stats));
+    let mut client = connect(router);
+    assert_eq!(exchange(&mut client, &token).await, 200);
+    assert_eq!(stats.authenticated(), 1);
+    drop(client);
+    eventually(|| stats.authenticated() == 0).await;
+    let mut stray = stray.lock().unwrap().take().unwrap();
+    assert!(stray.is_released());
+    assert_eq!(stray.original_token(), None);
+    assert!(!stray.client().is_empty(), "the client name stays available");
+}
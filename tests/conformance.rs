@@ -0,0 +1,161 @@
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{
+    InitialToken, Mech, NegotiateInfo, NegotiateLayer, NegotiateToken, NtlmDetails, NtlmPolicy,
+    parse_negotiate_authorization,
+};
+use base64::{
+    Engine,
+    prelude::{BASE64_STANDARD, BASE64_STANDARD_NO_PAD},
+};
+use http::{HeaderValue, Request, StatusCode, header::AUTHORIZATION};
+use tower::Service;
+
+mod vectors;
+use vectors::*;
+
+type Expected = Option<fn() -> InitialToken>;
+
+/// Every vector with what it should be classified as when sent as the first token of a handshake
+const CLASSIFICATION: &[(&str, &[u8], Expected)] = &[
+    (
+        "windows",
+        WINDOWS_NEG_TOKEN_INIT,
+        Some(|| InitialToken::Spnego(vec![Mech::MsKerberos, Mech::Kerberos, Mech::NegoEx, Mech::Ntlm])),
+    ),
+    (
+        "firefox",
+        FIREFOX_NEG_TOKEN_INIT,
+        Some(|| InitialToken::Spnego(vec![Mech::Kerberos, Mech::MsKerberos])),
+    ),
+    (
+        "curl",
+        CURL_NEG_TOKEN_INIT,
+        Some(|| InitialToken::Spnego(vec![Mech::Kerberos])),
+    ),
+    (
+        "ntlm only",
+        NTLM_ONLY_NEG_TOKEN_INIT,
+        Some(|| InitialToken::Spnego(vec![Mech::Ntlm])),
+    ),
+    ("accept-incomplete", NEG_TOKEN_RESP_ACCEPT_INCOMPLETE, None),
+    ("accept-completed", NEG_TOKEN_RESP_ACCEPT_COMPLETED, None),
+    ("reject", NEG_TOKEN_RESP_REJECT, None),
+    ("ntlm negotiate", NTLM_NEGOTIATE, Some(|| InitialToken::Ntlm)),
+    ("ntlm authenticate", NTLM_AUTHENTICATE, Some(|| InitialToken::Ntlm)),
+    ("kerberos", KERBEROS_AP_REQ, Some(|| InitialToken::Kerberos)),
+];
+
+#[test]
+fn classification() {
+    for (name, token, expected) in CLASSIFICATION {
+        assert_eq!(InitialToken::parse(token), expected.map(|f| f()), "{name}");
+    }
+}
+
+#[test]
+fn kerberos_offered() {
+    let offers_kerberos = |token| InitialToken::parse(token).is_some_and(|parsed| parsed.offers_kerberos());
+    assert!(offers_kerberos(WINDOWS_NEG_TOKEN_INIT));
+    assert!(offers_kerberos(FIREFOX_NEG_TOKEN_INIT));
+    assert!(offers_kerberos(CURL_NEG_TOKEN_INIT));
+    assert!(offers_kerberos(KERBEROS_AP_REQ));
+    assert!(!offers_kerberos(NTLM_ONLY_NEG_TOKEN_INIT));
+    assert!(!offers_kerberos(NTLM_NEGOTIATE));
+}
+
+#[test]
+fn truncated_der_is_rejected() {
+    for (name, token, _) in CLASSIFICATION {
+        if token.starts_with(b"NTLMSSP\0") {
+            continue;
+        }
+        for end in 0..token.len() {
+            assert_eq!(InitialToken::parse(&token[..end]), None, "{name} truncated at {end}");
+        }
+    }
+}
+
+#[test]
+fn ntlm_messages() {
+    let details = NtlmDetails::from_token(NTLM_AUTHENTICATE).unwrap();
+    assert_eq!(details.domain, "DOM");
+    assert_eq!(details.workstation, "WS");
+    assert_eq!(NtlmPolicy::default().check(NTLM_AUTHENTICATE), Ok(()));
+    assert_eq!(NtlmDetails::from_token(NTLM_NEGOTIATE), None);
+    assert_eq!(NtlmPolicy::default().check(NTLM_NEGOTIATE), Ok(()));
+    for end in 0..NTLM_AUTHENTICATE.len() {
+        assert_eq!(
+            NtlmDetails::from_token(&NTLM_AUTHENTICATE[..end]),
+            None,
+            "truncated at {end}"
+        );
+        // Without signature and message type, the token isn't recognized as an AUTHENTICATE message at all
+        if end >= 12 {
+            let result = NtlmPolicy::default().check(&NTLM_AUTHENTICATE[..end]);
+            assert!(result.is_err(), "truncated at {end}");
+        }
+    }
+}
+
+/// Tokens sent in accepted `Authorization` headers, each encoded with and without padding
+const ACCEPTED_TOKENS: &[(&str, &[u8])] = &[
+    ("windows", WINDOWS_NEG_TOKEN_INIT),
+    ("curl", CURL_NEG_TOKEN_INIT),
+    ("ntlm negotiate", NTLM_NEGOTIATE),
+    ("ntlm authenticate", NTLM_AUTHENTICATE),
+    ("kerberos", KERBEROS_AP_REQ),
+];
+
+/// Spellings of an `Authorization` header carrying the token encoded as `{}`
+const ACCEPTED_HEADERS: &[&str] = &[
+    "Negotiate {}",
+    "negotiate {}",
+    "NEGOTIATE {}",
+    "Negotiate  {}",
+    "Negotiate\t{}",
+    " Negotiate {} ",
+    "\tNegotiate \t{}\t",
+];
+
+#[test]
+fn accepted_headers() {
+    for (name, token) in ACCEPTED_TOKENS {
+        for encoded in [BASE64_STANDARD.encode(token), BASE64_STANDARD_NO_PAD.encode(token)] {
+            for spelling in ACCEPTED_HEADERS {
+                let header = HeaderValue::from_str(&spelling.replace("{}", &encoded)).unwrap();
+                let parsed = NegotiateToken::parse_header(&header).unwrap_or_else(|e| panic!("{name} {header:?}: {e}"));
+                assert_eq!(parsed.decode().unwrap(), *token, "{name} {header:?}");
+                let parsed = parse_negotiate_authorization(&header, &["Negotiate"]).unwrap();
+                assert_eq!(parsed.decode().unwrap(), *token, "{name} {header:?}");
+            }
+        }
+    }
+}
+
+/// `Authorization` headers that are answered without involving the backend
+const REJECTED_HEADERS: &[(Option<&[u8]>, &str)] = &[
+    (None, "No Authorization given"),
+    (Some(b"Negotiate"), "Invalid Authorization Header"),
+    (Some(b"Basic dXNlcjpwYXNz"), "Invalid Authorization Header"),
+    (Some(b"NTLM TlRMTVNTUAABAAAA"), "Invalid Authorization Header"),
+    (Some(b"Negotiate\xff YIIB"), "Invalid Authorization Header"),
+];
+
+#[tokio::test]
+async fn rejected_headers() {
+    let mut router = Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(None));
+    for (authorization, message) in REJECTED_HEADERS {
+        let mut request = Request::builder().uri("/");
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, HeaderValue::from_bytes(authorization).unwrap());
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+        let response = router.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{authorization:?}");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, message.as_bytes(), "{authorization:?}");
+    }
+}
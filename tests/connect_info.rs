@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, connect_info::Connected},
+    routing::get,
+};
+use axum_negotiate_layer::{
+    HandshakeStatus, MisusePolicy, NegotiateInfo, NegotiateLayer, Negotiated, WithNegotiateInfo,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tower::ServiceExt;
+
+/// An application's own connect info
+#[derive(Clone, Debug)]
+struct Peer(SocketAddr);
+impl Connected<SocketAddr> for Peer {
+    fn connect_info(addr: SocketAddr) -> Self {
+        Self(addr)
+    }
+}
+
+/// Route answering with both connect infos
+fn router(layer: NegotiateLayer) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(
+                |ConnectInfo(negotiated): ConnectInfo<Negotiated<Peer>>, info: NegotiateInfo| async move {
+                    format!("{} {:?}", negotiated.inner.0, info.status())
+                },
+            ),
+        )
+        .layer(layer)
+}
+
+async fn body(response: axum::response::Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+fn request(token: Option<&[u8]>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(Negotiated {
+        inner: Peer(([192, 0, 2, 1], 1234).into()),
+        negotiate: NegotiateInfo::new(),
+    }));
+    request
+}
+
+#[tokio::test]
+async fn challenges() {
+    let layer = NegotiateLayer::new(None).misuse_policy(MisusePolicy::InternalServerError);
+    let response = router(layer.clone().with_connect_info::<Peer>())
+        .oneshot(request(None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    // Without knowing about the connect info type, the layer can't find the NegotiateInfo
+    let response = router(layer).oneshot(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn both_extractable() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let response = router(NegotiateLayer::new(Some(&spn)).with_connect_info::<Peer>())
+        .oneshot(request(Some(&token)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        body(response).await,
+        format!("192.0.2.1:1234 {:?}", HandshakeStatus::Authenticated)
+    );
+}
+
+#[tokio::test]
+async fn served() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = router(NegotiateLayer::new(None).with_connect_info::<Peer>())
+        .into_make_service_with_connect_info::<Negotiated<Peer>>();
+    tokio::spawn(async move { axum::serve(listener.with_negotiate_info(), service).await });
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    // The connect info was created, or the layer would have failed with a misuse panic
+    assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+}
@@ -0,0 +1,117 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    FailureReason, HandshakeStatus, LayerStats, NegotiateFailure, NegotiateInfo, NegotiateLayer, NtlmPolicy,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+async fn send(router: &Router, info: &NegotiateInfo, token: &[u8]) -> Response {
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)))
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    router.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn rejects_new_handshake_mid_exchange() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let stats = LayerStats::new();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)).with_stats(&stats));
+    let info = NegotiateInfo::new();
+    let response = send(&router, &info, vectors::NTLM_NEGOTIATE).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(info.status(), HandshakeStatus::Pending);
+
+    let response = send(&router, &info, vectors::WINDOWS_NEG_TOKEN_INIT).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(
+        &body[..],
+        b"authentication failed: token doesn't continue the pending handshake"
+    );
+    assert_eq!(stats.failed(FailureReason::WrongContinuation), 1);
+    assert_eq!(stats.failed(FailureReason::Rejected), 0);
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn repeats_response_to_retried_token() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let stats = LayerStats::new();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)).with_stats(&stats));
+    let info = NegotiateInfo::new();
+    let first = send(&router, &info, vectors::NTLM_NEGOTIATE).await;
+    let retry = send(&router, &info, vectors::NTLM_NEGOTIATE).await;
+    assert_eq!(retry.status(), StatusCode::UNAUTHORIZED);
+    // The backend would have issued a new challenge, had the context been stepped again
+    assert_eq!(retry.headers()[WWW_AUTHENTICATE], first.headers()[WWW_AUTHENTICATE]);
+    assert_eq!(info.status(), HandshakeStatus::Pending);
+    assert_eq!(stats.pending(), 1);
+    assert_eq!(stats.failed(FailureReason::WrongContinuation), 0);
+    assert_eq!(stats.failed(FailureReason::Rejected), 0);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn reports_reset_of_pending_handshake() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let resets = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = resets.clone();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)).on_handshake_reset(move |rounds| recorded.lock().unwrap().push(rounds)));
+    let info = NegotiateInfo::new();
+    send(&router, &info, vectors::NTLM_NEGOTIATE).await;
+    assert!(resets.lock().unwrap().is_empty());
+
+    let response = send(&router, &info, vectors::WINDOWS_NEG_TOKEN_INIT).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+    assert_eq!(*resets.lock().unwrap(), [2]);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn policy_rejection_is_not_a_reset() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let resets = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = resets.clone();
+    let policy = NtlmPolicy {
+        require_channel_bindings: true,
+        ..Default::default()
+    };
+    let stats = LayerStats::new();
+    let layer = NegotiateLayer::new(Some(&spn))
+        .ntlm_policy(policy)
+        .with_stats(&stats)
+        .on_handshake_reset(move |rounds| recorded.lock().unwrap().push(rounds));
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    let info = NegotiateInfo::new();
+    send(&router, &info, vectors::NTLM_NEGOTIATE).await;
+    assert_eq!(info.status(), HandshakeStatus::Pending);
+
+    // The AUTHENTICATE message carries no channel bindings
+    let response = send(&router, &info, vectors::NTLM_AUTHENTICATE).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(stats.failed(FailureReason::Policy), 1);
+    let failure = response.extensions().get::<NegotiateFailure>().unwrap();
+    assert_eq!((failure.reason, failure.leg), (FailureReason::Policy, 2));
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+    assert!(resets.lock().unwrap().is_empty());
+}
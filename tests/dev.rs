@@ -0,0 +1,116 @@
+#![cfg(feature = "dev-insecure")]
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{
+    Authenticated, FailureReason, IdentityChangePolicy, LayerStats, NegotiateInfo, NegotiateLayer,
+};
+use http::{Request, StatusCode};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn authenticates_without_credentials() {
+    let stats = LayerStats::new();
+    let handshakes = Arc::new(AtomicUsize::new(0));
+    let counter = handshakes.clone();
+    let layer = NegotiateLayer::new(Some("HTTP/unused.example.com"))
+        .with_stats(&stats)
+        .on_handshake_complete(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })
+        .dev_identity("alice@EXAMPLE.COM");
+    let router = Router::new()
+        .route("/", get(|mut auth: Authenticated| async move { auth.client() }))
+        .layer(layer);
+    let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"alice@EXAMPLE.COM");
+    assert_eq!(handshakes.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.succeeded(), 1);
+}
+
+#[cfg(feature = "health")]
+#[tokio::test]
+async fn healthy_without_backend() {
+    let layer = NegotiateLayer::new(Some("HTTP/unused.example.com")).dev_identity("alice@EXAMPLE.COM");
+    assert_eq!(
+        layer.credential_health().await,
+        axum_negotiate_layer::CredentialHealth::Ok
+    );
+}
+
+async fn client_response(layer: NegotiateLayer) -> axum::response::Response {
+    let router = Router::new()
+        .route("/", get(|mut auth: Authenticated| async move { auth.client() }))
+        .layer(layer);
+    let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    router.oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn rejects_machine_accounts() {
+    for machine in [
+        "WS01$@EXAMPLE.COM",
+        "EXAMPLE\\WS01$",
+        "host/ws01.example.com@EXAMPLE.COM",
+    ] {
+        let stats = LayerStats::new();
+        let layer = NegotiateLayer::new(None)
+            .reject_machine_accounts(true)
+            .with_stats(&stats)
+            .dev_identity(machine);
+        let response = client_response(layer).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{machine}");
+        assert!(response.extensions().get::<Authenticated>().is_none());
+        assert_eq!(stats.failed(FailureReason::MachineAccount), 1);
+        assert_eq!(stats.succeeded(), 0);
+    }
+    let layer = NegotiateLayer::new(None)
+        .reject_machine_accounts(true)
+        .dev_identity("alice@EXAMPLE.COM");
+    assert_eq!(client_response(layer).await.status(), StatusCode::OK);
+    let layer = NegotiateLayer::new(None).dev_identity("WS01$@EXAMPLE.COM");
+    assert_eq!(client_response(layer).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn machine_account_pattern() {
+    let layer = NegotiateLayer::new(None)
+        .reject_machine_accounts(true)
+        .machine_account_pattern(|principal| principal.starts_with("svc-"))
+        .dev_identity("svc-backup@EXAMPLE.COM");
+    assert_eq!(client_response(layer).await.status(), StatusCode::FORBIDDEN);
+    let layer = NegotiateLayer::new(None)
+        .reject_machine_accounts(true)
+        .machine_account_pattern(|principal| principal.starts_with("svc-"))
+        .dev_identity("WS01$@EXAMPLE.COM");
+    assert_eq!(client_response(layer).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn identity_change() {
+    let stats = LayerStats::new();
+    let info = NegotiateInfo::new();
+    let mut statuses = Vec::new();
+    // Routes with different development identities, served over the same connection
+    for client in ["alice@EXAMPLE.COM", "bob@EXAMPLE.COM"] {
+        let layer = NegotiateLayer::new(None)
+            .identity_change_policy(IdentityChangePolicy::Reject)
+            .with_stats(&stats)
+            .dev_identity(client);
+        let router = Router::new().route("/", get(|| async {})).layer(layer);
+        let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(info.clone()));
+        statuses.push(router.oneshot(request).await.unwrap().status());
+    }
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::FORBIDDEN]);
+    assert_eq!(stats.identity_changes(), 1);
+    assert_eq!(stats.failed(FailureReason::IdentityChanged), 1);
+}
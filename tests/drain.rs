@@ -0,0 +1,108 @@
+#![cfg(feature = "drain")]
+use std::time::Duration;
+
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    Drainer, FailureReason, NegotiateFailure, NegotiateInfo, NegotiateLayer, NegotiateProgress,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod keytab;
+
+async fn send(router: &Router, info: &NegotiateInfo, token: Option<&[u8]>) -> Response {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    router.clone().oneshot(request).await.unwrap()
+}
+
+fn refused(response: &Response) -> bool {
+    response.status() == StatusCode::SERVICE_UNAVAILABLE
+        && response
+            .extensions()
+            .get::<NegotiateFailure>()
+            .is_some_and(|failure| failure.reason == FailureReason::Draining)
+}
+
+#[tokio::test]
+async fn refuses_new_handshakes() {
+    let spn = keytab::install();
+    let drainer = Drainer::new();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(spn)).with_drainer(&drainer));
+    let pending = NegotiateInfo::new();
+    let response = send(&router, &pending, Some(keytab::NEG_TOKEN_INIT_WITHOUT_TOKEN)).await;
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::HandshakeLeg(1))
+    );
+    // Only authenticated connections are waited on
+    assert_eq!(drainer.active_connections(), 0);
+    assert!(drainer.drain(Duration::ZERO).await);
+    assert!(drainer.is_draining());
+    let response = send(
+        &router,
+        &NegotiateInfo::new(),
+        Some(keytab::NEG_TOKEN_INIT_WITHOUT_TOKEN),
+    )
+    .await;
+    assert!(refused(&response), "{response:?}");
+    // Neither can a pending handshake continue
+    let response = send(&router, &pending, Some(keytab::NEG_TOKEN_INIT_WITHOUT_TOKEN)).await;
+    assert!(refused(&response), "{response:?}");
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn waits_for_authenticated_connections() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let token = || {
+        let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+        match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+            .initialize()
+            .unwrap()
+        {
+            StepOut::Pending(pending) => pending.next_token().to_vec(),
+            StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+        }
+    };
+    let drainer = Drainer::new();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)).with_drainer(&drainer));
+    let authenticated = NegotiateInfo::new();
+    assert_eq!(
+        send(&router, &authenticated, Some(&token())).await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(drainer.active_connections(), 1);
+
+    let draining = tokio::spawn({
+        let drainer = drainer.clone();
+        async move { drainer.drain(Duration::from_secs(10)).await }
+    });
+    // Gives up while the connection is open
+    assert!(!drainer.drain(Duration::from_millis(50)).await);
+    assert!(!draining.is_finished());
+    // The authenticated connection is still served, new ones can't authenticate
+    assert_eq!(send(&router, &authenticated, None).await.status(), StatusCode::OK);
+    let response = send(&router, &NegotiateInfo::new(), Some(&token())).await;
+    assert!(refused(&response), "{response:?}");
+    assert_eq!(drainer.active_connections(), 1);
+
+    authenticated.close();
+    assert_eq!(drainer.active_connections(), 0);
+    assert!(draining.await.unwrap());
+}
@@ -0,0 +1,55 @@
+use std::env;
+
+use axum_negotiate_layer::{CCACHE_VAR, EnvError, KEYTAB_VAR, NegotiateLayer, SPN_VAR, SpnParseError};
+
+// The environment is process-wide, so all cases run sequentially in this single test
+#[test]
+fn from_env() {
+    let set = |var, value: Option<&str>| match value {
+        // SAFETY: no other test in this binary touches the environment
+        Some(value) => unsafe { env::set_var(var, value) },
+        None => unsafe { env::remove_var(var) },
+    };
+    let error = |spn, keytab, ccache| {
+        set(SPN_VAR, spn);
+        set(KEYTAB_VAR, keytab);
+        set(CCACHE_VAR, ccache);
+        NegotiateLayer::from_env().err()
+    };
+    let keytab = env::temp_dir().join("axum-negotiate-layer-env-test.keytab");
+    std::fs::write(&keytab, b"").unwrap();
+    let keytab = keytab.to_str().unwrap();
+    let file_keytab = format!("FILE:{keytab}");
+
+    assert_eq!(error(None, None, None), Some(EnvError::Missing(SPN_VAR)));
+    assert_eq!(error(Some(""), None, None), Some(EnvError::Empty(SPN_VAR)));
+    assert_eq!(error(Some("  "), None, None), Some(EnvError::Empty(SPN_VAR)));
+    assert_eq!(
+        error(Some("HTTP"), None, None),
+        Some(EnvError::InvalidSpn(SpnParseError::MissingHost))
+    );
+    assert_eq!(error(Some("HTTP/host.example.com"), None, None), None);
+    assert_eq!(error(Some("HTTP@host.example.com"), Some(keytab), None), None);
+    assert_eq!(error(Some("HTTP@host.example.com"), Some(&file_keytab), None), None);
+    assert_eq!(error(Some("HTTP@host.example.com"), Some("MEMORY:test"), None), None);
+    assert_eq!(
+        error(
+            Some("HTTP@host.example.com"),
+            Some("FILE:/nonexistent/http.keytab"),
+            None
+        ),
+        Some(EnvError::KeytabNotFound("/nonexistent/http.keytab".to_owned()))
+    );
+    assert_eq!(
+        error(Some("HTTP@host.example.com"), Some(""), None),
+        Some(EnvError::Empty(KEYTAB_VAR))
+    );
+    assert_eq!(
+        error(Some("HTTP@host.example.com"), None, Some("")),
+        Some(EnvError::Empty(CCACHE_VAR))
+    );
+    assert_eq!(
+        error(Some("HTTP@host.example.com"), None, Some("FILE:/tmp/krb5cc_test")),
+        None
+    );
+}
@@ -0,0 +1,54 @@
+use std::convert::Infallible;
+
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{EpaPolicy, FailureReason, LayerStats, NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use kenobi::channel_bindings::Channel;
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+/// A TLS connection whose library can't provide channel bindings
+struct NoBindings;
+impl Channel for NoBindings {
+    type Error = Infallible;
+    fn channel_bindings(&self) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(None)
+    }
+}
+
+async fn send(layer: NegotiateLayer, info: NegotiateInfo) -> Response {
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    let token = BASE64_STANDARD.encode(vectors::WINDOWS_NEG_TOKEN_INIT);
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, format!("Negotiate {token}"))
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info));
+    router.oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn required_refuses_connection_without_bindings() {
+    for info in [
+        NegotiateInfo::new(),
+        NegotiateInfo::new().with_channel(&NoBindings).unwrap(),
+    ] {
+        let stats = LayerStats::new();
+        let layer = NegotiateLayer::new(None)
+            .extended_protection(EpaPolicy::Required)
+            .with_stats(&stats);
+        let response = send(layer, info).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Negotiate");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"extended protection required");
+        assert_eq!(stats.failed(FailureReason::Policy), 1);
+    }
+}
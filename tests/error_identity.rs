@@ -0,0 +1,49 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{ERROR_CLIENT_HEADER, NegotiateInfo, NegotiateLayer, SessionIdentity};
+use http::{Request, StatusCode};
+use tower::ServiceExt;
+
+fn router(layer: NegotiateLayer) -> Router {
+    Router::new()
+        .route("/", get(|| async { "hello" }))
+        .route("/missing", get(|| async { StatusCode::NOT_FOUND }))
+        .route("/broken", get(|| async { StatusCode::INTERNAL_SERVER_ERROR }))
+        .layer(layer)
+}
+
+async fn send(router: &Router, uri: &str, client: Option<&str>) -> Response {
+    let mut request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    if let Some(client) = client {
+        request.extensions_mut().insert(SessionIdentity(client.to_owned()));
+    }
+    router.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn annotates_inner_errors() {
+    let router = router(
+        NegotiateLayer::new(None)
+            .or_session(true)
+            .annotate_errors_with_identity(true),
+    );
+    for uri in ["/missing", "/broken"] {
+        let response = send(&router, uri, Some("alice@EXAMPLE.COM")).await;
+        assert_eq!(response.headers()[ERROR_CLIENT_HEADER], "alice@EXAMPLE.COM", "{uri}");
+    }
+    let response = send(&router, "/", Some("alice@EXAMPLE.COM")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(ERROR_CLIENT_HEADER).is_none());
+    // Challenges of the layer itself have no client to report
+    let response = send(&router, "/broken", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get(ERROR_CLIENT_HEADER).is_none());
+}
+
+#[tokio::test]
+async fn disabled_by_default() {
+    let router = router(NegotiateLayer::new(None).or_session(true));
+    let response = send(&router, "/broken", Some("alice@EXAMPLE.COM")).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(response.headers().get(ERROR_CLIENT_HEADER).is_none());
+}
@@ -0,0 +1,74 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{Authenticated, Clock, HandshakeStatus, NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+/// A clock that only moves when advanced
+struct MockClock(Mutex<SystemTime>);
+impl MockClock {
+    fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+fn token(spn: &str) -> Vec<u8> {
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    match ClientBuilder::new_from_credentials(credentials, Some(spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    }
+}
+
+fn request(info: &NegotiateInfo, token: Option<&[u8]>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    request
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn expires_mid_connection() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let clock = Arc::new(MockClock(Mutex::new(SystemTime::UNIX_EPOCH)));
+    let layer = NegotiateLayer::new(Some(&spn))
+        .with_clock(clock.clone())
+        .max_session_age(Duration::from_secs(60));
+    let router = Router::new().route("/", get(|_: Authenticated| async {})).layer(layer);
+    let info = NegotiateInfo::new();
+    let response = router
+        .clone()
+        .oneshot(request(&info, Some(&token(&spn))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    clock.advance(Duration::from_secs(59));
+    let response = router.clone().oneshot(request(&info, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    clock.advance(Duration::from_secs(1));
+    let response = router.oneshot(request(&info, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+}
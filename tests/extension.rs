@@ -0,0 +1,97 @@
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{MisusePolicy, NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+fn router(spn: Option<&str>) -> Router {
+    Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(spn))
+}
+
+#[tokio::test]
+async fn bare_extension_is_used() {
+    let info = NegotiateInfo::new();
+    let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    request.extensions_mut().insert(info.clone());
+    let response = router(None).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!info.is_authenticated());
+}
+
+#[tokio::test]
+#[should_panic = "No NegotiateInfo"]
+async fn missing_info_panics() {
+    let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let _ = router(None).oneshot(request).await;
+}
+
+/// Authenticates `info` with a full handshake, inserting it into each request with `insert`
+async fn handshake(spn: &str, info: &NegotiateInfo, insert: impl Fn(&mut Request<Body>, NegotiateInfo)) {
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let mut step = ClientBuilder::new_from_credentials(credentials, Some(spn))
+        .initialize()
+        .unwrap();
+    loop {
+        let token = match &step {
+            StepOut::Pending(pending) => pending.next_token(),
+            StepOut::Finished(finished) => finished.last_token().unwrap(),
+        };
+        let mut request = Request::builder()
+            .uri("/")
+            .header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)))
+            .body(Body::empty())
+            .unwrap();
+        insert(&mut request, info.clone());
+        let response = router(Some(spn)).oneshot(request).await.unwrap();
+        if response.status() == StatusCode::OK {
+            break;
+        }
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let StepOut::Pending(pending) = step else {
+            panic!("server wants another round after the client finished");
+        };
+        let challenge = response.headers()[http::header::WWW_AUTHENTICATE].to_str().unwrap();
+        let token = BASE64_STANDARD
+            .decode(challenge.strip_prefix("Negotiate ").unwrap())
+            .unwrap();
+        step = pending.step(&token).unwrap();
+    }
+    assert!(info.is_authenticated());
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn handshake_with_connect_info() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    handshake(&spn, &NegotiateInfo::new(), |request, info| {
+        request.extensions_mut().insert(ConnectInfo(info));
+    })
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn handshake_with_bare_extension() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    handshake(&spn, &NegotiateInfo::new(), |request, info| {
+        request.extensions_mut().insert(info);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn missing_info_as_internal_server_error() {
+    let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let router = Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(None).misuse_policy(MisusePolicy::InternalServerError));
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -0,0 +1,60 @@
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum_negotiate_layer::{
+    AnonymousIdentity, Authenticated, AuthenticatedRejection, Identity, MisusePolicy, NegotiateInfo,
+};
+use http::{Request, StatusCode};
+
+#[test]
+fn rejects_missing_negotiate_info() {
+    let (parts, ()) = Request::new(()).into_parts();
+    assert_eq!(
+        Authenticated::try_from_parts(&parts).unwrap_err(),
+        AuthenticatedRejection::MissingNegotiateInfo
+    );
+}
+
+#[test]
+fn rejects_unauthenticated_connection() {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    parts.extensions.insert(ConnectInfo(NegotiateInfo::new()));
+    assert_eq!(
+        Authenticated::try_from_parts(&parts).unwrap_err(),
+        AuthenticatedRejection::NotAuthenticated
+    );
+}
+
+#[tokio::test]
+async fn anonymous_identity() {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    parts.extensions.insert(ConnectInfo(NegotiateInfo::new()));
+    parts.extensions.insert(AnonymousIdentity::new("ANONYMOUS"));
+    let identity = Identity::from_request_parts(&mut parts, &()).await.unwrap();
+    assert_eq!(identity.name(), "ANONYMOUS");
+    assert!(!identity.is_authenticated());
+}
+
+#[tokio::test]
+async fn rejects_identity_without_anonymous_fallback() {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    let rejection = Identity::from_request_parts(&mut parts, &()).await.unwrap_err();
+    assert_eq!(rejection.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn misuse_as_internal_server_error() {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    parts.extensions.insert(MisusePolicy::InternalServerError);
+    let rejection = Authenticated::from_request_parts(&mut parts, &()).await.unwrap_err();
+    assert_eq!(rejection.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    parts.extensions.insert(ConnectInfo(NegotiateInfo::new()));
+    let rejection = Authenticated::from_request_parts(&mut parts, &()).await.unwrap_err();
+    assert_eq!(rejection.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+#[should_panic = "NegotiateInfo was not authorized"]
+async fn misuse_panics_by_default() {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    parts.extensions.insert(ConnectInfo(NegotiateInfo::new()));
+    let _ = Authenticated::from_request_parts(&mut parts, &()).await;
+}
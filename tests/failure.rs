@@ -0,0 +1,159 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    EpaPolicy, FailureReason, NegotiateFailure, NegotiateInfo, NegotiateLayer, NegotiateProgress, Scheme,
+    SessionIdentity,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode, Version,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod keytab;
+#[allow(dead_code)]
+mod vectors;
+
+fn request(authorization: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    request
+}
+
+fn token(token: &[u8]) -> String {
+    format!("Negotiate {}", BASE64_STANDARD.encode(token))
+}
+
+async fn send(layer: NegotiateLayer, request: Request<Body>) -> Response {
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    router.oneshot(request).await.unwrap()
+}
+
+fn failure(response: &Response) -> Option<(FailureReason, u8)> {
+    let failure = response.extensions().get::<NegotiateFailure>()?;
+    Some((failure.reason, failure.leg))
+}
+
+#[tokio::test]
+async fn invalid_header() {
+    let response = send(NegotiateLayer::new(None), request(Some("Basic c2VjcmV0"))).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(failure(&response), Some((FailureReason::InvalidHeader, 0)));
+    // Without a host to derive the SPN from
+    let layer = NegotiateLayer::new(None).spn_from_host("HTTP");
+    let response = send(layer, request(Some("Negotiate dG9rZW4="))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(failure(&response), Some((FailureReason::InvalidHeader, 0)));
+}
+
+#[tokio::test]
+async fn policy_on_first_leg() {
+    let layer = NegotiateLayer::new(None).extended_protection(EpaPolicy::Required);
+    let response = send(layer, request(Some(&token(vectors::WINDOWS_NEG_TOKEN_INIT)))).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(failure(&response), Some((FailureReason::Policy, 1)));
+    // NTLM can't complete on a connection closed after the response
+    let mut http10 = request(Some(&token(vectors::NTLM_NEGOTIATE)));
+    *http10.version_mut() = Version::HTTP_10;
+    let response = send(NegotiateLayer::new(None), http10).await;
+    assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(failure(&response), Some((FailureReason::Policy, 1)));
+}
+
+#[tokio::test]
+async fn basic_auth() {
+    let layer = || {
+        NegotiateLayer::new(None)
+            .basic_auth("intranet", |user, password| user == "alice" && password == "secret")
+            .scheme_order(&[Scheme::Negotiate, Scheme::Basic])
+    };
+    let wrong = format!("Basic {}", BASE64_STANDARD.encode("alice:wrong"));
+    let response = send(layer(), request(Some(&wrong))).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(failure(&response), Some((FailureReason::Rejected, 0)));
+    let response = send(layer(), request(Some("Basic !!"))).await;
+    assert_eq!(failure(&response), Some((FailureReason::InvalidHeader, 0)));
+    let right = format!("Basic {}", BASE64_STANDARD.encode("alice:secret"));
+    let response = send(layer(), request(Some(&right))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(failure(&response), None);
+}
+
+#[tokio::test]
+async fn absent_without_failure() {
+    // The challenge to a request without credentials
+    let response = send(NegotiateLayer::new(None), request(None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::ChallengeIssued)
+    );
+    assert_eq!(failure(&response), None);
+    // An authenticated request
+    let mut in_session = request(None);
+    in_session
+        .extensions_mut()
+        .insert(SessionIdentity("alice@EXAMPLE.COM".to_owned()));
+    let response = send(NegotiateLayer::new(None).or_session(true), in_session).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(failure(&response), None);
+}
+
+#[cfg(feature = "drain")]
+#[tokio::test]
+async fn draining() {
+    let drainer = axum_negotiate_layer::Drainer::new();
+    let layer = NegotiateLayer::new(None).with_drainer(&drainer);
+    assert!(drainer.drain(std::time::Duration::ZERO).await);
+    let response = send(layer, request(Some("Negotiate dG9rZW4="))).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(failure(&response), Some((FailureReason::Draining, 0)));
+}
+
+#[tokio::test]
+#[cfg_attr(unix, ignore = "the GSSAPI backend of kenobi panics on tokens it rejects")]
+async fn forbidden_after_failed_handshake() {
+    let spn = keytab::install();
+    // A token the backend can't make sense of
+    let malformed = token(b"not a security token");
+    let response = send(NegotiateLayer::new(Some(spn)), request(Some(&malformed))).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(failure(&response), Some((FailureReason::Rejected, 1)));
+    assert!(response.headers().contains_key(WWW_AUTHENTICATE));
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(spn)).forbid_failed_handshakes(true));
+    let info = NegotiateInfo::new();
+    let on_connection = |authorization: Option<&str>| {
+        let mut request = request(authorization);
+        request.extensions_mut().insert(ConnectInfo(info.clone()));
+        request
+    };
+    let response = router.clone().oneshot(on_connection(Some(&malformed))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(failure(&response), Some((FailureReason::Rejected, 1)));
+    assert!(!response.headers().contains_key(WWW_AUTHENTICATE));
+    // The connection stays unauthenticated, and without an attempted handshake the client is asked to authenticate
+    assert!(!info.is_authenticated());
+    let response = router.oneshot(on_connection(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key(WWW_AUTHENTICATE));
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN and a keytab for it"]
+async fn rejected_by_backend() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let response = send(
+        NegotiateLayer::new(Some(&spn)),
+        request(Some(&token(vectors::KERBEROS_AP_REQ))),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(failure(&response), Some((FailureReason::Rejected, 1)));
+}
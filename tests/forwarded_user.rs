@@ -0,0 +1,58 @@
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{Authenticated, CertIdentityMapper, ClientCertificate, NegotiateInfo, NegotiateLayer};
+use http::{HeaderMap, HeaderName, Request, StatusCode};
+use tower::ServiceExt;
+
+const FORWARDED_USER: HeaderName = HeaderName::from_static("x-forwarded-user");
+
+/// Answers with the client, the transport client and the forwarded user header as seen by the handler
+async fn send(cert: &[u8], forwarded_user: Option<&str>) -> String {
+    let mapper = CertIdentityMapper::new(|cert| String::from_utf8(cert.der().to_vec()).ok());
+    let layer = NegotiateLayer::new(None)
+        .or_client_cert(mapper)
+        .trust_forwarded_user(FORWARDED_USER, &["proxy@EXAMPLE.COM"]);
+    let router = Router::new()
+        .route(
+            "/",
+            get(|mut auth: Authenticated, headers: HeaderMap| async move {
+                let header = headers
+                    .get(FORWARDED_USER)
+                    .map(|value| value.to_str().unwrap().to_owned());
+                format!("{} {} {header:?}", auth.client(), auth.transport_client())
+            }),
+        )
+        .layer(layer);
+    let mut request = Request::builder().uri("/");
+    if let Some(user) = forwarded_user {
+        request = request.header(FORWARDED_USER, user);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    let info = NegotiateInfo::new().with_client_cert(ClientCertificate::verified(cert));
+    request.extensions_mut().insert(ConnectInfo(info));
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn trusted_proxy() {
+    assert_eq!(
+        send(b"proxy@EXAMPLE.COM", Some("alice@EXAMPLE.COM")).await,
+        r#"alice@EXAMPLE.COM proxy@EXAMPLE.COM Some("alice@EXAMPLE.COM")"#
+    );
+    // Requests of the proxy itself
+    assert_eq!(
+        send(b"proxy@EXAMPLE.COM", None).await,
+        "proxy@EXAMPLE.COM proxy@EXAMPLE.COM None"
+    );
+}
+
+#[tokio::test]
+async fn untrusted_client() {
+    // The header is stripped, so the handler can't be misled by it either
+    assert_eq!(
+        send(b"mallory@EXAMPLE.COM", Some("alice@EXAMPLE.COM")).await,
+        "mallory@EXAMPLE.COM mallory@EXAMPLE.COM None"
+    );
+}
@@ -0,0 +1,111 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    HandshakeOutcome, HandshakeStatus, NegotiateHandshake, NegotiateInfo, NegotiateLayer, NegotiateProgress,
+    NegotiateToken,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    HeaderName, Request, StatusCode,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+/// Enrollment endpoint running the handshake itself, next to a route behind the layer
+fn router(layer: NegotiateLayer) -> Router {
+    let configured = layer.clone();
+    let enroll = move |handshake: NegotiateHandshake| async move {
+        let handshake = handshake.configured(&configured);
+        let Some(Ok(token)) = handshake.token() else {
+            return handshake.challenge();
+        };
+        let outcome = handshake.step(&token);
+        handshake.challenge_response(outcome)
+    };
+    Router::new()
+        .route("/enroll", get(enroll))
+        .merge(Router::new().route("/", get(|| async {})).layer(layer))
+}
+
+async fn send(router: &Router, info: Option<&NegotiateInfo>, uri: &str, token: Option<&[u8]>) -> Response {
+    let mut request = Request::builder().uri(uri);
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    if let Some(info) = info {
+        request.extensions_mut().insert(ConnectInfo(info.clone()));
+    }
+    router.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn challenges_without_token() {
+    let router = router(NegotiateLayer::new(None));
+    let info = NegotiateInfo::new();
+    let response = send(&router, Some(&info), "/enroll", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[WWW_AUTHENTICATE], "Negotiate");
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::ChallengeIssued)
+    );
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+}
+
+#[tokio::test]
+async fn uses_layer_configuration() {
+    let header = HeaderName::from_static("x-negotiate");
+    let router = router(NegotiateLayer::new(None).challenge_header(header.clone()));
+    let response = send(&router, Some(&NegotiateInfo::new()), "/enroll", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get(WWW_AUTHENTICATE).is_none());
+    assert!(response.headers().contains_key(header));
+}
+
+#[tokio::test]
+async fn requires_negotiate_info() {
+    let router = router(NegotiateLayer::new(None));
+    let response = send(&router, None, "/enroll", None).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it, a client ticket cache and an NTLM capable backend"]
+async fn manual_two_leg_flow() {
+    let router = router(NegotiateLayer::new(Some(&std::env::var("TEST_SPN").unwrap())));
+    let info = NegotiateInfo::new();
+    let response = send(&router, Some(&info), "/enroll", Some(vectors::NTLM_NEGOTIATE)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::HandshakeLeg(1))
+    );
+    assert_eq!(info.status(), HandshakeStatus::Pending);
+    let response = send(&router, Some(&info), "/enroll", Some(vectors::NTLM_AUTHENTICATE)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(info.status(), HandshakeStatus::Authenticated);
+    // The route behind the layer shares the connection's state
+    let response = send(&router, Some(&info), "/", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn rejects_foreign_token() {
+    let info = NegotiateInfo::new();
+    let router = Router::new().route(
+        "/enroll",
+        get(|handshake: NegotiateHandshake| async move {
+            let handshake = handshake.configured(&NegotiateLayer::new(Some(&std::env::var("TEST_SPN").unwrap())));
+            let outcome = handshake.step(&NegotiateToken::new(vectors::KERBEROS_AP_REQ));
+            assert!(matches!(outcome, HandshakeOutcome::Failed(_)));
+            handshake.challenge_response(outcome)
+        }),
+    );
+    let response = send(&router, Some(&info), "/enroll", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+}
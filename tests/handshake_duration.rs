@@ -0,0 +1,90 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{NegotiateInfo, NegotiateLayer, SessionIdentity};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod keytab;
+
+/// A router whose layer records the durations passed to its callback
+fn router(spn: &str) -> (Router, Arc<Mutex<Vec<Duration>>>) {
+    let durations = Arc::new(Mutex::new(Vec::new()));
+    let recorded = durations.clone();
+    let layer = NegotiateLayer::new(Some(spn))
+        .or_session(true)
+        .on_handshake_complete(move |duration| recorded.lock().unwrap().push(duration));
+    (Router::new().route("/", get(|| async {})).layer(layer), durations)
+}
+
+fn request(info: &NegotiateInfo, token: Option<&[u8]>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    request
+}
+
+#[tokio::test]
+async fn not_called_without_completed_handshake() {
+    let (router, durations) = router(keytab::install());
+    let info = NegotiateInfo::new();
+    // The challenge, a round needing another one and an invalid token
+    let response = router.clone().oneshot(request(&info, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let continued = request(&info, Some(keytab::NEG_TOKEN_INIT_WITHOUT_TOKEN));
+    let response = router.clone().oneshot(continued).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let mut invalid = request(&NegotiateInfo::new(), None);
+    invalid
+        .headers_mut()
+        .insert(AUTHORIZATION, "Negotiate !!!".parse().unwrap());
+    let response = router.clone().oneshot(invalid).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    // A request authenticated by its session involves no handshake
+    let mut in_session = request(&NegotiateInfo::new(), None);
+    in_session
+        .extensions_mut()
+        .insert(SessionIdentity("alice@EXAMPLE.COM".to_owned()));
+    let response = router.oneshot(in_session).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(durations.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn called_once_per_handshake() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let (router, durations) = router(&spn);
+    let info = NegotiateInfo::new();
+    let started = std::time::Instant::now();
+    let response = router.clone().oneshot(request(&info, Some(&token))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let elapsed = started.elapsed();
+    // Requests on the authenticated connection don't complete another handshake
+    let response = router.oneshot(request(&info, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let durations = durations.lock().unwrap();
+    assert_eq!(durations.len(), 1);
+    assert!(durations[0] <= elapsed, "{durations:?} took longer than the request");
+}
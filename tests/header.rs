@@ -0,0 +1,146 @@
+//! Property tests for `Authorization` header parsing over pseudo-randomly generated inputs
+
+use axum_negotiate_layer::{ParseError, negotiate_header, parse_negotiate_authorization};
+use http::HeaderValue;
+
+const CASES: usize = 2000;
+
+/// Deterministic xorshift generator, so failures are reproducible
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+        (0..self.below(max_len + 1)).map(|_| self.next() as u8).collect()
+    }
+    fn pick(&mut self, alphabet: &[u8], max_len: usize) -> String {
+        (0..self.below(max_len + 1))
+            .map(|_| char::from(alphabet[self.below(alphabet.len())]))
+            .collect()
+    }
+    fn casing(&mut self, s: &str) -> String {
+        s.chars()
+            .map(|c| {
+                if self.next().is_multiple_of(2) {
+                    c.to_ascii_uppercase()
+                } else {
+                    c.to_ascii_lowercase()
+                }
+            })
+            .collect()
+    }
+}
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/=";
+
+#[test]
+fn arbitrary_input_never_panics() {
+    let mut rng = Rng(1);
+    for _ in 0..CASES {
+        let Ok(header) = HeaderValue::from_bytes(&rng.bytes(64)) else {
+            continue;
+        };
+        let first = parse_negotiate_authorization(&header, &["Negotiate"]);
+        assert_eq!(first, parse_negotiate_authorization(&header, &["Negotiate"]));
+        if let Ok(token) = first {
+            let _ = token.decode();
+        }
+    }
+}
+
+#[test]
+fn scheme_casing_and_whitespace() {
+    let mut rng = Rng(2);
+    for _ in 0..CASES {
+        let scheme = rng.casing("Negotiate");
+        let token = format!("Y{}", rng.pick(BASE64, 40));
+        let before = rng.pick(b" \t", 2);
+        let separator = if rng.next().is_multiple_of(2) { ' ' } else { '\t' };
+        let leading = format!("{separator}{}", rng.pick(b" \t", 3));
+        let trailing = rng.pick(b" \t", 3);
+        let header = HeaderValue::from_str(&format!("{before}{scheme}{leading}{token}{trailing}")).unwrap();
+        let parsed = parse_negotiate_authorization(&header, &["Negotiate"]).unwrap();
+        assert_eq!(parsed.scheme, scheme);
+        assert_eq!(parsed.token, token);
+    }
+}
+
+#[test]
+fn trailing_spaces_and_tabs() {
+    for header in [
+        "Negotiate YII= ",
+        "Negotiate YII=\t",
+        "Negotiate\tYII= \t ",
+        "Negotiate  \t YII=\t\t",
+    ] {
+        let header = HeaderValue::from_str(header).unwrap();
+        let parsed = parse_negotiate_authorization(&header, &["Negotiate"]).unwrap();
+        assert_eq!(parsed.token, "YII=");
+        assert_eq!(parsed.decode(), Ok(vec![0x60, 0x82]));
+    }
+    let header = HeaderValue::from_static("Negotiate \t ");
+    assert_eq!(
+        parse_negotiate_authorization(&header, &["Negotiate"]),
+        Err(ParseError::MissingToken)
+    );
+}
+
+#[test]
+fn other_schemes_are_unsupported() {
+    let mut rng = Rng(3);
+    for _ in 0..CASES {
+        let scheme = format!("X{}", rng.pick(b"abcdefghijklmnopqrstuvwxyz-", 12));
+        let header = HeaderValue::from_str(&format!("{scheme} {}", rng.pick(BASE64, 20))).unwrap();
+        let parsed = parse_negotiate_authorization(&header, &["Negotiate"]);
+        assert!(
+            matches!(parsed, Err(ParseError::UnsupportedScheme | ParseError::MissingToken)),
+            "{header:?}: {parsed:?}"
+        );
+    }
+}
+
+#[test]
+fn missing_token() {
+    for header in ["Negotiate", "Negotiate ", "negotiate    ", "Negotiate \t"] {
+        let header = HeaderValue::from_str(header).unwrap();
+        assert_eq!(
+            parse_negotiate_authorization(&header, &["Negotiate"]),
+            Err(ParseError::MissingToken)
+        );
+    }
+}
+
+#[test]
+fn round_trip_through_response_header() {
+    let mut rng = Rng(4);
+    for _ in 0..CASES {
+        let token = rng.bytes(256);
+        let header = negotiate_header(&token);
+        let parsed = parse_negotiate_authorization(&header, &["Negotiate"]);
+        if token.is_empty() {
+            assert_eq!(parsed, Err(ParseError::MissingToken));
+        } else {
+            assert_eq!(parsed.unwrap().decode().unwrap(), token);
+        }
+    }
+}
+
+#[test]
+fn bounded_decode_checks_size_first() {
+    let header = HeaderValue::from_static("Negotiate YIIGAA==");
+    let parsed = parse_negotiate_authorization(&header, &["Negotiate"]).unwrap();
+    assert_eq!(parsed.decode_bounded(4), Ok(vec![0x60, 0x82, 0x06, 0x00]));
+    assert_eq!(parsed.decode_bounded(3), Err(ParseError::TooLarge));
+    // Rejected by its length alone, without being decoded
+    let header = HeaderValue::from_str(&format!("Negotiate {}", "!".repeat(64))).unwrap();
+    let parsed = parse_negotiate_authorization(&header, &["Negotiate"]).unwrap();
+    assert_eq!(parsed.decode_bounded(47), Err(ParseError::TooLarge));
+    assert_eq!(parsed.decode_bounded(48), Err(ParseError::InvalidBase64));
+}
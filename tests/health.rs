@@ -0,0 +1,124 @@
+#![cfg(feature = "health")]
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum_negotiate_layer::{
+    CredentialHealth, NegotiateLayer, Spn, SpnError,
+    sink::{Event, Sink},
+};
+
+#[allow(dead_code)]
+mod keytab;
+
+const UNKNOWN: &str = "HTTP/unknown.invalid@EXAMPLE.COM";
+
+#[tokio::test]
+async fn healthy_with_keytab() {
+    let spn = keytab::install();
+    let health = NegotiateLayer::new(Some(spn)).credential_health().await;
+    assert_eq!(health, CredentialHealth::Ok);
+    assert!(health.is_ready());
+}
+
+#[tokio::test]
+async fn failed_without_keys() {
+    keytab::install();
+    let health = NegotiateLayer::new(Some(UNKNOWN)).credential_health().await;
+    let CredentialHealth::Failed(failures) = &health else {
+        panic!("{health:?}");
+    };
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].spn.as_deref(), Some("HTTP@unknown.invalid"));
+    assert!(matches!(failures[0].error, SpnError::NotRegistered(_)), "{health:?}");
+    assert!(!health.is_ready());
+}
+
+#[tokio::test]
+async fn degraded_by_one_server_name() {
+    let spn = Spn::parse(keytab::install()).unwrap();
+    let spns = HashMap::from([("unknown.invalid".to_owned(), Spn::parse(UNKNOWN).unwrap())]);
+    let health = NegotiateLayer::new(None)
+        .spn_from_sni(spns, spn)
+        .credential_health()
+        .await;
+    let CredentialHealth::Degraded(failures) = &health else {
+        panic!("{health:?}");
+    };
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].spn.as_deref(), Some("HTTP@unknown.invalid"));
+    assert!(health.is_ready());
+}
+
+#[tokio::test]
+async fn failed_by_every_server_name() {
+    keytab::install();
+    let spns = HashMap::from([("unknown.invalid".to_owned(), Spn::parse(UNKNOWN).unwrap())]);
+    let fallback = Spn::parse("HTTP/other.invalid@EXAMPLE.COM").unwrap();
+    let health = NegotiateLayer::new(None)
+        .spn_from_sni(spns, fallback)
+        .credential_health()
+        .await;
+    let CredentialHealth::Failed(failures) = &health else {
+        panic!("{health:?}");
+    };
+    assert_eq!(failures.len(), 2);
+    assert!(!health.is_ready());
+}
+
+/// Counts the credential acquisitions of a layer
+#[derive(Default)]
+struct Acquisitions(AtomicUsize);
+impl Sink for Acquisitions {
+    fn event(&self, event: &Event<'_>) {
+        if event.message == "Getting local SPNEGO credentials" {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[tokio::test]
+async fn reused_for_ttl() {
+    keytab::install();
+    let acquisitions = Arc::new(Acquisitions::default());
+    let layer = NegotiateLayer::new(Some(UNKNOWN))
+        .credential_health_ttl(Duration::from_secs(60))
+        .with_log_sink(acquisitions.clone());
+    let health = layer.credential_health().await;
+    assert!(!health.is_ready());
+    // Shared by clones, failures included
+    assert_eq!(layer.clone().credential_health().await, health);
+    assert_eq!(acquisitions.0.load(Ordering::Relaxed), 1);
+
+    let acquisitions = Arc::new(Acquisitions::default());
+    let layer = NegotiateLayer::new(Some(UNKNOWN))
+        .credential_health_ttl(Duration::ZERO)
+        .with_log_sink(acquisitions.clone());
+    let _ = layer.credential_health().await;
+    let _ = layer.credential_health().await;
+    assert_eq!(acquisitions.0.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_probes_acquire_once() {
+    keytab::install();
+    let acquisitions = Arc::new(Acquisitions::default());
+    let layer = NegotiateLayer::new(Some(UNKNOWN))
+        .credential_health_ttl(Duration::from_secs(60))
+        .with_log_sink(acquisitions.clone());
+    let probes: Vec<_> = (0..8)
+        .map(|_| {
+            let layer = layer.clone();
+            tokio::spawn(async move { layer.credential_health().await })
+        })
+        .collect();
+    for probe in probes {
+        assert!(!probe.await.unwrap().is_ready());
+    }
+    assert_eq!(acquisitions.0.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,38 @@
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{FailureReason, LayerStats, NegotiateInfo, NegotiateLayer};
+use http::{Request, StatusCode, header::HOST};
+use tower::ServiceExt;
+
+fn request(host: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(host) = host {
+        request = request.header(HOST, host);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    request
+}
+
+#[tokio::test]
+async fn host_required() {
+    let stats = LayerStats::new();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(None).spn_from_host("HTTP").with_stats(&stats));
+    let response = router.clone().oneshot(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"Host header required for Negotiate authentication");
+    assert_eq!(stats.failed(FailureReason::InvalidHeader), 1);
+    let response = router.oneshot(request(Some("www.example.com"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn host_optional_otherwise() {
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(None));
+    let response = router.oneshot(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,136 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::ConnectInfo,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use axum_negotiate_layer::{FailureReason, LayerStats, NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode, Version,
+    header::{AUTHORIZATION, CONNECTION, UPGRADE},
+};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+async fn respond(layer: NegotiateLayer, version: Version, connection: Option<&str>, token: Option<&[u8]>) -> Response {
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    let mut request = Request::builder().uri("/").version(version);
+    if let Some(connection) = connection {
+        request = request.header(CONNECTION, connection);
+    }
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    router.oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn challenge_keeps_alive() {
+    for version in [Version::HTTP_10, Version::HTTP_11] {
+        let response = respond(NegotiateLayer::new(None), version, None, None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[CONNECTION], "keep-alive");
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn multi_leg_needs_keep_alive() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let cases = [
+        (Version::HTTP_10, None, StatusCode::UPGRADE_REQUIRED),
+        (Version::HTTP_10, Some("keep-alive"), StatusCode::UNAUTHORIZED),
+        (Version::HTTP_11, None, StatusCode::UNAUTHORIZED),
+        (Version::HTTP_11, Some("close"), StatusCode::FORBIDDEN),
+        (Version::HTTP_2, None, StatusCode::UNAUTHORIZED),
+    ];
+    for (version, connection, status) in cases {
+        let layer = NegotiateLayer::new(Some(&spn));
+        let response = respond(layer, version, connection, Some(vectors::NTLM_NEGOTIATE)).await;
+        assert_eq!(response.status(), status, "{version:?} with {connection:?}");
+        if status == StatusCode::UNAUTHORIZED {
+            assert_eq!(response.headers()[CONNECTION], "keep-alive");
+        }
+    }
+}
+
+#[tokio::test]
+async fn refuses_ntlm_without_keep_alive() {
+    let response = respond(
+        NegotiateLayer::new(None),
+        Version::HTTP_10,
+        None,
+        Some(vectors::NTLM_NEGOTIATE),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(response.headers()[UPGRADE], "HTTP/1.1");
+    let response = respond(
+        NegotiateLayer::new(None),
+        Version::HTTP_11,
+        Some("close"),
+        Some(vectors::NTLM_ONLY_NEG_TOKEN_INIT),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn non_persistent_transport() {
+    let stats = LayerStats::new();
+    let layer = NegotiateLayer::new(None)
+        .non_persistent_transport(true)
+        .with_stats(&stats);
+    let response = respond(layer, Version::HTTP_11, None, Some(vectors::NTLM_NEGOTIATE)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(
+        &body[..],
+        b"authentication needs another round trip, which this server doesn't support, use Kerberos instead"
+    );
+    assert_eq!(stats.failed(FailureReason::Policy), 1);
+}
+
+#[tokio::test]
+async fn custom_response() {
+    let layer = NegotiateLayer::new(None).non_persistent_response(|| {
+        (
+            StatusCode::UPGRADE_REQUIRED,
+            [(UPGRADE, "HTTP/1.1")],
+            "HTTP/1.1 required",
+        )
+            .into_response()
+    });
+    let response = respond(layer, Version::HTTP_10, None, Some(vectors::NTLM_NEGOTIATE)).await;
+    assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(response.headers()[UPGRADE], "HTTP/1.1");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"HTTP/1.1 required");
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn single_leg_kerberos() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let response = respond(NegotiateLayer::new(Some(&spn)), Version::HTTP_10, None, Some(&token)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,75 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    Authenticated, FailureReason, IdentityChangePolicy, LayerStats, NegotiateInfo, NegotiateLayer, SessionIdentity,
+};
+use http::{Request, StatusCode, Version, header::CONNECTION};
+use tower::ServiceExt;
+
+/// Authenticates `clients` in order by their sessions over one connection, returning the last response
+async fn switch(policy: IdentityChangePolicy, version: Version, clients: &[&str]) -> (Response, LayerStats) {
+    let stats = LayerStats::new();
+    let layer = NegotiateLayer::new(None)
+        .or_session(true)
+        .identity_change_policy(policy)
+        .with_stats(&stats);
+    let router = Router::new()
+        .route("/", get(|mut auth: Authenticated| async move { auth.client() }))
+        .layer(layer);
+    let info = NegotiateInfo::new();
+    let mut responses = Vec::new();
+    for client in clients {
+        let mut request = Request::builder()
+            .uri("/")
+            .version(version)
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(info.clone()));
+        request.extensions_mut().insert(SessionIdentity((*client).to_owned()));
+        responses.push(router.clone().oneshot(request).await.unwrap());
+    }
+    assert_eq!(responses[0].status(), StatusCode::OK);
+    (responses.pop().unwrap(), stats)
+}
+
+const SWITCH: [&str; 2] = ["alice@EXAMPLE.COM", "bob@EXAMPLE.COM"];
+
+#[tokio::test]
+async fn replace() {
+    let (response, stats) = switch(IdentityChangePolicy::Replace, Version::HTTP_11, &SWITCH).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(stats.identity_changes(), 1);
+    assert_eq!(stats.failed(FailureReason::IdentityChanged), 0);
+}
+
+#[tokio::test]
+async fn reject() {
+    let (response, stats) = switch(IdentityChangePolicy::Reject, Version::HTTP_11, &SWITCH).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!response.headers().contains_key(CONNECTION));
+    assert!(response.extensions().get::<Authenticated>().is_none());
+    assert_eq!(stats.identity_changes(), 1);
+    assert_eq!(stats.failed(FailureReason::IdentityChanged), 1);
+}
+
+#[tokio::test]
+async fn close_connection() {
+    let (response, stats) = switch(IdentityChangePolicy::CloseConnection, Version::HTTP_11, &SWITCH).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.headers()[CONNECTION], "close");
+    assert_eq!(stats.identity_changes(), 1);
+    assert_eq!(stats.failed(FailureReason::IdentityChanged), 1);
+    // HTTP/2 has no connection-specific headers
+    let (response, _) = switch(IdentityChangePolicy::CloseConnection, Version::HTTP_2, &SWITCH).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!response.headers().contains_key(CONNECTION));
+}
+
+#[tokio::test]
+async fn same_client_again() {
+    // Realms compare case-insensitively
+    let clients = ["alice@EXAMPLE.COM", "alice@example.com"];
+    let (response, stats) = switch(IdentityChangePolicy::Reject, Version::HTTP_11, &clients).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(stats.identity_changes(), 0);
+    assert_eq!(stats.failed(FailureReason::IdentityChanged), 0);
+}
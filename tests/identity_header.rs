@@ -0,0 +1,52 @@
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    HeaderMap, HeaderName, Request, StatusCode,
+    header::{AUTHORIZATION, USER_AGENT},
+};
+use tower::ServiceExt;
+
+const HEADER: HeaderName = HeaderName::from_static("x-authenticated-user");
+
+/// Responds with the identity header as seen by the inner service
+async fn forwarded(credentials: &str) -> (StatusCode, String) {
+    let layer = NegotiateLayer::new(None)
+        .basic_auth("monitoring", |_, password| password == "secret")
+        .basic_for_user_agents(vec!["check_http".to_owned()])
+        .forward_identity_header(HEADER);
+    let router = Router::new()
+        .route(
+            "/",
+            get(
+                |headers: HeaderMap| async move { format!("{:?}", headers.get_all(HEADER).iter().collect::<Vec<_>>()) },
+            ),
+        )
+        .layer(layer);
+    let mut request = Request::builder()
+        .uri("/")
+        .header(USER_AGENT, "check_http/v2.3.3")
+        .header(AUTHORIZATION, format!("Basic {}", BASE64_STANDARD.encode(credentials)))
+        .header(HEADER, "administrator")
+        .header(HEADER, "root")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    let response = router.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn overwrites_client_value() {
+    assert_eq!(
+        forwarded("nagios:secret").await,
+        (StatusCode::OK, r#"["nagios"]"#.to_owned())
+    );
+}
+
+#[tokio::test]
+async fn strips_client_value_if_name_is_invalid() {
+    assert_eq!(forwarded("na\ngios:secret").await, (StatusCode::OK, "[]".to_owned()));
+}
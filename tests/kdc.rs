@@ -0,0 +1,265 @@
+//! End-to-end handshakes against a real KDC
+//!
+//! Only built with the `kdc-tests` feature, see the README on how to run them. Starting the KDC, e.g. as a container,
+//! is left to the caller, which has to set:
+//! - `KRB5_CONFIG` to a configuration for the realm
+//! - `IT_KDC_REALM`, the realm of the KDC
+//! - `IT_KDC_ADMIN_PRINCIPAL` and `IT_KDC_ADMIN_PASSWORD`, a principal allowed to add principals and extract their keys
+//! - `KRB5_KTNAME` and `KRB5CCNAME` to files the server keytab and the client credential cache are written to
+//! - optionally `IT_KDC_HOST`, the host name of the test SPN (default `localhost`)
+//!
+//! The principals are provisioned with `kadmin` and tickets are obtained with `kinit`, both of which must be in `PATH`.
+//! As the credential cache is shared by the whole process, all cases run sequentially in a single test.
+#![cfg(feature = "kdc-tests")]
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use axum::{Router, routing::get};
+use axum_negotiate_layer::{Authenticated, LayerStats, NegotiateInfo, NegotiateLayer, WithNegotiateInfo};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+const CLIENT: &str = "it-client";
+
+/// Connection details of the KDC, as given in the environment
+struct Kdc {
+    realm: String,
+    host: String,
+    admin: String,
+    password: String,
+    keytab: PathBuf,
+    ccache: String,
+}
+impl Kdc {
+    fn from_env() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_else(|_| panic!("kdc-tests require {name} to be set"));
+        let keytab = var("KRB5_KTNAME");
+        Self {
+            realm: var("IT_KDC_REALM"),
+            host: std::env::var("IT_KDC_HOST").unwrap_or_else(|_| "localhost".to_owned()),
+            admin: var("IT_KDC_ADMIN_PRINCIPAL"),
+            password: var("IT_KDC_ADMIN_PASSWORD"),
+            keytab: PathBuf::from(keytab.strip_prefix("FILE:").unwrap_or(&keytab)),
+            ccache: var("KRB5CCNAME"),
+        }
+    }
+    fn principal(&self, name: &str) -> String {
+        format!("{name}@{}", self.realm)
+    }
+    fn spn(&self, host: &str) -> String {
+        self.principal(&format!("HTTP/{host}"))
+    }
+    fn kadmin(&self, query: &str) {
+        run(Command::new("kadmin").args(["-p", &self.admin, "-w", &self.password, "-q", query]))
+    }
+    /// Adds `principal` with a random key, replacing an existing one
+    fn add_principal(&self, principal: &str) {
+        // Fails if the principal doesn't exist yet, which is fine
+        let _ = Command::new("kadmin")
+            .args(["-p", &self.admin, "-w", &self.password, "-q"])
+            .arg(format!("delprinc -force {principal}"))
+            .output();
+        self.kadmin(&format!("addprinc -randkey {principal}"));
+    }
+    fn export_key(&self, principal: &str, keytab: &Path) {
+        self.kadmin(&format!("ktadd -k {} {principal}", keytab.display()));
+    }
+    /// Obtains a ticket for the client, valid for `lifetime`
+    fn kinit(&self, client_keytab: &Path, lifetime: &str) {
+        run(Command::new("kinit").args(["-k", "-t"]).arg(client_keytab).args([
+            "-c",
+            &self.ccache,
+            "-l",
+            lifetime,
+            &self.principal(CLIENT),
+        ]))
+    }
+}
+
+fn run(command: &mut Command) {
+    let output = command.output().expect("failed to run command");
+    assert!(
+        output.status.success(),
+        "{command:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Serves a route answering with the client's name on a random port
+async fn serve(layer: NegotiateLayer) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = Router::new()
+        .route("/", get(|mut auth: Authenticated| async move { auth.client() }))
+        .layer(layer)
+        .into_make_service_with_connect_info::<NegotiateInfo>();
+    tokio::spawn(async move { axum::serve(listener.with_negotiate_info(), router).await });
+    addr
+}
+
+struct Response {
+    status: u16,
+    negotiate: Option<Vec<u8>>,
+    cache_control: Option<String>,
+    body: String,
+}
+
+/// A minimal HTTP/1.1 client, as the handshake has to happen on a single connection
+struct Client(BufReader<TcpStream>);
+impl Client {
+    async fn connect(addr: SocketAddr) -> Self {
+        Self(BufReader::new(TcpStream::connect(addr).await.unwrap()))
+    }
+    async fn get(&mut self, token: Option<&[u8]>) -> Response {
+        let authorization = match token {
+            Some(token) => format!("Authorization: Negotiate {}\r\n", BASE64_STANDARD.encode(token)),
+            None => String::new(),
+        };
+        let request = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{authorization}\r\n");
+        self.0.get_mut().write_all(request.as_bytes()).await.unwrap();
+        let mut status_line = String::new();
+        self.0.read_line(&mut status_line).await.unwrap();
+        let status = status_line.split(' ').nth(1).unwrap().parse().unwrap();
+        let mut negotiate = None;
+        let mut cache_control = None;
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            self.0.read_line(&mut line).await.unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap();
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap();
+            } else if name.eq_ignore_ascii_case("cache-control") {
+                cache_control = Some(value.to_owned());
+            } else if name.eq_ignore_ascii_case("www-authenticate")
+                && let Some(token) = value.strip_prefix("Negotiate ")
+            {
+                negotiate = Some(BASE64_STANDARD.decode(token).unwrap());
+            }
+        }
+        let mut body = vec![0; content_length];
+        self.0.read_exact(&mut body).await.unwrap();
+        Response {
+            status,
+            negotiate,
+            cache_control,
+            body: String::from_utf8(body).unwrap(),
+        }
+    }
+}
+
+/// Creates the client's first token for `spn` from the credential cache
+fn initial_step(spn: &str) -> StepOut<kenobi::cred::Outbound> {
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    ClientBuilder::new_from_credentials(credentials, Some(spn))
+        .request_mutual_auth()
+        .initialize()
+        .unwrap()
+}
+
+/// Runs a full handshake with mutual authentication, returning the final response
+async fn handshake(client: &mut Client, spn: &str) -> Response {
+    let mut step = initial_step(spn);
+    loop {
+        let token = match &step {
+            StepOut::Pending(pending) => pending.next_token().to_vec(),
+            StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+        };
+        let response = client.get(Some(&token)).await;
+        let (StepOut::Pending(pending), Some(server_token)) = (step, &response.negotiate) else {
+            return response;
+        };
+        step = pending.step(server_token).expect("server token rejected by the client");
+        if response.status != 401 {
+            assert!(matches!(step, StepOut::Finished(_)), "mutual authentication incomplete");
+            return response;
+        }
+    }
+}
+
+#[tokio::test]
+async fn end_to_end() {
+    let kdc = Kdc::from_env();
+    let spn = kdc.spn(&kdc.host);
+    let alias_spn = kdc.spn(&format!("alias.{}", kdc.host));
+    let unknown_spn = kdc.spn(&format!("unknown.{}", kdc.host));
+    let client_keytab = std::env::temp_dir().join("axum-negotiate-layer-it-client.keytab");
+    let _ = std::fs::remove_file(&client_keytab);
+    kdc.add_principal(&spn);
+    kdc.add_principal(&alias_spn);
+    kdc.add_principal(&unknown_spn);
+    kdc.add_principal(CLIENT);
+    kdc.export_key(&spn, &kdc.keytab);
+    kdc.export_key(&alias_spn, &kdc.keytab);
+    kdc.export_key(CLIENT, &client_keytab);
+    kdc.kinit(&client_keytab, "1h");
+    let stats = LayerStats::new();
+    let addr = serve(NegotiateLayer::new(Some(&spn)).with_stats(&stats)).await;
+
+    // Multiple requests on one connection, with the handshake only on the first
+    let mut client = Client::connect(addr).await;
+    let response = handshake(&mut client, &spn).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, kdc.principal(CLIENT));
+    // The final token is specific to the connection
+    assert_eq!(response.cache_control.as_deref(), Some("no-store"));
+    // Kerberos completes with the first token
+    assert_eq!(stats.succeeded_in_rounds(1), 1);
+    let response = client.get(None).await;
+    assert_eq!(response.status, 200, "connection should stay authenticated");
+    assert_eq!(response.cache_control, None);
+
+    // A ticket for an SPN the server has no keys for
+    let mut client = Client::connect(addr).await;
+    let response = handshake(&mut client, &unknown_spn).await;
+    assert_eq!(response.status, 401);
+
+    // Without a configured SPN, every principal in the keytab is accepted
+    let any_addr = serve(NegotiateLayer::new(Some(&spn)).accept_any_keytab_principal()).await;
+    for accepted in [&spn, &alias_spn] {
+        let mut client = Client::connect(any_addr).await;
+        let response = handshake(&mut client, accepted).await;
+        assert_eq!(response.status, 200, "{accepted}");
+    }
+    let mut client = Client::connect(any_addr).await;
+    let response = handshake(&mut client, &unknown_spn).await;
+    assert_eq!(response.status, 401);
+
+    // Every request has to authenticate again
+    let no_cache_addr = serve(NegotiateLayer::new(Some(&spn)).no_cache(true)).await;
+    let mut client = Client::connect(no_cache_addr).await;
+    let response = handshake(&mut client, &spn).await;
+    assert_eq!(response.status, 200);
+    let response = client.get(None).await;
+    assert_eq!(response.status, 401, "authentication should be discarded");
+    let response = handshake(&mut client, &spn).await;
+    assert_eq!(response.status, 200);
+
+    // A token whose ticket expires before it reaches the server
+    kdc.kinit(&client_keytab, "10s");
+    let token = match initial_step(&spn) {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    tokio::time::sleep(Duration::from_secs(15)).await;
+    let mut client = Client::connect(addr).await;
+    let response = client.get(Some(&token)).await;
+    assert_eq!(response.status, 401);
+}
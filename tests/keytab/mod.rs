@@ -0,0 +1,54 @@
+//! A keytab with a made-up key, for tests that need server credentials but no KDC
+//!
+//! No ticket is ever accepted with it, but the backend acquires credentials for [`SPN`] and answers a handshake without
+//! an optimistic token with a continue.
+
+use std::sync::OnceLock;
+
+use axum_negotiate_layer::KEYTAB_VAR;
+
+/// The principal with a key in the keytab
+pub const SPN: &str = "HTTP/localhost@EXAMPLE.COM";
+
+/// SPNEGO `negTokenInit` offering Kerberos without an optimistic token, which the acceptor answers with a continue
+pub const NEG_TOKEN_INIT_WITHOUT_TOKEN: &[u8] = &[
+    0x60, 0x1b, 0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02, 0xa0, 0x11, 0x30, 0x0f, 0xa0, 0x0d, 0x30, 0x0b, 0x06,
+    0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02,
+];
+
+/// Writes the keytab and points the backend at it, returning the SPN with a key
+///
+/// Must be called before the backend is used in the test binary. The file is written once per process below cargo's
+/// temporary directory for integration tests. With a keytab configured already, that one is used together with
+/// `TEST_SPN`, as for the tests against a KDC.
+pub fn install() -> &'static str {
+    static SPN_WITH_KEY: OnceLock<String> = OnceLock::new();
+    SPN_WITH_KEY.get_or_init(|| {
+        if std::env::var_os(KEYTAB_VAR).is_some() {
+            return std::env::var("TEST_SPN").expect("TEST_SPN is required with a configured keytab");
+        }
+        let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.keytab", std::process::id()));
+        std::fs::write(&path, contents("HTTP", "localhost")).unwrap();
+        // SAFETY: the tests calling this do so before using the backend, the others don't read the environment
+        unsafe { std::env::set_var(KEYTAB_VAR, format!("FILE:{}", path.display())) };
+        SPN.to_owned()
+    })
+}
+
+/// A keytab with a made-up key for `service/host@EXAMPLE.COM`
+pub fn contents(service: &str, host: &str) -> Vec<u8> {
+    let counted = |bytes: &[u8]| [&(bytes.len() as u16).to_be_bytes()[..], bytes].concat();
+    let entry = [
+        &2u16.to_be_bytes()[..],
+        &counted(b"EXAMPLE.COM"),
+        &counted(service.as_bytes()),
+        &counted(host.as_bytes()),
+        &1u32.to_be_bytes(),
+        &0u32.to_be_bytes(),
+        &[1],
+        &18u16.to_be_bytes(),
+        &counted(&[0x42; 32]),
+    ]
+    .concat();
+    [&[0x05, 0x02][..], &(entry.len() as u32).to_be_bytes(), &entry].concat()
+}
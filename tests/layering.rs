@@ -0,0 +1,136 @@
+#![cfg(debug_assertions)]
+use std::sync::{Arc, Mutex};
+
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{
+    NegotiateInfo, NegotiateLayer,
+    sink::{Event, Level, Sink},
+};
+use http::{
+    Request,
+    header::{CONTENT_LENGTH, HOST},
+};
+use tower::ServiceExt;
+
+/// Records the warnings logged
+#[derive(Default)]
+struct Warnings(Mutex<Vec<String>>);
+impl Sink for Warnings {
+    fn event(&self, event: &Event<'_>) {
+        if event.level == Level::Warn {
+            self.0.lock().unwrap().push(event.message.to_owned());
+        }
+    }
+}
+impl Warnings {
+    fn count(&self, prefix: &str) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| message.starts_with(prefix))
+            .count()
+    }
+}
+
+async fn send_all(layer: NegotiateLayer, requests: Vec<(http::request::Builder, Body, NegotiateInfo)>) {
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    for (request, body, info) in requests {
+        let mut request = request.body(body).unwrap();
+        request.extensions_mut().insert(ConnectInfo(info));
+        router.clone().oneshot(request).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn consumed_body() {
+    let warnings = Arc::new(Warnings::default());
+    let layer = NegotiateLayer::new(None).with_log_sink(warnings.clone());
+    let consumed = || Request::builder().uri("/").header(CONTENT_LENGTH, "5");
+    send_all(
+        layer,
+        vec![
+            (consumed(), Body::empty(), NegotiateInfo::new()),
+            (consumed(), Body::empty(), NegotiateInfo::new()),
+        ],
+    )
+    .await;
+    assert_eq!(warnings.count("Request body was consumed"), 1);
+    // Intact bodies and requests without one
+    let warnings = Arc::new(Warnings::default());
+    let layer = NegotiateLayer::new(None).with_log_sink(warnings.clone());
+    send_all(
+        layer,
+        vec![
+            (consumed(), Body::from("hello"), NegotiateInfo::new()),
+            (Request::builder().uri("/"), Body::empty(), NegotiateInfo::new()),
+        ],
+    )
+    .await;
+    assert_eq!(warnings.count("Request body was consumed"), 0);
+}
+
+#[tokio::test]
+async fn rewritten_host() {
+    let warnings = Arc::new(Warnings::default());
+    let layer = NegotiateLayer::new(None)
+        .spn_from_host("HTTP")
+        .with_log_sink(warnings.clone());
+    send_all(
+        layer,
+        vec![
+            (
+                Request::builder().uri("/").header(HOST, "backend:8080"),
+                Body::empty(),
+                NegotiateInfo::new().with_sni("www.example.com"),
+            ),
+            (
+                Request::builder()
+                    .uri("https://www.example.com/")
+                    .header(HOST, "backend"),
+                Body::empty(),
+                NegotiateInfo::new(),
+            ),
+        ],
+    )
+    .await;
+    assert_eq!(warnings.count("Host doesn't match"), 1);
+}
+
+#[tokio::test]
+async fn matching_host() {
+    let warnings = Arc::new(Warnings::default());
+    let layer = NegotiateLayer::new(None)
+        .spn_from_host("HTTP")
+        .with_log_sink(warnings.clone());
+    send_all(
+        layer,
+        vec![
+            (
+                Request::builder().uri("/").header(HOST, "WWW.example.com:443"),
+                Body::empty(),
+                NegotiateInfo::new().with_sni("www.example.com"),
+            ),
+            (
+                Request::builder().uri("/").header(HOST, "www.example.com"),
+                Body::empty(),
+                NegotiateInfo::new(),
+            ),
+        ],
+    )
+    .await;
+    assert_eq!(warnings.count("Host doesn't match"), 0);
+    // Only checked if the SPN is derived from the host
+    let warnings = Arc::new(Warnings::default());
+    let layer = NegotiateLayer::new(None).with_log_sink(warnings.clone());
+    send_all(
+        layer,
+        vec![(
+            Request::builder().uri("/").header(HOST, "backend"),
+            Body::empty(),
+            NegotiateInfo::new().with_sni("www.example.com"),
+        )],
+    )
+    .await;
+    assert_eq!(warnings.count("Host doesn't match"), 0);
+}
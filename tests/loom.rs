@@ -0,0 +1,209 @@
+//! Model checks of the connection state, run with `RUSTFLAGS="--cfg negotiate_loom" cargo test --release --test loom`
+#![cfg(negotiate_loom)]
+
+use axum_negotiate_layer::state::{Connection, State};
+use loom::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
+
+type TestConnection = Connection<u32, u32>;
+
+#[test]
+fn rounds_never_interleave() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        let in_round = loom::sync::Arc::new(AtomicBool::new(false));
+        let started = loom::sync::Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..2)
+            .map(|id| {
+                let connection = connection.clone();
+                let in_round = in_round.clone();
+                let started = started.clone();
+                thread::spawn(move || {
+                    connection.round(|pending| {
+                        assert!(!in_round.swap(true, Ordering::SeqCst), "rounds interleaved");
+                        let next = match pending {
+                            None => {
+                                started.fetch_add(1, Ordering::SeqCst);
+                                State::Pending(id)
+                            }
+                            Some(other) => {
+                                assert_ne!(other, id, "a round saw its own pending handshake");
+                                State::Authenticated(other)
+                            }
+                        };
+                        in_round.store(false, Ordering::SeqCst);
+                        (next, ())
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Some(()));
+        }
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+        assert!(connection.is_authenticated());
+    });
+}
+
+#[test]
+fn finish_racing_read() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        let finisher = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.round(|_| (State::Authenticated(7), ())))
+        };
+        let seen = connection.with_authenticated(|context| *context);
+        assert!(seen.is_none() || seen == Some(7));
+        assert_eq!(finisher.join().unwrap(), Some(()));
+        assert_eq!(connection.with_authenticated(|context| *context), Some(7));
+    });
+}
+
+#[test]
+fn authentication_is_never_undone() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        let finisher = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.round(|_| (State::Authenticated(1), ())))
+        };
+        let reset = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.round(|_| (State::Unauthorized, ())))
+        };
+        if connection.is_authenticated() {
+            // The reset may run in between, but must not revert the authentication
+            thread::yield_now();
+            assert!(connection.is_authenticated());
+        }
+        assert_eq!(finisher.join().unwrap(), Some(()));
+        reset.join().unwrap();
+        assert!(connection.is_authenticated());
+    });
+}
+
+#[test]
+fn round_after_authentication_is_refused() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        let rounds: Vec<_> = (1..=2)
+            .map(|id| {
+                let connection = connection.clone();
+                thread::spawn(move || connection.round(|_| (State::Authenticated(id), id)))
+            })
+            .collect();
+        let ran: Vec<_> = rounds.into_iter().filter_map(|round| round.join().unwrap()).collect();
+        assert_eq!(ran.len(), 1);
+        assert_eq!(connection.with_authenticated(|context| *context), Some(ran[0]));
+    });
+}
+
+/// Asserts that the mirror agrees with the state, which it must whenever the state isn't locked
+fn assert_mirrored(connection: &TestConnection) {
+    connection.inspect(|state| {
+        assert_eq!(
+            connection.is_authenticated(),
+            matches!(state, State::Authenticated(_)),
+            "mirror disagrees with {state:?}"
+        );
+    });
+}
+
+#[test]
+fn mirror_follows_eviction() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        let finisher = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.round(|_| (State::Authenticated(1), ())))
+        };
+        let evictor = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.evict_if(|context| *context == 1))
+        };
+        assert_mirrored(&connection);
+        assert_eq!(finisher.join().unwrap(), Some(()));
+        let evicted = evictor.join().unwrap();
+        assert_mirrored(&connection);
+        // Eviction only misses a context established after it
+        assert_eq!(connection.is_authenticated(), !evicted);
+    });
+}
+
+#[test]
+fn eviction_racing_round() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        connection.round(|_| (State::Authenticated(1), ()));
+        let evictor = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.evict_if(|context| *context == 1))
+        };
+        let round = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.round(|_| (State::Authenticated(2), ())))
+        };
+        assert!(evictor.join().unwrap(), "the established context wasn't evicted");
+        let ran = round.join().unwrap().is_some();
+        assert_mirrored(&connection);
+        // The round is only refused while the first context is still there, and then it is evicted after all
+        let context = connection.with_authenticated(|context| *context);
+        assert_eq!(context, ran.then_some(2));
+    });
+}
+
+#[test]
+fn eviction_keeps_other_contexts() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        connection.round(|_| (State::Authenticated(1), ()));
+        let evictor = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.evict_if(|context| *context == 2))
+        };
+        assert!(connection.is_authenticated());
+        assert!(!evictor.join().unwrap());
+        assert_mirrored(&connection);
+        assert_eq!(connection.with_authenticated(|context| *context), Some(1));
+    });
+}
+
+#[test]
+fn close_racing_read() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        connection.round(|_| (State::Authenticated(1), ()));
+        let closer = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.reset())
+        };
+        let seen = connection.with_authenticated(|context| *context);
+        assert!(seen.is_none() || seen == Some(1));
+        assert_mirrored(&connection);
+        assert!(matches!(closer.join().unwrap(), State::Authenticated(1)));
+        assert_mirrored(&connection);
+        assert!(!connection.is_authenticated());
+        assert_eq!(connection.with_authenticated(|context| *context), None);
+    });
+}
+
+#[test]
+fn close_racing_round() {
+    loom::model(|| {
+        let connection = TestConnection::new();
+        let round = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.round(|_| (State::Pending(1), ())))
+        };
+        let previous = connection.reset();
+        assert_eq!(round.join().unwrap(), Some(()));
+        assert_mirrored(&connection);
+        // Either the close dropped the pending handshake, or the round started after it
+        let pending = connection.inspect(|state| matches!(state, State::Pending(1)));
+        assert_eq!(pending, matches!(previous, State::Unauthorized));
+        assert!(!connection.is_authenticated());
+    });
+}
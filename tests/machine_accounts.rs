@@ -0,0 +1,26 @@
+use axum_negotiate_layer::is_machine_account;
+
+#[test]
+fn windows_forms() {
+    assert!(is_machine_account("WS01$@EXAMPLE.COM"));
+    assert!(is_machine_account("EXAMPLE\\WS01$"));
+    assert!(is_machine_account("example\\ws01$"));
+}
+
+#[test]
+fn mit_forms() {
+    assert!(is_machine_account("host/ws01.example.com@EXAMPLE.COM"));
+    assert!(is_machine_account("HOST/ws01.example.com"));
+    assert!(is_machine_account("ws01$@EXAMPLE.COM"));
+}
+
+#[test]
+fn users_and_services() {
+    assert!(!is_machine_account("alice@EXAMPLE.COM"));
+    assert!(!is_machine_account("EXAMPLE\\alice"));
+    assert!(!is_machine_account("alice"));
+    // A user whose name merely contains a dollar sign
+    assert!(!is_machine_account("$alice@EXAMPLE.COM"));
+    assert!(!is_machine_account("HTTP/web.example.com@EXAMPLE.COM"));
+    assert!(!is_machine_account("hostmaster@EXAMPLE.COM"));
+}
@@ -0,0 +1,184 @@
+use axum_negotiate_layer::{NtlmDetails, NtlmPolicy, NtlmPolicyViolation};
+
+const ESS: u32 = 0x0008_0000;
+const ANONYMOUS: u32 = 0x0000_0800;
+const UNICODE: u32 = 0x0000_0001;
+
+fn utf16(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// Builds an NTLM AUTHENTICATE message with the given NT response, user name and flags
+fn authenticate(nt_response: &[u8], user: &str, flags: u32) -> Vec<u8> {
+    message(
+        [&[0; 24], nt_response, &utf16("DOM"), &utf16(user), &utf16("WS"), &[]],
+        flags,
+    )
+}
+
+/// Builds an NTLM AUTHENTICATE message from its payload fields in order
+fn message(payloads: [&[u8]; 6], flags: u32) -> Vec<u8> {
+    let mut header = b"NTLMSSP\0".to_vec();
+    header.extend_from_slice(&3u32.to_le_bytes());
+    let mut payload = Vec::new();
+    let mut offset = 64u32;
+    for field in payloads {
+        let length = field.len() as u16;
+        header.extend_from_slice(&length.to_le_bytes());
+        header.extend_from_slice(&length.to_le_bytes());
+        header.extend_from_slice(&offset.to_le_bytes());
+        payload.extend_from_slice(field);
+        offset += u32::from(length);
+    }
+    header.extend_from_slice(&flags.to_le_bytes());
+    header.extend(payload);
+    header
+}
+
+fn v2_response(av_pairs: &[u8]) -> Vec<u8> {
+    let mut response = vec![0xAA; 16];
+    response.extend_from_slice(&[1, 1, 0, 0, 0, 0, 0, 0]);
+    response.extend_from_slice(&[0x11; 16]);
+    response.extend_from_slice(&[0; 4]);
+    response.extend_from_slice(av_pairs);
+    response
+}
+
+const TARGET_INFO: &[u8] = &[2, 0, 6, 0, b'D', 0, b'O', 0, b'M', 0, 0, 0, 0, 0];
+
+#[test]
+fn accepts_ntlm_v2() {
+    let message = authenticate(&v2_response(TARGET_INFO), "user", UNICODE | ESS);
+    assert_eq!(NtlmPolicy::default().check(&message), Ok(()));
+}
+
+#[test]
+fn rejects_ntlm_v1() {
+    let message = authenticate(&[0x55; 24], "user", UNICODE | ESS);
+    assert_eq!(NtlmPolicy::default().check(&message), Err(NtlmPolicyViolation::NtlmV1));
+    let lenient = NtlmPolicy {
+        require_v2: false,
+        require_target_info: false,
+        ..Default::default()
+    };
+    assert_eq!(lenient.check(&message), Ok(()));
+}
+
+#[test]
+fn rejects_missing_ess() {
+    let message = authenticate(&v2_response(TARGET_INFO), "user", UNICODE);
+    assert_eq!(
+        NtlmPolicy::default().check(&message),
+        Err(NtlmPolicyViolation::NoExtendedSessionSecurity)
+    );
+}
+
+#[test]
+fn rejects_missing_target_info() {
+    let message = authenticate(&v2_response(&[0, 0, 0, 0]), "user", UNICODE | ESS);
+    assert_eq!(
+        NtlmPolicy::default().check(&message),
+        Err(NtlmPolicyViolation::NoTargetInfo)
+    );
+}
+
+#[test]
+fn requires_channel_bindings() {
+    let require = NtlmPolicy {
+        require_channel_bindings: true,
+        ..Default::default()
+    };
+    let message = authenticate(&v2_response(TARGET_INFO), "user", UNICODE | ESS);
+    assert_eq!(NtlmPolicy::default().check(&message), Ok(()));
+    assert_eq!(require.check(&message), Err(NtlmPolicyViolation::NoChannelBindings));
+    // Clients without TLS send the pair with zeros
+    let mut unbound = vec![10, 0, 16, 0];
+    unbound.extend_from_slice(&[0; 16]);
+    unbound.extend_from_slice(TARGET_INFO);
+    let message = authenticate(&v2_response(&unbound), "user", UNICODE | ESS);
+    assert_eq!(require.check(&message), Err(NtlmPolicyViolation::NoChannelBindings));
+    let mut bound = TARGET_INFO[..TARGET_INFO.len() - 4].to_vec();
+    bound.extend_from_slice(&[10, 0, 16, 0]);
+    bound.extend_from_slice(&[0x5c; 16]);
+    bound.extend_from_slice(&[0, 0, 0, 0]);
+    let message = authenticate(&v2_response(&bound), "user", UNICODE | ESS);
+    assert_eq!(require.check(&message), Ok(()));
+}
+
+#[test]
+fn rejects_anonymous_unless_allowed() {
+    let message = authenticate(&[], "", UNICODE | ANONYMOUS);
+    assert_eq!(
+        NtlmPolicy::default().check(&message),
+        Err(NtlmPolicyViolation::Anonymous)
+    );
+    let allow_anonymous = NtlmPolicy {
+        allow_anonymous: true,
+        ..Default::default()
+    };
+    assert_eq!(allow_anonymous.check(&message), Ok(()));
+}
+
+#[test]
+fn finds_message_wrapped_in_spnego() {
+    let mut token = vec![
+        0xa1, 0x82, 0x01, 0x00, 0x30, 0x81, 0xfd, 0xa2, 0x81, 0xfa, 0x04, 0x81, 0xf7,
+    ];
+    token.extend(authenticate(&[0x55; 24], "user", UNICODE | ESS));
+    assert_eq!(NtlmPolicy::default().check(&token), Err(NtlmPolicyViolation::NtlmV1));
+}
+
+#[test]
+fn rejects_truncated_message() {
+    let message = authenticate(&v2_response(TARGET_INFO), "user", UNICODE | ESS);
+    assert_eq!(
+        NtlmPolicy::default().check(&message[..40]),
+        Err(NtlmPolicyViolation::Malformed)
+    );
+}
+
+#[test]
+fn ignores_non_ntlm_tokens() {
+    let kerberos = [
+        0x60, 0x82, 0x02, 0x00, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02,
+    ];
+    assert_eq!(NtlmPolicy::default().check(&kerberos), Ok(()));
+}
+
+#[test]
+fn details_unicode() {
+    let message = message(
+        [
+            &[0; 24],
+            &v2_response(TARGET_INFO),
+            &utf16("KÖLN"),
+            &utf16("user"),
+            &utf16("WS-01"),
+            &[],
+        ],
+        UNICODE | ESS,
+    );
+    let expected = NtlmDetails {
+        domain: "KÖLN".to_owned(),
+        workstation: "WS-01".to_owned(),
+    };
+    assert_eq!(NtlmDetails::from_token(&message), Some(expected));
+}
+
+#[test]
+fn details_oem() {
+    let message = message([&[0; 24], &[0x55; 24], b"CORP\x99", b"user", b"WS-01", &[]], ESS);
+    let expected = NtlmDetails {
+        domain: "CORP\u{FFFD}".to_owned(),
+        workstation: "WS-01".to_owned(),
+    };
+    assert_eq!(NtlmDetails::from_token(&message), Some(expected));
+}
+
+#[test]
+fn no_details_for_kerberos() {
+    let kerberos = [
+        0x60, 0x82, 0x02, 0x00, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02,
+    ];
+    assert_eq!(NtlmDetails::from_token(&kerberos), None);
+}
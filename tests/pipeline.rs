@@ -0,0 +1,99 @@
+use axum::{Router, routing::get, serve::Listener};
+use axum_negotiate_layer::{FailureReason, LayerStats, NegotiateInfo, NegotiateLayer, WithNegotiateInfo};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+
+#[allow(dead_code)]
+mod vectors;
+
+/// Listener accepting a single in-memory connection
+struct OneConnection(Option<DuplexStream>);
+impl Listener for OneConnection {
+    type Io = DuplexStream;
+    type Addr = ();
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.0.take() {
+            Some(io) => (io, ()),
+            None => std::future::pending().await,
+        }
+    }
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(())
+    }
+}
+
+/// Reads the next response from `client`, keeping what follows it in `buffer`, and returns its head and body
+async fn read_response(client: &mut DuplexStream, buffer: &mut Vec<u8>) -> (String, String) {
+    let mut chunk = [0; 4096];
+    let head_end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = client.read(&mut chunk).await.unwrap();
+        assert_ne!(read, 0, "connection closed before the response");
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+    let head = String::from_utf8(buffer[..head_end].to_vec()).unwrap();
+    let content_length: usize = head
+        .lines()
+        .find_map(|line| {
+            line.to_ascii_lowercase()
+                .strip_prefix("content-length:")?
+                .trim()
+                .parse()
+                .ok()
+        })
+        .unwrap_or(0);
+    while buffer.len() < head_end + content_length {
+        let read = client.read(&mut chunk).await.unwrap();
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = String::from_utf8(buffer[head_end..head_end + content_length].to_vec()).unwrap();
+    buffer.drain(..head_end + content_length);
+    (head, body)
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn one_handshake_for_pipelined_requests() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let stats = LayerStats::new();
+    let layer = NegotiateLayer::new(Some(&spn)).with_stats(&stats);
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    let (mut client, server) = duplex(64 * 1024);
+    let listener = OneConnection(Some(server)).with_negotiate_info();
+    let service = router.into_make_service_with_connect_info::<NegotiateInfo>();
+    tokio::spawn(async move { axum::serve(listener, service).await });
+
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Negotiate {}\r\n\r\n",
+        BASE64_STANDARD.encode(vectors::NTLM_NEGOTIATE)
+    );
+    client.write_all(request.repeat(3).as_bytes()).await.unwrap();
+    let mut buffer = Vec::new();
+    let (first, body) = read_response(&mut client, &mut buffer).await;
+    assert!(first.starts_with("HTTP/1.1 401"), "{first}");
+    assert_eq!(body, "continue");
+    // Retries of the first token get the same challenge
+    for _ in 0..2 {
+        let (head, body) = read_response(&mut client, &mut buffer).await;
+        assert!(head.starts_with("HTTP/1.1 401"), "{head}");
+        assert_eq!(body, "continue");
+        let challenge = |head: &str| {
+            head.lines()
+                .find(|line| line.starts_with("www-authenticate"))
+                .map(str::to_owned)
+        };
+        assert_eq!(challenge(&head), challenge(&first));
+    }
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let (head, body) = read_response(&mut client, &mut buffer).await;
+    assert!(head.starts_with("HTTP/1.1 401"), "{head}");
+    assert_eq!(body, "authentication pending");
+    // The pipelined requests neither failed nor restarted the handshake of the first one
+    assert_eq!(stats.pending(), 1);
+    assert_eq!(stats.failed(FailureReason::Rejected), 0);
+}
@@ -0,0 +1,198 @@
+use std::{future::poll_fn, pin::Pin};
+
+use axum::{
+    Router,
+    body::{Body, HttpBody},
+    extract::ConnectInfo,
+    routing::get,
+};
+use axum_negotiate_layer::{
+    CLIENT_TRAILER, LayerStats, MECHANISM_TRAILER, NegotiateInfo, NegotiateLayer, NegotiateProgress,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, TE, TRAILER, WWW_AUTHENTICATE},
+};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::Service;
+
+#[allow(dead_code)]
+mod keytab;
+
+/// Creates the first token of a Kerberos handshake for `spn` from the default credential cache
+fn initial_token(spn: &str) -> Vec<u8> {
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    match ClientBuilder::new_from_credentials(credentials, Some(spn))
+        .request_mutual_auth()
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn credentials_on_first_request() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let mut router = Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(Some(&spn)));
+    let info = NegotiateInfo::new();
+    let token = BASE64_STANDARD.encode(initial_token(&spn));
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, format!("Negotiate {token}"))
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    let response = router.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(info.is_authenticated());
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn final_token_on_success() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let mut router = Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(Some(&spn)));
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let StepOut::Pending(client) = ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .request_mutual_auth()
+        .initialize()
+        .unwrap()
+    else {
+        panic!("mutual authentication should need a reply from the server");
+    };
+    let token = BASE64_STANDARD.encode(client.next_token());
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, format!("Negotiate {token}"))
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    let response = router.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let challenge = response.headers()[WWW_AUTHENTICATE].to_str().unwrap();
+    let final_token = challenge.strip_prefix("Negotiate ").expect("no final token on the 200");
+    let final_token = BASE64_STANDARD.decode(final_token).unwrap();
+    assert!(matches!(client.step(&final_token).unwrap(), StepOut::Finished(_)));
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn stateless_connection_stays_unauthenticated() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let mut router = Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(Some(&spn)).stateless(true));
+    let info = NegotiateInfo::new();
+    let token = BASE64_STANDARD.encode(initial_token(&spn));
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, format!("Negotiate {token}"))
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    let response = router.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!info.is_authenticated());
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn auth_trailers() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let mut router = Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(Some(&spn)).auth_trailers(true));
+    let token = BASE64_STANDARD.encode(initial_token(&spn));
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, format!("Negotiate {token}"))
+        .header(TE, "trailers")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    let response = router.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(TRAILER));
+    let mut body = response.into_body();
+    let mut trailers = None;
+    while let Some(frame) = poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+        if let Ok(frame_trailers) = frame.unwrap().into_trailers() {
+            trailers = Some(frame_trailers);
+        }
+    }
+    let trailers = trailers.expect("no trailers sent");
+    assert!(trailers.contains_key(CLIENT_TRAILER));
+    assert_eq!(trailers[MECHANISM_TRAILER], "Kerberos");
+}
+
+#[tokio::test]
+async fn connection_without_credentials_stays_unauthenticated() {
+    let mut router = Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(None));
+    let info = NegotiateInfo::new();
+    let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    let response = router.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!info.is_authenticated());
+}
+
+/// Sends the first request of a new connection with a token of a handshake needing another round
+async fn preemptive_round(layer: NegotiateLayer) -> (http::Response<Body>, NegotiateInfo) {
+    let mut router = Router::new().route("/", get(|| async { "hello" })).layer(layer);
+    let info = NegotiateInfo::new();
+    let token = BASE64_STANDARD.encode(keytab::NEG_TOKEN_INIT_WITHOUT_TOKEN);
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, format!("Negotiate {token}"))
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    let response = router.call(request).await.unwrap();
+    (response.map(Body::new), info)
+}
+
+#[tokio::test]
+async fn token_on_first_request_is_processed() {
+    let stats = LayerStats::new();
+    let (response, info) = preemptive_round(NegotiateLayer::new(Some(keytab::install())).with_stats(&stats)).await;
+    // Answered with the server's token instead of the challenge
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::HandshakeLeg(1))
+    );
+    let challenge = response.headers()[WWW_AUTHENTICATE].to_str().unwrap();
+    assert!(challenge.starts_with("Negotiate "), "{challenge}");
+    assert_eq!(stats.pending(), 1);
+    assert!(!info.is_authenticated());
+}
+
+#[tokio::test]
+async fn stateless_keeps_pending_rounds() {
+    let stats = LayerStats::new();
+    let layer = NegotiateLayer::new(Some(keytab::install()))
+        .stateless(true)
+        .with_stats(&stats);
+    let (response, info) = preemptive_round(layer).await;
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::HandshakeLeg(1))
+    );
+    // A handshake of several rounds can only complete on the connection
+    assert_eq!(stats.pending(), 1);
+    assert!(!info.is_authenticated());
+}
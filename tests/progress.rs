@@ -0,0 +1,83 @@
+use axum::{Extension, Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{MisusePolicy, NegotiateInfo, NegotiateLayer, NegotiateProgress};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, USER_AGENT},
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+/// Routes to a handler responding with the progress it found in the request extensions
+fn router(layer: NegotiateLayer) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(|Extension(progress): Extension<NegotiateProgress>| async move { format!("{progress:?}") }),
+        )
+        .layer(layer)
+}
+
+fn request(authorization: Option<String>) -> Request<Body> {
+    let mut request = Request::builder().uri("/").header(USER_AGENT, "check_http/v2.3.3");
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    request
+}
+
+fn progress(response: &Response) -> Option<NegotiateProgress> {
+    response.extensions().get::<NegotiateProgress>().copied()
+}
+
+#[tokio::test]
+async fn challenge_on_response() {
+    let response = router(NegotiateLayer::new(None)).oneshot(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(progress(&response), Some(NegotiateProgress::ChallengeIssued));
+    let response = router(NegotiateLayer::new(None))
+        .oneshot(request(Some("Bearer token".to_owned())))
+        .await
+        .unwrap();
+    assert_eq!(progress(&response), Some(NegotiateProgress::ChallengeIssued));
+}
+
+#[tokio::test]
+async fn skipped_without_negotiate_info() {
+    let layer = NegotiateLayer::new(None).misuse_policy(MisusePolicy::InternalServerError);
+    let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let response = router(layer).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(progress(&response), Some(NegotiateProgress::Skipped));
+}
+
+#[tokio::test]
+async fn authenticated_on_request() {
+    let layer = NegotiateLayer::new(None)
+        .basic_auth("monitoring", |user, password| user == "nagios" && password == "secret")
+        .basic_for_user_agents(vec!["check_http".to_owned()]);
+    let authorization = format!("Basic {}", BASE64_STANDARD.encode("nagios:secret"));
+    let response = router(layer).oneshot(request(Some(authorization))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // Only set on requests that are passed on
+    assert_eq!(progress(&response), None);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"Authenticated");
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn handshake_leg_on_response() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let authorization = format!("Negotiate {}", BASE64_STANDARD.encode(vectors::NTLM_NEGOTIATE));
+    let response = router(NegotiateLayer::new(Some(&spn)))
+        .oneshot(request(Some(authorization)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(progress(&response), Some(NegotiateProgress::HandshakeLeg(1)));
+}
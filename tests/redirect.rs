@@ -0,0 +1,138 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    EpaPolicy, FailureReason, NegotiateFailure, NegotiateInfo, NegotiateLayer, NegotiateProgress,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode, Uri, Version,
+    header::{ACCEPT, AUTHORIZATION, LOCATION},
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+const BROWSER: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+fn router(login: &str, after_attempts: u8) -> Router {
+    router_with(NegotiateLayer::new(None), login, after_attempts)
+}
+
+fn router_with(layer: NegotiateLayer, login: &str, after_attempts: u8) -> Router {
+    let layer = layer.failure_redirect(Uri::try_from(login).unwrap(), after_attempts);
+    Router::new().route("/", get(|| async {})).layer(layer)
+}
+
+async fn send(router: &Router, info: &NegotiateInfo, uri: &str, accept: &str) -> Response {
+    let mut request = Request::builder()
+        .uri(uri)
+        .header(ACCEPT, accept)
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    router.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn counts_challenges_per_connection() {
+    let router = router("https://login.example.com/", 2);
+    let info = NegotiateInfo::new();
+    for _ in 0..2 {
+        let response = send(&router, &info, "/", BROWSER).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = send(&router, &info, "/", BROWSER).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::Redirected)
+    );
+    // Another connection is challenged first
+    let response = send(&router, &NegotiateInfo::new(), "/", BROWSER).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn only_redirects_browsers() {
+    let router = router("https://login.example.com/", 0);
+    let info = NegotiateInfo::new();
+    for accept in [
+        "application/json",
+        "*/*",
+        "application/json, text/html;q=0.5",
+        "text/html;q=0",
+    ] {
+        let response = send(&router, &info, "/", accept).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{accept}");
+    }
+    for accept in [BROWSER, "text/html", "application/json;q=0.5, text/html"] {
+        let response = send(&router, &info, "/", accept).await;
+        assert_eq!(response.status(), StatusCode::FOUND, "{accept}");
+    }
+}
+
+#[tokio::test]
+async fn preserves_return_path() {
+    let info = NegotiateInfo::new();
+    let response = send(
+        &router("https://login.example.com/sso", 0),
+        &info,
+        "/reports?year=2024&team=a%2Fb",
+        BROWSER,
+    )
+    .await;
+    assert_eq!(
+        response.headers()[LOCATION],
+        "https://login.example.com/sso?return_to=%2Freports%3Fyear%3D2024%26team%3Da%252Fb"
+    );
+    let response = send(&router("/login?lang=en", 0), &info, "/", BROWSER).await;
+    assert_eq!(response.headers()[LOCATION], "/login?lang=en&return_to=%2F");
+}
+
+async fn send_token(router: &Router, info: &NegotiateInfo, version: Version, token: &[u8]) -> Response {
+    let mut request = Request::builder()
+        .uri("/")
+        .version(version)
+        .header(ACCEPT, BROWSER)
+        .header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)))
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    router.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn keeps_failure_of_handshake() {
+    let layer = NegotiateLayer::new(None).extended_protection(EpaPolicy::Required);
+    let router = router_with(layer, "/login", 0);
+    let response = send_token(
+        &router,
+        &NegotiateInfo::new(),
+        Version::HTTP_11,
+        vectors::WINDOWS_NEG_TOKEN_INIT,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    let failure = response.extensions().get::<NegotiateFailure>().unwrap();
+    assert_eq!((failure.reason, failure.leg), (FailureReason::Policy, 1));
+}
+
+#[tokio::test]
+async fn only_redirects_challenges() {
+    let router = router("/login", 1);
+    let info = NegotiateInfo::new();
+    // NTLM can't complete on a connection closed after the response, which isn't answered with a challenge
+    for _ in 0..2 {
+        let response = send_token(&router, &info, Version::HTTP_10, vectors::NTLM_NEGOTIATE).await;
+        assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(
+            response.extensions().get::<NegotiateProgress>(),
+            Some(&NegotiateProgress::ChallengeIssued)
+        );
+    }
+    // Nor counted as one
+    let response = send(&router, &info, "/", BROWSER).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(&router, &info, "/", BROWSER).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+}
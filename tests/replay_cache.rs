@@ -0,0 +1,31 @@
+use axum_negotiate_layer::{InMemoryReplayCache, ReplayCache};
+use std::time::{Duration, Instant};
+
+#[test]
+fn rejects_replayed_token() {
+    let cache = InMemoryReplayCache::new();
+    let expiry = Instant::now() + Duration::from_secs(60);
+    assert!(cache.check_and_insert(b"token", expiry));
+    assert!(!cache.check_and_insert(b"token", expiry));
+    assert!(cache.check_and_insert(b"other token", expiry));
+}
+
+#[test]
+fn accepts_token_after_expiry() {
+    let cache = InMemoryReplayCache::new();
+    assert!(cache.check_and_insert(b"token", Instant::now()));
+    assert!(cache.check_and_insert(b"token", Instant::now() + Duration::from_secs(60)));
+}
+
+#[test]
+fn evicts_expired_entries() {
+    let cache = InMemoryReplayCache::new();
+    let now = Instant::now();
+    for i in 0..100u8 {
+        cache.check_and_insert(&[i], now);
+    }
+    cache.check_and_insert(b"token", now + Duration::from_secs(60));
+    assert_eq!(cache.len(), 101);
+    cache.evict_expired();
+    assert_eq!(cache.len(), 1);
+}
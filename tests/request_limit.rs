@@ -0,0 +1,75 @@
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode, Version,
+    header::{AUTHORIZATION, CONNECTION},
+};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+const MAX: u32 = 3;
+
+fn token(spn: &str) -> Vec<u8> {
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    match ClientBuilder::new_from_credentials(credentials, Some(spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    }
+}
+
+fn request(info: &NegotiateInfo, version: Version, token: Option<&[u8]>) -> Request<Body> {
+    let mut request = Request::builder().uri("/").version(version);
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    request
+}
+
+/// Drives `MAX` requests over one connection, returning the response to the one after them
+async fn exceed(version: Version) -> (NegotiateInfo, axum::response::Response) {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)).max_requests_per_connection(MAX));
+    let info = NegotiateInfo::new();
+    let response = router
+        .clone()
+        .oneshot(request(&info, version, Some(&token(&spn))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    for _ in 1..MAX {
+        let response = router.clone().oneshot(request(&info, version, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = router.oneshot(request(&info, version, None)).await.unwrap();
+    (info, response)
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn closes_http1_connection() {
+    let (info, response) = exceed(Version::HTTP_11).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[CONNECTION], "close");
+    assert!(!info.is_authenticated());
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn rechallenges_http2_connection() {
+    let (info, response) = exceed(Version::HTTP_2).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_ne!(response.headers()[CONNECTION], "close");
+    assert!(!info.is_authenticated());
+}
@@ -0,0 +1,184 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::IntoResponse, routing::get};
+use axum_negotiate_layer::{NegotiateInfo, NegotiateLayer, NegotiateProgress, SessionIdentity};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    HeaderValue, Request, StatusCode,
+    header::{AUTHORIZATION, CACHE_CONTROL, PRAGMA, RETRY_AFTER, VARY, WWW_AUTHENTICATE},
+};
+use tower::Service;
+
+#[allow(dead_code)]
+mod keytab;
+#[allow(dead_code)]
+mod vectors;
+
+async fn respond(layer: NegotiateLayer, authorization: Option<&str>) -> axum::response::Response {
+    let mut router = Router::new().route("/", get(|| async { "hello" })).layer(layer);
+    let mut request = Request::builder().uri("/");
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    router.call(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn challenge_is_not_cacheable() {
+    for authorization in [None, Some("Basic dXNlcjpwYXNz"), Some("Negotiate")] {
+        let response = respond(NegotiateLayer::new(None), authorization).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let headers = response.headers();
+        assert_eq!(headers[WWW_AUTHENTICATE], "Negotiate");
+        assert_eq!(headers[CACHE_CONTROL], "no-store");
+        assert_eq!(headers[PRAGMA], "no-cache");
+        assert_eq!(headers[VARY], "Authorization");
+    }
+}
+
+#[tokio::test]
+async fn caching_headers_can_be_disabled() {
+    let response = respond(NegotiateLayer::new(None).prevent_caching(false), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let headers = response.headers();
+    assert!(!headers.contains_key(CACHE_CONTROL));
+    assert!(!headers.contains_key(PRAGMA));
+    assert!(!headers.contains_key(VARY));
+}
+
+#[tokio::test]
+async fn continue_is_not_cacheable() {
+    let spn = keytab::install();
+    let token = format!(
+        "Negotiate {}",
+        BASE64_STANDARD.encode(keytab::NEG_TOKEN_INIT_WITHOUT_TOKEN)
+    );
+    let response = respond(NegotiateLayer::new(Some(spn)), Some(&token)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::HandshakeLeg(1))
+    );
+    let headers = response.headers();
+    assert_eq!(headers[CACHE_CONTROL], "no-store");
+    assert_eq!(headers[PRAGMA], "no-cache");
+    assert_eq!(headers[VARY], "Authorization");
+    let response = respond(NegotiateLayer::new(Some(spn)).prevent_caching(false), Some(&token)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!response.headers().contains_key(CACHE_CONTROL));
+    assert!(!response.headers().contains_key(VARY));
+}
+
+#[tokio::test]
+async fn authenticated_response_without_final_token_is_untouched() {
+    let mut router = Router::new()
+        .route("/", get(|| async { ([(CACHE_CONTROL, "max-age=60")], "hello") }))
+        .layer(NegotiateLayer::new(None).or_session(true));
+    let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    request
+        .extensions_mut()
+        .insert(SessionIdentity("alice@EXAMPLE.COM".to_owned()));
+    let response = router.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers[CACHE_CONTROL], "max-age=60");
+    assert!(!headers.contains_key(PRAGMA));
+    assert!(!headers.contains_key(VARY));
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN and a keytab for it"]
+async fn invalid_token_response() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let response = respond(NegotiateLayer::new(Some(&spn)), Some("Negotiate not*base64")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let layer = NegotiateLayer::new(Some(&spn)).invalid_token_response(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            "the Authorization header was altered on its way",
+        )
+            .into_response()
+    });
+    let response = respond(layer, Some("Negotiate not*base64")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"the Authorization header was altered on its way");
+}
+
+fn ntlm_negotiate() -> String {
+    format!("Negotiate {}", BASE64_STANDARD.encode(vectors::NTLM_NEGOTIATE))
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn continue_body() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let layer = NegotiateLayer::new(Some(&spn)).continue_body("");
+    let response = respond(layer, Some(&ntlm_negotiate())).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let token = response.headers()[WWW_AUTHENTICATE].to_str().unwrap().to_owned();
+    assert!(token.starts_with("Negotiate "), "{token}");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(body.is_empty());
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn continue_token_survives_mapping() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let layer = NegotiateLayer::new(Some(&spn)).map_continue_response(|mut response| {
+        let headers = response.headers_mut();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("0"));
+        headers.insert(WWW_AUTHENTICATE, HeaderValue::from_static("Negotiate"));
+        response
+    });
+    let response = respond(layer, Some(&ntlm_negotiate())).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[RETRY_AFTER], "0");
+    let challenges: Vec<_> = response.headers().get_all(WWW_AUTHENTICATE).iter().collect();
+    assert_eq!(challenges.len(), 1);
+    assert_ne!(challenges[0], "Negotiate", "the token was restored");
+}
+
+#[tokio::test]
+#[ignore = "requires a GSSAPI or SSPI backend"]
+async fn credentials_failure_response() {
+    let spn = "HTTP/missing.invalid";
+    // The credentials are acquired before the token is looked at
+    let token = Some(ntlm_negotiate());
+    let token = token.as_deref();
+    let response = respond(NegotiateLayer::new(Some(spn)), token).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::ServerError)
+    );
+    let layer = NegotiateLayer::new(Some(spn)).credentials_failure_response(|| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "authentication unavailable, reference 42",
+        )
+            .into_response()
+    });
+    let response = respond(layer, token).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"authentication unavailable, reference 42");
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN and a keytab for it"]
+async fn oversized_token() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let token = BASE64_STANDARD.encode(vec![0x60; 1024]);
+    let response = respond(
+        NegotiateLayer::new(Some(&spn)).max_token_size(1023),
+        Some(&format!("Negotiate {token}")),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+}
@@ -0,0 +1,68 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    Authenticated, HandshakeStatus, LayerStats, NegotiateInfo, NegotiateLayer, NegotiateProgress, ROUNDS_BUCKETS,
+    SessionIdentity,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+async fn send(router: &Router, info: &NegotiateInfo, token: &[u8]) -> Response {
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)))
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    router.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn no_rounds_without_handshake() {
+    let stats = LayerStats::new();
+    let router = Router::new()
+        .route(
+            "/",
+            get(|auth: Authenticated| async move { auth.handshake_rounds().to_string() }),
+        )
+        .layer(NegotiateLayer::new(None).or_session(true).with_stats(&stats));
+    let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    request
+        .extensions_mut()
+        .insert(SessionIdentity("alice@EXAMPLE.COM".to_owned()));
+    let response = router.oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"0");
+    for rounds in 0..=u8::MAX {
+        assert_eq!(stats.succeeded_in_rounds(rounds), 0);
+    }
+    assert_eq!(ROUNDS_BUCKETS, ["1", "2", "3", "4+"]);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn counted_again_after_failed_handshake() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)));
+    let info = NegotiateInfo::new();
+    let response = send(&router, &info, vectors::NTLM_NEGOTIATE).await;
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::HandshakeLeg(1))
+    );
+    // Fails the pending handshake, which the next token starts over
+    let response = send(&router, &info, vectors::WINDOWS_NEG_TOKEN_INIT).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+    let response = send(&router, &info, vectors::NTLM_NEGOTIATE).await;
+    assert_eq!(
+        response.extensions().get::<NegotiateProgress>(),
+        Some(&NegotiateProgress::HandshakeLeg(1))
+    );
+}
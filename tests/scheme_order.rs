@@ -0,0 +1,123 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{
+    Authenticated, ChallengeStyle, FailureReason, LayerStats, NegotiateInfo, NegotiateLayer, Scheme,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+fn router(layer: NegotiateLayer) -> Router {
+    Router::new()
+        .route("/", get(|mut auth: Authenticated| async move { auth.client() }))
+        .layer(layer)
+}
+
+fn with_basic() -> NegotiateLayer {
+    NegotiateLayer::new(None).basic_auth("intranet", |user, password| user == "alice" && password == "secret")
+}
+
+fn request(authorization: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    request
+}
+
+fn challenges(response: &Response) -> Vec<&str> {
+    response
+        .headers()
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn negotiate_only_by_default() {
+    let response = router(with_basic()).oneshot(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(challenges(&response), ["Negotiate"]);
+}
+
+#[tokio::test]
+async fn exact_order() {
+    let layer = with_basic().scheme_order(&[Scheme::Basic, Scheme::Ntlm, Scheme::Negotiate]);
+    let response = router(layer).oneshot(request(None)).await.unwrap();
+    assert_eq!(challenges(&response), ["Basic realm=\"intranet\"", "NTLM", "Negotiate"]);
+    let layer = with_basic().scheme_order(&[Scheme::Negotiate, Scheme::Ntlm, Scheme::Negotiate]);
+    let response = router(layer).oneshot(request(None)).await.unwrap();
+    assert_eq!(challenges(&response), ["Negotiate", "NTLM"]);
+}
+
+#[tokio::test]
+async fn comma_joined_order() {
+    let layer = with_basic()
+        .scheme_order(&[Scheme::Ntlm, Scheme::Negotiate, Scheme::Basic])
+        .challenge_style(ChallengeStyle::CommaJoined);
+    let response = router(layer).oneshot(request(None)).await.unwrap();
+    assert_eq!(challenges(&response), ["NTLM, Negotiate, Basic realm=\"intranet\""]);
+}
+
+#[tokio::test]
+async fn presence() {
+    let layer = with_basic().scheme_order(&[Scheme::Basic]);
+    let response = router(layer).oneshot(request(None)).await.unwrap();
+    assert_eq!(challenges(&response), ["Basic realm=\"intranet\""]);
+    // Without a callback to verify them, Basic credentials aren't asked for
+    let layer = NegotiateLayer::new(None).scheme_order(&[Scheme::Basic, Scheme::Negotiate]);
+    let response = router(layer).oneshot(request(None)).await.unwrap();
+    assert_eq!(challenges(&response), ["Negotiate"]);
+}
+
+#[tokio::test]
+async fn basic_accepted_when_listed() {
+    let authorization = format!("Basic {}", BASE64_STANDARD.encode("alice:secret"));
+    let layer = with_basic().scheme_order(&[Scheme::Negotiate, Scheme::Basic]);
+    let response = router(layer).oneshot(request(Some(&authorization))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"alice");
+    // Not listed, so taken for a malformed Negotiate header
+    let stats = LayerStats::new();
+    let response = router(with_basic().with_stats(&stats))
+        .oneshot(request(Some(&authorization)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(stats.failed(FailureReason::InvalidHeader), 1);
+}
+
+#[tokio::test]
+async fn ntlm_scheme_only_when_listed() {
+    let authorization = format!("NTLM {}", BASE64_STANDARD.encode(vectors::NTLM_NEGOTIATE));
+    let stats = LayerStats::new();
+    let response = router(NegotiateLayer::new(None).with_stats(&stats))
+        .oneshot(request(Some(&authorization)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(challenges(&response), ["Negotiate"]);
+    assert_eq!(stats.failed(FailureReason::InvalidHeader), 1);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn ntlm_scheme_continued_with_ntlm() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let layer = NegotiateLayer::new(Some(&spn)).scheme_order(&[Scheme::Ntlm, Scheme::Negotiate]);
+    let authorization = format!("NTLM {}", BASE64_STANDARD.encode(vectors::NTLM_NEGOTIATE));
+    let response = router(layer).oneshot(request(Some(&authorization))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let challenges = challenges(&response);
+    assert_eq!(challenges.len(), 1);
+    assert!(challenges[0].starts_with("NTLM "), "{challenges:?}");
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, Request},
+    middleware::{self, Next},
+    response::Response,
+    routing::get,
+};
+use axum_negotiate_layer::{Authenticated, Mech, NegotiateInfo, NegotiateLayer, SessionIdentity, session_client};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    HeaderValue, StatusCode,
+    header::{AUTHORIZATION, COOKIE, SET_COOKIE, USER_AGENT},
+};
+use tower::ServiceExt;
+
+type Sessions = Arc<Mutex<HashMap<String, String>>>;
+
+fn handler() -> Router {
+    Router::new().route(
+        "/",
+        get(|mut auth: Authenticated| async move { format!("{} {:?}", auth.client(), auth.mechanism()) }),
+    )
+}
+
+fn request(headers: &[(http::HeaderName, &str)], session: Option<&str>) -> Request {
+    let mut request = http::Request::builder().uri("/");
+    for (name, value) in headers {
+        request = request.header(name, *value);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    if let Some(client) = session {
+        request.extensions_mut().insert(SessionIdentity(client.to_owned()));
+    }
+    request
+}
+
+async fn body(response: Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn authenticates_session() {
+    let router = handler().layer(NegotiateLayer::new(None).or_session(true));
+    let response = router.oneshot(request(&[], Some("alice@EXAMPLE.COM"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(session_client(&response), None);
+    assert_eq!(
+        body(response).await,
+        format!("alice@EXAMPLE.COM {:?}", Some(Mech::Session))
+    );
+}
+
+#[tokio::test]
+async fn session_ignored_unless_accepted() {
+    let router = handler().layer(NegotiateLayer::new(None));
+    let response = router.oneshot(request(&[], Some("alice@EXAMPLE.COM"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+/// Stands in for a session library, keeping the client by a cookie
+async fn bridge(sessions: Sessions, mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(COOKIE)
+        .and_then(|cookie| cookie.to_str().ok())
+        .and_then(|cookie| cookie.strip_prefix("session="))
+        .map(str::to_owned);
+    if let Some(client) = id.as_ref().and_then(|id| sessions.lock().unwrap().get(id).cloned()) {
+        request.extensions_mut().insert(SessionIdentity(client));
+    }
+    let mut response = next.run(request).await;
+    if let Some(client) = session_client(&response) {
+        let mut sessions = sessions.lock().unwrap();
+        let id = sessions.len().to_string();
+        sessions.insert(id.clone(), client);
+        let cookie = HeaderValue::from_str(&format!("session={id}")).unwrap();
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    response
+}
+
+#[tokio::test]
+async fn first_authentication_establishes_session() {
+    let sessions = Sessions::default();
+    let layer = NegotiateLayer::new(None)
+        .basic_auth("monitoring", |user, password| user == "nagios" && password == "secret")
+        .basic_for_user_agents(vec!["check_http".to_owned()])
+        .or_session(true);
+    let bridge_sessions = sessions.clone();
+    let router = handler().layer(layer).layer(middleware::from_fn(move |request, next| {
+        bridge(bridge_sessions.clone(), request, next)
+    }));
+
+    let credentials = format!("Basic {}", BASE64_STANDARD.encode("nagios:secret"));
+    let response = router
+        .clone()
+        .oneshot(request(
+            &[(USER_AGENT, "check_http"), (AUTHORIZATION, &credentials)],
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = response.headers()[SET_COOKIE].to_str().unwrap().to_owned();
+    assert_eq!(sessions.lock().unwrap().values().collect::<Vec<_>>(), ["nagios"]);
+
+    let response = router.oneshot(request(&[(COOKIE, &cookie)], None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(SET_COOKIE).is_none());
+    assert_eq!(body(response).await, format!("nagios {:?}", Some(Mech::Session)));
+    assert_eq!(sessions.lock().unwrap().len(), 1);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn no_session_for_authenticated_connection() {
+    use axum_negotiate_layer::ClientIdentity;
+    // The layer passes on later requests of an authenticated connection with the connection's Authenticated only
+    let mut response = Response::new(Body::empty());
+    let identity = ClientIdentity::new("alice@EXAMPLE.COM").mechanism(Mech::Kerberos);
+    response.extensions_mut().insert(Authenticated::for_tests(identity));
+    assert_eq!(session_client(&response), None);
+}
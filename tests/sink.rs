@@ -0,0 +1,223 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{Router, body::Body, routing::get};
+use axum_negotiate_layer::{
+    MisusePolicy, NegotiateInfo, NegotiateLayer,
+    sink::{Event, Level, Sink},
+};
+use http::{HeaderName, Request, StatusCode, header::AUTHORIZATION};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod keytab;
+
+/// An event as received by [`Capture`]
+#[derive(Debug, PartialEq, Eq)]
+struct Captured {
+    level: Level,
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+#[derive(Default)]
+struct Capture(Mutex<Vec<Captured>>);
+impl Sink for Capture {
+    fn event(&self, event: &Event<'_>) {
+        self.0.lock().unwrap().push(Captured {
+            level: event.level,
+            message: event.message.to_owned(),
+            fields: event
+                .fields
+                .iter()
+                .map(|(key, value)| (*key, value.to_string()))
+                .collect(),
+        });
+    }
+}
+
+async fn call(layer: NegotiateLayer, authorization: Option<&str>, info: Option<NegotiateInfo>) -> StatusCode {
+    let mut request = Request::builder().uri("/");
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    if let Some(info) = info {
+        request.extensions_mut().insert(info);
+    }
+    let router = Router::new().route("/", get(|| async { "hello" })).layer(layer);
+    router.oneshot(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn invalid_header() {
+    let capture = Arc::new(Capture::default());
+    let layer = NegotiateLayer::new(None).with_log_sink(capture.clone());
+    let status = call(layer, Some("Basic c2VjcmV0"), Some(NegotiateInfo::new())).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let events = capture.0.lock().unwrap();
+    assert_eq!(
+        *events,
+        [Captured {
+            level: Level::Debug,
+            message: "Invalid Authorization header".to_owned(),
+            fields: vec![("error", "unsupported authentication scheme".to_owned())],
+        }]
+    );
+    assert!(!format!("{events:?}").contains("c2VjcmV0"), "credentials were logged");
+}
+
+#[tokio::test]
+async fn misuse() {
+    let capture = Arc::new(Capture::default());
+    let layer = NegotiateLayer::new(None)
+        .misuse_policy(MisusePolicy::InternalServerError)
+        .with_log_sink(capture.clone());
+    let status = call(layer, None, None).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    let events = capture.0.lock().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].level, Level::Error);
+    assert_eq!(events[0].message, "Misconfigured NegotiateLayer");
+    assert_eq!(events[0].fields[0].0, "reason");
+}
+
+#[tokio::test]
+async fn connection_events() {
+    let capture = Arc::new(Capture::default());
+    let info = NegotiateInfo::new();
+    let layer = NegotiateLayer::new(None).with_log_sink(capture.clone());
+    let status = call(layer, None, Some(info.clone())).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    info.close();
+    let events = capture.0.lock().unwrap();
+    assert_eq!(
+        events.last(),
+        Some(&Captured {
+            level: Level::Debug,
+            message: "Connection closed".to_owned(),
+            fields: vec![("state", "Unauthenticated".to_owned())],
+        })
+    );
+}
+
+#[cfg(feature = "drain")]
+#[tokio::test]
+async fn drainer_events() {
+    let capture = Arc::new(Capture::default());
+    let drainer = axum_negotiate_layer::Drainer::new();
+    // The drainer learns about the sink when the layer is applied, whatever the order of the builder calls
+    let layer = NegotiateLayer::new(None)
+        .with_drainer(&drainer)
+        .with_log_sink(capture.clone());
+    let _router: Router = Router::new().route("/", get(|| async { "hello" })).layer(layer);
+    assert!(drainer.drain(std::time::Duration::ZERO).await);
+    assert_eq!(
+        *capture.0.lock().unwrap(),
+        [Captured {
+            level: Level::Info,
+            message: "Draining authenticated connections".to_owned(),
+            fields: vec![("active", "0".to_owned())],
+        }]
+    );
+}
+
+#[test]
+fn display() {
+    let event = Event {
+        level: Level::Info,
+        message: "Handshake complete",
+        fields: &[("client", &"alice@EXAMPLE.COM"), ("rounds", &2)],
+    };
+    assert_eq!(
+        event.to_string(),
+        "Handshake complete client=alice@EXAMPLE.COM rounds=2"
+    );
+}
+
+#[tokio::test]
+async fn no_cache_warns() {
+    let capture = Arc::new(Capture::default());
+    let layer = NegotiateLayer::new(None).with_log_sink(capture.clone()).no_cache(false);
+    let _router: Router = Router::new().route("/", get(|| async { "hello" })).layer(layer);
+    assert!(capture.0.lock().unwrap().is_empty());
+    // Warned once the layer is applied, whatever the order of the builder calls
+    let layer = NegotiateLayer::new(None).no_cache(true).with_log_sink(capture.clone());
+    assert!(capture.0.lock().unwrap().is_empty());
+    let status = call(layer, None, Some(NegotiateInfo::new())).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let events = capture.0.lock().unwrap();
+    assert_eq!(events[0].level, Level::Warn);
+    assert!(events[0].message.starts_with("DEBUG: "), "{:?}", events[0]);
+    assert_eq!(events.iter().filter(|event| event.level == Level::Warn).count(), 1);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_fields() {
+    use std::{collections::HashMap, fmt::Debug};
+
+    use axum_negotiate_layer::sink::TracingSink;
+    use tracing::{
+        Subscriber,
+        field::{Field, Visit},
+    };
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+
+    /// Collects the fields of every event
+    #[derive(Clone, Default)]
+    struct Fields(Arc<Mutex<HashMap<String, String>>>);
+    impl Visit for &Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_owned(), format!("{value:?}"));
+        }
+    }
+    impl<S: Subscriber> tracing_subscriber::Layer<S> for Fields {
+        fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+            event.record(&mut &*self);
+        }
+    }
+
+    let fields = Fields::default();
+    let subscriber = tracing_subscriber::registry().with(fields.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        TracingSink.event(&Event {
+            level: Level::Info,
+            message: "Handshake complete",
+            fields: &[("client", &"alice@EXAMPLE.COM"), ("rounds", &2), ("custom", &"value")],
+        });
+    });
+    let fields = fields.0.lock().unwrap();
+    assert_eq!(fields["message"], "Handshake complete");
+    assert_eq!(fields["fields"], "client=alice@EXAMPLE.COM rounds=2 custom=value");
+    assert_eq!(fields.len(), 2, "{fields:?}");
+}
+
+#[tokio::test]
+async fn correlation_id() {
+    let capture = Arc::new(Capture::default());
+    let layer = NegotiateLayer::new(Some(keytab::install()))
+        .correlation_header(HeaderName::from_static("x-request-id"))
+        .with_log_sink(capture.clone());
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, "Negotiate !!!")
+        .header("x-request-id", "4711")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(NegotiateInfo::new());
+    let router = Router::new().route("/", get(|| async { "hello" })).layer(layer);
+    let status = router.oneshot(request).await.unwrap().status();
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let events = capture.0.lock().unwrap();
+    let round = events
+        .iter()
+        .find(|event| event.message == "Handshake round")
+        .expect("no event for the handshake round");
+    assert!(
+        round.fields.contains(&("correlation_id", r#"Some("4711")"#.to_owned())),
+        "{round:?}"
+    );
+}
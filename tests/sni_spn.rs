@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{
+    NegotiateInfo, NegotiateLayer, Spn,
+    sink::{Event, Sink},
+};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use tower::ServiceExt;
+
+/// Records the SPN of every credential acquisition, which fails without keys for it
+#[derive(Default)]
+struct AcquiredSpns(Mutex<Vec<String>>);
+impl Sink for AcquiredSpns {
+    fn event(&self, event: &Event<'_>) {
+        if event.message != "Failed to create credentials handle" {
+            return;
+        }
+        if let Some((_, spn)) = event.fields.iter().find(|(key, _)| *key == "spn") {
+            self.0.lock().unwrap().push(spn.to_string());
+        }
+    }
+}
+
+/// Starts a handshake on a connection with the server name `sni`, returning the SPN it acquired credentials for
+async fn acquired_spn(router: &Router, spns: &AcquiredSpns, sni: Option<&str>) -> String {
+    let info = match sni {
+        Some(sni) => NegotiateInfo::new().with_sni(sni),
+        None => NegotiateInfo::new(),
+    };
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, "Negotiate dG9rZW4=")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(info));
+    let response = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    spns.0.lock().unwrap().pop().unwrap()
+}
+
+fn router(spns: &Arc<AcquiredSpns>) -> Router {
+    let by_name = HashMap::from([
+        ("a.example.com".to_owned(), Spn::new("HTTP", "a.example.com")),
+        ("B.example.com".to_owned(), Spn::new("HTTP", "b.example.com")),
+    ]);
+    let layer = NegotiateLayer::new(Some("HTTP/configured.example.com"))
+        .spn_from_sni(by_name, Spn::new("HTTP", "fallback.example.com"))
+        .with_log_sink(spns.clone());
+    Router::new().route("/", get(|| async {})).layer(layer)
+}
+
+#[tokio::test]
+async fn selects_by_server_name() {
+    let spns = Arc::new(AcquiredSpns::default());
+    let router = router(&spns);
+    for (sni, spn) in [
+        ("a.example.com", "HTTP@a.example.com"),
+        ("b.example.com", "HTTP@b.example.com"),
+        // Matched case-insensitively, on both sides
+        ("A.EXAMPLE.COM", "HTTP@a.example.com"),
+    ] {
+        assert_eq!(acquired_spn(&router, &spns, Some(sni)).await, format!("Some({spn:?})"));
+    }
+}
+
+#[tokio::test]
+async fn falls_back_without_a_known_server_name() {
+    let spns = Arc::new(AcquiredSpns::default());
+    let router = router(&spns);
+    for sni in [Some("c.example.com"), Some("example.com"), None] {
+        assert_eq!(
+            acquired_spn(&router, &spns, sni).await,
+            r#"Some("HTTP@fallback.example.com")"#,
+            "{sni:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn replaces_the_configured_name() {
+    let spns = Arc::new(AcquiredSpns::default());
+    let layer = NegotiateLayer::new(Some("HTTP/configured.example.com")).with_log_sink(spns.clone());
+    let without_sni = Router::new().route("/", get(|| async {})).layer(layer);
+    assert_eq!(
+        acquired_spn(&without_sni, &spns, Some("a.example.com")).await,
+        r#"Some("HTTP@configured.example.com")"#
+    );
+    assert_eq!(
+        acquired_spn(&router(&spns), &spns, Some("a.example.com")).await,
+        r#"Some("HTTP@a.example.com")"#
+    );
+}
@@ -0,0 +1,132 @@
+use axum_negotiate_layer::{AcceptorName, CanonicalizeOptions, Spn, SpnParseError};
+
+#[test]
+fn parses_principal_form() {
+    let spn = Spn::parse("HTTP/www.example.com@EXAMPLE.COM").unwrap();
+    assert_eq!(spn.service(), "HTTP");
+    assert_eq!(spn.host(), "www.example.com");
+    assert_eq!(spn.realm(), Some("EXAMPLE.COM"));
+    assert_eq!(spn.to_string(), "HTTP/www.example.com@EXAMPLE.COM");
+    assert_eq!(Spn::parse("HTTP/www.example.com").unwrap().realm(), None);
+}
+
+#[test]
+fn parses_host_based_form() {
+    let spn = Spn::parse("HTTP@www.example.com").unwrap();
+    assert_eq!(spn, Spn::new("HTTP", "www.example.com"));
+    assert_eq!(spn.to_string(), "HTTP/www.example.com");
+}
+
+#[test]
+fn rejects_invalid_spns() {
+    assert_eq!(Spn::parse("HTTP"), Err(SpnParseError::MissingHost));
+    assert_eq!(Spn::parse("HTTP/a/b"), Err(SpnParseError::TooManyComponents));
+    assert_eq!(Spn::parse("HTTP@a@b"), Err(SpnParseError::TooManyComponents));
+    assert_eq!(Spn::parse("/host"), Err(SpnParseError::EmptyComponent));
+    assert_eq!(Spn::parse("HTTP/host@"), Err(SpnParseError::EmptyComponent));
+}
+
+#[test]
+fn converts_to_host_based_form() {
+    let spn = Spn::new("HTTP", "www.example.com").with_realm("EXAMPLE.COM");
+    assert_eq!(
+        spn.host_based().host_based_form().as_deref(),
+        Some("HTTP@www.example.com")
+    );
+    assert_eq!(
+        spn.principal().host_based_form().as_deref(),
+        Some("HTTP@www.example.com")
+    );
+    assert_eq!(AcceptorName::Principal("HTTP/a/b".to_owned()).host_based_form(), None);
+    assert_eq!(
+        AcceptorName::Principal("service-account".to_owned()).host_based_form(),
+        None
+    );
+}
+
+#[test]
+fn converts_to_principal_form() {
+    let spn = Spn::new("HTTP", "www.example.com").with_realm("EXAMPLE.COM");
+    assert_eq!(spn.host_based().principal_form(), "HTTP/www.example.com");
+    assert_eq!(spn.principal().principal_form(), "HTTP/www.example.com@EXAMPLE.COM");
+}
+
+#[test]
+fn interprets_strings() {
+    assert_eq!(
+        AcceptorName::from("HTTP@www.example.com"),
+        AcceptorName::HostBased {
+            service: "HTTP".to_owned(),
+            host: "www.example.com".to_owned()
+        }
+    );
+    assert_eq!(
+        AcceptorName::from("HTTP/www.example.com"),
+        AcceptorName::Principal("HTTP/www.example.com".to_owned())
+    );
+}
+
+fn canonical(spn: &str, options: &CanonicalizeOptions) -> String {
+    Spn::parse(spn).unwrap().canonicalize(options).to_string()
+}
+
+#[test]
+fn canonicalizes_host() {
+    let options = CanonicalizeOptions::default();
+    assert_eq!(canonical("HTTP/WWW.Example.COM", &options), "HTTP/www.example.com");
+    assert_eq!(canonical("HTTP/www.example.com.", &options), "HTTP/www.example.com");
+    assert_eq!(canonical("HTTP/www.example.com..", &options), "HTTP/www.example.com");
+    assert_eq!(canonical("HTTP@WWW.example.com.", &options), "HTTP/www.example.com");
+    assert_eq!(
+        canonical("HTTP/www.example.com:443", &options),
+        "HTTP/www.example.com:443"
+    );
+    assert_eq!(
+        canonical("HTTP/Www.Example.com@EXAMPLE.COM", &options),
+        "HTTP/www.example.com@EXAMPLE.COM"
+    );
+}
+
+#[test]
+fn canonicalizes_ports() {
+    let options = CanonicalizeOptions {
+        strip_default_ports: true,
+        ..Default::default()
+    };
+    assert_eq!(canonical("HTTP/www.example.com:443", &options), "HTTP/www.example.com");
+    assert_eq!(canonical("HTTP/www.example.com:80", &options), "HTTP/www.example.com");
+    assert_eq!(canonical("HTTP/www.example.com.:443", &options), "HTTP/www.example.com");
+    assert_eq!(
+        canonical("HTTP/www.example.com:8443", &options),
+        "HTTP/www.example.com:8443"
+    );
+}
+
+#[test]
+fn canonicalizes_realm() {
+    let options = CanonicalizeOptions {
+        default_realm: Some("EXAMPLE.COM".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        canonical("HTTP/www.example.com", &options),
+        "HTTP/www.example.com@EXAMPLE.COM"
+    );
+    assert_eq!(
+        canonical("HTTP/www.example.com@OTHER.COM", &options),
+        "HTTP/www.example.com@OTHER.COM"
+    );
+    let host_based = AcceptorName::from("HTTP@WWW.example.com");
+    assert_eq!(host_based.canonicalize(&options).to_string(), "HTTP@www.example.com");
+    let account = AcceptorName::Principal("Service-Account".to_owned());
+    assert_eq!(account.canonicalize(&options), account);
+}
+
+#[test]
+fn builds_spn_for_host() {
+    assert_eq!(
+        Spn::for_host("HTTP", "WWW.Example.com.:443").to_string(),
+        "HTTP/www.example.com"
+    );
+    assert_eq!(Spn::for_host("HTTP", "intranet:8080").to_string(), "HTTP/intranet:8080");
+}
@@ -0,0 +1,178 @@
+#![cfg(feature = "spn-file")]
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{
+    NegotiateInfo, NegotiateLayer,
+    sink::{Event, Sink},
+};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use tower::ServiceExt;
+
+/// The message and fields of an event
+type Captured = (String, Vec<(&'static str, String)>);
+
+/// Records every event
+#[derive(Default)]
+struct Capture(Mutex<Vec<Captured>>);
+impl Sink for Capture {
+    fn event(&self, event: &Event<'_>) {
+        let fields = event
+            .fields
+            .iter()
+            .map(|(key, value)| (*key, value.to_string()))
+            .collect();
+        self.0.lock().unwrap().push((event.message.to_owned(), fields));
+    }
+}
+impl Capture {
+    fn messages(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(message, _)| message.clone())
+            .collect()
+    }
+}
+
+/// Writes `contents` to a file in a directory of its own
+fn spn_file(name: &str, contents: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("spn-file-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("spn");
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn remove(path: &Path) {
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The layer reading `path`, with the sink set afterwards
+fn layer(path: &Path, capture: &Arc<Capture>) -> NegotiateLayer {
+    NegotiateLayer::new(None)
+        .spn_file_poll_interval(POLL_INTERVAL)
+        .spn_from_file(path)
+        .unwrap()
+        .with_log_sink(capture.clone())
+}
+
+/// The SPN a handshake acquires the server credentials for, as logged when that fails for lack of a keytab
+async fn effective_spn(router: &Router, capture: &Capture) -> String {
+    let mut request = Request::builder()
+        .uri("/")
+        .header(AUTHORIZATION, "Negotiate dG9rZW4=")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    let response = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let events = capture.0.lock().unwrap();
+    let (_, fields) = events
+        .iter()
+        .rfind(|(message, _)| message == "Failed to create credentials handle")
+        .unwrap();
+    fields.iter().find(|(key, _)| *key == "spn").unwrap().1.clone()
+}
+
+/// Waits for `condition`, as the watcher notices changes asynchronously
+async fn eventually(condition: impl Fn() -> bool) {
+    for _ in 0..500 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    panic!("condition not met after changing the file");
+}
+
+#[tokio::test]
+async fn reloads_on_change() {
+    let path = spn_file("reload", "HTTP/old.example.com\n");
+    let capture = Arc::new(Capture::default());
+    let router = Router::new().route("/", get(|| async {})).layer(layer(&path, &capture));
+    assert_eq!(
+        effective_spn(&router, &capture).await,
+        r#"Some("HTTP@old.example.com")"#
+    );
+    std::fs::write(&path, "\n  HTTP/rotated.example.com  \n").unwrap();
+    eventually(|| capture.messages().contains(&"Reloaded SPN from file".to_owned())).await;
+    assert_eq!(
+        effective_spn(&router, &capture).await,
+        r#"Some("HTTP@rotated.example.com")"#
+    );
+    let events = capture.0.lock().unwrap();
+    assert_eq!(events[0].0, "Read SPN from file");
+    assert_eq!(events[0].1, [("spn", "HTTP/old.example.com".to_owned())]);
+    let reloaded = events.iter().find(|(message, _)| message == "Reloaded SPN from file");
+    assert_eq!(
+        reloaded.unwrap().1,
+        [
+            ("before", "HTTP/old.example.com".to_owned()),
+            ("after", "HTTP/rotated.example.com".to_owned())
+        ]
+    );
+    remove(&path);
+}
+
+#[tokio::test]
+async fn keeps_name_when_unreadable() {
+    let path = spn_file("unreadable", "HTTP/kept.example.com\n");
+    let capture = Arc::new(Capture::default());
+    let router = Router::new().route("/", get(|| async {})).layer(layer(&path, &capture));
+    std::fs::write(&path, "").unwrap();
+    let failed = "Failed to reload SPN from file, keeping the current one".to_owned();
+    eventually(|| capture.messages().contains(&failed)).await;
+    assert_eq!(
+        effective_spn(&router, &capture).await,
+        r#"Some("HTTP@kept.example.com")"#
+    );
+    remove(&path);
+}
+
+#[tokio::test]
+async fn watches_once_applied() {
+    let path = spn_file("applied", "HTTP/old.example.com\n");
+    let capture = Arc::new(Capture::default());
+    let layer = layer(&path, &capture);
+    std::fs::write(&path, "HTTP/changed.example.com\n").unwrap();
+    tokio::time::sleep(POLL_INTERVAL * 10).await;
+    assert!(capture.messages().is_empty());
+    // A change before the layer is applied is still picked up
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    eventually(|| capture.messages().contains(&"Reloaded SPN from file".to_owned())).await;
+    assert_eq!(
+        effective_spn(&router, &capture).await,
+        r#"Some("HTTP@changed.example.com")"#
+    );
+    remove(&path);
+}
+
+#[tokio::test]
+async fn stops_with_the_layer() {
+    let path = spn_file("dropped", "HTTP/old.example.com\n");
+    let capture = Arc::new(Capture::default());
+    let layer = layer(&path, &capture);
+    drop(Router::<()>::new().route("/", get(|| async {})).layer(layer.clone()));
+    drop(layer);
+    std::fs::write(&path, "HTTP/ignored.example.com\n").unwrap();
+    tokio::time::sleep(POLL_INTERVAL * 10).await;
+    assert_eq!(capture.messages(), ["Read SPN from file"]);
+    remove(&path);
+}
+
+#[test]
+fn initial_read_fails() {
+    let path = spn_file("empty", " \n\n");
+    assert!(NegotiateLayer::new(None).spn_from_file(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert!(NegotiateLayer::new(None).spn_from_file(&path).is_err());
+    remove(&path);
+}
@@ -0,0 +1,89 @@
+use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+use axum_negotiate_layer::{HandshakeStatus, NegotiateInfo, NegotiateLayer, Spn, SpnOverride};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+fn request(info: &NegotiateInfo, token: Option<&[u8]>, spn_override: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    if let Some(spn) = spn_override {
+        request.extensions_mut().insert(SpnOverride(Spn::parse(spn).unwrap()));
+    }
+    request
+}
+
+#[tokio::test]
+async fn override_replaces_missing_host() {
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(None).spn_from_host("HTTP"));
+    let info = NegotiateInfo::new();
+    let response = router.clone().oneshot(request(&info, None, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = router
+        .oneshot(request(&info, None, Some("HTTP/tenant.example.com")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn override_picked_up_on_first_leg() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)));
+    let info = NegotiateInfo::new();
+    // No keys for the override in the keytab, so the handshake can't start with it
+    let response = router
+        .oneshot(request(
+            &info,
+            Some(vectors::NTLM_NEGOTIATE),
+            Some("HTTP/missing.invalid"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn override_ignored_on_later_legs() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(Some(&spn)));
+    let info = NegotiateInfo::new();
+    let response = router
+        .clone()
+        .oneshot(request(&info, Some(vectors::NTLM_NEGOTIATE), None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(info.status(), HandshakeStatus::Pending);
+    // The round is stepped in the pending handshake, instead of acquiring credentials for the override and failing
+    let response = router
+        .oneshot(request(
+            &info,
+            Some(vectors::WINDOWS_NEG_TOKEN_INIT),
+            Some("HTTP/missing.invalid"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(
+        &body[..],
+        b"authentication failed: token doesn't continue the pending handshake"
+    );
+}
@@ -0,0 +1,154 @@
+use axum_negotiate_layer::{InitialToken, Mech};
+
+const SPNEGO: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+const KERBEROS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02];
+const MS_KERBEROS: &[u8] = &[0x2a, 0x86, 0x48, 0x82, 0xf7, 0x12, 0x01, 0x02, 0x02];
+const NTLM: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a];
+
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match contents.len() {
+        len @ 0..0x80 => out.push(len as u8),
+        len @ 0x80..0x100 => out.extend([0x81, len as u8]),
+        len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+fn neg_token_init(mechs: &[&[u8]]) -> Vec<u8> {
+    let list: Vec<u8> = mechs.iter().flat_map(|oid| der(0x06, oid)).collect();
+    let mut init = der(0xa0, &der(0x30, &list));
+    // mechToken [2], contents are irrelevant
+    init.extend(der(0xa2, &der(0x04, &[0xde, 0xad, 0xbe, 0xef])));
+    let mut application = der(0x06, SPNEGO);
+    application.extend(der(0xa0, &der(0x30, &init)));
+    der(0x60, &application)
+}
+
+#[test]
+fn spnego_mech_list() {
+    let token = neg_token_init(&[MS_KERBEROS, KERBEROS, NTLM]);
+    let parsed = InitialToken::parse(&token).unwrap();
+    assert_eq!(
+        parsed,
+        InitialToken::Spnego(vec![Mech::MsKerberos, Mech::Kerberos, Mech::Ntlm])
+    );
+    assert!(parsed.offers_kerberos());
+}
+
+#[test]
+fn ntlm_only() {
+    let parsed = InitialToken::parse(&neg_token_init(&[NTLM])).unwrap();
+    assert_eq!(parsed, InitialToken::Spnego(vec![Mech::Ntlm]));
+    assert!(!parsed.offers_kerberos());
+}
+
+#[test]
+fn unknown_mech_is_dotted() {
+    let parsed = InitialToken::parse(&neg_token_init(&[&[
+        0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x1f,
+    ]]));
+    let Some(InitialToken::Spnego(mechs)) = parsed else {
+        panic!("not parsed as SPNEGO");
+    };
+    assert_eq!(mechs, [Mech::Other("1.3.6.1.4.1.311.2.2.31".to_owned())]);
+}
+
+#[test]
+fn raw_tokens() {
+    let mut kerberos = der(0x06, KERBEROS);
+    kerberos.extend([0x01, 0x00, 0x6e, 0x00]);
+    assert_eq!(InitialToken::parse(&der(0x60, &kerberos)), Some(InitialToken::Kerberos));
+    assert_eq!(
+        InitialToken::parse(b"NTLMSSP\0\x01\x00\x00\x00"),
+        Some(InitialToken::Ntlm)
+    );
+    assert!(!InitialToken::Ntlm.offers_kerberos());
+}
+
+#[test]
+fn long_mech_list_is_truncated() {
+    let mechs = vec![NTLM; 100];
+    let Some(InitialToken::Spnego(parsed)) = InitialToken::parse(&neg_token_init(&mechs)) else {
+        panic!("not parsed as SPNEGO");
+    };
+    assert_eq!(parsed.len(), 16);
+}
+
+#[test]
+fn malformed_input_is_rejected() {
+    let token = neg_token_init(&[KERBEROS, NTLM]);
+    for end in 0..token.len() {
+        assert_eq!(InitialToken::parse(&token[..end]), None, "truncated at {end}");
+    }
+    for index in 0..token.len() {
+        for bit in 0..8 {
+            let mut flipped = token.clone();
+            flipped[index] ^= 1 << bit;
+            let _ = InitialToken::parse(&flipped);
+        }
+    }
+    assert_eq!(InitialToken::parse(&[0x60, 0x84, 0xff, 0xff, 0xff, 0xff]), None);
+    assert_eq!(InitialToken::parse(&[0x60, 0x80, 0x06, 0x00, 0x00, 0x00]), None);
+    assert_eq!(InitialToken::parse(&[]), None);
+}
+
+/// A Kerberos `InitialContextToken` with an `AP-REQ` for `service/host@realm`
+fn ap_req(service: &str, host: &str, realm: &str) -> Vec<u8> {
+    let mut names = der(0x1b, service.as_bytes());
+    names.extend(der(0x1b, host.as_bytes()));
+    let mut sname = der(0xa0, &der(0x02, &[0x02]));
+    sname.extend(der(0xa1, &der(0x30, &names)));
+    let mut ticket = der(0xa0, &der(0x02, &[0x05]));
+    ticket.extend(der(0xa1, &der(0x1b, realm.as_bytes())));
+    ticket.extend(der(0xa2, &der(0x30, &sname)));
+    // enc-part [3], the encrypted part isn't looked at
+    ticket.extend(der(0xa3, &der(0x30, &[0; 200])));
+    let mut ap_req = der(0xa0, &der(0x02, &[0x05]));
+    ap_req.extend(der(0xa1, &der(0x02, &[0x0e])));
+    ap_req.extend(der(0xa2, &der(0x03, &[0x00, 0x20, 0x00, 0x00, 0x00])));
+    ap_req.extend(der(0xa3, &der(0x61, &der(0x30, &ticket))));
+    ap_req.extend(der(0xa4, &der(0x30, &[0; 16])));
+    let mut token = der(0x06, KERBEROS);
+    token.extend([0x01, 0x00]);
+    token.extend(der(0x6e, &der(0x30, &ap_req)));
+    der(0x60, &token)
+}
+
+fn spnego_with(mech_token: &[u8], req_flags: bool) -> Vec<u8> {
+    let mut init = der(0xa0, &der(0x30, &der(0x06, KERBEROS)));
+    if req_flags {
+        init.extend(der(0xa1, &der(0x03, &[0x00, 0x00])));
+    }
+    init.extend(der(0xa2, &der(0x04, mech_token)));
+    let mut application = der(0x06, SPNEGO);
+    application.extend(der(0xa0, &der(0x30, &init)));
+    der(0x60, &application)
+}
+
+#[test]
+fn target_spn_of_raw_ticket() {
+    let target = InitialToken::target_spn(&ap_req("HTTP", "www.example.com", "EXAMPLE.COM")).unwrap();
+    assert_eq!(target.to_string(), "HTTP/www.example.com@EXAMPLE.COM");
+}
+
+#[test]
+fn target_spn_of_spnego_ticket() {
+    let ticket = ap_req("HTTP", "api.example.com", "EXAMPLE.COM");
+    for req_flags in [false, true] {
+        let target = InitialToken::target_spn(&spnego_with(&ticket, req_flags)).unwrap();
+        assert_eq!(target.host(), "api.example.com", "{req_flags}");
+    }
+}
+
+#[test]
+fn no_target_spn_without_ticket() {
+    assert_eq!(InitialToken::target_spn(&neg_token_init(&[KERBEROS, NTLM])), None);
+    assert_eq!(InitialToken::target_spn(b"NTLMSSP\0\x01\0\0\0"), None);
+    let ticket = ap_req("HTTP", "www.example.com", "EXAMPLE.COM");
+    for end in [0, 20, 60, ticket.len() - 1] {
+        assert_eq!(InitialToken::target_spn(&ticket[..end]), None, "{end}");
+    }
+    assert_eq!(InitialToken::target_spn(&ap_req("HTTP", "", "EXAMPLE.COM")), None);
+}
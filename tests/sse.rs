@@ -0,0 +1,119 @@
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::ConnectInfo,
+    response::sse::{Event, Sse},
+    routing::get,
+};
+use axum_negotiate_layer::{Authenticated, Clock, HandshakeStatus, NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use futures_util::{Stream, stream};
+use http::{
+    Request, StatusCode,
+    header::{AUTHORIZATION, USER_AGENT},
+};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+const EVENTS: usize = 3;
+
+/// Streams an event with the client and whether its context was released, every 10 milliseconds
+async fn events(auth: Authenticated) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    Sse::new(stream::unfold((0, auth), |(sent, mut auth)| async move {
+        if sent == EVENTS {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let event = Event::default().data(format!("{} {}", auth.client(), auth.is_released()));
+        Some((Ok(event), (sent + 1, auth)))
+    }))
+}
+
+fn request(info: &NegotiateInfo, uri: &str, authorization: Option<String>) -> Request<Body> {
+    let mut request = Request::builder().uri(uri).header(USER_AGENT, "check_http/v2.3.3");
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    request
+}
+
+async fn body(response: axum::response::Response) -> String {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn stream_keeps_identity() {
+    let layer = NegotiateLayer::new(None)
+        .basic_auth("events", |user, password| user == "alice" && password == "secret")
+        .basic_for_user_agents(vec!["check_http".to_owned()]);
+    let router = Router::new().route("/events", get(events)).layer(layer);
+    let authorization = format!("Basic {}", BASE64_STANDARD.encode("alice:secret"));
+    let response = router
+        .oneshot(request(&NegotiateInfo::new(), "/events", Some(authorization)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // Basic authentication establishes no context to begin with
+    assert_eq!(body(response).await, "data: alice true\n\n".repeat(EVENTS));
+}
+
+/// A clock that only moves when told to
+struct ManualClock(Mutex<SystemTime>);
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn session_expiry_doesnt_end_stream() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let clock = Arc::new(ManualClock(Mutex::new(SystemTime::now())));
+    let layer = NegotiateLayer::new(Some(&spn))
+        .with_clock(clock.clone())
+        .max_session_age(Duration::from_secs(60));
+    let router = Router::new()
+        .route("/events", get(events))
+        .route("/", get(|| async {}))
+        .layer(layer);
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let info = NegotiateInfo::new();
+    let authorization = format!("Negotiate {}", BASE64_STANDARD.encode(token));
+    let stream = router
+        .clone()
+        .oneshot(request(&info, "/events", Some(authorization)))
+        .await
+        .unwrap();
+    assert_eq!(stream.status(), StatusCode::OK);
+
+    // The session expires while the stream is running, and the next request is challenged
+    *clock.0.lock().unwrap() += Duration::from_secs(61);
+    let response = router.oneshot(request(&info, "/", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+
+    let events = body(stream).await;
+    assert_eq!(events.lines().filter(|line| line.starts_with("data: ")).count(), EVENTS);
+    assert!(events.ends_with(" true\n\n"), "{events}");
+}
@@ -0,0 +1,87 @@
+use axum::{Router, body::Body, routing::get};
+use axum_negotiate_layer::{FailureReason, LayerStats, NegotiateInfo, NegotiateLayer};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use tower::ServiceExt;
+
+/// Sends requests with the given `Authorization` headers, each on a new connection
+async fn workload(stats: &LayerStats, authorizations: &[Option<&str>]) {
+    let router = Router::new()
+        .route("/", get(|| async { "hello" }))
+        .layer(NegotiateLayer::new(None).with_stats(stats));
+    for authorization in authorizations {
+        let mut request = Request::builder().uri("/");
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, *authorization);
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        request.extensions_mut().insert(NegotiateInfo::new());
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+const WORKLOAD: &[Option<&str>] = &[
+    None,
+    Some("Basic dXNlcjpwYXNz"),
+    Some("Negotiate"),
+    Some("NTLM TlRMTVNTUAABAAAA"),
+];
+
+#[tokio::test]
+async fn counts_failures() {
+    let stats = LayerStats::new();
+    workload(&stats, WORKLOAD).await;
+    // The challenge for a request without credentials isn't a failure
+    assert_eq!(stats.failed(FailureReason::InvalidHeader), 3);
+    assert_eq!(stats.failed(FailureReason::Rejected), 0);
+    assert_eq!(stats.succeeded(), 0);
+    assert_eq!(stats.pending(), 0);
+    assert_eq!(stats.authenticated(), 0);
+    assert!(stats.recent_failures().is_empty());
+}
+
+#[tokio::test]
+async fn keeps_recent_failures() {
+    let stats = LayerStats::with_recent_failures(2);
+    workload(&stats, WORKLOAD).await;
+    let recent = stats.recent_failures();
+    assert_eq!(recent.len(), 2);
+    assert!(
+        recent
+            .iter()
+            .all(|failure| failure.reason == FailureReason::InvalidHeader)
+    );
+    assert!(recent.iter().all(|failure| failure.client.is_none()));
+}
+
+#[cfg(feature = "admin")]
+#[tokio::test]
+async fn admin_router() {
+    let stats = LayerStats::with_recent_failures(1);
+    workload(&stats, WORKLOAD).await;
+    let admin = axum_negotiate_layer::admin_router(stats);
+    let get = |uri: &'static str| {
+        let admin = admin.clone();
+        async move {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let response = admin.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], "application/json");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+    };
+    assert_eq!(
+        get("/stats").await,
+        concat!(
+            r#"{"handshakes":{"succeeded":0,"rounds":{"1":0,"2":0,"3":0,"4+":0},"failed":{"invalid_header":3,"rejected":0,"policy":0,"replay":0,"#,
+            r#""server_credentials":0,"draining":0,"wrong_continuation":0,"identity_changed":0,"machine_account":0},"identity_changes":0},"connections":{"pending":0,"authenticated":0}}"#
+        )
+    );
+    let failures = get("/failures").await;
+    assert!(
+        failures.starts_with(r#"[{"reason":"invalid_header","client":null,"time":"#),
+        "{failures}"
+    );
+    assert!(failures.ends_with("}]"), "{failures}");
+}
@@ -0,0 +1,109 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, FromRequestParts},
+    routing::get,
+};
+use axum_negotiate_layer::{HandshakeStatus, NegotiateInfo, NegotiateLayer, is_connection_authenticated};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{Request, StatusCode, header::AUTHORIZATION};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+#[allow(dead_code)]
+mod vectors;
+
+/// Route reporting the handshake status as seen by the handler
+fn router(layer: NegotiateLayer) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(|info: NegotiateInfo| async move { format!("{:?}", info.status()) }),
+        )
+        .layer(layer)
+}
+
+async fn body(response: axum::response::Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+fn request(info: &NegotiateInfo, token: Option<&[u8]>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    request
+}
+
+#[tokio::test]
+async fn unauthenticated() {
+    let info = NegotiateInfo::new();
+    assert_eq!(info.status(), HandshakeStatus::Unauthenticated);
+    // The layer rejects unauthenticated requests, so only routes outside of it see this status
+    let (mut parts, _) = request(&info, None).into_parts();
+    let extracted = NegotiateInfo::from_request_parts(&mut parts, &()).await.unwrap();
+    assert_eq!(extracted.status(), HandshakeStatus::Unauthenticated);
+}
+
+#[tokio::test]
+async fn missing_info_is_rejected() {
+    let route = Router::new().route("/", get(|_: NegotiateInfo| async {}));
+    let response = route
+        .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[test]
+fn connection_authenticated_from_parts() {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    assert_eq!(is_connection_authenticated(&parts), None);
+    parts.extensions.insert(NegotiateInfo::new());
+    assert_eq!(is_connection_authenticated(&parts), Some(false));
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and an NTLM capable backend"]
+async fn pending() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let info = NegotiateInfo::new();
+    let response = router(NegotiateLayer::new(Some(&spn)))
+        .oneshot(request(&info, Some(vectors::NTLM_NEGOTIATE)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(info.status(), HandshakeStatus::Pending);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn authenticated() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let info = NegotiateInfo::new();
+    let response = router(NegotiateLayer::new(Some(&spn)))
+        .oneshot(request(&info, Some(&token)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await, "Authenticated");
+    assert_eq!(info.status(), HandshakeStatus::Authenticated);
+    let (mut parts, ()) = Request::new(()).into_parts();
+    parts.extensions.insert(ConnectInfo(info));
+    assert_eq!(is_connection_authenticated(&parts), Some(true));
+}
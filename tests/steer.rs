@@ -0,0 +1,108 @@
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, Request},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use axum_negotiate_layer::{Authenticated, NegotiateInfo, NegotiateLayer, NegotiateSteer, SessionIdentity};
+use futures_util::future::{Ready, ready};
+use http::StatusCode;
+use tower::{Service, ServiceExt};
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+/// Records the client of every request it receives
+fn recording(log: &Log) -> Router {
+    let log = log.clone();
+    Router::new().route(
+        "/",
+        get(move |mut auth: Authenticated| async move {
+            log.lock().unwrap().push(auth.client());
+        }),
+    )
+}
+
+fn request(session: Option<&str>) -> Request {
+    let mut request = http::Request::builder().uri("/").body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    if let Some(client) = session {
+        request.extensions_mut().insert(SessionIdentity(client.to_owned()));
+    }
+    request
+}
+
+fn steered(admins: &Log, others: &Log) -> Router {
+    let steer = NegotiateSteer::new(
+        |auth, _| usize::from(auth.clone().client() != "admin@EXAMPLE.COM"),
+        vec![recording(admins), recording(others)],
+    );
+    Router::new()
+        .fallback_service(steer)
+        .layer(NegotiateLayer::new(None).or_session(true))
+}
+
+#[tokio::test]
+async fn steers_by_identity() {
+    let admins = Log::default();
+    let others = Log::default();
+    let router = steered(&admins, &others);
+    for client in ["admin@EXAMPLE.COM", "alice@EXAMPLE.COM", "admin@EXAMPLE.COM"] {
+        let response = router.clone().oneshot(request(Some(client))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(*admins.lock().unwrap(), ["admin@EXAMPLE.COM", "admin@EXAMPLE.COM"]);
+    assert_eq!(*others.lock().unwrap(), ["alice@EXAMPLE.COM"]);
+}
+
+#[tokio::test]
+async fn unauthenticated_requests_are_challenged_first() {
+    let admins = Log::default();
+    let others = Log::default();
+    let response = steered(&admins, &others).oneshot(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(admins.lock().unwrap().is_empty());
+    assert!(others.lock().unwrap().is_empty());
+}
+
+/// Ready on every other poll
+#[derive(Clone)]
+struct Flaky(bool);
+impl Service<Request> for Flaky {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Ready<Result<Response, Infallible>>;
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        self.0 = !self.0;
+        if self.0 {
+            Poll::Ready(Ok(()))
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+    fn call(&mut self, _: Request) -> Self::Future {
+        ready(Ok(StatusCode::NO_CONTENT.into_response()))
+    }
+}
+
+#[tokio::test]
+async fn ready_once_all_services_are() {
+    let mut steer = NegotiateSteer::new(|_, _| 1, vec![Flaky(true), Flaky(false)]);
+    let waker = futures_util::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    // The first service isn't ready yet, the second is
+    assert!(steer.poll_ready(&mut cx).is_pending());
+    assert!(steer.poll_ready(&mut cx).is_ready());
+    let response = steer.call(request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    // Only the service that was called has to become ready again
+    assert!(steer.poll_ready(&mut cx).is_pending());
+    assert!(steer.poll_ready(&mut cx).is_ready());
+}
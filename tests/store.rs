@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use axum::{Router, http::Uri, routing::get, serve::Listener};
+use axum_negotiate_layer::{NegotiateInfo, NegotiateLayer, RecyclingStore, WithNegotiateInfo};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex},
+    sync::mpsc,
+};
+
+/// Listener accepting the in-memory connections sent to it
+struct Connections(mpsc::UnboundedReceiver<DuplexStream>);
+impl Listener for Connections {
+    type Io = DuplexStream;
+    type Addr = ();
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.0.recv().await {
+            Some(io) => (io, ()),
+            None => std::future::pending().await,
+        }
+    }
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(())
+    }
+}
+
+/// Sends one unauthenticated request of a browser over a new connection, returning the start of the response
+async fn challenge(connections: &mpsc::UnboundedSender<DuplexStream>) -> String {
+    let (mut client, server) = duplex(4096);
+    connections.send(server).unwrap();
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nAccept: text/html\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = [0; 12];
+    client.read_exact(&mut response).await.unwrap();
+    String::from_utf8(response.to_vec()).unwrap()
+}
+
+async fn eventually(cond: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if cond() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("condition not reached");
+}
+
+#[tokio::test]
+async fn reuses_closed_connection_state() {
+    let store = RecyclingStore::new(1);
+    let (connections, receiver) = mpsc::unbounded_channel();
+    let listener = Connections(receiver).with_negotiate_store(store.clone());
+    // Browsers are redirected on their second challenge, which tells whether the counter was reset
+    let layer = NegotiateLayer::new(None).failure_redirect(Uri::from_static("/login"), 1);
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    let service = router.into_make_service_with_connect_info::<NegotiateInfo>();
+    tokio::spawn(async move { axum::serve(listener, service).await });
+
+    assert_eq!(challenge(&connections).await, "HTTP/1.1 401");
+    eventually(|| store.available() == 1).await;
+    assert_eq!(store.reuses(), 0);
+    // The second connection gets the state of the first, but not its challenge count
+    assert_eq!(challenge(&connections).await, "HTTP/1.1 401");
+    assert_eq!(store.reuses(), 1);
+    eventually(|| store.available() == 1).await;
+    assert_eq!(challenge(&connections).await, "HTTP/1.1 401");
+    assert_eq!(store.reuses(), 2);
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "test-util")]
+use axum_negotiate_layer::{Authenticated, ClientIdentity, Mech};
+
+async fn hello(mut auth: Authenticated) -> String {
+    format!("Hello, {}! ({:?})", auth.client(), auth.mechanism())
+}
+
+#[tokio::test]
+async fn handler_without_middleware() {
+    let identity = ClientIdentity::new("alice@EXAMPLE.COM").mechanism(Mech::Kerberos);
+    let response = hello(Authenticated::for_tests(identity)).await;
+    assert_eq!(response, "Hello, alice@EXAMPLE.COM! (Some(Kerberos))");
+}
+
+#[test]
+fn forwarded_client() {
+    let identity = ClientIdentity::new("HTTP/proxy.example.com@EXAMPLE.COM").forwarded_client("bob");
+    let mut auth = Authenticated::for_tests(identity);
+    assert_eq!(auth.client(), "bob");
+    assert_eq!(auth.transport_client(), "HTTP/proxy.example.com@EXAMPLE.COM");
+    assert!(auth.is_released());
+    assert_eq!(auth.original_token(), None);
+}
+
+#[test]
+fn handshake_rounds() {
+    let auth = Authenticated::for_tests(ClientIdentity::new("alice@EXAMPLE.COM"));
+    assert_eq!(auth.handshake_rounds(), 0);
+    let identity = ClientIdentity::new("alice@EXAMPLE.COM")
+        .mechanism(Mech::Ntlm)
+        .handshake_rounds(3);
+    assert_eq!(Authenticated::for_tests(identity).handshake_rounds(), 3);
+}
@@ -0,0 +1,101 @@
+use axum_negotiate_layer::{NegotiateToken, ParseError, TokenKind, TokenParseError};
+use http::HeaderValue;
+
+mod vectors;
+use vectors::*;
+
+/// Every vector with its kind
+const KINDS: &[(&str, &[u8], TokenKind)] = &[
+    ("windows", WINDOWS_NEG_TOKEN_INIT, TokenKind::SpnegoInit),
+    ("firefox", FIREFOX_NEG_TOKEN_INIT, TokenKind::SpnegoInit),
+    ("curl", CURL_NEG_TOKEN_INIT, TokenKind::SpnegoInit),
+    ("ntlm only", NTLM_ONLY_NEG_TOKEN_INIT, TokenKind::SpnegoInit),
+    (
+        "accept-incomplete",
+        NEG_TOKEN_RESP_ACCEPT_INCOMPLETE,
+        TokenKind::SpnegoResp,
+    ),
+    (
+        "accept-completed",
+        NEG_TOKEN_RESP_ACCEPT_COMPLETED,
+        TokenKind::SpnegoResp,
+    ),
+    ("reject", NEG_TOKEN_RESP_REJECT, TokenKind::SpnegoResp),
+    ("ntlm negotiate", NTLM_NEGOTIATE, TokenKind::Ntlm),
+    ("ntlm authenticate", NTLM_AUTHENTICATE, TokenKind::Ntlm),
+    ("kerberos", KERBEROS_AP_REQ, TokenKind::RawKerberos),
+];
+
+#[test]
+fn corpus_round_trip() {
+    for (name, token, kind) in KINDS {
+        let header = NegotiateToken::new(*token).to_header_value();
+        let parsed = NegotiateToken::parse_header(&header).unwrap();
+        assert_eq!(&*parsed.decode().unwrap(), *token, "{name}");
+        assert_eq!(parsed.kind(), *kind, "{name}");
+        assert_eq!(NegotiateToken::new(*token).kind(), *kind, "{name}");
+    }
+}
+
+#[test]
+fn unknown_kinds() {
+    assert_eq!(NegotiateToken::new(*b"").kind(), TokenKind::Unknown);
+    assert_eq!(NegotiateToken::new(*b"\x30\x00").kind(), TokenKind::Unknown);
+    // Truncated framing
+    assert_eq!(
+        NegotiateToken::new(&WINDOWS_NEG_TOKEN_INIT[..1]).kind(),
+        TokenKind::Unknown
+    );
+    let header = HeaderValue::from_static("Negotiate !!");
+    let invalid = NegotiateToken::parse_header(&header).unwrap();
+    assert_eq!(invalid.kind(), TokenKind::Unknown);
+}
+
+#[test]
+fn parsing_is_lenient_like_the_layer() {
+    for header in [
+        "negotiate TlRMTVNTUAA=",
+        "\tNegotiate   TlRMTVNTUAA= ",
+        "NTLM TlRMTVNTUAA=",
+        // Without padding
+        "Negotiate TlRMTVNTUAA",
+    ] {
+        let value = HeaderValue::from_static(header);
+        let token = NegotiateToken::parse_header(&value).unwrap();
+        assert_eq!(&*token.decode().unwrap(), b"NTLMSSP\0", "{header}");
+    }
+}
+
+#[test]
+fn parse_errors() {
+    let parse = |header| NegotiateToken::parse_header(&HeaderValue::from_static(header)).map(|_| ());
+    assert_eq!(
+        parse("Negotiate"),
+        Err(TokenParseError::Header(ParseError::MissingToken))
+    );
+    assert_eq!(
+        parse("Negotiate   "),
+        Err(TokenParseError::Header(ParseError::MissingToken))
+    );
+    assert_eq!(
+        parse("Basic c2VjcmV0"),
+        Err(TokenParseError::Header(ParseError::UnsupportedScheme))
+    );
+    let header = HeaderValue::from_static("Negotiate TlRMTVNTUAA=A");
+    let truncated = NegotiateToken::parse_header(&header).unwrap();
+    assert_eq!(truncated.decode(), Err(TokenParseError::InvalidBase64));
+}
+
+#[test]
+fn bounded_decoding() {
+    let header = NegotiateToken::new(NTLM_NEGOTIATE).to_header_value();
+    let parsed = NegotiateToken::parse_header(&header).unwrap();
+    let constructed = NegotiateToken::new(NTLM_NEGOTIATE);
+    for token in [parsed, constructed] {
+        assert!(token.decode_bounded(NTLM_NEGOTIATE.len()).is_ok());
+        assert_eq!(
+            token.decode_bounded(NTLM_NEGOTIATE.len() - 1),
+            Err(TokenParseError::TooLarge)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use axum::{Router, body::Body, extract::ConnectInfo, response::Response, routing::get};
+use axum_negotiate_layer::{Authenticated, FailureReason, LayerStats, NegotiateInfo, NegotiateLayer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+    HeaderName, Request, StatusCode,
+    header::{AUTHORIZATION, VARY, WWW_AUTHENTICATE},
+};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+
+const TOKEN: HeaderName = HeaderName::from_static("x-kerberos-token");
+const CHALLENGE: HeaderName = HeaderName::from_static("x-kerberos-challenge");
+
+fn layer(spn: Option<&str>) -> NegotiateLayer {
+    NegotiateLayer::new(spn)
+        .token_header(TOKEN)
+        .challenge_header(CHALLENGE)
+        .prefixed(false)
+}
+
+async fn send(layer: NegotiateLayer, headers: &[(HeaderName, &str)]) -> Response {
+    let router = Router::new()
+        .route("/", get(|mut auth: Authenticated| async move { auth.client() }))
+        .layer(layer);
+    let mut request = Request::builder().uri("/");
+    for (name, value) in headers {
+        request = request.header(name, *value);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    router.oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn challenges_in_custom_header() {
+    let response = send(layer(None), &[]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[CHALLENGE], "Negotiate");
+    assert!(!response.headers().contains_key(WWW_AUTHENTICATE));
+    assert_eq!(response.headers()[VARY], "x-kerberos-token");
+}
+
+#[tokio::test]
+async fn ignores_authorization() {
+    let token = format!("Negotiate {}", BASE64_STANDARD.encode(b"token"));
+    let response = send(layer(None), &[(AUTHORIZATION, &token)]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[CHALLENGE], "Negotiate");
+}
+
+#[tokio::test]
+async fn rejects_empty_token() {
+    let stats = LayerStats::new();
+    let response = send(layer(None).with_stats(&stats), &[(TOKEN, " ")]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(stats.failed(FailureReason::InvalidHeader), 1);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn handshake_over_custom_headers() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let StepOut::Pending(client) = ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .request_mutual_auth()
+        .initialize()
+        .unwrap()
+    else {
+        panic!("mutual authentication needs a second round");
+    };
+    let token = BASE64_STANDARD.encode(client.next_token());
+    let response = send(layer(Some(&spn)), &[(TOKEN, &token)]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(WWW_AUTHENTICATE));
+    // The final token, bare as well
+    let final_token = response.headers()[CHALLENGE].to_str().unwrap();
+    let final_token = BASE64_STANDARD.decode(final_token).unwrap();
+    assert!(matches!(client.step(&final_token).unwrap(), StepOut::Finished(_)));
+}
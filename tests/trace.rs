@@ -0,0 +1,239 @@
+#![cfg(feature = "tracing")]
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, Request},
+    middleware::{Next, from_fn},
+    response::Response,
+    routing::get,
+};
+use axum_negotiate_layer::{NegotiateInfo, NegotiateLayer, trace};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{HeaderName, StatusCode, header::AUTHORIZATION};
+use kenobi::{
+    client::{ClientBuilder, StepOut},
+    cred::Credentials,
+    mech::Mechanism,
+};
+use tower::ServiceExt;
+use tracing::{
+    Event, Instrument, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry,
+    registry::LookupSpan,
+};
+
+/// Collects the fields of the request span
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<HashMap<String, String>>>);
+impl Capture {
+    fn field(&self, name: &str) -> Option<String> {
+        self.0.lock().unwrap().get(name).cloned()
+    }
+}
+impl Visit for &Capture {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(field.name().to_owned(), format!("{value:?}"));
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.lock().unwrap().insert(field.name().to_owned(), value.to_owned());
+    }
+}
+impl<S: Subscriber> tracing_subscriber::Layer<S> for Capture {
+    fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+        if attrs.metadata().name() == "request" {
+            attrs.record(&mut &*self);
+        }
+    }
+    fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+        values.record(&mut &*self);
+    }
+}
+
+/// The value of the field `name`, as recorded by a span or an event
+struct FieldValue(&'static str, Option<String>);
+impl Visit for FieldValue {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == self.0 {
+            self.1 = Some(format!("{value:?}"));
+        }
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == self.0 {
+            self.1 = Some(value.to_owned());
+        }
+    }
+}
+
+/// An event as received by [`Correlated`]
+#[derive(Debug)]
+struct CorrelatedEvent {
+    message: String,
+    correlation_id: Option<String>,
+}
+
+/// Collects the message of every event with the `correlation_id` of the `negotiate` span it happened in
+#[derive(Clone, Default)]
+struct Correlated {
+    spans: Arc<Mutex<HashMap<Id, String>>>,
+    events: Arc<Mutex<Vec<CorrelatedEvent>>>,
+}
+impl Correlated {
+    fn correlation_id(&self, message: &str) -> Option<String> {
+        let events = self.events.lock().unwrap();
+        let event = events.iter().find(|event| event.message == message);
+        event
+            .unwrap_or_else(|| panic!("no event {message:?} in {events:?}"))
+            .correlation_id
+            .clone()
+    }
+}
+impl<S: Subscriber + for<'a> LookupSpan<'a>> tracing_subscriber::Layer<S> for Correlated {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _: Context<'_, S>) {
+        if attrs.metadata().name() == "negotiate" {
+            let mut correlation_id = FieldValue("correlation_id", None);
+            attrs.record(&mut correlation_id);
+            let correlation_id = correlation_id.1.unwrap_or_default();
+            self.spans.lock().unwrap().insert(id.clone(), correlation_id);
+        }
+    }
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut message = FieldValue("message", None);
+        event.record(&mut message);
+        let spans = self.spans.lock().unwrap();
+        let correlation_id = ctx
+            .event_scope(event)
+            .into_iter()
+            .flatten()
+            .find_map(|span| spans.get(&span.id()).cloned());
+        let message = message.1.unwrap_or_default();
+        self.events.lock().unwrap().push(CorrelatedEvent {
+            message,
+            correlation_id,
+        });
+    }
+}
+
+/// Stands in for `TraceLayer` with the span functions
+async fn traced(request: Request, next: Next) -> Response {
+    let span = trace::make_span(&request);
+    let start = Instant::now();
+    let response = next.run(request).instrument(span.clone()).await;
+    trace::on_response(&response, start.elapsed(), &span);
+    response
+}
+
+fn request(info: &NegotiateInfo, token: Option<&[u8]>) -> Request<Body> {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Negotiate {}", BASE64_STANDARD.encode(token)));
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(info.clone()));
+    request
+}
+
+#[tokio::test]
+async fn challenge_outside() {
+    let capture = Capture::default();
+    let _guard = tracing::subscriber::set_default(registry().with(capture.clone()));
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(NegotiateLayer::new(None))
+        .layer(from_fn(traced));
+    let response = router.oneshot(request(&NegotiateInfo::new(), None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(capture.field("auth.outcome").as_deref(), Some("challenged"));
+    assert_eq!(capture.field("enduser.id"), None);
+    assert_eq!(capture.field("method").as_deref(), Some("GET"));
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn client_inside() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let capture = Capture::default();
+    let _guard = tracing::subscriber::set_default(registry().with(capture.clone()));
+    let router = Router::new()
+        .route("/", get(|| async {}))
+        .layer(from_fn(traced))
+        .layer(NegotiateLayer::new(Some(&spn)));
+    let response = router
+        .oneshot(request(&NegotiateInfo::new(), Some(&token)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(capture.field("auth.outcome").as_deref(), Some("authenticated"));
+    assert!(capture.field("enduser.id").is_some_and(|client| !client.is_empty()));
+}
+
+#[tokio::test]
+async fn correlation_span() {
+    let correlated = Correlated::default();
+    let _guard = tracing::subscriber::set_default(registry().with(correlated.clone()));
+    let layer = NegotiateLayer::new(None).correlation_header(HeaderName::from_static("x-request-id"));
+    let router = Router::new().route("/", get(|| async {})).layer(layer);
+    let mut request = request(&NegotiateInfo::new(), None);
+    let headers = request.headers_mut();
+    headers.insert(AUTHORIZATION, "Basic c2VjcmV0".parse().unwrap());
+    headers.insert("x-request-id", "4711".parse().unwrap());
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        correlated.correlation_id("Invalid Authorization header").as_deref(),
+        Some("4711")
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_SPN, a keytab for it and a client ticket cache"]
+async fn correlation_span_covers_handler() {
+    let spn = std::env::var("TEST_SPN").unwrap();
+    let credentials = Credentials::outbound(None, Mechanism::Spnego).expect("no client credentials available");
+    let token = match ClientBuilder::new_from_credentials(credentials, Some(&spn))
+        .initialize()
+        .unwrap()
+    {
+        StepOut::Pending(pending) => pending.next_token().to_vec(),
+        StepOut::Finished(finished) => finished.last_token().unwrap().to_vec(),
+    };
+    let correlated = Correlated::default();
+    let _guard = tracing::subscriber::set_default(registry().with(correlated.clone()));
+    let layer = NegotiateLayer::new(Some(&spn)).correlation_header(HeaderName::from_static("x-request-id"));
+    let router = Router::new()
+        .route(
+            "/",
+            get(|| async {
+                tokio::task::yield_now().await;
+                tracing::info!("In the handler");
+            }),
+        )
+        .layer(layer);
+    let mut request = request(&NegotiateInfo::new(), Some(&token));
+    request.headers_mut().insert("x-request-id", "4711".parse().unwrap());
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(correlated.correlation_id("In the handler").as_deref(), Some("4711"));
+}
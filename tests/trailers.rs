@@ -0,0 +1,124 @@
+use std::{
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    Router,
+    body::{Body, Bytes, HttpBody},
+    extract::ConnectInfo,
+    response::Response,
+    routing::get,
+};
+use axum_negotiate_layer::{CLIENT_TRAILER, MECHANISM_TRAILER, NegotiateInfo, NegotiateLayer, SessionIdentity};
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Request, StatusCode,
+    header::{CONTENT_LENGTH, TE, TRAILER},
+};
+use http_body::Frame;
+use tower::ServiceExt;
+
+/// Sends a request authenticated by its session, so the trailers need no handshake
+async fn respond(router: Router, te: Option<&str>) -> Response {
+    let router = router.layer(NegotiateLayer::new(None).or_session(true).auth_trailers(true));
+    let mut request = Request::builder().uri("/");
+    if let Some(te) = te {
+        request = request.header(TE, te);
+    }
+    let mut request = request.body(Body::empty()).unwrap();
+    request.extensions_mut().insert(ConnectInfo(NegotiateInfo::new()));
+    request
+        .extensions_mut()
+        .insert(SessionIdentity("alice@EXAMPLE.COM".to_owned()));
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    response
+}
+
+/// The data and the trailers of `body`, checking that trailers are the last frame
+async fn frames(mut body: Body) -> (Vec<u8>, Option<HeaderMap>) {
+    let mut data = Vec::new();
+    let mut trailers = None;
+    while let Some(frame) = poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+        assert!(trailers.is_none(), "frame after the trailers");
+        match frame.unwrap().into_data() {
+            Ok(chunk) => data.extend_from_slice(&chunk),
+            Err(frame) => trailers = Some(frame.into_trailers().unwrap()),
+        }
+    }
+    assert!(body.is_end_stream());
+    (data, trailers)
+}
+
+/// A body sending the given frames, e.g. with its own trailers
+struct WithOwnTrailers(Vec<Frame<Bytes>>);
+impl HttpBody for WithOwnTrailers {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let frames = &mut self.get_mut().0;
+        Poll::Ready((!frames.is_empty()).then(|| Ok(frames.remove(0))))
+    }
+    fn is_end_stream(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[tokio::test]
+async fn appended_after_the_body() {
+    let response = respond(Router::new().route("/", get(|| async { "hello" })), Some("trailers")).await;
+    let headers = response.headers();
+    assert!(!headers.contains_key(CONTENT_LENGTH));
+    let announced: Vec<_> = headers.get_all(TRAILER).iter().collect();
+    assert_eq!(announced, ["negotiate-client", "negotiate-mechanism"]);
+    let body = response.into_body();
+    assert!(!body.is_end_stream());
+    assert_eq!(body.size_hint().upper(), None);
+    let (data, trailers) = frames(body).await;
+    assert_eq!(data, b"hello");
+    let trailers = trailers.expect("no trailers sent");
+    assert_eq!(trailers[CLIENT_TRAILER], "alice@EXAMPLE.COM");
+    assert_eq!(trailers[MECHANISM_TRAILER], "session");
+}
+
+#[tokio::test]
+async fn sent_for_an_empty_body() {
+    let response = respond(Router::new().route("/", get(|| async {})), Some("gzip, Trailers")).await;
+    let body = response.into_body();
+    assert!(!body.is_end_stream());
+    let (data, trailers) = frames(body).await;
+    assert!(data.is_empty());
+    assert_eq!(trailers.expect("no trailers sent").len(), 2);
+}
+
+#[tokio::test]
+async fn merged_with_the_inner_trailers() {
+    let handler = || async {
+        let mut own = HeaderMap::new();
+        own.insert(HeaderName::from_static("checksum"), HeaderValue::from_static("42"));
+        Body::new(WithOwnTrailers(vec![
+            Frame::data(Bytes::from_static(b"hello")),
+            Frame::trailers(own),
+        ]))
+    };
+    let response = respond(Router::new().route("/", get(handler)), Some("trailers")).await;
+    let (data, trailers) = frames(response.into_body()).await;
+    assert_eq!(data, b"hello");
+    let trailers = trailers.expect("no trailers sent");
+    assert_eq!(trailers["checksum"], "42");
+    assert_eq!(trailers[CLIENT_TRAILER], "alice@EXAMPLE.COM");
+    assert_eq!(trailers[MECHANISM_TRAILER], "session");
+}
+
+#[tokio::test]
+async fn only_when_accepted() {
+    let response = respond(Router::new().route("/", get(|| async { "hello" })), None).await;
+    let headers = response.headers();
+    assert!(!headers.contains_key(TRAILER));
+    assert_eq!(headers[CONTENT_LENGTH], "5");
+    let (data, trailers) = frames(response.into_body()).await;
+    assert_eq!(data, b"hello");
+    assert_eq!(trailers, None);
+}
@@ -0,0 +1,65 @@
+#![cfg(unix)]
+//! The classification of [`SpnError`] by the keytab named in the environment
+
+use std::{
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+};
+
+use axum_negotiate_layer::{KEYTAB_VAR, SpnError, validate_spn};
+
+#[allow(dead_code)]
+mod keytab;
+
+/// Points the backend at `name` below a directory of this test binary, holding the environment until dropped
+fn use_keytab(name: &str) -> (MutexGuard<'static, ()>, PathBuf) {
+    static ENV: Mutex<()> = Mutex::new(());
+    let guard = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("validate-spn-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    // SAFETY: the tests of this binary only read the environment while holding the guard
+    unsafe { std::env::set_var(KEYTAB_VAR, format!("FILE:{}", path.display())) };
+    (guard, path)
+}
+
+#[test]
+fn keytab_missing() {
+    let (_guard, _) = use_keytab("missing.keytab");
+    assert!(matches!(validate_spn(keytab::SPN), Err(SpnError::KeytabMissing(_))));
+}
+
+#[test]
+fn not_registered() {
+    let (_guard, path) = use_keytab("localhost.keytab");
+    std::fs::write(&path, keytab::contents("HTTP", "localhost")).unwrap();
+    assert_eq!(validate_spn(keytab::SPN), Ok(()));
+    assert_eq!(validate_spn("HTTP@localhost"), Ok(()));
+    let unknown = validate_spn("HTTP/unknown.invalid@EXAMPLE.COM");
+    assert!(matches!(unknown, Err(SpnError::NotRegistered(_))), "{unknown:?}");
+    let unknown = validate_spn("HTTP@unknown.invalid");
+    assert!(matches!(unknown, Err(SpnError::NotRegistered(_))), "{unknown:?}");
+}
+
+#[test]
+fn not_a_keytab() {
+    let (_guard, path) = use_keytab("garbage.keytab");
+    std::fs::write(&path, b"not a keytab").unwrap();
+    let error = validate_spn(keytab::SPN);
+    assert!(matches!(error, Err(SpnError::Other(_))), "{error:?}");
+}
+
+#[test]
+fn permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (_guard, path) = use_keytab("unreadable.keytab");
+    std::fs::write(&path, keytab::contents("HTTP", "localhost")).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o000)).unwrap();
+    // Permissions don't apply to root
+    if std::fs::read(&path).is_ok() {
+        return;
+    }
+    let error = validate_spn(keytab::SPN);
+    assert!(matches!(error, Err(SpnError::PermissionDenied(_))), "{error:?}");
+}
@@ -0,0 +1,94 @@
+//! Tokens as sent by common clients, for conformance tests
+//!
+//! These are reconstructed after the layout of real captures. Everything secret or identifying, i.e. tickets,
+//! authenticators, responses and names, is replaced by placeholders, so they can't be accepted by any backend.
+
+/// SPNEGO `negTokenInit` as sent by Windows clients, offering Kerberos under both OIDs, NegoEx and NTLM
+pub const WINDOWS_NEG_TOKEN_INIT: &[u8] = &[
+    0x60, 0x81, 0xa1, 0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02, 0xa0, 0x81, 0x96, 0x30, 0x81, 0x93, 0xa0, 0x30,
+    0x30, 0x2e, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x82, 0xf7, 0x12, 0x01, 0x02, 0x02, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86,
+    0xf7, 0x12, 0x01, 0x02, 0x02, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x1e, 0x06, 0x0a,
+    0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a, 0xa2, 0x5f, 0x04, 0x5d, 0x60, 0x5b, 0x06, 0x09, 0x2a,
+    0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02, 0x01, 0x00, 0x6e, 0x4c, 0x30, 0x4a, 0xa0, 0x03, 0x02, 0x01, 0x05,
+    0xa1, 0x03, 0x02, 0x01, 0x0e, 0xa2, 0x07, 0x03, 0x05, 0x00, 0x20, 0x00, 0x00, 0x00, 0xa3, 0x18, 0x61, 0x16, 0x30,
+    0x14, 0xa0, 0x03, 0x02, 0x01, 0x05, 0xa1, 0x0d, 0x1b, 0x0b, 0x45, 0x58, 0x41, 0x4d, 0x50, 0x4c, 0x45, 0x2e, 0x43,
+    0x4f, 0x4d, 0xa4, 0x1b, 0x30, 0x19, 0xa0, 0x03, 0x02, 0x01, 0x12, 0xa2, 0x12, 0x04, 0x10, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// SPNEGO `negTokenInit` as sent by Firefox through MIT Kerberos, offering Kerberos under both OIDs
+pub const FIREFOX_NEG_TOKEN_INIT: &[u8] = &[
+    0x60, 0x81, 0x87, 0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02, 0xa0, 0x7d, 0x30, 0x7b, 0xa0, 0x18, 0x30, 0x16,
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x82, 0xf7, 0x12,
+    0x01, 0x02, 0x02, 0xa2, 0x5f, 0x04, 0x5d, 0x60, 0x5b, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02,
+    0x02, 0x01, 0x00, 0x6e, 0x4c, 0x30, 0x4a, 0xa0, 0x03, 0x02, 0x01, 0x05, 0xa1, 0x03, 0x02, 0x01, 0x0e, 0xa2, 0x07,
+    0x03, 0x05, 0x00, 0x20, 0x00, 0x00, 0x00, 0xa3, 0x18, 0x61, 0x16, 0x30, 0x14, 0xa0, 0x03, 0x02, 0x01, 0x05, 0xa1,
+    0x0d, 0x1b, 0x0b, 0x45, 0x58, 0x41, 0x4d, 0x50, 0x4c, 0x45, 0x2e, 0x43, 0x4f, 0x4d, 0xa4, 0x1b, 0x30, 0x19, 0xa0,
+    0x03, 0x02, 0x01, 0x12, 0xa2, 0x12, 0x04, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// SPNEGO `negTokenInit` as sent by curl with `--negotiate`, offering only Kerberos
+pub const CURL_NEG_TOKEN_INIT: &[u8] = &[
+    0x60, 0x7c, 0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02, 0xa0, 0x72, 0x30, 0x70, 0xa0, 0x0d, 0x30, 0x0b, 0x06,
+    0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02, 0xa2, 0x5f, 0x04, 0x5d, 0x60, 0x5b, 0x06, 0x09, 0x2a,
+    0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02, 0x01, 0x00, 0x6e, 0x4c, 0x30, 0x4a, 0xa0, 0x03, 0x02, 0x01, 0x05,
+    0xa1, 0x03, 0x02, 0x01, 0x0e, 0xa2, 0x07, 0x03, 0x05, 0x00, 0x20, 0x00, 0x00, 0x00, 0xa3, 0x18, 0x61, 0x16, 0x30,
+    0x14, 0xa0, 0x03, 0x02, 0x01, 0x05, 0xa1, 0x0d, 0x1b, 0x0b, 0x45, 0x58, 0x41, 0x4d, 0x50, 0x4c, 0x45, 0x2e, 0x43,
+    0x4f, 0x4d, 0xa4, 0x1b, 0x30, 0x19, 0xa0, 0x03, 0x02, 0x01, 0x12, 0xa2, 0x12, 0x04, 0x10, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// SPNEGO `negTokenInit` of a client without Kerberos credentials, offering only NTLM
+pub const NTLM_ONLY_NEG_TOKEN_INIT: &[u8] = &[
+    0x60, 0x48, 0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02, 0xa0, 0x3e, 0x30, 0x3c, 0xa0, 0x0e, 0x30, 0x0c, 0x06,
+    0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a, 0xa2, 0x2a, 0x04, 0x28, 0x4e, 0x54, 0x4c, 0x4d,
+    0x53, 0x53, 0x50, 0x00, 0x01, 0x00, 0x00, 0x00, 0x97, 0x82, 0x08, 0xe2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x61, 0x4a, 0x00, 0x00, 0x00, 0x0f,
+];
+
+/// SPNEGO `negTokenResp` continuing an NTLM handshake
+pub const NEG_TOKEN_RESP_ACCEPT_INCOMPLETE: &[u8] = &[
+    0xa1, 0x41, 0x30, 0x3f, 0xa0, 0x03, 0x0a, 0x01, 0x01, 0xa1, 0x0c, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82,
+    0x37, 0x02, 0x02, 0x0a, 0xa2, 0x2a, 0x04, 0x28, 0x4e, 0x54, 0x4c, 0x4d, 0x53, 0x53, 0x50, 0x00, 0x01, 0x00, 0x00,
+    0x00, 0x97, 0x82, 0x08, 0xe2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x0a, 0x00, 0x61, 0x4a, 0x00, 0x00, 0x00, 0x0f,
+];
+
+/// SPNEGO `negTokenResp` completing a Kerberos handshake
+pub const NEG_TOKEN_RESP_ACCEPT_COMPLETED: &[u8] = &[
+    0xa1, 0x14, 0x30, 0x12, 0xa0, 0x03, 0x0a, 0x01, 0x00, 0xa1, 0x0b, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12,
+    0x01, 0x02, 0x02,
+];
+
+/// SPNEGO `negTokenResp` rejecting the handshake
+pub const NEG_TOKEN_RESP_REJECT: &[u8] = &[0xa1, 0x07, 0x30, 0x05, 0xa0, 0x03, 0x0a, 0x01, 0x02];
+
+/// Raw NTLM NEGOTIATE (type 1) message
+pub const NTLM_NEGOTIATE: &[u8] = &[
+    0x4e, 0x54, 0x4c, 0x4d, 0x53, 0x53, 0x50, 0x00, 0x01, 0x00, 0x00, 0x00, 0x97, 0x82, 0x08, 0xe2, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x61, 0x4a, 0x00, 0x00,
+    0x00, 0x0f,
+];
+
+/// Raw NTLMv2 AUTHENTICATE (type 3) message from user `user` in domain `DOM` on workstation `WS`
+pub const NTLM_AUTHENTICATE: &[u8] = &[
+    0x4e, 0x54, 0x4c, 0x4d, 0x53, 0x53, 0x50, 0x00, 0x03, 0x00, 0x00, 0x00, 0x18, 0x00, 0x18, 0x00, 0x40, 0x00, 0x00,
+    0x00, 0x3a, 0x00, 0x3a, 0x00, 0x58, 0x00, 0x00, 0x00, 0x06, 0x00, 0x06, 0x00, 0x92, 0x00, 0x00, 0x00, 0x08, 0x00,
+    0x08, 0x00, 0x98, 0x00, 0x00, 0x00, 0x04, 0x00, 0x04, 0x00, 0xa0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xa4,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+    0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x00, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x06, 0x00, 0x44, 0x00, 0x4f, 0x00, 0x4d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x44, 0x00, 0x4f, 0x00, 0x4d, 0x00,
+    0x75, 0x00, 0x73, 0x00, 0x65, 0x00, 0x72, 0x00, 0x57, 0x00, 0x53, 0x00,
+];
+
+/// Raw Kerberos `AP-REQ` in its GSS-API framing
+pub const KERBEROS_AP_REQ: &[u8] = &[
+    0x60, 0x5b, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02, 0x01, 0x00, 0x6e, 0x4c, 0x30, 0x4a,
+    0xa0, 0x03, 0x02, 0x01, 0x05, 0xa1, 0x03, 0x02, 0x01, 0x0e, 0xa2, 0x07, 0x03, 0x05, 0x00, 0x20, 0x00, 0x00, 0x00,
+    0xa3, 0x18, 0x61, 0x16, 0x30, 0x14, 0xa0, 0x03, 0x02, 0x01, 0x05, 0xa1, 0x0d, 0x1b, 0x0b, 0x45, 0x58, 0x41, 0x4d,
+    0x50, 0x4c, 0x45, 0x2e, 0x43, 0x4f, 0x4d, 0xa4, 0x1b, 0x30, 0x19, 0xa0, 0x03, 0x02, 0x01, 0x12, 0xa2, 0x12, 0x04,
+    0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];